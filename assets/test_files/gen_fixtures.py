@@ -0,0 +1,665 @@
+"""Generates the lossless test files in `synth_keys/` from the WAV files next to them.
+
+    python3 assets/test_files/gen_fixtures.py
+
+The test files are used by `decodes_lossless_formats` in
+`src/backend/resource_loader/pcm/loader.rs`, which checks that they decode to exactly the
+same samples as the WAV files. Only the Python standard library is used.
+
+Limits:
+
+- These are not made by the reference encoders (libFLAC and Apple's ALAC encoder), so
+  they only cover the parts of the formats written below. Replace them with the output
+  of the reference encoders when those are available.
+- FLAC: Blocks of 4096 frames. Each block uses the cheapest of the fixed (order 0 to 4)
+  and LPC (order 8) subframes and of the four stereo decorrelation modes, with
+  partitioned Rice coding of the residual. For the 24-bit file this picks LPC subframes
+  with independent and mid/side stereo. There is no seek table, and constant, verbatim
+  and escaped Rice partitions are never written. The 16-bit `synth_keys_48000.flac` was
+  made with libFLAC.
+- ALAC: Stereo frames of 4096 frames with the adaptive predictor (mode 0, order 8),
+  adaptive Golomb coding of the residual with runs of zeros, and mid/side mixing. The low
+  byte of 24-bit samples is stored uncompressed like the reference encoder does.
+  Uncompressed (escaped) frames are never written.
+- AIFF and AIFC files are uncompressed, so these match what any encoder would write.
+"""
+
+import hashlib
+import math
+import os
+import struct
+
+DIR = os.path.join(os.path.dirname(os.path.abspath(__file__)), 'synth_keys')
+
+
+def read_wav(path):
+    d = open(path, 'rb').read()
+    assert d[:4] == b'RIFF' and d[8:12] == b'WAVE'
+    off = 12
+    fmt = None
+    data = None
+    while off + 8 <= len(d):
+        cid = d[off:off + 4]
+        size = struct.unpack('<I', d[off + 4:off + 8])[0]
+        body = d[off + 8:off + 8 + size]
+        if cid == b'fmt ':
+            tag, ch, sr, _, _, bits = struct.unpack('<HHIIHH', body[:16])
+            if tag == 0xFFFE:
+                tag = struct.unpack('<H', body[24:26])[0]
+            fmt = (tag, ch, sr, bits)
+        elif cid == b'data':
+            data = body
+        off += 8 + size + (size & 1)
+    tag, ch, sr, bits = fmt
+    bps = bits // 8
+    n = len(data) // (bps * ch)
+    chans = [[] for _ in range(ch)]
+    for i in range(n):
+        for c in range(ch):
+            b = data[(i * ch + c) * bps:(i * ch + c + 1) * bps]
+            if tag == 3:
+                v = struct.unpack('<f', b)[0]
+            elif bits == 8:
+                v = b[0] - 128
+            else:
+                v = int.from_bytes(b, 'little', signed=True)
+            chans[c].append(v)
+    return tag, sr, bits, chans
+
+
+def ext80(x):
+    x = float(x)
+    e = int(math.floor(math.log2(x)))
+    m = int(x * (2 ** (63 - e)))
+    return struct.pack('>HQ', 16383 + e, m)
+
+
+def chunk_be(cid, body):
+    out = cid + struct.pack('>I', len(body)) + body
+    if len(body) & 1:
+        out += b'\0'
+    return out
+
+
+def write_aiff(path, sr, bits, chans, aifc=None):
+    ch = len(chans)
+    n = len(chans[0])
+    samples = bytearray()
+    for i in range(n):
+        for c in range(ch):
+            v = chans[c][i]
+            if aifc == b'fl32':
+                samples += struct.pack('>f', v)
+            elif aifc == b'sowt':
+                samples += int(v).to_bytes(bits // 8, 'little', signed=True)
+            else:
+                samples += int(v).to_bytes(bits // 8, 'big', signed=True)
+    comm = struct.pack('>hIh', ch, n, bits) + ext80(sr)
+    chunks = b''
+    if aifc is not None:
+        name = {b'sowt': b'little-endian', b'fl32': b'32-bit floating point'}[aifc]
+        pstr = bytes([len(name)]) + name
+        if len(pstr) & 1:
+            pstr += b'\0'
+        comm += aifc + pstr
+        chunks += chunk_be(b'FVER', struct.pack('>I', 0xA2805140))
+    chunks += chunk_be(b'COMM', comm)
+    chunks += chunk_be(b'SSND', struct.pack('>II', 0, 0) + bytes(samples))
+    form = b'AIFC' if aifc is not None else b'AIFF'
+    open(path, 'wb').write(b'FORM' + struct.pack('>I', len(chunks) + 4) + form + chunks)
+
+
+class BitWriter:
+    def __init__(self):
+        self.out = bytearray()
+        self.acc = 0
+        self.n = 0
+
+    def write(self, v, bits):
+        v &= (1 << bits) - 1
+        self.acc = (self.acc << bits) | v
+        self.n += bits
+        while self.n >= 8:
+            self.n -= 8
+            self.out.append((self.acc >> self.n) & 0xFF)
+        self.acc &= (1 << self.n) - 1
+
+    def align(self):
+        if self.n:
+            self.write(0, 8 - self.n)
+
+    def bytes(self):
+        assert self.n == 0
+        return bytes(self.out)
+
+
+def crc8(data):
+    crc = 0
+    for b in data:
+        crc ^= b
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x07) & 0xFF if crc & 0x80 else (crc << 1) & 0xFF
+    return crc
+
+
+def crc16(data):
+    crc = 0
+    for b in data:
+        crc ^= b << 8
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x8005) & 0xFFFF if crc & 0x8000 else (crc << 1) & 0xFFFF
+    return crc
+
+
+def utf8_int(v):
+    if v < 0x80:
+        return bytes([v])
+    out = []
+    nbytes = 2
+    while v >= (1 << (5 * nbytes + 1)):
+        nbytes += 1
+    for _ in range(nbytes - 1):
+        out.insert(0, 0x80 | (v & 0x3F))
+        v >>= 6
+    first = ((0xFF << (8 - nbytes)) & 0xFF) | v
+    return bytes([first] + out)
+
+
+# -- FLAC ----------------------------------------------------------------------------------
+
+FLAC_BLOCK_LEN = 4096
+FLAC_MAX_LPC_ORDER = 8
+FLAC_QLP_PRECISION = 14
+FLAC_VENDOR = b'meadowlark gen_fixtures.py'
+
+
+def zigzag(v):
+    return v << 1 if v >= 0 else ((-v) << 1) - 1
+
+
+def rice_bits(values, k):
+    return len(values) * (k + 1) + sum(v >> k for v in values)
+
+
+def best_rice_param(values):
+    """Returns the (bits, parameter) of the cheapest Rice parameter for the values."""
+    if not values:
+        return 0, 0
+    mean = sum(values) / len(values)
+    guess = int(math.log2(mean)) if mean >= 1.0 else 0
+    return min((rice_bits(values, k), k) for k in range(max(guess - 1, 0), min(guess + 2, 31)))
+
+
+def estimate_residual_bits(residual):
+    return best_rice_param([zigzag(r) for r in residual])[0]
+
+
+def fixed_residual(x, order):
+    res = list(x)
+    for _ in range(order):
+        res = [res[i] - res[i - 1] for i in range(1, len(res))]
+    return res
+
+
+def autocorrelation(x, max_lag):
+    n = len(x)
+    # Welch window.
+    half = (n - 1) / 2.0
+    w = [1.0 - ((i - half) / (half + 1.0)) ** 2 for i in range(n)]
+    xw = [a * b for a, b in zip(x, w)]
+    return [sum(xw[i] * xw[i - lag] for i in range(lag, n)) for lag in range(max_lag + 1)]
+
+
+def levinson(autoc, order):
+    """Returns the coefficients `a` of the predictor `x[i] = sum(a[k] * x[i - 1 - k])`."""
+    if autoc[0] == 0.0:
+        return None
+    a = []
+    err = autoc[0]
+    for i in range(order):
+        acc = autoc[i + 1] - sum(a[j] * autoc[i - j] for j in range(i))
+        k = acc / err
+        a = [a[j] - k * a[i - 1 - j] for j in range(i)] + [k]
+        err *= 1.0 - k * k
+        if err <= 0.0:
+            return None
+    return a
+
+
+def quantize_lpc(a, precision):
+    cmax = max(abs(c) for c in a)
+    if cmax == 0.0:
+        return None
+    _, log2cmax = math.frexp(cmax)
+    shift = min(max(precision - 1 - log2cmax, 0), 15)
+    qmax = (1 << (precision - 1)) - 1
+    q = []
+    error = 0.0
+    for c in a:
+        error += c * (1 << shift)
+        v = max(-qmax - 1, min(qmax, int(round(error))))
+        error -= v
+        q.append(v)
+    return q, shift
+
+
+def lpc_residual(x, q, shift):
+    order = len(q)
+    return [
+        x[i] - (sum(c * x[i - 1 - k] for k, c in enumerate(q)) >> shift)
+        for i in range(order, len(x))
+    ]
+
+
+def write_residual(bw, residual, order, block_len):
+    """Write the residual with the cheapest Rice partitioning."""
+    values = [zigzag(r) for r in residual]
+    best = None
+    for porder in range(0, 9):
+        if block_len % (1 << porder) or (block_len >> porder) < order:
+            break
+        part_len = block_len >> porder
+        bits = 0
+        parts = []
+        start = 0
+        for p in range(1 << porder):
+            n = part_len - (order if p == 0 else 0)
+            part_bits, k = best_rice_param(values[start:start + n])
+            parts.append((start, n, k))
+            bits += part_bits
+            start += n
+        if best is None or bits < best[0]:
+            best = (bits, porder, parts)
+
+    _, porder, parts = best
+    rice2 = any(k > 14 for _, _, k in parts)
+    bw.write(1 if rice2 else 0, 2)
+    bw.write(porder, 4)
+    for start, n, k in parts:
+        bw.write(k, 5 if rice2 else 4)
+        for v in values[start:start + n]:
+            bw.write(1, (v >> k) + 1)
+            bw.write(v, k)
+
+
+def subframe_candidates(x):
+    """Returns the (estimated bits, kind, params, residual) of each way to encode `x`."""
+    candidates = []
+    for order in range(min(4, len(x) - 1) + 1):
+        res = fixed_residual(x, order)
+        candidates.append((estimate_residual_bits(res), 'fixed', order, res))
+
+    if len(x) > FLAC_MAX_LPC_ORDER * 4:
+        a = levinson(autocorrelation(x, FLAC_MAX_LPC_ORDER), FLAC_MAX_LPC_ORDER)
+        quantized = quantize_lpc(a, FLAC_QLP_PRECISION) if a else None
+        if quantized:
+            q, shift = quantized
+            res = lpc_residual(x, q, shift)
+            bits = estimate_residual_bits(res) + len(q) * FLAC_QLP_PRECISION + 9
+            candidates.append((bits, 'lpc', (q, shift), res))
+
+    return candidates
+
+
+def best_subframe(x, bps):
+    best = min(subframe_candidates(x), key=lambda c: c[0])
+    bits, kind, params, res = best
+    order = params if kind == 'fixed' else len(params[0])
+    return bits + order * bps, best
+
+
+def write_subframe(bw, x, bps, subframe):
+    _, kind, params, res = subframe
+    bw.write(0, 1)
+    if kind == 'fixed':
+        order = params
+        bw.write(0x08 | order, 6)
+    else:
+        q, shift = params
+        order = len(q)
+        bw.write(0x20 | (order - 1), 6)
+    bw.write(0, 1)  # no wasted bits
+
+    for v in x[:order]:
+        bw.write(v, bps)
+
+    if kind == 'lpc':
+        bw.write(FLAC_QLP_PRECISION - 1, 4)
+        bw.write(shift, 5)
+        for c in q:
+            bw.write(c, FLAC_QLP_PRECISION)
+
+    write_residual(bw, res, order, len(x))
+
+
+def flac_frame(index, bps, left, right):
+    n = len(left)
+    side = [l - r for l, r in zip(left, right)]
+    mid = [(l + r) >> 1 for l, r in zip(left, right)]
+
+    (l_bits, l_sub) = best_subframe(left, bps)
+    (r_bits, r_sub) = best_subframe(right, bps)
+    (m_bits, m_sub) = best_subframe(mid, bps)
+    (s_bits, s_sub) = best_subframe(side, bps + 1)
+
+    # (bits, channel assignment, subframes)
+    modes = [
+        (l_bits + r_bits, 0b0001, [(left, bps, l_sub), (right, bps, r_sub)]),
+        (l_bits + s_bits, 0b1000, [(left, bps, l_sub), (side, bps + 1, s_sub)]),
+        (s_bits + r_bits, 0b1001, [(side, bps + 1, s_sub), (right, bps, r_sub)]),
+        (m_bits + s_bits, 0b1010, [(mid, bps, m_sub), (side, bps + 1, s_sub)]),
+    ]
+    _, assignment, subframes = min(modes, key=lambda m: m[0])
+
+    hdr = bytearray(b'\xFF\xF8')
+    bs_code = 0b1100 if n == FLAC_BLOCK_LEN else 0b0111
+    hdr.append((bs_code << 4) | 0b1010)  # 48kHz
+    hdr.append((assignment << 4) | ({16: 0b100, 24: 0b110}[bps] << 1))
+    hdr += utf8_int(index)
+    if bs_code == 0b0111:
+        hdr += struct.pack('>H', n - 1)
+    hdr.append(crc8(hdr))
+
+    bw = BitWriter()
+    for x, sub_bps, subframe in subframes:
+        write_subframe(bw, x, sub_bps, subframe)
+    bw.align()
+
+    frame = bytes(hdr) + bw.bytes()
+    return frame + struct.pack('>H', crc16(frame))
+
+
+def write_flac(path, sr, bits, chans):
+    assert sr == 48000 and len(chans) == 2
+    left, right = chans
+    n = len(left)
+
+    frames = []
+    for i, start in enumerate(range(0, n, FLAC_BLOCK_LEN)):
+        end = min(start + FLAC_BLOCK_LEN, n)
+        frames.append(flac_frame(i, bits, left[start:end], right[start:end]))
+
+    md5 = hashlib.md5()
+    bps = bits // 8
+    for i in range(n):
+        md5.update(left[i].to_bytes(bps, 'little', signed=True))
+        md5.update(right[i].to_bytes(bps, 'little', signed=True))
+
+    frame_sizes = [len(f) for f in frames]
+    si = struct.pack('>HH', FLAC_BLOCK_LEN, FLAC_BLOCK_LEN)
+    si += min(frame_sizes).to_bytes(3, 'big') + max(frame_sizes).to_bytes(3, 'big')
+    si += ((sr << 44) | (1 << 41) | ((bits - 1) << 36) | n).to_bytes(8, 'big')
+    si += md5.digest()
+
+    comments = struct.pack('<I', len(FLAC_VENDOR)) + FLAC_VENDOR + struct.pack('<I', 0)
+
+    out = bytearray(b'fLaC')
+    out += bytes([0x00]) + len(si).to_bytes(3, 'big') + si
+    out += bytes([0x80 | 4]) + len(comments).to_bytes(3, 'big') + comments
+    for frame in frames:
+        out += frame
+    open(path, 'wb').write(bytes(out))
+
+
+# -- ALAC ----------------------------------------------------------------------------------
+
+ALAC_FRAME_LEN = 4096
+ALAC_LPC_ORDER = 8
+ALAC_DENSHIFT = 9
+ALAC_PB = 40
+ALAC_MB = 10
+ALAC_KB = 14
+ALAC_PB_CODE = 4
+ALAC_MIX_BITS = 2
+
+
+def sign_extend(v, bits):
+    v &= (1 << bits) - 1
+    return v - (1 << bits) if v >> (bits - 1) else v
+
+
+def alac_coefs(x):
+    """The initial predictor coefficients. The decoder adapts them as it goes."""
+    if len(x) <= ALAC_LPC_ORDER * 4:
+        return [0] * ALAC_LPC_ORDER
+    a = levinson(autocorrelation(x, ALAC_LPC_ORDER), ALAC_LPC_ORDER)
+    if a is None:
+        return [0] * ALAC_LPC_ORDER
+    return [max(-32768, min(32767, int(round(c * (1 << ALAC_DENSHIFT))))) for c in a]
+
+
+def alac_residual(x, coefs, pred_bits):
+    """Run the adaptive predictor of the decoder in reverse."""
+    n = len(x)
+    order = len(coefs)
+    coefs = list(coefs)
+    shift = ALAC_DENSHIFT
+
+    res = [0] * n
+    if n:
+        res[0] = x[0]
+    for i in range(1, min(1 + order, n)):
+        res[i] = sign_extend(x[i] - x[i - 1], pred_bits)
+
+    for i in range(1 + order, n):
+        past0 = x[i - order - 1]
+        window = x[i - order:i]
+
+        # `coefs[0]` is applied to the most recent sample.
+        s = sign_extend(sum(coefs[order - 1 - j] * (window[j] - past0) for j in range(order)), 32)
+        val = sign_extend(s + ((1 << shift) >> 1), 32) >> shift
+        r = sign_extend(x[i] - past0 - val, pred_bits)
+        res[i] = r
+
+        if r > 0:
+            for j in range(order):
+                v = past0 - window[j]
+                sign = (v > 0) - (v < 0)
+                coefs[order - 1 - j] -= sign
+                r -= (1 + j) * ((sign * v) >> shift)
+                if r <= 0:
+                    break
+        elif r < 0:
+            for j in range(order):
+                v = past0 - window[j]
+                sign = (v > 0) - (v < 0)
+                coefs[order - 1 - j] += sign
+                r -= (1 + j) * ((-sign * v) >> shift)
+                if r >= 0:
+                    break
+
+    return res
+
+
+def alac_write_rice(bw, value, k, escape_bits):
+    m = (1 << k) - 1
+    q, r = divmod(value, m)
+    if q > 8:
+        bw.write(0x1FF, 9)
+        bw.write(value, escape_bits)
+    else:
+        bw.write((1 << q) - 1, q)
+        bw.write(0, 1)
+        if k > 1:
+            if r == 0:
+                bw.write(0, k - 1)
+            else:
+                bw.write(r + 1, k)
+
+
+def alac_write_residual(bw, res, pred_bits):
+    """Write the residual with the adaptive Golomb coding of the decoder."""
+    pb_factor = (ALAC_PB_CODE * ALAC_PB) >> 2
+    mb = ALAC_MB
+    sign_toggle = 0
+    n = len(res)
+
+    i = 0
+    while i < n:
+        value = zigzag(res[i])
+        k = min(((mb >> 9) + 3).bit_length() - 1, ALAC_KB)
+        alac_write_rice(bw, value - sign_toggle, k, pred_bits)
+
+        if value > 0xFFFF:
+            mb = 0xFFFF
+        else:
+            mb -= (pb_factor * mb) >> 9
+            mb += pb_factor * value
+        sign_toggle = 0
+        i += 1
+
+        # Runs of zeros are coded by their length.
+        if mb < 128 and i < n:
+            k = min((32 - mb.bit_length()) - 24 + ((mb + 16) >> 6), ALAC_KB)
+            zeros = 0
+            while i + zeros < n and res[i + zeros] == 0 and zeros < 0xFFFF:
+                zeros += 1
+            alac_write_rice(bw, zeros, k, 16)
+            if zeros < 0xFFFF:
+                sign_toggle = 1
+            mb = 0
+            i += zeros
+
+
+def alac_packet(bits, left, right):
+    n = len(left)
+
+    # 24-bit samples keep their low byte uncompressed, like the reference encoder.
+    shift_bytes = 1 if bits > 16 else 0
+    shift = 8 * shift_bytes
+    upper = [[v >> shift for v in ch] for ch in (left, right)]
+    pred_bits = bits - shift + 1
+
+    # Pick the mid/side weight with the smallest first differences.
+    best = None
+    for weight in range(5):
+        if weight == 0:
+            mixed = upper
+        else:
+            side = [l - r for l, r in zip(*upper)]
+            mid = [r + ((s * weight) >> ALAC_MIX_BITS) for r, s in zip(upper[1], side)]
+            mixed = [mid, side]
+        cost = sum(abs(ch[i] - ch[i - 1]) for ch in mixed for i in range(1, n))
+        if best is None or cost < best[0]:
+            best = (cost, weight, mixed)
+    _, weight, mixed = best
+
+    bw = BitWriter()
+    bw.write(1, 3)  # ID_CPE
+    bw.write(0, 4)  # element instance tag
+    bw.write(0, 12)  # unused
+    partial = n != ALAC_FRAME_LEN
+    bw.write(int(partial), 1)
+    bw.write(shift_bytes, 2)
+    bw.write(0, 1)  # compressed
+    if partial:
+        bw.write(n, 32)
+    bw.write(ALAC_MIX_BITS if weight else 0, 8)
+    bw.write(weight, 8)
+
+    residuals = []
+    for ch in mixed:
+        coefs = alac_coefs(ch)
+        bw.write(0, 4)  # mode
+        bw.write(ALAC_DENSHIFT, 4)
+        bw.write(ALAC_PB_CODE, 3)
+        bw.write(len(coefs), 5)
+        for c in coefs:
+            bw.write(c, 16)
+        residuals.append(alac_residual(ch, coefs, pred_bits))
+
+    if shift:
+        for l, r in zip(left, right):
+            bw.write(l, shift)
+            bw.write(r, shift)
+
+    for res in residuals:
+        alac_write_residual(bw, res, pred_bits)
+
+    bw.write(7, 3)  # ID_END
+    bw.align()
+    return bw.bytes()
+
+
+def box(t, body):
+    return struct.pack('>I', len(body) + 8) + t + body
+
+
+def full_box(t, version, flags, body):
+    return box(t, struct.pack('>I', (version << 24) | flags) + body)
+
+
+MATRIX = struct.pack('>9I', 0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000)
+
+
+def write_alac_m4a(path, sr, bits, chans, frame_len=ALAC_FRAME_LEN):
+    ch = len(chans)
+    n = len(chans[0])
+    assert ch == 2
+    packets = [
+        alac_packet(bits, chans[0][start:start + frame_len], chans[1][start:start + frame_len])
+        for start in range(0, n, frame_len)
+    ]
+
+    max_frame = max(len(p) for p in packets)
+    avg_bitrate = int(sum(len(p) for p in packets) * 8 * sr / n)
+    cookie = struct.pack('>IBBBBBBHIII', frame_len, 0, bits, ALAC_PB, ALAC_MB, ALAC_KB, ch, 255,
+                         max_frame, avg_bitrate, sr)
+    alac_box = full_box(b'alac', 0, 0, cookie)
+    entry = b'\0' * 6 + struct.pack('>H', 1) + struct.pack('>HHI', 0, 0, 0)
+    entry += struct.pack('>HHHHI', ch, bits, 0, 0, sr << 16) + alac_box
+    stsd = full_box(b'stsd', 0, 0, struct.pack('>I', 1) + box(b'alac', entry))
+
+    full = n // frame_len
+    rem = n % frame_len
+    stts_entries = [(full, frame_len)] + ([(1, rem)] if rem else [])
+    stts = full_box(b'stts', 0, 0, struct.pack('>I', len(stts_entries)) +
+                    b''.join(struct.pack('>II', c, d) for c, d in stts_entries))
+    stsc = full_box(b'stsc', 0, 0, struct.pack('>IIII', 1, 1, len(packets), 1))
+    stsz = full_box(b'stsz', 0, 0, struct.pack('>II', 0, len(packets)) +
+                    b''.join(struct.pack('>I', len(p)) for p in packets))
+
+    def build_moov(chunk_offset):
+        stco = full_box(b'stco', 0, 0, struct.pack('>II', 1, chunk_offset))
+        stbl = box(b'stbl', stsd + stts + stsc + stsz + stco)
+        dinf = box(b'dinf', full_box(b'dref', 0, 0, struct.pack('>I', 1) +
+                                     full_box(b'url ', 0, 1, b'')))
+        smhd = full_box(b'smhd', 0, 0, struct.pack('>hH', 0, 0))
+        minf = box(b'minf', smhd + dinf + stbl)
+        hdlr = full_box(b'hdlr', 0, 0, struct.pack('>I', 0) + b'soun' + b'\0' * 12 +
+                        b'SoundHandler\0')
+        mdhd = full_box(b'mdhd', 0, 0, struct.pack('>IIIIHH', 0, 0, sr, n, 0x55C4, 0))
+        mdia = box(b'mdia', mdhd + hdlr + minf)
+        tkhd = full_box(b'tkhd', 0, 7, struct.pack('>IIIII', 0, 0, 1, 0, n) + b'\0' * 8 +
+                        struct.pack('>hhhH', 0, 0, 0x0100, 0) + MATRIX + struct.pack('>II', 0, 0))
+        trak = box(b'trak', tkhd + mdia)
+        mvhd = full_box(b'mvhd', 0, 0, struct.pack('>IIII', 0, 0, sr, n) +
+                        struct.pack('>IH', 0x00010000, 0x0100) + b'\0' * 10 + MATRIX +
+                        b'\0' * 24 + struct.pack('>I', 2))
+        return box(b'moov', mvhd + trak)
+
+    ftyp = box(b'ftyp', b'M4A ' + struct.pack('>I', 0) + b'M4A mp42isom')
+    moov = build_moov(0)
+    offset = len(ftyp) + len(moov) + 8
+    moov = build_moov(offset)
+    mdat = box(b'mdat', b''.join(packets))
+    open(path, 'wb').write(ftyp + moov + mdat)
+
+
+def main():
+    _, sr, bits16, c16 = read_wav(os.path.join(DIR, 'synth_keys_48000_16bit.wav'))
+    _, _, bits24, c24 = read_wav(os.path.join(DIR, 'synth_keys_48000_24bit.wav'))
+    tag32, _, bits32, c32 = read_wav(os.path.join(DIR, 'synth_keys_48000_float32.wav'))
+    assert (bits16, bits24, bits32, tag32) == (16, 24, 32, 3)
+
+    write_flac(os.path.join(DIR, 'synth_keys_48000_24bit.flac'), sr, 24, c24)
+    write_aiff(os.path.join(DIR, 'synth_keys_48000_16bit.aiff'), sr, 16, c16)
+    write_aiff(os.path.join(DIR, 'synth_keys_48000_24bit.aiff'), sr, 24, c24)
+    write_aiff(os.path.join(DIR, 'synth_keys_48000_16bit_sowt.aifc'), sr, 16, c16, aifc=b'sowt')
+    write_aiff(os.path.join(DIR, 'synth_keys_48000_float32.aifc'), sr, 32, c32, aifc=b'fl32')
+    write_alac_m4a(os.path.join(DIR, 'synth_keys_48000_16bit_alac.m4a'), sr, 16, c16)
+    write_alac_m4a(os.path.join(DIR, 'synth_keys_48000_24bit_alac.m4a'), sr, 24, c24)
+
+
+if __name__ == '__main__':
+    main()
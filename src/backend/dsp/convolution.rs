@@ -0,0 +1,193 @@
+// Offline FFT convolution.
+
+use super::fft::{Complex, Fft};
+
+/// The smallest size of each partition of the impulse response.
+static CONVOLUTION_MIN_PARTITION_LEN: usize = 1024;
+
+/// The largest size of each partition of the impulse response. Impulse responses up to
+/// this length (about 1.4 seconds at 48kHz) are convolved with a single partition.
+/// Larger partitions are faster but use more memory.
+static CONVOLUTION_MAX_PARTITION_LEN: usize = 65_536;
+
+/// A uniformly partitioned (overlap-save) FFT convolver.
+///
+/// Since this is only used offline, latency doesn't matter. So the partitions are as
+/// large as the impulse response (up to `CONVOLUTION_MAX_PARTITION_LEN`), which keeps
+/// the number of partitions to multiply with each block of input small. The time it
+/// takes to process each frame grows with the number of partitions, so impulse
+/// responses much longer than `CONVOLUTION_MAX_PARTITION_LEN` (such as reverbs that are
+/// tens of seconds long) are still slow.
+pub struct PartitionedConvolver {
+    fft: Fft,
+    partition_len: usize,
+
+    /// The spectrum of each partition of the impulse response.
+    ir_partitions: Vec<Vec<Complex>>,
+
+    ir_len: usize,
+}
+
+impl PartitionedConvolver {
+    /// Create a new convolver for the given impulse response.
+    ///
+    /// This function allocates memory and is *not* realtime safe.
+    pub fn new(ir: &[f32]) -> Self {
+        let partition_len = ir
+            .len()
+            .next_power_of_two()
+            .clamp(CONVOLUTION_MIN_PARTITION_LEN, CONVOLUTION_MAX_PARTITION_LEN);
+        let fft = Fft::new(partition_len * 2);
+
+        let ir_partitions = ir
+            .chunks(partition_len)
+            .map(|chunk| {
+                let mut spectrum = vec![Complex::default(); partition_len * 2];
+                for (c, smp) in spectrum.iter_mut().zip(chunk.iter()) {
+                    c.re = *smp;
+                }
+
+                fft.forward(&mut spectrum);
+                spectrum
+            })
+            .collect();
+
+        Self { fft, partition_len, ir_partitions, ir_len: ir.len() }
+    }
+
+    /// Convolve `src` with the impulse response.
+    ///
+    /// This function allocates memory and is *not* realtime safe.
+    ///
+    /// Returns the full result, which is `ir.len() - 1` frames longer than `src`.
+    pub fn process(&self, src: &[f32]) -> Vec<f32> {
+        if src.is_empty() || self.ir_len == 0 {
+            return Vec::new();
+        }
+
+        let out_len = src.len() + self.ir_len - 1;
+        let block_len = self.partition_len;
+        let fft_len = block_len * 2;
+
+        let mut out = Vec::with_capacity(out_len);
+
+        // The spectra of the most recent input blocks, with the most recent one first.
+        let mut input_spectra: Vec<Vec<Complex>> =
+            vec![vec![Complex::default(); fft_len]; self.ir_partitions.len()];
+        let mut newest = 0;
+
+        let mut input_window = vec![0.0f32; fft_len];
+        let mut acc = vec![Complex::default(); fft_len];
+
+        let mut block_start = 0;
+        while out.len() < out_len {
+            // Slide the input window by one block.
+            input_window.copy_within(block_len.., 0);
+            for (i, smp) in input_window[block_len..].iter_mut().enumerate() {
+                *smp = src.get(block_start + i).copied().unwrap_or(0.0);
+            }
+
+            newest = if newest == 0 { input_spectra.len() - 1 } else { newest - 1 };
+            let spectrum = &mut input_spectra[newest];
+            for (c, smp) in spectrum.iter_mut().zip(input_window.iter()) {
+                *c = Complex::new(*smp, 0.0);
+            }
+            self.fft.forward(spectrum);
+
+            // Multiply each input block with the matching partition of the impulse
+            // response, and sum the results.
+            for c in acc.iter_mut() {
+                *c = Complex::default();
+            }
+            for (p, ir_partition) in self.ir_partitions.iter().enumerate() {
+                let spectrum = &input_spectra[(newest + p) % input_spectra.len()];
+
+                for ((a, x), h) in acc.iter_mut().zip(spectrum.iter()).zip(ir_partition.iter()) {
+                    *a = *a + (*x * *h);
+                }
+            }
+
+            self.fft.inverse(&mut acc);
+
+            // The first half of the result is aliased, so only keep the second half.
+            let remaining = out_len - out.len();
+            out.extend(acc[block_len..].iter().take(remaining).map(|c| c.re));
+
+            block_start += block_len;
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn direct_convolve(src: &[f32], ir: &[f32]) -> Vec<f32> {
+        let mut out = vec![0.0; src.len() + ir.len() - 1];
+        for (i, x) in src.iter().enumerate() {
+            for (j, h) in ir.iter().enumerate() {
+                out[i + j] += x * h;
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn partitioned_convolution() {
+        let src: Vec<f32> = (0..5_000).map(|i| (i as f32 * 0.05).sin()).collect();
+
+        // Test impulse responses shorter than, equal to, and longer than a partition.
+        for ir_len in [1, 100, CONVOLUTION_MIN_PARTITION_LEN, 3_000].iter() {
+            let ir: Vec<f32> = (0..*ir_len).map(|i| (-(i as f32) / 500.0).exp() * 0.1).collect();
+
+            let out = PartitionedConvolver::new(&ir).process(&src);
+            let expected = direct_convolve(&src, &ir);
+
+            assert_eq!(out.len(), expected.len());
+            for (a, b) in out.iter().zip(expected.iter()) {
+                assert!((a - b).abs() < 0.001, "{} != {} (ir len {})", a, b, ir_len);
+            }
+        }
+
+        // A unit impulse should give back the input.
+        let out = PartitionedConvolver::new(&[1.0]).process(&src);
+        for (a, b) in out.iter().zip(src.iter()) {
+            assert!((a - b).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn long_impulse_response() {
+        // A sparse impulse response (like the early reflections of a reverb) that is
+        // split into several of the largest partitions, so it can be checked against a
+        // direct convolution quickly.
+        let ir_len = CONVOLUTION_MAX_PARTITION_LEN * 3 + 100;
+        let mut ir = vec![0.0f32; ir_len];
+        let taps =
+            [(0, 0.5), (1, -0.25), (CONVOLUTION_MAX_PARTITION_LEN + 7, 0.3), (ir_len - 1, 0.1)];
+        for (i, gain) in taps.iter() {
+            ir[*i] = *gain;
+        }
+
+        let convolver = PartitionedConvolver::new(&ir);
+        assert_eq!(convolver.partition_len, CONVOLUTION_MAX_PARTITION_LEN);
+        assert_eq!(convolver.ir_partitions.len(), 4);
+
+        let src: Vec<f32> = (0..100_000).map(|i| (i as f32 * 0.01).sin()).collect();
+        let out = convolver.process(&src);
+
+        let mut expected = vec![0.0; src.len() + ir_len - 1];
+        for (i, x) in src.iter().enumerate() {
+            for (j, gain) in taps.iter() {
+                expected[i + j] += x * gain;
+            }
+        }
+
+        assert_eq!(out.len(), expected.len());
+        for (i, (a, b)) in out.iter().zip(expected.iter()).enumerate() {
+            assert!((a - b).abs() < 0.001, "{} != {} at {}", a, b, i);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// DC offset removal.
+
+use rusty_daw_core::SampleRate;
+
+/// Remove the DC offset by subtracting the mean of all samples.
+///
+/// This is only accurate when the offset stays constant over the whole buffer.
+pub fn remove_dc_offset_mean(data: &mut [f32]) {
+    if data.is_empty() {
+        return;
+    }
+
+    let mean = (data.iter().map(|smp| f64::from(*smp)).sum::<f64>() / data.len() as f64) as f32;
+
+    for smp in data.iter_mut() {
+        *smp -= mean;
+    }
+}
+
+/// Remove the DC offset with a one-pole high-pass filter (a "DC blocker").
+///
+/// This also removes offsets that drift over time, at the cost of slightly
+/// affecting frequencies near `cutoff_hz`.
+pub fn remove_dc_offset_high_pass(data: &mut [f32], cutoff_hz: f32, sample_rate: SampleRate) {
+    let cutoff_hz = f64::from(cutoff_hz.max(0.0)).min(sample_rate.0 / 2.0);
+    let r = (-2.0 * std::f64::consts::PI * cutoff_hz / sample_rate.0).exp();
+
+    // Start from the first sample so the offset of the first sample does not cause a
+    // transient.
+    let mut x1 = data.first().map(|smp| f64::from(*smp)).unwrap_or(0.0);
+    let mut y1 = 0.0f64;
+
+    for smp in data.iter_mut() {
+        let x0 = f64::from(*smp);
+        let y0 = x0 - x1 + (r * y1);
+
+        x1 = x0;
+        y1 = y0;
+
+        *smp = y0 as f32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine_with_offset(offset: f32) -> Vec<f32> {
+        (0..48_000)
+            .map(|i| (2.0 * std::f32::consts::PI * 100.0 * i as f32 / 48_000.0).sin() + offset)
+            .collect()
+    }
+
+    fn mean(data: &[f32]) -> f32 {
+        data.iter().sum::<f32>() / data.len() as f32
+    }
+
+    #[test]
+    fn dc_offset_mean() {
+        let mut data = sine_with_offset(0.25);
+        remove_dc_offset_mean(&mut data);
+        assert!(mean(&data).abs() < 0.0001);
+
+        remove_dc_offset_mean(&mut []);
+    }
+
+    #[test]
+    fn dc_offset_high_pass() {
+        let mut data = sine_with_offset(0.25);
+        remove_dc_offset_high_pass(&mut data, 5.0, SampleRate(48_000.0));
+
+        // Ignore the settling time of the filter.
+        assert!(mean(&data[24_000..]).abs() < 0.01);
+
+        // The signal itself should be left (mostly) intact.
+        let peak = data[24_000..].iter().fold(0.0f32, |p, smp| p.max(smp.abs()));
+        assert!((peak - 1.0).abs() < 0.05);
+    }
+}
@@ -0,0 +1,170 @@
+// A basic radix-2 FFT used by the offline audio clip effects.
+
+use std::ops::{Add, Mul, Sub};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    pub fn from_polar(magnitude: f32, phase: f32) -> Self {
+        Self { re: magnitude * phase.cos(), im: magnitude * phase.sin() }
+    }
+
+    pub fn norm(&self) -> f32 {
+        ((self.re * self.re) + (self.im * self.im)).sqrt()
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    #[inline]
+    fn add(self, rhs: Complex) -> Complex {
+        Complex { re: self.re + rhs.re, im: self.im + rhs.im }
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    #[inline]
+    fn sub(self, rhs: Complex) -> Complex {
+        Complex { re: self.re - rhs.re, im: self.im - rhs.im }
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    #[inline]
+    fn mul(self, rhs: Complex) -> Complex {
+        Complex {
+            re: (self.re * rhs.re) - (self.im * rhs.im),
+            im: (self.re * rhs.im) + (self.im * rhs.re),
+        }
+    }
+}
+
+/// An in-place radix-2 FFT of a fixed power-of-two length.
+pub struct Fft {
+    len: usize,
+
+    /// `e^(-2*pi*i*k/len)` for `k` in `0..len/2`.
+    twiddles: Vec<Complex>,
+}
+
+impl Fft {
+    /// Create a new FFT of the given length.
+    ///
+    /// This will panic if `len` is not a power of two.
+    pub fn new(len: usize) -> Self {
+        assert!(len.is_power_of_two());
+
+        let twiddles = (0..len / 2)
+            .map(|k| {
+                let phase = -2.0 * std::f64::consts::PI * k as f64 / len as f64;
+                Complex::new(phase.cos() as f32, phase.sin() as f32)
+            })
+            .collect();
+
+        Self { len, twiddles }
+    }
+
+    /// Compute the forward FFT of `buf` in place.
+    pub fn forward(&self, buf: &mut [Complex]) {
+        self.process(buf, false);
+    }
+
+    /// Compute the inverse FFT of `buf` in place. The result is normalized by `1 / len`.
+    pub fn inverse(&self, buf: &mut [Complex]) {
+        self.process(buf, true);
+
+        let norm = 1.0 / self.len as f32;
+        for c in buf.iter_mut() {
+            c.re *= norm;
+            c.im *= norm;
+        }
+    }
+
+    fn process(&self, buf: &mut [Complex], inverse: bool) {
+        assert_eq!(buf.len(), self.len);
+
+        if self.len < 2 {
+            return;
+        }
+
+        // Bit-reversal permutation
+        let bits = self.len.trailing_zeros();
+        for i in 0..self.len {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if i < j {
+                buf.swap(i, j);
+            }
+        }
+
+        // Butterflies
+        let mut size = 2;
+        while size <= self.len {
+            let half = size / 2;
+            let twiddle_step = self.len / size;
+
+            for start in (0..self.len).step_by(size) {
+                for k in 0..half {
+                    let mut twiddle = self.twiddles[k * twiddle_step];
+                    if inverse {
+                        twiddle.im = -twiddle.im;
+                    }
+
+                    let a = buf[start + k];
+                    let b = buf[start + k + half] * twiddle;
+
+                    buf[start + k] = a + b;
+                    buf[start + k + half] = a - b;
+                }
+            }
+
+            size *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fft_matches_dft() {
+        let len = 64;
+        let fft = Fft::new(len);
+
+        let input: Vec<Complex> = (0..len)
+            .map(|i| Complex::new((i as f32 * 0.37).sin(), (i as f32 * 0.11).cos()))
+            .collect();
+
+        let mut buf = input.clone();
+        fft.forward(&mut buf);
+
+        for (k, bin) in buf.iter().enumerate() {
+            let mut expected = Complex::default();
+            for (n, x) in input.iter().enumerate() {
+                let phase = -2.0 * std::f32::consts::PI * (k * n) as f32 / len as f32;
+                expected = expected + (*x * Complex::from_polar(1.0, phase));
+            }
+
+            assert!((*bin - expected).norm() < 0.001);
+        }
+
+        // The inverse should give back the input.
+        fft.inverse(&mut buf);
+        for (a, b) in buf.iter().zip(input.iter()) {
+            assert!((*a - *b).norm() < 0.0001);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+// Peak and loudness measurement of audio clips.
+
+use rusty_daw_core::SampleRate;
+
+/// The length of a gating block as defined in ITU-R BS.1770-4.
+static LOUDNESS_BLOCK_SECS: f64 = 0.4;
+
+/// The absolute gating threshold as defined in ITU-R BS.1770-4.
+static LOUDNESS_ABSOLUTE_GATE_LUFS: f64 = -70.0;
+
+/// The relative gating threshold as defined in ITU-R BS.1770-4.
+static LOUDNESS_RELATIVE_GATE_LU: f64 = -10.0;
+
+/// Returns the peak absolute value of all samples in all the given channels.
+pub fn peak(channels: &[&[f32]]) -> f32 {
+    let mut peak = 0.0f32;
+
+    for channel in channels.iter() {
+        for smp in channel.iter() {
+            peak = peak.max(smp.abs());
+        }
+    }
+
+    peak
+}
+
+/// Measure the integrated loudness of the given channels in LUFS as defined in
+/// ITU-R BS.1770-4. All channels are weighted equally (as left/right/center channels).
+///
+/// This function allocates memory and is *not* realtime safe.
+///
+/// Returns `None` if the audio is too short or too quiet to be measured.
+pub fn integrated_loudness(channels: &[&[f32]], sample_rate: SampleRate) -> Option<f64> {
+    let len = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+
+    let block_len = (LOUDNESS_BLOCK_SECS * sample_rate.0).round() as usize;
+    // Gating blocks overlap by 75%.
+    let block_step = block_len / 4;
+
+    if block_len == 0 || block_step == 0 || len < block_len {
+        return None;
+    }
+
+    let num_blocks = ((len - block_len) / block_step) + 1;
+
+    // The sum of the mean square of each gating block across all channels.
+    let mut block_power = vec![0.0f64; num_blocks];
+
+    let mut weighted = Vec::with_capacity(len);
+    for channel in channels.iter() {
+        weighted.clear();
+        weighted.extend_from_slice(&channel[0..len]);
+        k_weighting(&mut weighted, sample_rate);
+
+        // Sum the squares of each quarter block, and then combine them into gating blocks.
+        let quarter_sums: Vec<f64> = weighted
+            .chunks(block_step)
+            .map(|chunk| chunk.iter().map(|smp| f64::from(*smp) * f64::from(*smp)).sum())
+            .collect();
+
+        for (i, power) in block_power.iter_mut().enumerate() {
+            let sum: f64 = quarter_sums[i..i + 4].iter().sum();
+            *power += sum / (block_step * 4) as f64;
+        }
+    }
+
+    let block_loudness = |power: f64| -0.691 + (10.0 * power.log10());
+
+    // Absolute gate
+    let gated: Vec<f64> = block_power
+        .into_iter()
+        .filter(|p| *p > 0.0 && block_loudness(*p) > LOUDNESS_ABSOLUTE_GATE_LUFS)
+        .collect();
+    if gated.is_empty() {
+        return None;
+    }
+
+    // Relative gate
+    let relative_gate =
+        block_loudness(gated.iter().sum::<f64>() / gated.len() as f64) + LOUDNESS_RELATIVE_GATE_LU;
+    let gated: Vec<f64> =
+        gated.into_iter().filter(|p| block_loudness(*p) > relative_gate).collect();
+    if gated.is_empty() {
+        return None;
+    }
+
+    Some(block_loudness(gated.iter().sum::<f64>() / gated.len() as f64))
+}
+
+/// Apply the K-weighting filter from ITU-R BS.1770-4 in place. The coefficients are
+/// derived for the given sample rate.
+fn k_weighting(data: &mut [f32], sample_rate: SampleRate) {
+    use std::f64::consts::PI;
+
+    // Stage 1: High shelf modeling the acoustic effects of the head.
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+
+    let k = (PI * f0 / sample_rate.0).tan();
+    let vh = 10.0f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + (k / q) + (k * k);
+
+    let shelf = Biquad {
+        b0: (vh + (vb * k / q) + (k * k)) / a0,
+        b1: 2.0 * ((k * k) - vh) / a0,
+        b2: (vh - (vb * k / q) + (k * k)) / a0,
+        a1: 2.0 * ((k * k) - 1.0) / a0,
+        a2: (1.0 - (k / q) + (k * k)) / a0,
+    };
+
+    // Stage 2: High pass (the "RLB" weighting curve).
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+
+    let k = (PI * f0 / sample_rate.0).tan();
+    let a0 = 1.0 + (k / q) + (k * k);
+
+    let high_pass = Biquad {
+        b0: 1.0,
+        b1: -2.0,
+        b2: 1.0,
+        a1: 2.0 * ((k * k) - 1.0) / a0,
+        a2: (1.0 - (k / q) + (k * k)) / a0,
+    };
+
+    shelf.process(data);
+    high_pass.process(data);
+}
+
+/// A biquad filter (direct form I) with normalized coefficients.
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Biquad {
+    fn process(&self, data: &mut [f32]) {
+        let (mut x1, mut x2, mut y1, mut y2) = (0.0f64, 0.0f64, 0.0f64, 0.0f64);
+
+        for smp in data.iter_mut() {
+            let x0 = f64::from(*smp);
+            let y0 =
+                (self.b0 * x0) + (self.b1 * x1) + (self.b2 * x2) - (self.a1 * y1) - (self.a2 * y2);
+
+            x2 = x1;
+            x1 = x0;
+            y2 = y1;
+            y1 = y0;
+
+            *smp = y0 as f32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f64, amp: f32, sample_rate: f64, secs: f64) -> Vec<f32> {
+        (0..(sample_rate * secs) as usize)
+            .map(|i| {
+                (2.0 * std::f64::consts::PI * freq * i as f64 / sample_rate).sin() as f32 * amp
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loudness_of_sine() {
+        for sample_rate in [44_100.0, 48_000.0, 96_000.0].iter() {
+            // A full scale 1kHz sine on a single channel should measure -3.01 LUFS.
+            let data = sine(1_000.0, 1.0, *sample_rate, 3.0);
+            let loudness = integrated_loudness(&[&data], SampleRate(*sample_rate)).unwrap();
+            assert!((loudness + 3.01).abs() < 0.05, "{} at {}", loudness, sample_rate);
+
+            // Halving the amplitude is -6.02 LU.
+            let data = sine(1_000.0, 0.5, *sample_rate, 3.0);
+            let loudness = integrated_loudness(&[&data], SampleRate(*sample_rate)).unwrap();
+            assert!((loudness + 9.03).abs() < 0.05, "{} at {}", loudness, sample_rate);
+        }
+
+        // Silence and audio shorter than a single block cannot be measured.
+        let silence = vec![0.0; 48_000];
+        assert!(integrated_loudness(&[&silence], SampleRate(48_000.0)).is_none());
+        let short = sine(1_000.0, 1.0, 48_000.0, 0.1);
+        assert!(integrated_loudness(&[&short], SampleRate(48_000.0)).is_none());
+    }
+
+    #[test]
+    fn peak_of_channels() {
+        assert_eq!(peak(&[&[0.1, -0.5, 0.2], &[0.3, 0.4]]), 0.5);
+        assert_eq!(peak(&[]), 0.0);
+    }
+}
@@ -1 +1,7 @@
+pub mod convolution;
+pub mod dc_offset;
+pub mod fft;
+pub mod loudness;
+pub mod paulstretch;
 pub mod resample;
+pub mod transients;
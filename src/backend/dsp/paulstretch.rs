@@ -0,0 +1,219 @@
+// Extreme time stretching based on the PaulStretch algorithm by Nasca Octavian Paul.
+//
+// Each output window is made from an input window with the same magnitude spectrum,
+// but with randomized phases. This smears the audio in time, which makes very large
+// stretch factors possible (at the cost of transients).
+
+use rusty_daw_core::{SampleRate, Seconds};
+
+use super::fft::{Complex, Fft};
+
+pub static EXTREME_STRETCH_MIN_FACTOR: f32 = 1.0;
+pub static EXTREME_STRETCH_MAX_FACTOR: f32 = 1_000.0;
+
+pub static EXTREME_STRETCH_MIN_WINDOW_LEN: usize = 128;
+pub static EXTREME_STRETCH_MAX_WINDOW_LEN: usize = 1 << 20;
+
+/// Returns the window length in frames (rounded up to a power of two) for the given
+/// window size.
+pub fn extreme_stretch_window_len(window_size: Seconds, sample_rate: SampleRate) -> usize {
+    let len = (window_size.0.max(0.0) * sample_rate.0).round() as usize;
+
+    len.next_power_of_two().max(EXTREME_STRETCH_MIN_WINDOW_LEN).min(EXTREME_STRETCH_MAX_WINDOW_LEN)
+}
+
+/// Returns the largest stretch factor for which the stretched channels fit in `max_bytes`
+/// of memory.
+///
+/// `len` - The length of the channels in frames.
+pub fn extreme_stretch_max_factor(len: usize, num_channels: usize, max_bytes: u64) -> f32 {
+    let bytes = (len.max(1) * num_channels.max(1) * std::mem::size_of::<f32>()) as f64;
+
+    (max_bytes as f64 / bytes)
+        .max(f64::from(EXTREME_STRETCH_MIN_FACTOR))
+        .min(f64::from(EXTREME_STRETCH_MAX_FACTOR)) as f32
+}
+
+/// Stretch the given channels by `factor` using the PaulStretch algorithm. All channels
+/// use the same random phases, so the stereo image is preserved.
+///
+/// `window_len` - The length of the analysis window in frames. This must be a power of
+/// two. Larger windows give smoother results, smaller windows preserve more of the
+/// time structure.
+///
+/// `progress` - Called with the progress of the render in the range `[0.0, 1.0]`.
+/// The render is cancelled if this returns `false`.
+///
+/// This function allocates memory and is *not* realtime safe. It is intended to be run
+/// on a background thread.
+///
+/// Returns `None` if the render was cancelled.
+pub fn extreme_stretch(
+    channels: &[&[f32]],
+    factor: f32,
+    window_len: usize,
+    progress: &mut dyn FnMut(f32) -> bool,
+) -> Option<Vec<Vec<f32>>> {
+    let len = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    let factor = f64::from(factor.max(EXTREME_STRETCH_MIN_FACTOR).min(EXTREME_STRETCH_MAX_FACTOR));
+
+    if len == 0 {
+        return Some(vec![Vec::new(); channels.len()]);
+    }
+
+    let fft = Fft::new(window_len);
+    let half = window_len / 2;
+
+    // Square root of a Hann window. Since it is used for both analysis and synthesis, the
+    // overlapped windows add up to a constant power.
+    let window: Vec<f32> = (0..window_len)
+        .map(|i| {
+            let x = i as f64 / window_len as f64;
+            (0.5 - (0.5 * (2.0 * std::f64::consts::PI * x).cos())).sqrt() as f32
+        })
+        .collect();
+
+    // Both windows halve the power, while two uncorrelated windows overlap at each point.
+    let gain = std::f32::consts::SQRT_2;
+
+    let out_len = (len as f64 * factor).ceil() as usize;
+    let num_windows = (out_len / half) + 1;
+    let in_hop = half as f64 / factor;
+
+    let mut out = vec![vec![0.0f32; out_len + window_len]; channels.len()];
+
+    let mut buf = vec![Complex::default(); window_len];
+    let mut phases = vec![0.0f32; half + 1];
+    let mut rng = XorShift(0x9E37_79B9);
+
+    for w in 0..num_windows {
+        let in_start = (w as f64 * in_hop) as usize;
+        let out_start = w * half;
+
+        for phase in phases.iter_mut() {
+            *phase = rng.next_f32() * 2.0 * std::f32::consts::PI;
+        }
+
+        for (channel, out) in channels.iter().zip(out.iter_mut()) {
+            for (i, c) in buf.iter_mut().enumerate() {
+                let smp = channel.get(in_start + i).copied().unwrap_or(0.0);
+                *c = Complex::new(smp * window[i], 0.0);
+            }
+
+            fft.forward(&mut buf);
+
+            // Keep the magnitudes, but randomize the phases. The spectrum must stay
+            // conjugate symmetric for the output to be real.
+            buf[0] = Complex::new(buf[0].norm(), 0.0);
+            buf[half] = Complex::new(buf[half].norm(), 0.0);
+            for (k, phase) in phases.iter().enumerate().take(half).skip(1) {
+                let c = Complex::from_polar(buf[k].norm(), *phase);
+
+                buf[k] = c;
+                buf[window_len - k] = Complex::new(c.re, -c.im);
+            }
+
+            fft.inverse(&mut buf);
+
+            for (i, (o, c)) in
+                out[out_start..out_start + window_len].iter_mut().zip(buf.iter()).enumerate()
+            {
+                *o += c.re * window[i] * gain;
+            }
+        }
+
+        if w % 64 == 0 && !progress(w as f32 / num_windows as f32) {
+            return None;
+        }
+    }
+
+    for out in out.iter_mut() {
+        out.truncate(out_len);
+    }
+
+    if !progress(1.0) {
+        return None;
+    }
+
+    Some(out)
+}
+
+/// A simple deterministic random number generator, so rendering the same effect twice
+/// gives the same result.
+struct XorShift(u32);
+
+impl XorShift {
+    /// Returns a random number in the range `[0.0, 1.0)`.
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+
+        (self.0 >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms(data: &[f32]) -> f32 {
+        (data.iter().map(|s| s * s).sum::<f32>() / data.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn stretch_sine() {
+        let sample_rate = 44_100.0;
+        let freq = 441.0;
+        let src: Vec<f32> = (0..44_100)
+            .map(|i| (2.0 * std::f32::consts::PI * freq * i as f32 / sample_rate).sin() * 0.5)
+            .collect();
+
+        let mut last_progress = 0.0;
+        let out = extreme_stretch(&[&src], 8.0, 4096, &mut |progress| {
+            assert!(progress >= last_progress && progress <= 1.0);
+            last_progress = progress;
+            true
+        })
+        .unwrap();
+        assert_eq!(last_progress, 1.0);
+
+        // The render is cancelled when the progress callback returns false.
+        assert!(extreme_stretch(&[&src], 8.0, 4096, &mut |_| false).is_none());
+
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].len(), src.len() * 8);
+
+        // Ignore the fade in and out caused by the windows.
+        let middle = &out[0][44_100..44_100 * 7];
+
+        // The level should be roughly preserved.
+        let gain_db = 20.0 * (rms(middle) / rms(&src)).log10();
+        assert!(gain_db.abs() < 3.0, "gain: {} dB", gain_db);
+
+        // The pitch should be preserved (a sine crosses zero twice per period).
+        let crossings = middle.windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count() as f32;
+        let measured_freq = crossings / 2.0 / (middle.len() as f32 / sample_rate);
+        assert!((measured_freq - freq).abs() < freq * 0.05, "freq: {}", measured_freq);
+    }
+
+    #[test]
+    fn stretch_factor_is_capped() {
+        let src = [0.5f32; 16];
+
+        let out = extreme_stretch(&[&src], 1_000_000.0, 128, &mut |_| true).unwrap();
+        assert_eq!(out[0].len(), 16 * EXTREME_STRETCH_MAX_FACTOR as usize);
+
+        // One second of stereo audio at 48kHz takes 384kB.
+        assert_eq!(extreme_stretch_max_factor(48_000, 2, 384_000 * 10), 10.0);
+        assert_eq!(extreme_stretch_max_factor(48_000, 2, 1_000), EXTREME_STRETCH_MIN_FACTOR);
+        assert_eq!(extreme_stretch_max_factor(1, 1, u64::MAX), EXTREME_STRETCH_MAX_FACTOR);
+    }
+
+    #[test]
+    fn stretch_window_len() {
+        let sample_rate = SampleRate(44_100.0);
+        assert_eq!(extreme_stretch_window_len(Seconds(0.1), sample_rate), 8192);
+        assert_eq!(extreme_stretch_window_len(Seconds(0.0), sample_rate), 128);
+    }
+}
@@ -6,3 +6,615 @@
 //
 // A copy of this paper can be found here:
 // https://github.com/BillyDM/Awesome-Audio-DSP/blob/main/deip.pdf
+//
+// The "optimal" interpolators are piecewise polynomials in the "z-form" of the paper, where
+// the output between `y[0]` and `y[1]` is evaluated at `z = x - 1/2` from the sums and
+// differences of the points that are symmetric around the middle:
+//
+//     even_j = y[j] + y[1 - j]
+//     odd_j  = y[j] - y[1 - j]
+//     c_m    = sum over j of (coefs[m][j - 1] * (if m is even { even_j } else { odd_j }))
+//     out    = sum over m of (c_m * z^m)
+//
+// The coefficients are the ones published in the tables of the paper, rounded
+// to `f32`. They are optimized for the best SNR when the input is band-limited to the
+// passband of the oversampled signal (`1 / (2 * factor)` of the sample rate).
+//
+// Note these interpolators are not flat in the passband, and they assume the input is
+// oversampled. Audio clips are oversampled with a windowed-sinc filter first (see
+// `deip_resample_non_rt_into()`).
+
+use std::f64::consts::PI;
+
+use super::sinc::SincWindow;
+
+/// The maximum number of points used by any of the interpolators.
+pub static DEIP_MAX_POINTS: usize = 6;
+
+/// The number of taps (per phase) of the lowpass filter used to oversample audio before
+/// it is interpolated.
+pub static DEIP_OVERSAMPLE_TAPS: usize = 64;
+
+/// The cutoff of the lowpass filter used to oversample audio, relative to the sample rate
+/// of the audio (or of the resampled audio when downsampling). Frequencies above this
+/// are attenuated.
+pub static DEIP_OVERSAMPLE_CUTOFF: f64 = 0.46;
+
+/// The beta parameter of the Kaiser window of the oversampling filter (around 80 dB of
+/// stopband attenuation).
+static DEIP_OVERSAMPLE_KAISER_BETA: f32 = 8.0;
+
+/// Audio is oversampled in chunks of this many frames of output, so the oversampled audio
+/// never has to be stored in memory all at once.
+static DEIP_CHUNK_FRAMES: usize = 4096;
+
+// 2x, 4-point, 2nd-order
+static OPTIMAL_2X_4P2O: [[f32; 3]; 3] =
+    [[0.42334634, 0.07668732, 0.0], [0.26126048, 0.24778879, 0.0], [-0.21343979, 0.21303593, 0.0]];
+// 2x, 4-point, 3rd-order
+static OPTIMAL_2X_4P3O: [[f32; 3]; 4] = [
+    [0.45868972, 0.04131402, 0.0],
+    [0.48068026, 0.17577925, 0.0],
+    [-0.246185, 0.24614027, 0.0],
+    [-0.36030924, 0.10174986, 0.0],
+];
+// 2x, 6-point, 5th-order
+static OPTIMAL_2X_6P5O: [[f32; 3]; 6] = [
+    [0.40513396, 0.09251794, 0.002348066],
+    [0.28342807, 0.21703278, 0.013092947],
+    [-0.19133769, 0.16187845, 0.029460171],
+    [-0.16471626, -0.0015454721, 0.033992715],
+    [0.038457986, -0.05712936, 0.01866751],
+    [0.0431795, -0.018028142, 0.0015217002],
+];
+
+// 4x, 4-point, 2nd-order
+static OPTIMAL_4X_4P2O: [[f32; 3]; 3] =
+    [[0.46835497, 0.031645026, 0.0], [0.56001294, 0.14666238, 0.0], [-0.25003877, 0.25003877, 0.0]];
+// 4x, 4-point, 3rd-order
+static OPTIMAL_4X_4P3O: [[f32; 3]; 4] = [
+    [0.46567255, 0.0343273, 0.0],
+    [0.53743833, 0.15429462, 0.0],
+    [-0.2519421, 0.25194746, 0.0],
+    [-0.4689607, 0.155788, 0.0],
+];
+// 4x, 6-point, 5th-order
+static OPTIMAL_4X_6P5O: [[f32; 3]; 6] = [
+    [0.41660798, 0.08188468, 0.0015073412],
+    [0.32232782, 0.21076322, 0.009077264],
+    [-0.20522, 0.18282942, 0.022390574],
+    [-0.21022299, 0.022028457, 0.028736278],
+    [0.040936403, -0.061513495, 0.020577665],
+    [0.071219586, -0.03532627, 0.006501482],
+];
+
+// 8x, 4-point, 2nd-order
+static OPTIMAL_8X_4P2O: [[f32; 3]; 3] =
+    [[0.48537102, 0.014628965, 0.0], [0.7275774, 0.090804204, 0.0], [-0.24983887, 0.24983887, 0.0]];
+// 8x, 4-point, 3rd-order
+static OPTIMAL_8X_4P3O: [[f32; 3]; 4] = [
+    [0.4665852, 0.0334148, 0.0],
+    [0.54458994, 0.15180333, 0.0],
+    [-0.25024226, 0.25024226, 0.0],
+    [-0.4866971, 0.16203102, 0.0],
+];
+// 8x, 6-point, 5th-order
+static OPTIMAL_8X_6P5O: [[f32; 3]; 6] = [
+    [0.41809988, 0.0804934, 0.001406708],
+    [0.32767597, 0.2097819, 0.008595672],
+    [-0.20694461, 0.18541689, 0.021527722],
+    [-0.21686623, 0.02538315, 0.02735012],
+    [0.041634988, -0.06244036, 0.020805376],
+    [0.075420044, -0.037318397, 0.00740212],
+];
+
+/// How much the input of a DEIP interpolator is oversampled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeipOversampling {
+    X2,
+    X4,
+    X8,
+}
+
+impl DeipOversampling {
+    pub fn factor(&self) -> usize {
+        match self {
+            DeipOversampling::X2 => 2,
+            DeipOversampling::X4 => 4,
+            DeipOversampling::X8 => 8,
+        }
+    }
+}
+
+/// The number of points and the order of the polynomial of a DEIP interpolator. More
+/// points and higher orders have less aliasing, but are slower.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeipPolynomial {
+    /// 4-point, 2nd-order.
+    FourPoint2ndOrder,
+
+    /// 4-point, 3rd-order.
+    FourPoint3rdOrder,
+
+    /// 6-point, 5th-order.
+    SixPoint5thOrder,
+}
+
+/// One of the "optimal" polynomial interpolators from the paper, designed for audio that
+/// is oversampled by the given factor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeipInterpolator {
+    pub oversampling: DeipOversampling,
+    pub polynomial: DeipPolynomial,
+}
+
+impl DeipInterpolator {
+    pub fn new(oversampling: DeipOversampling, polynomial: DeipPolynomial) -> Self {
+        Self { oversampling, polynomial }
+    }
+
+    /// The number of points (input samples) used for each output sample.
+    pub fn points(&self) -> usize {
+        match self.polynomial {
+            DeipPolynomial::FourPoint2ndOrder | DeipPolynomial::FourPoint3rdOrder => 4,
+            DeipPolynomial::SixPoint5thOrder => 6,
+        }
+    }
+
+    /// The coefficients (in z-form), indexed by `[power of z][pair of points]`.
+    fn coefs(&self) -> &'static [[f32; 3]] {
+        match (self.oversampling, self.polynomial) {
+            (DeipOversampling::X2, DeipPolynomial::FourPoint2ndOrder) => &OPTIMAL_2X_4P2O,
+            (DeipOversampling::X2, DeipPolynomial::FourPoint3rdOrder) => &OPTIMAL_2X_4P3O,
+            (DeipOversampling::X2, DeipPolynomial::SixPoint5thOrder) => &OPTIMAL_2X_6P5O,
+            (DeipOversampling::X4, DeipPolynomial::FourPoint2ndOrder) => &OPTIMAL_4X_4P2O,
+            (DeipOversampling::X4, DeipPolynomial::FourPoint3rdOrder) => &OPTIMAL_4X_4P3O,
+            (DeipOversampling::X4, DeipPolynomial::SixPoint5thOrder) => &OPTIMAL_4X_6P5O,
+            (DeipOversampling::X8, DeipPolynomial::FourPoint2ndOrder) => &OPTIMAL_8X_4P2O,
+            (DeipOversampling::X8, DeipPolynomial::FourPoint3rdOrder) => &OPTIMAL_8X_4P3O,
+            (DeipOversampling::X8, DeipPolynomial::SixPoint5thOrder) => &OPTIMAL_8X_6P5O,
+        }
+    }
+
+    /// Interpolate between the two middle points of `y` (which has `points()` samples),
+    /// where `x` is the position between them in the range `[0.0, 1.0)`.
+    ///
+    /// This is realtime safe.
+    #[inline]
+    pub fn interpolate(&self, y: &[f32], x: f32) -> f32 {
+        let half = self.points() / 2;
+
+        let mut even = [0.0; 3];
+        let mut odd = [0.0; 3];
+        for j in 0..half {
+            let after = y[half + j];
+            let before = y[half - 1 - j];
+
+            even[j] = after + before;
+            odd[j] = after - before;
+        }
+
+        let z = x - 0.5;
+
+        let mut out = 0.0;
+        for (m, coefs) in self.coefs().iter().enumerate().rev() {
+            let pairs = if m % 2 == 0 { &even } else { &odd };
+            let c = (coefs[0] * pairs[0]) + (coefs[1] * pairs[1]) + (coefs[2] * pairs[2]);
+
+            out = (out * z) + c;
+        }
+
+        out
+    }
+}
+
+/// Resample audio that is already oversampled by the interpolator's oversampling factor.
+///
+/// This is realtime safe.
+///
+/// `src_pos` - The position in `src` of the first frame of `dst`. Frames outside of `src`
+/// are silent.
+///
+/// `resample_ratio` - The ratio between the destination samplerate / (oversampled) source
+/// samplerate.
+///
+/// Returns the position in `src` of the frame after the last frame of `dst`.
+pub fn deip_resample_rt_mono(
+    src: &[f32],
+    src_pos: f64,
+    resample_ratio: f64,
+    dst: &mut [f32],
+    interpolator: DeipInterpolator,
+) -> f64 {
+    let points = interpolator.points();
+    let half = points / 2;
+    let ratio_inv = 1.0 / resample_ratio;
+
+    let mut y = [0.0; DEIP_MAX_POINTS];
+    let y = &mut y[0..points];
+
+    for (i, smp) in dst.iter_mut().enumerate() {
+        let pos = src_pos + (i as f64 * ratio_inv);
+        let frame = pos.floor();
+        let x = (pos - frame) as f32;
+        let first = frame as isize + 1 - half as isize;
+
+        if first >= 0 && first as usize + points <= src.len() {
+            y.copy_from_slice(&src[first as usize..first as usize + points]);
+        } else {
+            for (j, y) in y.iter_mut().enumerate() {
+                let frame = first + j as isize;
+                *y = if frame >= 0 { src.get(frame as usize).copied().unwrap_or(0.0) } else { 0.0 };
+            }
+        }
+
+        *smp = interpolator.interpolate(y, x);
+    }
+
+    src_pos + (dst.len() as f64 * ratio_inv)
+}
+
+/// Resample stereo audio that is already oversampled by the interpolator's oversampling
+/// factor.
+///
+/// This is realtime safe.
+///
+/// `src_pos` - The position in `src` of the first frame of `dst`. Frames outside of `src`
+/// are silent.
+///
+/// `resample_ratio` - The ratio between the destination samplerate / (oversampled) source
+/// samplerate.
+///
+/// Returns the position in `src` of the frame after the last frame of `dst`.
+pub fn deip_resample_rt_stereo(
+    src_l: &[f32],
+    src_r: &[f32],
+    src_pos: f64,
+    resample_ratio: f64,
+    dst_l: &mut [f32],
+    dst_r: &mut [f32],
+    interpolator: DeipInterpolator,
+) -> f64 {
+    let _ = deip_resample_rt_mono(src_l, src_pos, resample_ratio, dst_l, interpolator);
+    deip_resample_rt_mono(src_r, src_pos, resample_ratio, dst_r, interpolator)
+}
+
+/// Resample `src` into `dst` with the given interpolator. The audio is oversampled with a
+/// windowed-sinc filter first, since the interpolators assume oversampled audio.
+///
+/// This function allocates memory and is *not* realtime safe.
+///
+/// `src_start` - The position in `src` of the first frame of `dst`. Frames outside of
+/// `src` are silent.
+///
+/// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+pub fn deip_resample_non_rt_into(
+    src: &[f32],
+    src_start: f64,
+    resample_ratio: f64,
+    dst: &mut [f32],
+    interpolator: DeipInterpolator,
+) {
+    DeipResampler::new(interpolator, resample_ratio).process(src, src_start, dst);
+}
+
+/// Resamples with an "optimal" polynomial interpolator at a fixed resample ratio.
+///
+/// This keeps the oversampling filter around, so it is not recomputed every time a
+/// block of audio is resampled.
+pub struct DeipResampler {
+    interpolator: DeipInterpolator,
+    resample_ratio: f64,
+
+    filter: OversampleFilter,
+    oversampled: Vec<f32>,
+}
+
+impl DeipResampler {
+    /// Create a new resampler with the given interpolator.
+    ///
+    /// This allocates memory and is *not* realtime safe.
+    ///
+    /// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+    pub fn new(interpolator: DeipInterpolator, resample_ratio: f64) -> Self {
+        let factor = interpolator.oversampling.factor();
+
+        // When downsampling, the filter also removes the frequencies that would alias.
+        let filter =
+            OversampleFilter::new(factor, DEIP_OVERSAMPLE_CUTOFF * resample_ratio.min(1.0));
+
+        Self { interpolator, resample_ratio, filter, oversampled: Vec::new() }
+    }
+
+    /// Resample `src` into `dst`.
+    ///
+    /// This may allocate memory and is *not* realtime safe.
+    ///
+    /// `src_start` - The position in `src` of the first frame of `dst`. Frames outside of
+    /// `src` are silent.
+    pub fn process(&mut self, src: &[f32], src_start: f64, dst: &mut [f32]) {
+        let factor = self.interpolator.oversampling.factor();
+        let half = self.interpolator.points() as isize / 2;
+
+        for (chunk_i, chunk) in dst.chunks_mut(DEIP_CHUNK_FRAMES).enumerate() {
+            let chunk_start = chunk_i * DEIP_CHUNK_FRAMES;
+
+            // The positions of the first and last frame of the chunk in the oversampled
+            // audio.
+            let first_pos =
+                (src_start + (chunk_start as f64 / self.resample_ratio)) * factor as f64;
+            let last_pos = (src_start
+                + ((chunk_start + chunk.len() - 1) as f64 / self.resample_ratio))
+                * factor as f64;
+
+            let first = first_pos.floor() as isize + 1 - half;
+            let end = last_pos.floor() as isize + 1 + half;
+
+            self.filter.process(src, first, end, &mut self.oversampled);
+
+            let _ = deip_resample_rt_mono(
+                &self.oversampled,
+                first_pos - first as f64,
+                self.resample_ratio / factor as f64,
+                chunk,
+                self.interpolator,
+            );
+        }
+    }
+}
+
+/// Resample with the given "optimal" polynomial interpolator. The audio is oversampled with
+/// a windowed-sinc filter first, since the interpolators assume oversampled audio.
+///
+/// This function allocates memory and is *not* realtime safe. It is intended for
+/// resampling audio clips to be sent to the rt thread.
+///
+/// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+pub fn deip_resample_non_rt_mono(
+    src: &[f32],
+    resample_ratio: f64,
+    interpolator: DeipInterpolator,
+) -> Vec<f32> {
+    let dst_len = (src.len().saturating_sub(1) as f64 * resample_ratio).ceil() as usize;
+
+    let mut dst = vec![0.0; dst_len];
+    deip_resample_non_rt_into(src, 0.0, resample_ratio, &mut dst, interpolator);
+
+    dst
+}
+
+/// Resample with the given "optimal" polynomial interpolator. The audio is oversampled with
+/// a windowed-sinc filter first, since the interpolators assume oversampled audio.
+///
+/// This function allocates memory and is *not* realtime safe. It is intended for
+/// resampling audio clips to be sent to the rt thread.
+///
+/// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+pub fn deip_resample_non_rt_stereo(
+    src_l: &[f32],
+    src_r: &[f32],
+    resample_ratio: f64,
+    interpolator: DeipInterpolator,
+) -> (Vec<f32>, Vec<f32>) {
+    // Make sure we are given valid slices.
+    let len = src_l.len().min(src_r.len());
+
+    (
+        deip_resample_non_rt_mono(&src_l[0..len], resample_ratio, interpolator),
+        deip_resample_non_rt_mono(&src_r[0..len], resample_ratio, interpolator),
+    )
+}
+
+/// A polyphase windowed-sinc lowpass filter that oversamples audio by an integer factor.
+struct OversampleFilter {
+    factor: usize,
+
+    /// The taps of each phase.
+    phases: Vec<Vec<f32>>,
+}
+
+impl OversampleFilter {
+    /// `cutoff` - The cutoff frequency relative to the sample rate of the input.
+    fn new(factor: usize, cutoff: f64) -> Self {
+        let half_width = (DEIP_OVERSAMPLE_TAPS / 2) as f64;
+        let window = SincWindow::Kaiser { beta: DEIP_OVERSAMPLE_KAISER_BETA };
+
+        let phases = (0..factor)
+            .map(|phase| {
+                let mut taps: Vec<f64> = (0..DEIP_OVERSAMPLE_TAPS)
+                    .map(|tap| {
+                        // The distance (in input frames) from the input frame of this tap
+                        // to the output frame.
+                        let t = (phase as f64 / factor as f64) - tap as f64 + half_width - 1.0;
+
+                        let sinc = if t == 0.0 {
+                            1.0
+                        } else {
+                            (2.0 * PI * cutoff * t).sin() / (2.0 * PI * cutoff * t)
+                        };
+
+                        sinc * window.value(t / half_width)
+                    })
+                    .collect();
+
+                // Normalize each phase to unity gain at DC.
+                let sum: f64 = taps.iter().sum();
+                for tap in taps.iter_mut() {
+                    *tap /= sum;
+                }
+
+                taps.iter().map(|tap| *tap as f32).collect()
+            })
+            .collect();
+
+        Self { factor, phases }
+    }
+
+    /// Fill `out` with the oversampled frames `first..end`, where the oversampled frame `i`
+    /// is at the position `i / factor` in `src`. Frames outside of `src` are silent.
+    fn process(&self, src: &[f32], first: isize, end: isize, out: &mut Vec<f32>) {
+        let factor = self.factor as isize;
+        let half_taps = (DEIP_OVERSAMPLE_TAPS / 2) as isize;
+
+        out.clear();
+        out.extend((first..end).map(|i| {
+            let taps = &self.phases[i.rem_euclid(factor) as usize];
+
+            // The input frame of the first tap.
+            let start = i.div_euclid(factor) + 1 - half_taps;
+
+            // Skip the taps outside of `src`.
+            let skip = (-start).max(0) as usize;
+            let src_start = (start.max(0) as usize).min(src.len());
+            let src_end = ((start + DEIP_OVERSAMPLE_TAPS as isize).max(0) as usize).min(src.len());
+
+            src[src_start..src_end].iter().zip(taps[skip..].iter()).map(|(s, t)| s * t).sum::<f32>()
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::dsp::resample::measure::{sine, sine_snr_db};
+
+    static INTERPOLATORS: [DeipInterpolator; 9] = [
+        DeipInterpolator {
+            oversampling: DeipOversampling::X2,
+            polynomial: DeipPolynomial::FourPoint2ndOrder,
+        },
+        DeipInterpolator {
+            oversampling: DeipOversampling::X2,
+            polynomial: DeipPolynomial::FourPoint3rdOrder,
+        },
+        DeipInterpolator {
+            oversampling: DeipOversampling::X2,
+            polynomial: DeipPolynomial::SixPoint5thOrder,
+        },
+        DeipInterpolator {
+            oversampling: DeipOversampling::X4,
+            polynomial: DeipPolynomial::FourPoint2ndOrder,
+        },
+        DeipInterpolator {
+            oversampling: DeipOversampling::X4,
+            polynomial: DeipPolynomial::FourPoint3rdOrder,
+        },
+        DeipInterpolator {
+            oversampling: DeipOversampling::X4,
+            polynomial: DeipPolynomial::SixPoint5thOrder,
+        },
+        DeipInterpolator {
+            oversampling: DeipOversampling::X8,
+            polynomial: DeipPolynomial::FourPoint2ndOrder,
+        },
+        DeipInterpolator {
+            oversampling: DeipOversampling::X8,
+            polynomial: DeipPolynomial::FourPoint3rdOrder,
+        },
+        DeipInterpolator {
+            oversampling: DeipOversampling::X8,
+            polynomial: DeipPolynomial::SixPoint5thOrder,
+        },
+    ];
+
+    /// Quick test to make sure there are no off-by-one errors.
+    #[test]
+    fn test_deip_resample_non_rt() {
+        let src = vec![0.0; 1000];
+
+        for interpolator in INTERPOLATORS.iter() {
+            for ratio in [44100.0 / 48000.0, 48000.0 / 44100.0, 1.0 / 2.0, 2.0].iter() {
+                let dst = deip_resample_non_rt_mono(&src, *ratio, *interpolator);
+                assert_eq!(dst.len(), ((src.len() - 1) as f64 * ratio).ceil() as usize);
+            }
+        }
+
+        assert!(deip_resample_non_rt_mono(&[], 2.0, INTERPOLATORS[0]).is_empty());
+    }
+
+    #[test]
+    fn unity_gain_at_dc() {
+        for interpolator in INTERPOLATORS.iter() {
+            let y = [1.0; DEIP_MAX_POINTS];
+            let y = &y[0..interpolator.points()];
+
+            for x in [0.0, 0.25, 0.5, 0.75].iter() {
+                let out = interpolator.interpolate(y, *x);
+                assert!((out - 1.0).abs() < 0.005, "{:?} at {}: {}", interpolator, x, out);
+            }
+        }
+    }
+
+    #[test]
+    fn coefs_match_paper() {
+        // "Optimal 2x (4-point, 2nd-order) (z-form)" from the paper.
+        let paper: [[f64; 2]; 3] = [
+            [0.42334633257225274, 0.07668732202139628],
+            [0.26126047291143606, 0.24778879018226652],
+            [-0.21343978756177684, 0.21303593243799016],
+        ];
+
+        for (coefs, paper) in OPTIMAL_2X_4P2O.iter().zip(paper.iter()) {
+            for (c, p) in coefs.iter().zip(paper.iter()) {
+                assert!((f64::from(*c) - p).abs() < 1e-7, "{} != {}", c, p);
+            }
+        }
+    }
+
+    #[test]
+    fn resamples_sine() {
+        let freq = 1000.0 / 44100.0;
+        let src = sine(44100, freq);
+
+        let mut snrs = Vec::new();
+        for interpolator in INTERPOLATORS.iter() {
+            let dst = deip_resample_non_rt_mono(&src, 48000.0 / 44100.0, *interpolator);
+
+            // Skip the start and end, where the sine wave starts and stops abruptly.
+            let snr = sine_snr_db(&dst[4800..43200], 1000.0 / 48000.0);
+            assert!(snr > 60.0, "{:?}: {} dB", interpolator, snr);
+
+            snrs.push(snr);
+        }
+
+        // Higher orders and more oversampling have less aliasing.
+        assert!(snrs[2] > snrs[0]);
+        assert!(snrs[5] > snrs[3]);
+        assert!(snrs[6] > snrs[0]);
+    }
+
+    #[test]
+    fn resampled_sine_stays_in_phase() {
+        let interpolator =
+            DeipInterpolator::new(DeipOversampling::X4, DeipPolynomial::FourPoint3rdOrder);
+
+        let freq = 440.0 / 48000.0;
+        let src = sine(48000, freq);
+        let dst = deip_resample_non_rt_mono(&src, 0.5, interpolator);
+
+        // Compensate for the (small) droop in the passband.
+        let expected = sine(24000, freq * 2.0);
+        let peak = dst[1000..23000].iter().fold(0.0f32, |p, s| p.max(s.abs()));
+        for (d, e) in dst[1000..23000].iter().zip(expected[1000..23000].iter()) {
+            assert!((d / peak - e).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn rt_resample_continues_from_returned_position() {
+        let interpolator =
+            DeipInterpolator::new(DeipOversampling::X2, DeipPolynomial::SixPoint5thOrder);
+        let src = sine(1000, 0.01);
+
+        let mut whole = vec![0.0; 500];
+        deip_resample_rt_mono(&src, 3.0, 0.7, &mut whole, interpolator);
+
+        let mut parts = vec![0.0; 500];
+        let pos = deip_resample_rt_mono(&src, 3.0, 0.7, &mut parts[0..123], interpolator);
+        deip_resample_rt_mono(&src, pos, 0.7, &mut parts[123..], interpolator);
+
+        for (a, b) in whole.iter().zip(parts.iter()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+    }
+}
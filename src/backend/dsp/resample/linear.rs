@@ -77,6 +77,49 @@ pub fn linear_resample_non_rt_stereo(
     (dst_l, dst_r)
 }
 
+/// Basic low-quality (but fast) linear resampler.
+///
+/// This is realtime safe.
+///
+/// `src_pos` - The position in `src` of the first frame of `dst`. Frames outside of `src`
+/// are silent.
+///
+/// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+///
+/// Returns the position in `src` of the frame after the last frame of `dst`.
+pub fn linear_resample_rt_mono(
+    src: &[f32],
+    src_pos: f64,
+    resample_ratio: f64,
+    dst: &mut [f32],
+) -> f64 {
+    let ratio_inv = 1.0 / resample_ratio;
+
+    let get = |frame: isize| -> f32 {
+        if frame >= 0 {
+            src.get(frame as usize).copied().unwrap_or(0.0)
+        } else {
+            0.0
+        }
+    };
+
+    for (i, smp) in dst.iter_mut().enumerate() {
+        let pos = src_pos + (i as f64 * ratio_inv);
+        let frame = pos.floor();
+        let fract = (pos - frame) as f32;
+        let frame = frame as isize;
+
+        let smp_before = get(frame);
+        *smp = if fract > 0.0 {
+            smp_before + ((get(frame + 1) - smp_before) * fract)
+        } else {
+            smp_before
+        };
+    }
+
+    src_pos + (dst.len() as f64 * ratio_inv)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
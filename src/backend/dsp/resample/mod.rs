@@ -1,5 +1,166 @@
 pub mod deip_optimal;
 pub mod linear;
+pub mod sinc;
 
 pub use deip_optimal::*;
 pub use linear::*;
+pub use sinc::*;
+
+/// The interpolation used when resampling an audio clip to the project's sample rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InterpolationQuality {
+    /// Basic low-quality (but fast) linear interpolation.
+    Linear,
+
+    /// One of the "optimal" polynomial interpolators (see `DeipInterpolator`). The audio
+    /// is oversampled first, so these are slower (especially with more oversampling), but
+    /// they have a lot less aliasing.
+    Deip(DeipInterpolator),
+
+    /// A band-limited windowed-sinc filter (see `SincQuality`). This is the slowest, but
+    /// it has the flattest frequency response and the least aliasing, so it is the best
+    /// choice for converting the sample rate of imported files and for final renders.
+    Sinc(SincQuality),
+}
+
+impl Default for InterpolationQuality {
+    fn default() -> Self {
+        InterpolationQuality::Deip(DeipInterpolator::new(
+            DeipOversampling::X8,
+            DeipPolynomial::FourPoint3rdOrder,
+        ))
+    }
+}
+
+impl InterpolationQuality {
+    /// The number of frames before and after the resampled range that are used by the
+    /// interpolation.
+    ///
+    /// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+    pub fn margin_frames(&self, resample_ratio: f64) -> usize {
+        match self {
+            InterpolationQuality::Linear => 1,
+            InterpolationQuality::Deip(_) => (DEIP_OVERSAMPLE_TAPS / 2) + 2,
+            InterpolationQuality::Sinc(quality) => quality.margin_frames(resample_ratio),
+        }
+    }
+}
+
+/// Resample `src` into `dst` with the given interpolation.
+///
+/// This function allocates memory and is *not* realtime safe.
+///
+/// `src_start` - The position in `src` of the first frame of `dst`. Frames outside of
+/// `src` are silent.
+///
+/// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+pub fn resample_non_rt_into(
+    src: &[f32],
+    src_start: f64,
+    resample_ratio: f64,
+    dst: &mut [f32],
+    quality: InterpolationQuality,
+) {
+    match quality {
+        InterpolationQuality::Linear => {
+            let _ = linear_resample_rt_mono(src, src_start, resample_ratio, dst);
+        }
+        InterpolationQuality::Deip(interpolator) => {
+            deip_resample_non_rt_into(src, src_start, resample_ratio, dst, interpolator)
+        }
+        InterpolationQuality::Sinc(quality) => {
+            sinc_resample_non_rt_into(src, src_start, resample_ratio, dst, quality)
+        }
+    }
+}
+
+/// Resample with the given interpolation.
+///
+/// This function allocates memory and is *not* realtime safe. It is intended for
+/// resampling audio clips to be sent to the rt thread.
+///
+/// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+pub fn resample_non_rt_mono(
+    src: &[f32],
+    resample_ratio: f64,
+    quality: InterpolationQuality,
+) -> Vec<f32> {
+    match quality {
+        InterpolationQuality::Linear => linear_resample_non_rt_mono(src, resample_ratio),
+        InterpolationQuality::Deip(interpolator) => {
+            deip_resample_non_rt_mono(src, resample_ratio, interpolator)
+        }
+        InterpolationQuality::Sinc(quality) => {
+            sinc_resample_non_rt_mono(src, resample_ratio, quality)
+        }
+    }
+}
+
+/// Resample with the given interpolation.
+///
+/// This function allocates memory and is *not* realtime safe. It is intended for
+/// resampling audio clips to be sent to the rt thread.
+///
+/// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+pub fn resample_non_rt_stereo(
+    src_l: &[f32],
+    src_r: &[f32],
+    resample_ratio: f64,
+    quality: InterpolationQuality,
+) -> (Vec<f32>, Vec<f32>) {
+    match quality {
+        InterpolationQuality::Linear => linear_resample_non_rt_stereo(src_l, src_r, resample_ratio),
+        InterpolationQuality::Deip(interpolator) => {
+            deip_resample_non_rt_stereo(src_l, src_r, resample_ratio, interpolator)
+        }
+        InterpolationQuality::Sinc(quality) => {
+            sinc_resample_non_rt_stereo(src_l, src_r, resample_ratio, quality)
+        }
+    }
+}
+
+/// Helpers for measuring the quality of the resamplers in tests.
+#[cfg(test)]
+pub(crate) mod measure {
+    use std::f64::consts::PI;
+
+    pub fn sine(len: usize, freq: f64) -> Vec<f32> {
+        (0..len).map(|i| (2.0 * PI * freq * i as f64).sin() as f32).collect()
+    }
+
+    /// Returns the ratio (in dB) between the power of a sine wave at `freq` (fitted with
+    /// least squares) and the power of everything else in `signal`.
+    pub fn sine_snr_db(signal: &[f32], freq: f64) -> f64 {
+        let (mut ss, mut cc, mut sc, mut ys, mut yc) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for (i, y) in signal.iter().enumerate() {
+            let (s, c) = (2.0 * PI * freq * i as f64).sin_cos();
+            let y = f64::from(*y);
+            ss += s * s;
+            cc += c * c;
+            sc += s * c;
+            ys += y * s;
+            yc += y * c;
+        }
+        let det = (ss * cc) - (sc * sc);
+        let a = ((ys * cc) - (yc * sc)) / det;
+        let b = ((yc * ss) - (ys * sc)) / det;
+
+        let (mut signal_power, mut noise_power) = (0.0, 0.0);
+        for (i, y) in signal.iter().enumerate() {
+            let (s, c) = (2.0 * PI * freq * i as f64).sin_cos();
+            let fit = (a * s) + (b * c);
+            signal_power += fit * fit;
+            noise_power += (f64::from(*y) - fit).powi(2);
+        }
+
+        10.0 * (signal_power / noise_power).log10()
+    }
+
+    /// Returns the RMS level of `signal` in dB.
+    pub fn rms_db(signal: &[f32]) -> f64 {
+        let power: f64 =
+            signal.iter().map(|s| f64::from(*s).powi(2)).sum::<f64>() / signal.len() as f64;
+
+        10.0 * power.log10()
+    }
+}
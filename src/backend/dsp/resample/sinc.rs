@@ -0,0 +1,464 @@
+// Band-limited resampling with a windowed-sinc filter, as described in:
+//
+// Digital Audio Resampling Home Page
+//
+// - by Julius O. Smith III
+//
+// https://ccrma.stanford.edu/~jos/resample/
+//
+// The (one-sided) filter is stored in a table with `SINC_TABLE_RESOLUTION` entries per zero
+// crossing, and the table is linearly interpolated for each tap. This way any resample
+// ratio is supported (not only rational ones). When downsampling, the filter is stretched
+// so its cutoff is below the Nyquist frequency of the destination sample rate, which
+// means more taps are used per frame.
+
+use std::f64::consts::PI;
+use std::hash::{Hash, Hasher};
+
+/// The number of entries in the filter table per zero crossing of the sinc function.
+static SINC_TABLE_RESOLUTION: usize = 1024;
+
+/// The window applied to the sinc function. The window trades off the width of the
+/// transition band (narrowest with `Hann`) against the attenuation of the stopband
+/// (highest with `BlackmanHarris`). The `Kaiser` window can be tuned between the two
+/// with `beta`.
+#[derive(Debug, Clone, Copy)]
+pub enum SincWindow {
+    Hann,
+    Blackman,
+    BlackmanHarris,
+    Kaiser { beta: f32 },
+}
+
+// Floats can't be compared or hashed exactly, so their bit representation is used
+// instead. This way the quality can be used in the key of a rendered resource.
+impl PartialEq for SincWindow {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (SincWindow::Hann, SincWindow::Hann) => true,
+            (SincWindow::Blackman, SincWindow::Blackman) => true,
+            (SincWindow::BlackmanHarris, SincWindow::BlackmanHarris) => true,
+            (SincWindow::Kaiser { beta: a }, SincWindow::Kaiser { beta: b }) => {
+                a.to_bits() == b.to_bits()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Eq for SincWindow {}
+
+impl Hash for SincWindow {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        if let SincWindow::Kaiser { beta } = self {
+            beta.to_bits().hash(state);
+        }
+    }
+}
+
+impl SincWindow {
+    /// Returns the value of the window at `x` in the range `[-1.0, 1.0]`.
+    pub fn value(&self, x: f64) -> f64 {
+        if x.abs() > 1.0 {
+            return 0.0;
+        }
+
+        // The window is centered at `x = 0`.
+        let t = PI * x;
+
+        match self {
+            SincWindow::Hann => 0.5 + (0.5 * t.cos()),
+            SincWindow::Blackman => 0.42 + (0.5 * t.cos()) + (0.08 * (2.0 * t).cos()),
+            SincWindow::BlackmanHarris => {
+                0.35875
+                    + (0.48829 * t.cos())
+                    + (0.14128 * (2.0 * t).cos())
+                    + (0.01168 * (3.0 * t).cos())
+            }
+            SincWindow::Kaiser { beta } => {
+                let beta = f64::from(*beta);
+                bessel_i0(beta * (1.0 - (x * x)).sqrt()) / bessel_i0(beta)
+            }
+        }
+    }
+}
+
+/// The quality of a windowed-sinc resampler.
+#[derive(Debug, Clone, Copy)]
+pub struct SincQuality {
+    /// The number of taps (input frames) used for each output frame. More taps give a
+    /// narrower transition band, but are slower. When downsampling, the number of taps is
+    /// multiplied by the ratio between the sample rates.
+    ///
+    /// This must be even.
+    pub taps: usize,
+
+    /// The window applied to the sinc function.
+    pub window: SincWindow,
+
+    /// The cutoff frequency of the filter relative to the Nyquist frequency of the lower
+    /// of the two sample rates, in the range `(0.0, 1.0]`. This should be low enough that
+    /// the transition band ends below the Nyquist frequency, or else the frequencies just
+    /// above it will alias.
+    pub cutoff: f32,
+}
+
+impl SincQuality {
+    /// 16 taps. Flat up to around 30% of the sample rate, with around 55 dB of stopband
+    /// attenuation.
+    pub fn low() -> Self {
+        Self { taps: 16, window: SincWindow::Kaiser { beta: 5.0 }, cutoff: 0.8 }
+    }
+
+    /// 32 taps. Flat up to around 36% of the sample rate, with around 70 dB of stopband
+    /// attenuation.
+    pub fn medium() -> Self {
+        Self { taps: 32, window: SincWindow::Kaiser { beta: 7.0 }, cutoff: 0.86 }
+    }
+
+    /// 64 taps. Flat up to around 41% of the sample rate, with around 90 dB of stopband
+    /// attenuation.
+    pub fn high() -> Self {
+        Self { taps: 64, window: SincWindow::Kaiser { beta: 9.0 }, cutoff: 0.91 }
+    }
+
+    /// 128 taps. Flat up to around 44% of the sample rate, with around 115 dB of stopband
+    /// attenuation.
+    pub fn best() -> Self {
+        Self { taps: 128, window: SincWindow::Kaiser { beta: 12.0 }, cutoff: 0.94 }
+    }
+
+    /// The number of frames before and after the resampled range that are used by the
+    /// filter.
+    ///
+    /// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+    pub fn margin_frames(&self, resample_ratio: f64) -> usize {
+        ((self.taps / 2) as f64 / resample_ratio.min(1.0)).ceil() as usize + 1
+    }
+}
+
+impl PartialEq for SincQuality {
+    fn eq(&self, other: &Self) -> bool {
+        self.taps == other.taps
+            && self.window == other.window
+            && self.cutoff.to_bits() == other.cutoff.to_bits()
+    }
+}
+
+impl Eq for SincQuality {}
+
+impl Hash for SincQuality {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.taps.hash(state);
+        self.window.hash(state);
+        self.cutoff.to_bits().hash(state);
+    }
+}
+
+impl Default for SincQuality {
+    fn default() -> Self {
+        SincQuality::high()
+    }
+}
+
+/// A windowed-sinc resampler that supports any resample ratio.
+pub struct SincResampler {
+    quality: SincQuality,
+
+    /// The filter for one side of the sinc function, from the center to the last zero
+    /// crossing (plus an extra entry so the table can be interpolated).
+    table: Vec<f32>,
+}
+
+impl SincResampler {
+    /// Create a new resampler with the given quality.
+    ///
+    /// This allocates memory and is *not* realtime safe.
+    pub fn new(quality: SincQuality) -> Self {
+        let half_taps = (quality.taps / 2).max(1);
+        let cutoff = f64::from(quality.cutoff);
+
+        let len = (half_taps * SINC_TABLE_RESOLUTION) + 2;
+        let table = (0..len)
+            .map(|i| {
+                // The distance from the center of the filter (in zero crossings of the sinc
+                // function at the full bandwidth).
+                let u = i as f64 / SINC_TABLE_RESOLUTION as f64;
+
+                if u >= half_taps as f64 {
+                    return 0.0;
+                }
+
+                let sinc = if u == 0.0 { 1.0 } else { (PI * cutoff * u).sin() / (PI * cutoff * u) };
+
+                (sinc * quality.window.value(u / half_taps as f64)) as f32
+            })
+            .collect();
+
+        Self { quality, table }
+    }
+
+    pub fn quality(&self) -> SincQuality {
+        self.quality
+    }
+
+    /// Resample `src` into `dst`.
+    ///
+    /// This is realtime safe.
+    ///
+    /// `src_pos` - The position in `src` of the first frame of `dst`. Frames outside of
+    /// `src` are silent.
+    ///
+    /// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+    ///
+    /// Returns the position in `src` of the frame after the last frame of `dst`.
+    pub fn process(&self, src: &[f32], src_pos: f64, resample_ratio: f64, dst: &mut [f32]) -> f64 {
+        let ratio_inv = 1.0 / resample_ratio;
+
+        // When downsampling, the filter is stretched to remove the frequencies that would
+        // alias.
+        let scale = resample_ratio.min(1.0);
+        let gain = (f64::from(self.quality.cutoff) * scale) as f32;
+
+        // How far the filter reaches in source frames on each side.
+        let reach = (self.quality.taps / 2).max(1) as f64 / scale;
+        let table_scale = scale * SINC_TABLE_RESOLUTION as f64;
+        let last_entry = self.table.len() - 1;
+
+        for (i, smp) in dst.iter_mut().enumerate() {
+            let pos = src_pos + (i as f64 * ratio_inv);
+
+            let first = ((pos - reach).floor() as isize + 1).max(0) as usize;
+            let end = (((pos + reach).ceil() as isize).max(0) as usize).min(src.len());
+
+            let mut sum = 0.0;
+            for (frame, s) in src.iter().enumerate().take(end).skip(first) {
+                let t = (pos - frame as f64).abs() * table_scale;
+                let index = t as usize;
+
+                if index < last_entry {
+                    let fract = (t - index as f64) as f32;
+                    let a = self.table[index];
+                    let b = self.table[index + 1];
+
+                    sum += s * (a + ((b - a) * fract));
+                }
+            }
+
+            *smp = sum * gain;
+        }
+
+        src_pos + (dst.len() as f64 * ratio_inv)
+    }
+}
+
+/// High-quality band-limited resampler.
+///
+/// This function allocates memory and is *not* realtime safe.
+///
+/// `src_start` - The position in `src` of the first frame of `dst`. Frames outside of
+/// `src` are silent.
+///
+/// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+pub fn sinc_resample_non_rt_into(
+    src: &[f32],
+    src_start: f64,
+    resample_ratio: f64,
+    dst: &mut [f32],
+    quality: SincQuality,
+) {
+    let _ = SincResampler::new(quality).process(src, src_start, resample_ratio, dst);
+}
+
+/// High-quality band-limited resampler.
+///
+/// This function allocates memory and is *not* realtime safe. It is intended for
+/// resampling audio clips to be sent to the rt thread.
+///
+/// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+pub fn sinc_resample_non_rt_mono(
+    src: &[f32],
+    resample_ratio: f64,
+    quality: SincQuality,
+) -> Vec<f32> {
+    let dst_len = (src.len().saturating_sub(1) as f64 * resample_ratio).ceil() as usize;
+
+    let mut dst = vec![0.0; dst_len];
+    sinc_resample_non_rt_into(src, 0.0, resample_ratio, &mut dst, quality);
+
+    dst
+}
+
+/// High-quality band-limited resampler.
+///
+/// This function allocates memory and is *not* realtime safe. It is intended for
+/// resampling audio clips to be sent to the rt thread.
+///
+/// `resample_ratio` - The ratio between the destination samplerate / source samplerate.
+pub fn sinc_resample_non_rt_stereo(
+    src_l: &[f32],
+    src_r: &[f32],
+    resample_ratio: f64,
+    quality: SincQuality,
+) -> (Vec<f32>, Vec<f32>) {
+    // Make sure we are given valid slices.
+    let len = src_l.len().min(src_r.len());
+    let src_l = &src_l[0..len];
+    let src_r = &src_r[0..len];
+
+    let dst_len = (len.saturating_sub(1) as f64 * resample_ratio).ceil() as usize;
+
+    let mut dst_l = vec![0.0; dst_len];
+    let mut dst_r = vec![0.0; dst_len];
+
+    let resampler = SincResampler::new(quality);
+    let _ = resampler.process(src_l, 0.0, resample_ratio, &mut dst_l);
+    let _ = resampler.process(src_r, 0.0, resample_ratio, &mut dst_r);
+
+    (dst_l, dst_r)
+}
+
+/// The zeroth-order modified Bessel function of the first kind, used by the Kaiser window.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_x = x / 2.0;
+
+    for k in 1..64 {
+        term *= (half_x / k as f64) * (half_x / k as f64);
+        sum += term;
+
+        if term < sum * 1e-17 {
+            break;
+        }
+    }
+
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::dsp::resample::measure::{rms_db, sine, sine_snr_db};
+
+    static QUALITIES: [fn() -> SincQuality; 4] =
+        [SincQuality::low, SincQuality::medium, SincQuality::high, SincQuality::best];
+
+    /// Quick test to make sure there are no off-by-one errors.
+    #[test]
+    fn test_sinc_resample_non_rt() {
+        let src = vec![0.0; 1000];
+
+        for ratio in [44100.0 / 48000.0, 48000.0 / 44100.0, 1.0 / 2.0, 2.0].iter() {
+            let dst = sinc_resample_non_rt_mono(&src, *ratio, SincQuality::default());
+            assert_eq!(dst.len(), ((src.len() - 1) as f64 * ratio).ceil() as usize);
+        }
+
+        assert!(sinc_resample_non_rt_mono(&[], 2.0, SincQuality::default()).is_empty());
+    }
+
+    #[test]
+    fn unity_ratio_keeps_signal() {
+        let src = sine(4800, 1000.0 / 48000.0);
+        let dst = sinc_resample_non_rt_mono(&src, 1.0, SincQuality::high());
+
+        for (d, s) in dst[100..4700].iter().zip(src[100..4700].iter()) {
+            assert!((d - s).abs() < 1e-3);
+        }
+    }
+
+    /// The total harmonic distortion plus noise of a 1 kHz sine converted between 44.1 kHz
+    /// and 48 kHz, which is mostly the imaging/aliasing of the filter.
+    #[test]
+    fn thd_of_44100_48000_conversions() {
+        let min_snr_db = [55.0, 80.0, 100.0, 125.0];
+
+        for (quality, min_snr_db) in QUALITIES.iter().zip(min_snr_db.iter()) {
+            let quality = quality();
+
+            for (src_rate, dst_rate) in [(44100.0, 48000.0), (48000.0, 44100.0)].iter() {
+                let src = sine(*src_rate as usize, 1000.0 / src_rate);
+                let dst = sinc_resample_non_rt_mono(&src, dst_rate / src_rate, quality);
+
+                // Skip the start and end, where the sine wave starts and stops abruptly.
+                let skip = (*dst_rate as usize) / 10;
+                let snr = sine_snr_db(&dst[skip..dst.len() - skip], 1000.0 / dst_rate);
+
+                assert!(
+                    snr > *min_snr_db,
+                    "{:?} {} -> {}: {} dB",
+                    quality,
+                    src_rate,
+                    dst_rate,
+                    snr
+                );
+            }
+        }
+    }
+
+    /// A sine that is too high to be represented at the destination sample rate should be
+    /// removed instead of aliasing.
+    #[test]
+    fn removes_frequencies_above_nyquist_when_downsampling() {
+        let min_attenuation_db = [50.0, 70.0, 85.0, 115.0];
+
+        for (quality, min_attenuation_db) in QUALITIES.iter().zip(min_attenuation_db.iter()) {
+            let quality = quality();
+
+            // 23.5 kHz would alias to 20.6 kHz at 44.1 kHz.
+            let src = sine(48000, 23500.0 / 48000.0);
+            let dst = sinc_resample_non_rt_mono(&src, 44100.0 / 48000.0, quality);
+
+            let attenuation_db = rms_db(&src) - rms_db(&dst[4410..39690]);
+            assert!(attenuation_db > *min_attenuation_db, "{:?}: {} dB", quality, attenuation_db);
+
+            // Halving the sample rate.
+            let src = sine(48000, 15000.0 / 48000.0);
+            let dst = sinc_resample_non_rt_mono(&src, 0.5, quality);
+
+            let attenuation_db = rms_db(&src) - rms_db(&dst[2400..21600]);
+            assert!(attenuation_db > *min_attenuation_db, "{:?}: {} dB", quality, attenuation_db);
+        }
+    }
+
+    #[test]
+    fn passband_is_flat() {
+        for quality in QUALITIES.iter() {
+            let quality = quality();
+
+            // The highest frequency in the passband (see the docs of the presets).
+            let max_freq = match quality.taps {
+                16 => 0.3,
+                32 => 0.36,
+                64 => 0.41,
+                _ => 0.44,
+            } * 44100.0;
+
+            for freq in [100.0, 5000.0, max_freq].iter() {
+                let src = sine(48000, freq / 48000.0);
+                let dst = sinc_resample_non_rt_mono(&src, 44100.0 / 48000.0, quality);
+
+                let gain_db = rms_db(&dst[4410..39690]) - rms_db(&src);
+                assert!(gain_db.abs() < 0.1, "{:?} at {} Hz: {} dB", quality, freq, gain_db);
+            }
+        }
+    }
+
+    #[test]
+    fn process_continues_from_returned_position() {
+        let resampler = SincResampler::new(SincQuality::medium());
+        let src = sine(1000, 0.01);
+
+        let mut whole = vec![0.0; 500];
+        resampler.process(&src, 3.0, 0.7, &mut whole);
+
+        let mut parts = vec![0.0; 500];
+        let pos = resampler.process(&src, 3.0, 0.7, &mut parts[0..123]);
+        resampler.process(&src, pos, 0.7, &mut parts[123..]);
+
+        for (a, b) in whole.iter().zip(parts.iter()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+// Transient (onset) detection.
+
+use rusty_daw_core::{SampleRate, Seconds};
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::backend::resource_loader::AnyPcm;
+
+/// The length of each analysis frame.
+static TRANSIENT_HOP_SECS: f64 = 0.005;
+
+/// The number of previous analysis frames a new frame is compared against.
+static TRANSIENT_HISTORY_FRAMES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransientDetectorParams {
+    /// How much louder (in dB) the start of a transient has to be compared to
+    /// the audio right before it. Lower values detect more transients.
+    pub threshold_db: f32,
+
+    /// Audio quieter than this (in dBFS) is never detected as a transient.
+    pub silence_threshold_db: f32,
+
+    /// The minimum distance between two transients.
+    pub min_slice_length: Seconds,
+}
+
+impl Default for TransientDetectorParams {
+    fn default() -> Self {
+        Self { threshold_db: 9.0, silence_threshold_db: -50.0, min_slice_length: Seconds(0.05) }
+    }
+}
+
+/// Detect the transients (onsets) in the given range of frames of a PCM resource.
+///
+/// This function allocates memory and is *not* realtime safe.
+///
+/// Returns the positions of the transients in frames from the start of the resource,
+/// in ascending order. The start of the range is never returned as a transient.
+pub fn detect_transients(
+    pcm: &AnyPcm,
+    range: Range<usize>,
+    params: &TransientDetectorParams,
+) -> Vec<usize> {
+    let end = range.end.min(pcm.len());
+    let start = range.start.min(end);
+
+    let channels: Vec<Cow<[f32]>> =
+        (0..pcm.num_channels()).map(|i| pcm.channel(i).slice(start..end).to_f32()).collect();
+    let channels: Vec<&[f32]> = channels.iter().map(|channel| &**channel).collect();
+
+    let mut transients = detect_transients_in(&channels, pcm.sample_rate(), params);
+
+    for transient in transients.iter_mut() {
+        *transient += start;
+    }
+
+    transients
+}
+
+/// Detect the transients (onsets) in the given channels.
+///
+/// This function allocates memory and is *not* realtime safe.
+///
+/// Returns the positions of the transients in frames, in ascending order. The start of
+/// the audio is never returned as a transient, and every transient is at least
+/// `min_slice_length` away from the start and end of the audio.
+pub fn detect_transients_in(
+    channels: &[&[f32]],
+    sample_rate: SampleRate,
+    params: &TransientDetectorParams,
+) -> Vec<usize> {
+    let len = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    let hop = ((TRANSIENT_HOP_SECS * sample_rate.0).round() as usize).max(1);
+
+    // The energy of each analysis frame in dB. The signal is differentiated first to
+    // emphasize the high frequency content of transients.
+    let energy_db: Vec<f32> = (0..len / hop)
+        .map(|frame| {
+            let start = frame * hop;
+
+            let mut sum = 0.0f64;
+            for channel in channels.iter() {
+                let mut prev = if start > 0 { channel[start - 1] } else { channel[start] };
+
+                for smp in channel[start..start + hop].iter() {
+                    let diff = f64::from(*smp - prev);
+                    sum += diff * diff;
+                    prev = *smp;
+                }
+            }
+
+            let mean_square = sum / (hop * channels.len().max(1)) as f64;
+            (10.0 * mean_square.max(1e-20).log10()) as f32
+        })
+        .collect();
+
+    // The differentiated signal is quieter than the original, so compare the raw level
+    // of the frame against the silence threshold instead.
+    let is_silent = |frame: usize| -> bool {
+        let start = frame * hop;
+
+        let peak = channels.iter().fold(0.0f32, |peak, channel| {
+            channel[start..start + hop].iter().fold(peak, |peak, smp| peak.max(smp.abs()))
+        });
+
+        20.0 * peak.max(1e-10).log10() < params.silence_threshold_db
+    };
+
+    let min_distance = (params.min_slice_length.0.max(0.0) * sample_rate.0).round() as usize;
+
+    let mut transients = Vec::new();
+    let mut last_transient = 0;
+    let mut in_transient = false;
+
+    for (frame, frame_db) in energy_db.iter().enumerate().skip(1) {
+        let history = &energy_db[frame.saturating_sub(TRANSIENT_HISTORY_FRAMES)..frame];
+        let history_db = history.iter().sum::<f32>() / history.len() as f32;
+
+        let is_onset = frame_db - history_db >= params.threshold_db;
+
+        if is_onset && !in_transient && !is_silent(frame) {
+            let pos = frame * hop;
+
+            if pos - last_transient >= min_distance && len - pos >= min_distance {
+                transients.push(pos);
+                last_transient = pos;
+            }
+        }
+
+        // Only detect the first frame of a rising edge.
+        in_transient = is_onset;
+    }
+
+    transients
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decaying noise bursts starting at the given frames.
+    fn hits(starts: &[usize], len: usize) -> Vec<f32> {
+        let mut data = vec![0.0f32; len];
+        let mut seed = 1u32;
+
+        for start in starts.iter() {
+            for (i, smp) in data[*start..].iter_mut().enumerate().take(4_000) {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let noise = (seed >> 8) as f32 / (1u32 << 24) as f32 * 2.0 - 1.0;
+
+                *smp += noise * (-(i as f32) / 800.0).exp();
+            }
+        }
+
+        data
+    }
+
+    #[test]
+    fn detect_hits() {
+        let sample_rate = SampleRate(48_000.0);
+        let hop = 240;
+
+        let data = hits(&[0, 10_000, 20_000, 30_000], 40_000);
+        let transients =
+            detect_transients_in(&[&data], sample_rate, &TransientDetectorParams::default());
+
+        assert_eq!(transients.len(), 3);
+        for (transient, expected) in transients.iter().zip([10_000, 20_000, 30_000].iter()) {
+            // The transient must start at or right before the hit.
+            assert!(*transient <= *expected && expected - transient < hop);
+        }
+
+        // Transients closer than the minimum slice length are ignored.
+        let params =
+            TransientDetectorParams { min_slice_length: Seconds(0.3), ..Default::default() };
+        let transients = detect_transients_in(&[&data], sample_rate, &params);
+        assert_eq!(transients.len(), 1);
+
+        // Nothing is detected in silence.
+        let silence = vec![0.0f32; 40_000];
+        let transients =
+            detect_transients_in(&[&silence], sample_rate, &TransientDetectorParams::default());
+        assert!(transients.is_empty());
+    }
+}
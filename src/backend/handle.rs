@@ -3,25 +3,89 @@ use rusty_daw_audio_graph::{
     AudioGraphExecutor, CompilerError, CompilerWarning, GraphInterface, GraphStateRef,
 };
 use rusty_daw_core::SampleRate;
+use std::fs;
+use std::path::PathBuf;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc, LockResult, Mutex,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, LockResult, Mutex, Weak,
 };
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
-use crate::backend::resource_loader::ResourceLoader;
+use crate::backend::resource_loader::{
+    ResourceLoader, ResourceMemoryStats, DEFAULT_RESOURCE_MEMORY_BUDGET,
+};
 use crate::backend::save_state::BackendSaveState;
 use crate::backend::timeline::{
-    AudioClipResourceCache, TimelineTransport, TimelineTransportHandle, TimelineTransportSaveState,
+    AudioClipRenderQueue, AudioClipResourceCache, TimelineTransport, TimelineTransportHandle,
+    TimelineTransportSaveState, AUDIO_CLIP_RENDER_THREADS,
 };
+use crate::util::TwoXHashMap;
 
 use super::MAX_BLOCKSIZE;
 
 static COLLECT_INTERVAL: Duration = Duration::from_secs(3);
 
+/// How often the audio files used by the project are checked for modifications.
+static FILE_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct ResourceCache {
     pub(crate) resource_loader: Arc<Mutex<ResourceLoader>>,
     pub(crate) audio_clip_resource_cache: Arc<Mutex<AudioClipResourceCache>>,
+    pub(crate) audio_clip_render_queue: Arc<AudioClipRenderQueue>,
+
+    /// The maximum amount of memory used by the resources in bytes (see
+    /// `run_collector()`).
+    memory_budget: Arc<AtomicU64>,
+}
+
+impl ResourceCache {
+    /// Set the maximum amount of memory used by loaded and rendered resources in bytes.
+    ///
+    /// Resources that are no longer used by any audio clip are kept until this budget is
+    /// exceeded, at which point the ones that have been unused the longest are dropped.
+    /// Resources that are still used are never dropped, so the budget may be exceeded
+    /// if the project uses more memory than that.
+    pub fn set_memory_budget(&self, bytes: u64) {
+        self.memory_budget.store(bytes, Ordering::Relaxed);
+    }
+
+    /// The maximum amount of memory used by loaded and rendered resources in bytes.
+    pub fn memory_budget(&self) -> u64 {
+        self.memory_budget.load(Ordering::Relaxed)
+    }
+
+    /// Returns the memory currently used by loaded and rendered resources.
+    pub fn memory_stats(&self) -> ResourceMemoryStats {
+        let pcm = { self.resource_loader.lock().unwrap().pcm_loader.memory_usage() };
+
+        let (audio_clip, original_bytes) = {
+            let mut cache = self.audio_clip_resource_cache.lock().unwrap();
+            (cache.memory_usage(), cache.original_bytes())
+        };
+
+        ResourceMemoryStats { pcm, audio_clip, original_bytes, budget_bytes: self.memory_budget() }
+    }
+
+    /// A resource cache without a collector or file watcher thread, used in tests.
+    #[cfg(test)]
+    pub(crate) fn test(coll_handle: &Handle, sample_rate: SampleRate) -> Self {
+        let resource_loader =
+            Arc::new(Mutex::new(ResourceLoader::new(coll_handle.clone(), sample_rate)));
+        let audio_clip_resource_cache =
+            Arc::new(Mutex::new(AudioClipResourceCache::new(coll_handle.clone(), sample_rate)));
+        let audio_clip_render_queue = Arc::new(AudioClipRenderQueue::new(
+            Arc::clone(&resource_loader),
+            Arc::clone(&audio_clip_resource_cache),
+            1,
+        ));
+
+        Self {
+            resource_loader,
+            audio_clip_resource_cache,
+            audio_clip_render_queue,
+            memory_budget: Arc::new(AtomicU64::new(DEFAULT_RESOURCE_MEMORY_BUDGET)),
+        }
+    }
 }
 
 impl Clone for ResourceCache {
@@ -29,6 +93,8 @@ impl Clone for ResourceCache {
         Self {
             resource_loader: Arc::clone(&self.resource_loader),
             audio_clip_resource_cache: Arc::clone(&self.audio_clip_resource_cache),
+            audio_clip_render_queue: Arc::clone(&self.audio_clip_render_queue),
+            memory_budget: Arc::clone(&self.memory_budget),
         }
     }
 }
@@ -65,12 +131,29 @@ impl BackendHandle {
             Arc::new(Mutex::new(AudioClipResourceCache::new(collector.handle(), sample_rate)));
         let audio_clip_r_c_clone = Arc::clone(&audio_clip_resource_cache);
 
+        let audio_clip_render_queue = Arc::new(AudioClipRenderQueue::new(
+            Arc::clone(&resource_loader),
+            Arc::clone(&audio_clip_resource_cache),
+            AUDIO_CLIP_RENDER_THREADS,
+        ));
+
+        let memory_budget = Arc::new(AtomicU64::new(DEFAULT_RESOURCE_MEMORY_BUDGET));
+        let memory_budget_clone = Arc::clone(&memory_budget);
+
         let running = Arc::new(AtomicBool::new(true));
         let running_clone = Arc::clone(&running);
         std::thread::spawn(|| {
-            run_collector(collector, resource_loader_clone, audio_clip_r_c_clone, running_clone)
+            run_collector(
+                collector,
+                resource_loader_clone,
+                audio_clip_r_c_clone,
+                memory_budget_clone,
+                running_clone,
+            )
         });
 
+        spawn_file_watcher(&resource_loader, &audio_clip_render_queue, &running);
+
         let (timeline_transport, timeline_transport_handle) =
             TimelineTransport::new(coll_handle.clone(), sample_rate);
 
@@ -84,7 +167,12 @@ impl BackendHandle {
             Self {
                 graph_interface,
 
-                resource_cache: ResourceCache { resource_loader, audio_clip_resource_cache },
+                resource_cache: ResourceCache {
+                    resource_loader,
+                    audio_clip_resource_cache,
+                    audio_clip_render_queue,
+                    memory_budget,
+                },
 
                 timeline_transport: timeline_transport_handle,
 
@@ -114,12 +202,29 @@ impl BackendHandle {
             Arc::new(Mutex::new(AudioClipResourceCache::new(collector.handle(), sample_rate)));
         let audio_clip_r_c_clone = Arc::clone(&audio_clip_resource_cache);
 
+        let audio_clip_render_queue = Arc::new(AudioClipRenderQueue::new(
+            Arc::clone(&resource_loader),
+            Arc::clone(&audio_clip_resource_cache),
+            AUDIO_CLIP_RENDER_THREADS,
+        ));
+
+        let memory_budget = Arc::new(AtomicU64::new(DEFAULT_RESOURCE_MEMORY_BUDGET));
+        let memory_budget_clone = Arc::clone(&memory_budget);
+
         let running = Arc::new(AtomicBool::new(true));
         let running_clone = Arc::clone(&running);
         std::thread::spawn(|| {
-            run_collector(collector, resource_loader_clone, audio_clip_r_c_clone, running_clone)
+            run_collector(
+                collector,
+                resource_loader_clone,
+                audio_clip_r_c_clone,
+                memory_budget_clone,
+                running_clone,
+            )
         });
 
+        spawn_file_watcher(&resource_loader, &audio_clip_render_queue, &running);
+
         let (timeline_transport, mut timeline_transport_handle) =
             TimelineTransport::new(coll_handle.clone(), sample_rate);
 
@@ -146,7 +251,12 @@ impl BackendHandle {
             Self {
                 graph_interface,
 
-                resource_cache: ResourceCache { resource_loader, audio_clip_resource_cache },
+                resource_cache: ResourceCache {
+                    resource_loader,
+                    audio_clip_resource_cache,
+                    audio_clip_render_queue,
+                    memory_budget,
+                },
 
                 timeline_transport: timeline_transport_handle,
 
@@ -214,19 +324,66 @@ impl Drop for BackendHandle {
     }
 }
 
+/// Periodically drops the resources that are no longer used.
+///
+/// Unused resources are kept around until the memory budget is exceeded (see
+/// `ResourceCache::set_memory_budget()`). Then the following are dropped in order until
+/// the resources fit in the budget again:
+///
+/// 1. Unused audio clip resources, since they can be rendered again from the PCM
+/// resources.
+/// 2. Unused PCM resources.
+/// 3. The original samples kept by audio clip resources with pitch shifting or time
+/// stretching effects. The PCM resources used only by those are then dropped the next
+/// time this runs.
 fn run_collector(
     mut collector: Collector,
     resource_loader: Arc<Mutex<ResourceLoader>>,
     audio_clip_resource_cache: Arc<Mutex<AudioClipResourceCache>>,
+    memory_budget: Arc<AtomicU64>,
     running: Arc<AtomicBool>,
 ) {
     while running.load(Ordering::Relaxed) {
         std::thread::sleep(COLLECT_INTERVAL);
 
-        {
+        let budget = memory_budget.load(Ordering::Relaxed);
+
+        let pcm_bytes = match resource_loader.lock() {
+            LockResult::Ok(mut res_loader) => res_loader.pcm_loader.memory_usage().bytes,
+            LockResult::Err(e) => {
+                log::error!("{}", e);
+                break;
+            }
+        };
+
+        let audio_clip_bytes = match audio_clip_resource_cache.lock() {
+            LockResult::Ok(mut cache) => cache.evict(budget.saturating_sub(pcm_bytes)).bytes,
+            LockResult::Err(e) => {
+                log::error!("{}", e);
+                break;
+            }
+        };
+
+        let pcm_bytes = match resource_loader.lock() {
+            LockResult::Ok(mut res_loader) => {
+                res_loader.evict(budget.saturating_sub(audio_clip_bytes)).bytes
+            }
+            LockResult::Err(e) => {
+                log::error!("{}", e);
+                break;
+            }
+        };
+
+        if pcm_bytes + audio_clip_bytes > budget {
             match audio_clip_resource_cache.lock() {
                 LockResult::Ok(mut cache) => {
-                    cache.collect();
+                    let original_bytes = cache.evict_originals();
+                    if original_bytes > 0 {
+                        log::debug!(
+                            "Memory budget exceeded, dropped {} bytes of original samples",
+                            original_bytes
+                        );
+                    }
                 }
                 LockResult::Err(e) => {
                     log::error!("{}", e);
@@ -235,19 +392,77 @@ fn run_collector(
             }
         }
 
-        {
-            match resource_loader.lock() {
-                LockResult::Ok(mut res_loader) => {
-                    res_loader.collect();
-                }
-                LockResult::Err(e) => {
-                    log::error!("{}", e);
-                    break;
-                }
+        collector.collect();
+    }
+    log::info!("shutting down collector");
+}
+
+fn spawn_file_watcher(
+    resource_loader: &Arc<Mutex<ResourceLoader>>,
+    render_queue: &Arc<AudioClipRenderQueue>,
+    running: &Arc<AtomicBool>,
+) {
+    let resource_loader = Arc::clone(resource_loader);
+    // The file watcher should not keep the render queue alive.
+    let render_queue = Arc::downgrade(render_queue);
+    let running = Arc::clone(running);
+
+    std::thread::spawn(|| run_file_watcher(resource_loader, render_queue, running));
+}
+
+/// Reload the audio files used by the project when they are modified by another
+/// application (such as when a sample is exported again from an audio editor).
+///
+/// A modified file is only reloaded once its size and modification time stayed the same
+/// for one `FILE_WATCH_INTERVAL`, so files are not loaded while they are still being
+/// written.
+fn run_file_watcher(
+    resource_loader: Arc<Mutex<ResourceLoader>>,
+    render_queue: Weak<AudioClipRenderQueue>,
+    running: Arc<AtomicBool>,
+) {
+    // The (modification time, size) of every modified file when it was last checked.
+    let mut pending: TwoXHashMap<PathBuf, (Option<SystemTime>, u64)> = Default::default();
+
+    while running.load(Ordering::Relaxed) {
+        std::thread::sleep(FILE_WATCH_INTERVAL);
+
+        let modified_files = match resource_loader.lock() {
+            LockResult::Ok(res_loader) => res_loader.pcm_loader.modified_files(),
+            LockResult::Err(e) => {
+                log::error!("{}", e);
+                break;
+            }
+        };
+
+        let mut stable_files: Vec<PathBuf> = Vec::new();
+        let mut still_pending: TwoXHashMap<PathBuf, (Option<SystemTime>, u64)> = Default::default();
+
+        for path in modified_files {
+            // Files that were removed are reported by the `MissingMediaReport` instead.
+            let stamp = match fs::metadata(&path) {
+                Ok(metadata) => (metadata.modified().ok(), metadata.len()),
+                Err(_) => continue,
+            };
+
+            if pending.get(&path) == Some(&stamp) {
+                stable_files.push(path);
+            } else {
+                still_pending.insert(path, stamp);
             }
         }
 
-        collector.collect();
+        pending = still_pending;
+
+        if !stable_files.is_empty() {
+            match render_queue.upgrade() {
+                Some(render_queue) => {
+                    render_queue.reload_files(&stable_files);
+                }
+                // The backend was dropped.
+                None => break,
+            }
+        }
     }
-    log::info!("shutting down collector");
+    log::info!("shutting down file watcher");
 }
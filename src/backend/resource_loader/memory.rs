@@ -0,0 +1,196 @@
+use std::hash::Hash;
+use std::time::Instant;
+
+use crate::util::TwoXHashMap;
+
+/// The default maximum amount of memory used by loaded and rendered resources (2 GiB).
+///
+/// Resources that are no longer used by any audio clip stay cached until this budget is
+/// exceeded, so clips that are removed and added again don't have to be loaded again.
+pub static DEFAULT_RESOURCE_MEMORY_BUDGET: u64 = 2 * 1024 * 1024 * 1024;
+
+/// The memory used by the resources in a cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// The number of resources in the cache.
+    pub count: usize,
+
+    /// The memory used by all resources in the cache in bytes.
+    pub bytes: u64,
+
+    /// The number of resources that are not used by any audio clip (and are only kept
+    /// in case they are needed again).
+    pub unused_count: usize,
+
+    /// The memory used by the unused resources in bytes.
+    pub unused_bytes: u64,
+}
+
+/// The memory used by all loaded and rendered resources.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceMemoryStats {
+    /// The PCM resources loaded from audio files (see `PcmLoader`).
+    pub pcm: MemoryUsage,
+
+    /// The resources rendered for audio clips (resampled audio and offline effects).
+    /// Resources that use a PCM resource as is are not counted again here.
+    pub audio_clip: MemoryUsage,
+
+    /// The memory used by the original PCM resources kept around by audio clip resources
+    /// with pitch shifting or time stretching effects in bytes. These are also counted
+    /// in `pcm`, and are only dropped when the memory budget is still exceeded after all
+    /// unused resources were dropped.
+    pub original_bytes: u64,
+
+    /// The memory budget in bytes.
+    pub budget_bytes: u64,
+}
+
+impl ResourceMemoryStats {
+    /// The total memory used by all resources in bytes.
+    pub fn total_bytes(&self) -> u64 {
+        self.pcm.bytes + self.audio_clip.bytes
+    }
+}
+
+/// Keeps track of how long the resources of a cache have been unused, so the ones that
+/// have been unused the longest are evicted first.
+pub(crate) struct UnusedResources<K: Hash + Eq + Clone> {
+    unused_since: TwoXHashMap<K, Instant>,
+}
+
+impl<K: Hash + Eq + Clone> UnusedResources<K> {
+    pub(crate) fn new() -> Self {
+        Self { unused_since: Default::default() }
+    }
+
+    /// Drop unused resources from `resources` (the ones that have been unused the
+    /// longest first) until the resources use at most `max_bytes`. Resources that are
+    /// still used are never dropped.
+    ///
+    /// Use a `max_bytes` of `0` to drop all unused resources, or `u64::MAX` to only
+    /// measure the memory usage.
+    ///
+    /// `is_unused` - Returns `true` if the resource is not used by anything other than
+    /// the cache.
+    ///
+    /// Returns the memory usage after dropping resources.
+    pub(crate) fn evict<V>(
+        &mut self,
+        resources: &mut TwoXHashMap<K, V>,
+        mut is_unused: impl FnMut(&mut V) -> bool,
+        size_bytes: impl Fn(&V) -> u64,
+        max_bytes: u64,
+    ) -> MemoryUsage {
+        let now = Instant::now();
+        let mut usage = MemoryUsage::default();
+        let mut unused: Vec<(Instant, K)> = Vec::new();
+
+        let unused_since = &mut self.unused_since;
+        unused_since.retain(|key, _| resources.contains_key(key));
+
+        for (key, resource) in resources.iter_mut() {
+            let bytes = size_bytes(resource);
+
+            usage.count += 1;
+            usage.bytes += bytes;
+
+            if is_unused(resource) {
+                let since = *unused_since.entry(key.clone()).or_insert(now);
+                unused.push((since, key.clone()));
+
+                usage.unused_count += 1;
+                usage.unused_bytes += bytes;
+            } else {
+                let _ = unused_since.remove(key);
+            }
+        }
+
+        if usage.bytes <= max_bytes {
+            return usage;
+        }
+
+        unused.sort_by_key(|(since, _)| *since);
+
+        for (_, key) in unused {
+            if usage.bytes <= max_bytes {
+                break;
+            }
+
+            if let Some(resource) = resources.remove(&key) {
+                let bytes = size_bytes(&resource);
+
+                usage.count -= 1;
+                usage.bytes -= bytes;
+                usage.unused_count -= 1;
+                usage.unused_bytes -= bytes;
+            }
+            let _ = unused_since.remove(&key);
+        }
+
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (is used, size in bytes)
+    type TestResource = (bool, u64);
+
+    fn evict(
+        unused: &mut UnusedResources<u32>,
+        resources: &mut TwoXHashMap<u32, TestResource>,
+        max_bytes: u64,
+    ) -> MemoryUsage {
+        unused.evict(resources, |r| !r.0, |r| r.1, max_bytes)
+    }
+
+    #[test]
+    fn measures_usage() {
+        let mut unused = UnusedResources::new();
+        let mut resources: TwoXHashMap<u32, TestResource> = Default::default();
+        resources.insert(0, (true, 100));
+        resources.insert(1, (false, 20));
+        resources.insert(2, (false, 3));
+
+        let usage = evict(&mut unused, &mut resources, std::u64::MAX);
+
+        assert_eq!(usage, MemoryUsage { count: 3, bytes: 123, unused_count: 2, unused_bytes: 23 });
+        assert_eq!(resources.len(), 3);
+    }
+
+    #[test]
+    fn evicts_longest_unused_first() {
+        let mut unused = UnusedResources::new();
+        let mut resources: TwoXHashMap<u32, TestResource> = Default::default();
+        resources.insert(0, (true, 100));
+        resources.insert(1, (false, 10));
+        resources.insert(2, (true, 10));
+        resources.insert(3, (false, 10));
+
+        evict(&mut unused, &mut resources, std::u64::MAX);
+
+        std::thread::sleep(std::time::Duration::from_millis(2));
+
+        // Resource 2 becomes unused after resources 1 and 3, and resource 1 is used again.
+        resources.get_mut(&2).unwrap().0 = false;
+        resources.get_mut(&1).unwrap().0 = true;
+        evict(&mut unused, &mut resources, std::u64::MAX);
+
+        let usage = evict(&mut unused, &mut resources, 120);
+
+        assert_eq!(usage.bytes, 120);
+        assert!(!resources.contains_key(&3));
+        assert!(resources.contains_key(&1));
+        assert!(resources.contains_key(&2));
+
+        // Used resources are never evicted, even if the budget can't be met.
+        let usage = evict(&mut unused, &mut resources, 0);
+
+        assert_eq!(usage, MemoryUsage { count: 2, bytes: 110, unused_count: 0, unused_bytes: 0 });
+        assert!(resources.contains_key(&0));
+        assert!(resources.contains_key(&1));
+    }
+}
@@ -1,22 +1,92 @@
-use basedrop::Handle;
+use basedrop::{Handle, Shared};
 use rusty_daw_core::SampleRate;
 use std::error::Error;
 use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
 
+pub mod memory;
 pub mod pcm;
-pub use pcm::{AnyPcm, MonoPcm, PcmLoadError, PcmLoader, StereoPcm};
+pub mod peaks;
+pub use memory::{MemoryUsage, ResourceMemoryStats, DEFAULT_RESOURCE_MEMORY_BUDGET};
+pub use pcm::{
+    AnyPcm, BroadcastInfo, ChannelLayout, ChannelPosition, MonoPcm, MultiPcm, PcmChannel,
+    PcmDecoder, PcmFileInfo, PcmFileKey, PcmLoadError, PcmLoader, PcmLoop, PcmLoopType, PcmMarker,
+    PcmMetadata, PcmSample, PcmStream, PcmStreamInfo, PcmTag, StereoPcm, U24,
+};
+pub use peaks::{Peak, PeakCache, PeakLoader};
 
 pub struct ResourceLoader {
     pub pcm_loader: PcmLoader,
+    pub peak_loader: PeakLoader,
 }
 
 impl ResourceLoader {
     pub fn new(coll_handle: Handle, sample_rate: SampleRate) -> Self {
-        Self { pcm_loader: PcmLoader::new(coll_handle, sample_rate) }
+        Self {
+            pcm_loader: PcmLoader::new(coll_handle, sample_rate),
+            peak_loader: PeakLoader::new(),
+        }
+    }
+
+    /// Load the PCM resource at `path` without keeping the resource loader locked while the
+    /// file is being decoded, so multiple files can be loaded at the same time, and the
+    /// loader can be used in the meantime.
+    ///
+    /// Returns the resource and the hash of the contents of the file (see `PcmFileKey`).
+    pub fn load_pcm(
+        resource_loader: &Mutex<Self>,
+        path: &PathBuf,
+        progress: &mut dyn FnMut(f32) -> bool,
+    ) -> Result<(Shared<AnyPcm>, u64), PcmLoadError> {
+        log::info!("Loading PCM file: {:?}", path);
+
+        let decoder = {
+            let resource_loader = resource_loader.lock().unwrap();
+
+            if let Some(pcm) = resource_loader.pcm_loader.get(path) {
+                if let Some(key) = resource_loader.pcm_loader.file_key(path) {
+                    return Ok((pcm, key.hash));
+                }
+            }
+
+            resource_loader.pcm_loader.decoder()
+        };
+
+        // Check the size first, so files that are too large to be loaded (and are streamed
+        // from disk instead) are not read twice.
+        decoder.check_size(path)?;
+
+        // Files with the same contents as an already loaded file are not decoded again.
+        let key = PcmFileKey::read(path)?;
+        let hash = key.hash;
+        if let Some(pcm) = resource_loader.lock().unwrap().pcm_loader.get_by_key(&key) {
+            return Ok((pcm, hash));
+        }
+
+        // Note if two clips request the same file at the same time, then the file may be
+        // decoded twice. Only the first decoded resource is kept.
+        let (pcm, metadata) = decoder.decode(path, progress)?;
+
+        Ok((resource_loader.lock().unwrap().pcm_loader.insert(key, pcm, metadata), hash))
+    }
+
+    /// Drop resources not being currently used until the PCM resources use at most
+    /// `max_bytes` (see `PcmLoader::evict()`).
+    ///
+    /// Returns the memory used by the PCM resources afterwards.
+    pub fn evict(&mut self, max_bytes: u64) -> MemoryUsage {
+        let usage = self.pcm_loader.evict(max_bytes);
+
+        // Drop the peaks of files that are no longer loaded.
+        let pcm_loader = &self.pcm_loader;
+        self.peak_loader.retain(|path| pcm_loader.is_loaded(path));
+
+        usage
     }
 
     pub fn collect(&mut self) {
-        self.pcm_loader.collect();
+        let _ = self.evict(0);
     }
 }
 
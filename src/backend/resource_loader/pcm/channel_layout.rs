@@ -0,0 +1,250 @@
+use symphonia::core::audio::Channels;
+
+/// The speaker position of a channel in a multichannel PCM resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelPosition {
+    FrontLeft,
+    FrontRight,
+    FrontCentre,
+    LowFrequency,
+    RearLeft,
+    RearRight,
+    RearCentre,
+    SideLeft,
+    SideRight,
+    FrontLeftCentre,
+    FrontRightCentre,
+    TopCentre,
+    TopFrontLeft,
+    TopFrontCentre,
+    TopFrontRight,
+    TopRearLeft,
+    TopRearCentre,
+    TopRearRight,
+
+    /// The position of this channel is not known (such as in ambisonic or multi-mic
+    /// recordings). This holds the index of the channel in the file.
+    Unknown(usize),
+}
+
+impl ChannelPosition {
+    /// The (left, right) gains used when downmixing this channel to stereo, or `None` if
+    /// the position of this channel is unknown (see `ChannelLayout::downmix_gains`).
+    ///
+    /// These follow the ITU-R BS.775 downmix coefficients. The low frequency channel is
+    /// discarded.
+    pub fn downmix_gains(&self) -> Option<(f32, f32)> {
+        use ChannelPosition::*;
+
+        Some(match self {
+            FrontLeft | FrontLeftCentre | TopFrontLeft => (1.0, 0.0),
+            FrontRight | FrontRightCentre | TopFrontRight => (0.0, 1.0),
+            FrontCentre | RearCentre | TopCentre | TopFrontCentre | TopRearCentre => {
+                (MINUS_3_DB, MINUS_3_DB)
+            }
+            RearLeft | SideLeft | TopRearLeft => (MINUS_3_DB, 0.0),
+            RearRight | SideRight | TopRearRight => (0.0, MINUS_3_DB),
+            LowFrequency => (0.0, 0.0),
+            Unknown(_) => return None,
+        })
+    }
+}
+
+const MINUS_3_DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// The speaker positions of the channels in a multichannel PCM resource.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChannelLayout {
+    positions: Vec<ChannelPosition>,
+    num_unknown: usize,
+}
+
+impl ChannelLayout {
+    pub fn new(positions: Vec<ChannelPosition>) -> Self {
+        let num_unknown = positions
+            .iter()
+            .filter(|position| matches!(position, ChannelPosition::Unknown(_)))
+            .count();
+
+        Self { positions, num_unknown }
+    }
+
+    /// A layout of `num_channels` channels with unknown positions.
+    pub fn unknown(num_channels: usize) -> Self {
+        Self::new((0..num_channels).map(ChannelPosition::Unknown).collect())
+    }
+
+    /// Get the layout from the channels of a decoded file. Channels past the ones
+    /// described by `channels` have an unknown position.
+    pub(super) fn from_symphonia(channels: Channels, num_channels: usize) -> Self {
+        let mut positions: Vec<ChannelPosition> = channels
+            .iter()
+            .map(|channel| {
+                if channel == Channels::FRONT_LEFT {
+                    ChannelPosition::FrontLeft
+                } else if channel == Channels::FRONT_RIGHT {
+                    ChannelPosition::FrontRight
+                } else if channel == Channels::FRONT_CENTRE {
+                    ChannelPosition::FrontCentre
+                } else if channel == Channels::LFE1 {
+                    ChannelPosition::LowFrequency
+                } else if channel == Channels::REAR_LEFT {
+                    ChannelPosition::RearLeft
+                } else if channel == Channels::REAR_RIGHT {
+                    ChannelPosition::RearRight
+                } else if channel == Channels::REAR_CENTRE {
+                    ChannelPosition::RearCentre
+                } else if channel == Channels::SIDE_LEFT {
+                    ChannelPosition::SideLeft
+                } else if channel == Channels::SIDE_RIGHT {
+                    ChannelPosition::SideRight
+                } else if channel == Channels::FRONT_LEFT_CENTRE {
+                    ChannelPosition::FrontLeftCentre
+                } else if channel == Channels::FRONT_RIGHT_CENTRE {
+                    ChannelPosition::FrontRightCentre
+                } else if channel == Channels::TOP_CENTRE {
+                    ChannelPosition::TopCentre
+                } else if channel == Channels::TOP_FRONT_LEFT {
+                    ChannelPosition::TopFrontLeft
+                } else if channel == Channels::TOP_FRONT_CENTRE {
+                    ChannelPosition::TopFrontCentre
+                } else if channel == Channels::TOP_FRONT_RIGHT {
+                    ChannelPosition::TopFrontRight
+                } else if channel == Channels::TOP_REAR_LEFT {
+                    ChannelPosition::TopRearLeft
+                } else if channel == Channels::TOP_REAR_CENTRE {
+                    ChannelPosition::TopRearCentre
+                } else if channel == Channels::TOP_REAR_RIGHT {
+                    ChannelPosition::TopRearRight
+                } else {
+                    ChannelPosition::Unknown(0)
+                }
+            })
+            .take(num_channels)
+            .collect();
+
+        for i in positions.len()..num_channels {
+            positions.push(ChannelPosition::Unknown(i));
+        }
+
+        // Store the channel index for positions that are unknown.
+        for (i, position) in positions.iter_mut().enumerate() {
+            if let ChannelPosition::Unknown(index) = position {
+                *index = i;
+            }
+        }
+
+        Self::new(positions)
+    }
+
+    /// The (left, right) gains used when downmixing the channel at the given index to
+    /// stereo.
+    ///
+    /// Channels without a known position are spread to both sides at -3dB, divided by
+    /// the number of channels without a known position. This keeps a downmix of many
+    /// unknown channels (such as a multi-mic recording) from clipping.
+    pub fn downmix_gains(&self, channel: usize) -> (f32, f32) {
+        self.positions[channel].downmix_gains().unwrap_or_else(|| {
+            let gain = MINUS_3_DB / self.num_unknown as f32;
+            (gain, gain)
+        })
+    }
+
+    #[inline]
+    pub fn positions(&self) -> &[ChannelPosition] {
+        &self.positions
+    }
+
+    #[inline]
+    pub fn num_channels(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// The number of channels without a known position.
+    #[inline]
+    pub fn num_unknown(&self) -> usize {
+        self.num_unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_from_symphonia() {
+        let channels = Channels::FRONT_LEFT
+            | Channels::FRONT_RIGHT
+            | Channels::FRONT_CENTRE
+            | Channels::LFE1
+            | Channels::REAR_LEFT
+            | Channels::REAR_RIGHT;
+
+        let layout = ChannelLayout::from_symphonia(channels, 6);
+        assert_eq!(
+            layout.positions(),
+            &[
+                ChannelPosition::FrontLeft,
+                ChannelPosition::FrontRight,
+                ChannelPosition::FrontCentre,
+                ChannelPosition::LowFrequency,
+                ChannelPosition::RearLeft,
+                ChannelPosition::RearRight,
+            ]
+        );
+        assert_eq!(layout.num_unknown(), 0);
+
+        // Channels that are not described or that have no matching position are unknown.
+        let layout = ChannelLayout::from_symphonia(
+            Channels::FRONT_LEFT | Channels::FRONT_RIGHT | Channels::REAR_LEFT_CENTRE,
+            4,
+        );
+        assert_eq!(
+            layout.positions(),
+            &[
+                ChannelPosition::FrontLeft,
+                ChannelPosition::FrontRight,
+                ChannelPosition::Unknown(2),
+                ChannelPosition::Unknown(3),
+            ]
+        );
+        assert_eq!(layout.num_unknown(), 2);
+
+        // Positions past the number of channels in the file are ignored.
+        let layout = ChannelLayout::from_symphonia(channels, 3);
+        assert_eq!(layout.num_channels(), 3);
+        assert_eq!(layout.positions()[2], ChannelPosition::FrontCentre);
+    }
+
+    #[test]
+    fn downmix_gains() {
+        let layout = ChannelLayout::new(vec![
+            ChannelPosition::FrontLeft,
+            ChannelPosition::FrontRight,
+            ChannelPosition::FrontCentre,
+            ChannelPosition::LowFrequency,
+            ChannelPosition::SideLeft,
+            ChannelPosition::SideRight,
+        ]);
+        let gains: Vec<(f32, f32)> = (0..6).map(|i| layout.downmix_gains(i)).collect();
+        assert_eq!(
+            gains,
+            vec![
+                (1.0, 0.0),
+                (0.0, 1.0),
+                (MINUS_3_DB, MINUS_3_DB),
+                (0.0, 0.0),
+                (MINUS_3_DB, 0.0),
+                (0.0, MINUS_3_DB),
+            ]
+        );
+
+        // Unknown channels share the gain of a centre channel.
+        let layout = ChannelLayout::unknown(4);
+        assert_eq!(layout.num_unknown(), 4);
+        for i in 0..4 {
+            assert_eq!(layout.downmix_gains(i), (MINUS_3_DB / 4.0, MINUS_3_DB / 4.0));
+        }
+        assert_eq!(ChannelPosition::Unknown(0).downmix_gains(), None);
+    }
+}
@@ -1,34 +1,57 @@
 use std::error::Error;
 use std::fmt;
-use std::fs::File;
+use std::fs::{self, File};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
 
 use basedrop::{Handle, Shared};
 
-use rusty_daw_core::SampleRate;
-use symphonia::core::audio::AudioBufferRef;
-use symphonia::core::audio::Signal;
-use symphonia::core::codecs::{CodecRegistry, DecoderOptions};
-use symphonia::core::formats::FormatOptions;
+use rusty_daw_core::{SampleRate, Seconds};
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Channels, Signal};
+use symphonia::core::codecs::{
+    CodecParameters, CodecRegistry, Decoder, DecoderOptions, CODEC_TYPE_ALAC,
+};
+use symphonia::core::conv::IntoSample;
+use symphonia::core::formats::{FormatOptions, FormatReader};
 use symphonia::core::io::MediaSourceStream;
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::{Hint, Probe};
+use symphonia::core::sample::Sample;
 
-// TODO: Eventually we should use disk streaming to store large files. Using this as a stop-gap
-// safety check for now.
+/// Files larger than this (when decoded) are not loaded into memory. Use `open_stream()`
+/// to stream them from disk instead.
 pub static MAX_FILE_BYTES: u64 = 1_000_000_000;
 
-use super::{AnyPcm, MonoPcm, StereoPcm};
-use crate::util::TwoXHashMap;
+use super::metadata::{self, PcmMetadata, PcmTag};
+use super::stream::{self, PcmStream, StreamServer};
+use super::{AnyPcm, ChannelLayout, MonoPcm, PcmSample, U24};
+use crate::backend::dsp::resample::InterpolationQuality;
+use crate::backend::resource_loader::memory::{MemoryUsage, UnusedResources};
+use crate::util::{hash_file, TwoXHashMap};
 
 pub struct PcmLoader {
-    loaded: TwoXHashMap<PathBuf, Shared<AnyPcm>>,
+    /// The loaded resources, keyed by the hash of the contents of their file. Files with
+    /// the same contents share a single resource.
+    loaded: TwoXHashMap<u64, Shared<AnyPcm>>,
+
+    /// The files of the loaded resources, keyed by their canonical path.
+    files: TwoXHashMap<PathBuf, PcmFileKey>,
+
+    /// The metadata of the loaded resources, keyed by the hash of the contents of their
+    /// file.
+    metadata: TwoXHashMap<u64, Arc<PcmMetadata>>,
+
+    /// Resources that are no longer used are kept until the memory budget is exceeded.
+    unused: UnusedResources<u64>,
 
     /// The resource to send when the resource could not be loaded.
     empty_pcm: Shared<AnyPcm>,
 
-    codec_registry: &'static CodecRegistry,
-    probe: &'static Probe,
+    decoder: PcmDecoder,
+
+    /// The thread that decodes the opened streams.
+    stream_server: StreamServer,
 
     coll_handle: Handle,
 }
@@ -40,34 +63,377 @@ impl PcmLoader {
 
         Self {
             loaded: Default::default(),
+            files: Default::default(),
+            metadata: Default::default(),
+            unused: UnusedResources::new(),
             empty_pcm,
-            codec_registry: symphonia::default::get_codecs(),
-            probe: symphonia::default::get_probe(),
+            decoder: PcmDecoder {
+                codec_registry: symphonia::default::get_codecs(),
+                probe: symphonia::default::get_probe(),
+            },
+            stream_server: StreamServer::spawn(),
             coll_handle,
         }
     }
 
-    pub fn load(&mut self, path: &PathBuf) -> (Shared<AnyPcm>, Result<(), PcmLoadError>) {
-        match self.try_load(path) {
-            Ok(pcm) => (pcm, Ok(())),
-            Err(e) => {
-                log::error!("{}", e);
+    /// Open the given audio file for streaming from disk. Use this for files that are
+    /// too large to be loaded into memory (see `MAX_FILE_BYTES`).
+    ///
+    /// The file is resampled to `sample_rate` (with the given interpolation) while it is
+    /// being streamed.
+    ///
+    /// `start_frame` - The frame (at `sample_rate`) to start prefetching from.
+    pub fn open_stream(
+        &mut self,
+        path: &PathBuf,
+        sample_rate: SampleRate,
+        start_frame: usize,
+        interpolation_quality: InterpolationQuality,
+    ) -> Result<PcmStream, PcmLoadError> {
+        log::info!("Opening PCM stream: {:?}", path);
+
+        let file = self.decoder.open(path)?;
+
+        let (stream, source) = stream::new_stream(
+            path.clone(),
+            file,
+            self.decoder.codec_registry,
+            sample_rate,
+            start_frame,
+            interpolation_quality,
+            &self.stream_server,
+        )?;
+
+        self.stream_server.add(source);
+
+        Ok(stream)
+    }
+
+    /// Forget that the given file is loaded, so the next time it is requested it is
+    /// loaded from disk again. Audio clips keep using the previous resource until they
+    /// request the file again.
+    pub fn forget(&mut self, path: &PathBuf) {
+        let _ = self.files.remove(&canonical_path(path));
+    }
+
+    /// Returns the PCM resource at the given path if it is already loaded and the file was
+    /// not modified since.
+    ///
+    /// Paths are compared after resolving symbolic links and relative components.
+    pub fn get(&self, path: &PathBuf) -> Option<Shared<AnyPcm>> {
+        let key = self.files.get(&canonical_path(path))?;
+
+        if key.is_modified() {
+            return None;
+        }
+
+        self.loaded.get(&key.hash).map(Shared::clone)
+    }
+
+    /// Returns the PCM resource of a file with the same contents as the file of `key` if
+    /// one is already loaded, and remembers that the file of `key` uses this resource.
+    pub fn get_by_key(&mut self, key: &PcmFileKey) -> Option<Shared<AnyPcm>> {
+        let pcm = self.loaded.get(&key.hash).map(Shared::clone)?;
+
+        self.files.insert(key.path.clone(), key.clone());
+
+        Some(pcm)
+    }
+
+    /// Returns the metadata of the given file if it is loaded (see `PcmMetadata`).
+    ///
+    /// Note the metadata of files that are streamed from disk is not kept by the loader.
+    /// Use `PcmDecoder::read_metadata()` for those.
+    pub fn metadata(&self, path: &PathBuf) -> Option<Arc<PcmMetadata>> {
+        let key = self.files.get(&canonical_path(path))?;
+
+        self.metadata.get(&key.hash).map(Arc::clone)
+    }
+
+    /// Returns the key of the given file if it is loaded.
+    pub fn file_key(&self, path: &PathBuf) -> Option<&PcmFileKey> {
+        self.files.get(&canonical_path(path))
+    }
 
-                // Send an "empty" PCM resource instead.
-                (Shared::clone(&self.empty_pcm), Err(e))
+    /// Returns `true` if the PCM resource at the given path is loaded.
+    pub fn is_loaded(&self, path: &PathBuf) -> bool {
+        self.files.contains_key(&canonical_path(path))
+    }
+
+    /// Returns the (canonical) paths of all loaded files that were modified or removed
+    /// since they were loaded. Use `forget()` to load them again.
+    pub fn modified_files(&self) -> Vec<PathBuf> {
+        self.files.values().filter(|key| key.is_modified()).map(|key| key.path.clone()).collect()
+    }
+
+    /// Returns a decoder that can be used to decode files without locking the loader
+    /// (see `PcmDecoder`).
+    pub fn decoder(&self) -> PcmDecoder {
+        self.decoder.clone()
+    }
+
+    /// Add a PCM resource that was decoded with a `PcmDecoder` to the loaded resources.
+    ///
+    /// If a file with the same contents was already loaded in the meantime, then the
+    /// already loaded resource is returned instead.
+    pub fn insert(
+        &mut self,
+        key: PcmFileKey,
+        pcm: AnyPcm,
+        metadata: PcmMetadata,
+    ) -> Shared<AnyPcm> {
+        if let Some(loaded) = self.get_by_key(&key) {
+            return loaded;
+        }
+
+        let pcm = Shared::new(&self.coll_handle, pcm);
+
+        log::debug!("Successfully loaded PCM file: {:?}", &key.path);
+
+        self.loaded.insert(key.hash, Shared::clone(&pcm));
+        self.metadata.insert(key.hash, Arc::new(metadata));
+        self.files.insert(key.path.clone(), key);
+
+        pcm
+    }
+
+    /// The resource used when a resource could not be loaded.
+    pub fn empty_pcm(&self) -> Shared<AnyPcm> {
+        Shared::clone(&self.empty_pcm)
+    }
+
+    /// Returns the memory used by the loaded resources.
+    pub fn memory_usage(&mut self) -> MemoryUsage {
+        self.evict(std::u64::MAX)
+    }
+
+    /// Drop PCM resources not being currently used (the ones unused the longest first)
+    /// until the loaded resources use at most `max_bytes`.
+    ///
+    /// Returns the memory used by the loaded resources afterwards.
+    pub fn evict(&mut self, max_bytes: u64) -> MemoryUsage {
+        // If no other extant Shared pointers to the resource exists, then it is unused.
+        let usage = self.unused.evict(
+            &mut self.loaded,
+            |pcm| Shared::get_mut(pcm).is_some(),
+            |pcm| pcm.size_bytes(),
+            max_bytes,
+        );
+
+        let loaded = &self.loaded;
+        self.files.retain(|_, key| loaded.contains_key(&key.hash));
+        self.metadata.retain(|hash, _| loaded.contains_key(hash));
+
+        usage
+    }
+
+    /// Drop all PCM resources not being currently used.
+    pub fn collect(&mut self) {
+        let _ = self.evict(0);
+    }
+}
+
+/// Identifies the contents of an audio file on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcmFileKey {
+    /// The canonical path of the file (with all symbolic links and relative components
+    /// resolved).
+    pub path: PathBuf,
+
+    /// The hash of the contents of the file.
+    pub hash: u64,
+
+    modified: Option<SystemTime>,
+    size: u64,
+}
+
+impl PcmFileKey {
+    /// Read the key of the given file. This reads the whole file to hash its contents, so
+    /// avoid doing this while the `PcmLoader` is locked, and use
+    /// `PcmDecoder::check_size()` first for files that may be too large to be loaded.
+    pub fn read(path: &PathBuf) -> Result<Self, PcmLoadError> {
+        let canonical_path =
+            fs::canonicalize(path).map_err(|e| PcmLoadError::PathNotFound((path.clone(), e)))?;
+
+        // Read the metadata before the contents, so a modification while the file is being
+        // hashed is detected by `is_modified()`.
+        let metadata = fs::metadata(&canonical_path)
+            .map_err(|e| PcmLoadError::PathNotFound((path.clone(), e)))?;
+        let hash = hash_file(&canonical_path)
+            .map_err(|e| PcmLoadError::CouldNotRead((path.clone(), e)))?;
+
+        Ok(Self {
+            path: canonical_path,
+            hash,
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+        })
+    }
+
+    /// Whether the file was modified or removed since this key was read. This only
+    /// compares the modification time and size of the file, so it does not read the
+    /// contents of the file.
+    pub fn is_modified(&self) -> bool {
+        match fs::metadata(&self.path) {
+            Ok(metadata) => {
+                metadata.modified().ok() != self.modified || metadata.len() != self.size
             }
+            Err(_) => true,
         }
     }
+}
+
+/// Returns the layout of the channels of a file with more than two channels, or `None`
+/// for mono and stereo files.
+pub(super) fn channel_layout(
+    path: &PathBuf,
+    codec_params: &CodecParameters,
+    n_channels: usize,
+) -> Option<ChannelLayout> {
+    if n_channels <= 2 {
+        return None;
+    }
+
+    let layout = codec_params
+        .channels
+        .map(|channels| ChannelLayout::from_symphonia(channels, n_channels))
+        .filter(|layout| layout.num_channels() == n_channels)
+        .unwrap_or_else(|| ChannelLayout::unknown(n_channels));
+
+    if layout.num_unknown() > 0 {
+        log::warn!(
+            "{} of {} channels have an unknown position, downmixing evenly: {:?}",
+            layout.num_unknown(),
+            layout.num_channels(),
+            path,
+        );
+    }
+
+    Some(layout)
+}
+
+/// Returns the canonical path of the given path, or the path itself if it does not exist.
+fn canonical_path(path: &PathBuf) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.clone())
+}
+
+/// Decodes audio files into PCM resources.
+///
+/// This can be used to decode files on other threads without keeping the `PcmLoader`
+/// locked. The decoded resource is then added with `PcmLoader::insert()`.
+#[derive(Clone)]
+pub struct PcmDecoder {
+    codec_registry: &'static CodecRegistry,
+    probe: &'static Probe,
+}
+
+impl PcmDecoder {
+    /// Decode the given audio file into memory, and read its metadata.
+    ///
+    /// `progress` - Called with the progress of decoding in the range `[0.0, 1.0]`. If
+    /// this returns `false`, then decoding is cancelled and `PcmLoadError::Cancelled` is
+    /// returned.
+    pub fn decode(
+        &self,
+        path: &PathBuf,
+        progress: &mut dyn FnMut(f32) -> bool,
+    ) -> Result<(AnyPcm, PcmMetadata), PcmLoadError> {
+        log::info!("Decoding PCM file: {:?}", path);
+
+        let OpenedFile { mut format, codec_params, track_id, n_channels, sample_rate, tags } =
+            self.open(path)?;
+
+        let n_frames = codec_params.n_frames;
+
+        // Create a decoder for the track.
+        let mut decoder = self
+            .codec_registry
+            .make(&codec_params, &DecoderOptions::default())
+            .map_err(|e| PcmLoadError::CouldNotCreateDecoder((path.clone(), e)))?;
+
+        let layout = channel_layout(path, &codec_params, n_channels);
+        let sample_rate = SampleRate(sample_rate as f64);
+
+        let mut file = DecodeFile {
+            format: &mut *format,
+            decoder: &mut *decoder,
+            track_id,
+            n_channels,
+            n_frames,
+            path,
+            progress,
+        };
+
+        // Keep integer formats in their original bit depth to save memory.
+        let pcm = match codec_params.bits_per_sample {
+            Some(bits) if bits <= 8 => {
+                AnyPcm::from_channels(file.decode::<u8>()?, layout, sample_rate)
+            }
+            Some(bits) if bits <= 16 => {
+                AnyPcm::from_channels(file.decode::<i16>()?, layout, sample_rate)
+            }
+            Some(bits) if bits <= 24 => {
+                AnyPcm::from_channels(file.decode::<U24>()?, layout, sample_rate)
+            }
+            _ => AnyPcm::from_channels(file.decode::<f32>()?, layout, sample_rate),
+        };
+
+        let _ = decoder.finalize();
 
-    fn try_load(&mut self, path: &PathBuf) -> Result<Shared<AnyPcm>, PcmLoadError> {
-        log::info!("Loading PCM file: {:?}", path);
+        let metadata = PcmMetadata::read(path, tags, sample_rate);
 
-        if let Some(pcm) = self.loaded.get(path) {
-            // Resource is already loaded.
-            log::debug!("PCM file already loaded");
-            return Ok(Shared::clone(pcm));
+        Ok((pcm, metadata))
+    }
+
+    /// Returns `PcmLoadError::FileTooLarge` if the given audio file is too large to be
+    /// decoded into memory (see `MAX_FILE_BYTES`), without decoding it.
+    ///
+    /// Files that do not store their length in their header are only checked while they
+    /// are being decoded.
+    pub fn check_size(&self, path: &PathBuf) -> Result<(), PcmLoadError> {
+        let file = self.open(path)?;
+
+        if let Some(n_frames) = file.codec_params.n_frames {
+            // The same sample formats as in `decode()`.
+            let bytes_per_sample = match file.codec_params.bits_per_sample {
+                Some(bits) if bits <= 8 => std::mem::size_of::<u8>(),
+                Some(bits) if bits <= 16 => std::mem::size_of::<i16>(),
+                Some(bits) if bits <= 24 => std::mem::size_of::<U24>(),
+                _ => std::mem::size_of::<f32>(),
+            };
+
+            if n_frames * (file.n_channels * bytes_per_sample) as u64 > MAX_FILE_BYTES {
+                return Err(PcmLoadError::FileTooLarge(path.clone()));
+            }
         }
 
+        Ok(())
+    }
+
+    /// Read the metadata of the given audio file without decoding it.
+    pub fn read_metadata(&self, path: &PathBuf) -> Result<PcmMetadata, PcmLoadError> {
+        let file = self.open(path)?;
+
+        Ok(PcmMetadata::read(path, file.tags, SampleRate(f64::from(file.sample_rate))))
+    }
+
+    /// Read the size and length of the given audio file without decoding it.
+    pub fn file_info(&self, path: &PathBuf) -> Result<PcmFileInfo, PcmLoadError> {
+        let size_bytes = std::fs::metadata(path)
+            .map_err(|e| PcmLoadError::PathNotFound((path.clone(), e)))?
+            .len();
+
+        let file = self.open(path)?;
+        let len_seconds = file
+            .codec_params
+            .n_frames
+            .map(|n_frames| Seconds::new(n_frames as f64 / f64::from(file.sample_rate)));
+
+        Ok(PcmFileInfo { size_bytes, len_seconds })
+    }
+
+    /// Open the given audio file and find its default track.
+    pub(super) fn open(&self, path: &PathBuf) -> Result<OpenedFile, PcmLoadError> {
         // Try to open the file.
         let file = File::open(path).map_err(|e| PcmLoadError::PathNotFound((path.clone(), e)))?;
 
@@ -84,10 +450,9 @@ impl PcmLoader {
         // Create the media source stream.
         let mss = MediaSourceStream::new(Box::new(file), Default::default());
 
-        // Use the default options for format reader, metadata reader, and decoder.
+        // Use the default options for format reader and metadata reader.
         let format_opts: FormatOptions = Default::default();
         let metadata_opts: MetadataOptions = Default::default();
-        let decode_opts: DecoderOptions = Default::default();
 
         // Probe the media source stream for metadata and get the format reader.
         let mut probed = self
@@ -101,125 +466,228 @@ impl PcmLoader {
             .default_track()
             .ok_or_else(|| PcmLoadError::NoTrackFound(path.clone()))?;
         let track_id = track.id;
+        let mut codec_params = track.codec_params.clone();
+
+        if codec_params.channels.is_none() && codec_params.codec == CODEC_TYPE_ALAC {
+            if let Some((channels, bits_per_sample)) = alac_params(&codec_params) {
+                codec_params.with_channels(channels).with_bits_per_sample(bits_per_sample);
+            }
+        }
 
         // Get info.
-        let n_channels = track
-            .codec_params
+        let n_channels = codec_params
             .channels
             .ok_or_else(|| PcmLoadError::NoChannelsFound(path.clone()))?
             .count();
 
-        // TODO: Support loading multi-channel audio.
-        if n_channels > 2 {
-            return Err(PcmLoadError::UnkownChannelFormat((path.clone(), n_channels)));
+        if n_channels == 0 {
+            return Err(PcmLoadError::NoChannelsFound(path.clone()));
         }
 
-        let sample_rate = track.codec_params.sample_rate.unwrap_or_else(|| {
+        let sample_rate = codec_params.sample_rate.unwrap_or_else(|| {
             log::warn!("Could not find sample rate. Assuming a sample rate of 44100");
             44100
         });
 
-        let n_frames = track.codec_params.n_frames;
-
-        // Eventually we should use disk streaming to store large files. Using this as a stop-gap
-        // safety check for now.
-        if let Some(n_frames) = n_frames {
-            let total_bytes = n_channels as u64 * n_frames * 4;
-            if total_bytes > MAX_FILE_BYTES {
-                return Err(PcmLoadError::FileTooLarge(path.clone()));
+        // Tags can be stored in the container (such as ID3v2 tags at the start of an MP3
+        // file), or be read by the format reader.
+        let mut tags: Vec<PcmTag> = Vec::new();
+        if let Some(probed_metadata) = probed.metadata.get() {
+            if let Some(revision) = probed_metadata.current() {
+                tags.extend(metadata::symphonia_tags(revision));
             }
         }
+        if let Some(revision) = probed.format.metadata().current() {
+            tags.extend(metadata::symphonia_tags(revision));
+        }
 
-        // Create a decoder for the track.
-        let mut decoder = self
-            .codec_registry
-            .make(&track.codec_params, &decode_opts)
-            .map_err(|e| PcmLoadError::CouldNotCreateDecoder((path.clone(), e)))?;
+        Ok(OpenedFile {
+            format: probed.format,
+            codec_params,
+            track_id,
+            n_channels,
+            sample_rate,
+            tags,
+        })
+    }
+}
 
-        let mut decoded_channels = Vec::<Vec<f32>>::new();
-        for _ in 0..n_channels {
-            decoded_channels.push(Vec::with_capacity(n_frames.unwrap_or(0) as usize));
+/// Read the channels and bit depth of an ALAC track from its magic cookie, since the
+/// MP4 reader leaves them out. The channels are in the order that the decoder outputs
+/// them in.
+fn alac_params(codec_params: &CodecParameters) -> Option<(Channels, u32)> {
+    let cookie = codec_params.extra_data.as_ref().filter(|cookie| cookie.len() >= 24)?;
+    let bit_depth = u32::from(cookie[5]);
+
+    let channels = match cookie[9] {
+        1 => Channels::FRONT_LEFT,
+        2 => Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+        3 => Channels::FRONT_CENTRE | Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+        4 => {
+            Channels::FRONT_CENTRE
+                | Channels::FRONT_LEFT
+                | Channels::FRONT_RIGHT
+                | Channels::REAR_CENTRE
+        }
+        5 => {
+            Channels::FRONT_CENTRE
+                | Channels::FRONT_LEFT
+                | Channels::FRONT_RIGHT
+                | Channels::SIDE_LEFT
+                | Channels::SIDE_RIGHT
+        }
+        6 => {
+            Channels::FRONT_CENTRE
+                | Channels::FRONT_LEFT
+                | Channels::FRONT_RIGHT
+                | Channels::SIDE_LEFT
+                | Channels::SIDE_RIGHT
+                | Channels::LFE1
+        }
+        7 => {
+            Channels::FRONT_CENTRE
+                | Channels::FRONT_LEFT
+                | Channels::FRONT_RIGHT
+                | Channels::SIDE_LEFT
+                | Channels::SIDE_RIGHT
+                | Channels::REAR_CENTRE
+                | Channels::LFE1
+        }
+        8 => {
+            Channels::FRONT_CENTRE
+                | Channels::FRONT_LEFT_CENTRE
+                | Channels::FRONT_RIGHT_CENTRE
+                | Channels::FRONT_LEFT
+                | Channels::FRONT_RIGHT
+                | Channels::SIDE_LEFT
+                | Channels::SIDE_RIGHT
+                | Channels::LFE1
         }
+        _ => return None,
+    };
 
-        let max_frames = MAX_FILE_BYTES / (4 * n_channels as u64);
+    Some((channels, bit_depth))
+}
+
+/// The size and length of an audio file. This is used to find the file again if it is
+/// moved (see `StateSystem`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PcmFileInfo {
+    pub size_bytes: u64,
+
+    /// The length of the file. This is `None` if the length is not stored in the header
+    /// of the file.
+    pub len_seconds: Option<Seconds>,
+}
+
+/// A file that is being decoded into memory.
+struct DecodeFile<'a> {
+    format: &'a mut dyn FormatReader,
+    decoder: &'a mut dyn Decoder,
+    track_id: u32,
+    n_channels: usize,
+    n_frames: Option<u64>,
+    path: &'a PathBuf,
+    progress: &'a mut dyn FnMut(f32) -> bool,
+}
+
+impl<'a> DecodeFile<'a> {
+    /// Decode all packets of the track into channels stored as `T`.
+    fn decode<T: PcmSample>(&mut self) -> Result<Vec<Vec<T>>, PcmLoadError> {
+        let bytes_per_frame = (self.n_channels * std::mem::size_of::<T>()) as u64;
+
+        // Larger files should be streamed from disk instead.
+        if let Some(n_frames) = self.n_frames {
+            if n_frames * bytes_per_frame > MAX_FILE_BYTES {
+                return Err(PcmLoadError::FileTooLarge(self.path.clone()));
+            }
+        }
+
+        let mut decoded_channels = Vec::<Vec<T>>::new();
+        for _ in 0..self.n_channels {
+            decoded_channels.push(Vec::with_capacity(self.n_frames.unwrap_or(0) as usize));
+        }
+
+        let max_frames = MAX_FILE_BYTES / bytes_per_frame;
         let mut total_frames = 0;
 
-        while let Ok(packet) = probed.format.next_packet() {
+        while let Ok(packet) = self.format.next_packet() {
+            let progress = match self.n_frames {
+                Some(n_frames) if n_frames > 0 => (total_frames as f64 / n_frames as f64) as f32,
+                _ => 0.0,
+            };
+            if !(self.progress)(progress.min(1.0)) {
+                return Err(PcmLoadError::Cancelled(self.path.clone()));
+            }
+
             // If the packet does not belong to the selected track, skip over it.
-            if packet.track_id() != track_id {
+            if packet.track_id() != self.track_id {
                 continue;
             }
 
-            match decoder.decode(&packet) {
+            match self.decoder.decode(&packet) {
                 Ok(decoded) => {
-                    match decoded {
-                        AudioBufferRef::F32(d) => {
-                            total_frames += d.chan(0).len() as u64;
-                            if total_frames > max_frames {
-                                return Err(PcmLoadError::FileTooLarge(path.clone()));
-                            }
-                            for i in 0..n_channels {
-                                decoded_channels[i].extend_from_slice(d.chan(i));
-                            }
-                        }
-                        AudioBufferRef::S32(d) => {
-                            total_frames += d.chan(0).len() as u64;
-                            if total_frames > max_frames {
-                                return Err(PcmLoadError::FileTooLarge(path.clone()));
-                            }
-                            for i in 0..n_channels {
-                                for smp in d.chan(i).iter() {
-                                    decoded_channels[i].push(*smp as f32 / i32::MAX as f32);
-                                }
-                            }
-                        } // TODO: Ask creator of symphonia if we are able to get other sample formats from the decoder to save memory.
+                    total_frames += decoded.frames() as u64;
+                    if total_frames > max_frames {
+                        return Err(PcmLoadError::FileTooLarge(self.path.clone()));
                     }
+                    append_decoded(decoded, &mut decoded_channels);
                 }
                 Err(symphonia::core::errors::Error::DecodeError(err)) => {
                     // Decode errors are not fatal. Print the error message and try to decode the next
                     // packet as usual.
                     log::warn!("decode error: {}", err);
                 }
-                Err(e) => return Err(PcmLoadError::ErrorWhileDecoding((path.clone(), e))),
+                Err(e) => return Err(PcmLoadError::ErrorWhileDecoding((self.path.clone(), e))),
             }
         }
 
-        let pcm = if n_channels == 1 {
-            AnyPcm::Mono(MonoPcm::new(
-                decoded_channels.pop().unwrap(),
-                SampleRate(sample_rate as f64),
-            ))
-        } else {
-            // Two channels. TODO: Support loading multi-channel audio.
-
-            let right = decoded_channels.pop().unwrap();
-            let left = decoded_channels.pop().unwrap();
-
-            AnyPcm::Stereo(StereoPcm::new(left, right, SampleRate(sample_rate as f64)))
-        };
-
-        decoder.close();
-
-        let pcm = Shared::new(&self.coll_handle, pcm);
-
-        self.loaded.insert(path.to_owned(), Shared::clone(&pcm));
+        (self.progress)(1.0);
 
-        log::debug!("Successfully loaded PCM file");
+        Ok(decoded_channels)
+    }
+}
 
-        Ok(pcm)
+/// Append the decoded samples to `channels`.
+///
+/// Integer samples are converted to the full range of an `i32` first, so they keep their
+/// bit depth if `T` has at least as many bits as the file (see `PcmSample::from_i32()`).
+pub(super) fn append_decoded<T: PcmSample>(decoded: AudioBufferRef, channels: &mut [Vec<T>]) {
+    fn extend<S: Sample, T>(buf: &AudioBuffer<S>, channels: &mut [Vec<T>], f: impl Fn(S) -> T) {
+        for (i, channel) in channels.iter_mut().enumerate() {
+            channel.extend(buf.chan(i).iter().map(|smp| f(*smp)));
+        }
     }
 
-    /// Drop all PCM resources not being currently used.
-    pub fn collect(&mut self) {
-        // If no other extant Shared pointers to the resource exists, then
-        // remove that entry.
-        self.loaded.retain(|_, pcm| Shared::get_mut(pcm).is_none());
+    match decoded {
+        AudioBufferRef::U8(d) => extend(&d, channels, |smp| T::from_i32(smp.into_sample())),
+        AudioBufferRef::U16(d) => extend(&d, channels, |smp| T::from_i32(smp.into_sample())),
+        AudioBufferRef::U24(d) => extend(&d, channels, |smp| T::from_i32(smp.into_sample())),
+        AudioBufferRef::U32(d) => extend(&d, channels, |smp| T::from_i32(smp.into_sample())),
+        AudioBufferRef::S8(d) => extend(&d, channels, |smp| T::from_i32(smp.into_sample())),
+        AudioBufferRef::S16(d) => extend(&d, channels, |smp| T::from_i32(smp.into_sample())),
+        AudioBufferRef::S24(d) => extend(&d, channels, |smp| T::from_i32(smp.into_sample())),
+        AudioBufferRef::S32(d) => extend(&d, channels, T::from_i32),
+        AudioBufferRef::F32(d) => extend(&d, channels, T::from_f32),
+        AudioBufferRef::F64(d) => extend(&d, channels, |smp| T::from_f32(smp as f32)),
     }
 }
 
+pub(super) struct OpenedFile {
+    pub(super) format: Box<dyn FormatReader>,
+    pub(super) codec_params: CodecParameters,
+    pub(super) track_id: u32,
+    pub(super) n_channels: usize,
+    pub(super) sample_rate: u32,
+
+    /// The tags read by symphonia (see `PcmMetadata`).
+    pub(super) tags: Vec<PcmTag>,
+}
+
 #[derive(Debug)]
 pub enum PcmLoadError {
     PathNotFound((PathBuf, std::io::Error)),
+    CouldNotRead((PathBuf, std::io::Error)),
     UnkownFormat((PathBuf, symphonia::core::errors::Error)),
     NoTrackFound(PathBuf),
     NoChannelsFound(PathBuf),
@@ -227,6 +695,8 @@ pub enum PcmLoadError {
     FileTooLarge(PathBuf),
     CouldNotCreateDecoder((PathBuf, symphonia::core::errors::Error)),
     ErrorWhileDecoding((PathBuf, symphonia::core::errors::Error)),
+    UnknownLength(PathBuf),
+    Cancelled(PathBuf),
 }
 
 impl Error for PcmLoadError {}
@@ -237,6 +707,7 @@ impl fmt::Display for PcmLoadError {
 
         match self {
             PathNotFound((path, e)) => write!(f, "Failed to load PCM resource {:?}: file not found | {}", path, e),
+            CouldNotRead((path, e)) => write!(f, "Failed to load PCM resource {:?}: could not read file | {}", path, e),
             UnkownFormat((path, e)) => write!(
                 f,
                 "Failed to load PCM resource: format not supported | {} | path: {:?}",
@@ -269,6 +740,209 @@ impl fmt::Display for PcmLoadError {
                 e,
                 path
             ),
+            UnknownLength(path) => write!(
+                f,
+                "Failed to stream PCM resource: the length of the file is unknown | path: {:?}",
+                path
+            ),
+            Cancelled(path) => write!(f, "Loading PCM resource was cancelled | path: {:?}", path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use basedrop::Collector;
+    use std::sync::Mutex;
+
+    use super::super::test_wav;
+    use super::*;
+    use crate::backend::resource_loader::ResourceLoader;
+
+    static TEST_FILES_DIR: &str = "./assets/test_files/synth_keys";
+
+    /// Returns a new empty directory for the files of a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("meadowlark_test_pcm_loader").join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn decode(file_name: &str) -> AnyPcm {
+        let decoder = PcmDecoder {
+            codec_registry: symphonia::default::get_codecs(),
+            probe: symphonia::default::get_probe(),
+        };
+
+        let path = PathBuf::from(TEST_FILES_DIR).join(file_name);
+        match decoder.decode(&path, &mut |_| true) {
+            Ok((pcm, _)) => pcm,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // The AIFF, 24-bit FLAC and ALAC files are made from the WAV files by
+    // `assets/test_files/gen_fixtures.py`.
+    #[test]
+    fn decodes_lossless_formats() {
+        let tests = [
+            (
+                "synth_keys_48000_16bit.wav",
+                &[
+                    "synth_keys_48000.flac",
+                    "synth_keys_48000_16bit.aiff",
+                    "synth_keys_48000_16bit_sowt.aifc",
+                    "synth_keys_48000_16bit_alac.m4a",
+                ][..],
+            ),
+            (
+                "synth_keys_48000_24bit.wav",
+                &[
+                    "synth_keys_48000_24bit.flac",
+                    "synth_keys_48000_24bit.aiff",
+                    "synth_keys_48000_24bit_alac.m4a",
+                ][..],
+            ),
+            ("synth_keys_48000_float32.wav", &["synth_keys_48000_float32.aifc"][..]),
+        ];
+
+        for (reference, file_names) in tests.iter() {
+            let reference = decode(reference);
+
+            for file_name in file_names.iter() {
+                let pcm = decode(file_name);
+
+                // Files are kept in their original bit depth.
+                assert_eq!(
+                    std::mem::discriminant(&pcm),
+                    std::mem::discriminant(&reference),
+                    "{}",
+                    file_name
+                );
+                assert_eq!(pcm.sample_rate().0, reference.sample_rate().0, "{}", file_name);
+                assert_eq!(pcm.len(), reference.len(), "{}", file_name);
+
+                for i in 0..pcm.num_channels() {
+                    assert!(
+                        pcm.channel(i).to_f32() == reference.channel(i).to_f32(),
+                        "{}",
+                        file_name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_lossy_formats() {
+        let reference = decode("synth_keys_48000_16bit.wav");
+
+        for file_name in [
+            "synth_keys_48000.ogg",
+            "synth_keys_48000_cbr.mp3",
+            "synth_keys_48000_vbr.mp3",
+            "synth_keys_48000_abr.mp3",
+        ]
+        .iter()
+        {
+            let pcm = decode(file_name);
+
+            assert_eq!(pcm.sample_rate().0, 48_000.0, "{}", file_name);
+            assert_eq!(pcm.num_channels(), 2, "{}", file_name);
+
+            // Lossy encoders add padding at the start and end of the file.
+            assert!((pcm.len() as i64 - reference.len() as i64).abs() < 4096, "{}", file_name);
+
+            let peak = pcm.channel(0).to_f32().iter().fold(0.0f32, |peak, smp| peak.max(smp.abs()));
+            assert!(peak > 0.1, "{}", file_name);
         }
     }
+
+    #[test]
+    fn loads_files_with_same_contents_once() {
+        let collector = Collector::new();
+        let resource_loader =
+            Mutex::new(ResourceLoader::new(collector.handle(), SampleRate::new(48_000.0)));
+
+        let dir = test_dir("same_contents");
+        let a = dir.join("a.wav");
+        let b = dir.join("b.wav");
+        let reference = PathBuf::from(TEST_FILES_DIR).join("synth_keys_48000_16bit.wav");
+        fs::copy(&reference, &a).unwrap();
+        fs::copy(&reference, &b).unwrap();
+
+        let (pcm_a, _) = ResourceLoader::load_pcm(&resource_loader, &a, &mut |_| true).unwrap();
+        let (pcm_b, _) = ResourceLoader::load_pcm(&resource_loader, &b, &mut |_| true).unwrap();
+
+        let loader = &resource_loader.lock().unwrap().pcm_loader;
+        assert!(std::ptr::eq(&*pcm_a, &*pcm_b));
+        assert!(loader.is_loaded(&a) && loader.is_loaded(&b));
+        assert_eq!(loader.file_key(&a).unwrap().hash, loader.file_key(&b).unwrap().hash);
+        assert_ne!(loader.file_key(&a).unwrap().path, loader.file_key(&b).unwrap().path);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn detects_modified_files() {
+        let collector = Collector::new();
+        let resource_loader =
+            Mutex::new(ResourceLoader::new(collector.handle(), SampleRate::new(48_000.0)));
+
+        let dir = test_dir("modified");
+        let path = dir.join("a.wav");
+        fs::copy(PathBuf::from(TEST_FILES_DIR).join("synth_keys_48000_16bit.wav"), &path).unwrap();
+
+        let (_pcm, _) = ResourceLoader::load_pcm(&resource_loader, &path, &mut |_| true).unwrap();
+        let loader = &resource_loader.lock().unwrap().pcm_loader;
+        let key = PcmFileKey::read(&path).unwrap();
+        assert!(!key.is_modified());
+        assert!(loader.modified_files().is_empty());
+        assert!(loader.get(&path).is_some());
+
+        // Append some bytes, which changes the size of the file.
+        let mut contents = fs::read(&path).unwrap();
+        contents.extend_from_slice(&[0; 4]);
+        fs::write(&path, &contents).unwrap();
+
+        assert!(key.is_modified());
+        assert_eq!(loader.modified_files(), vec![key.path.clone()]);
+        assert!(loader.get(&path).is_none());
+
+        fs::remove_file(&path).unwrap();
+        assert!(key.is_modified());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn checks_size_before_loading() {
+        let collector = Collector::new();
+        let resource_loader =
+            Mutex::new(ResourceLoader::new(collector.handle(), SampleRate::new(48_000.0)));
+
+        // A WAV file with a header that claims about 4GB of 16 bit stereo audio.
+        let mut data = b"data".to_vec();
+        data.extend_from_slice(&0xFFFF_FFD0u32.to_le_bytes());
+        data.extend_from_slice(&[0; 64]);
+        let mut wav = test_wav::wav(&[test_wav::fmt_chunk(2, 48_000), data]);
+        wav[4..8].copy_from_slice(&0xFFFF_FFF8u32.to_le_bytes());
+
+        let dir = test_dir("too_large");
+        let path = dir.join("large.wav");
+        fs::write(&path, &wav).unwrap();
+
+        let loaded = ResourceLoader::load_pcm(&resource_loader, &path, &mut |_| true);
+        assert!(matches!(loaded, Err(PcmLoadError::FileTooLarge(_))));
+
+        let loader = &resource_loader.lock().unwrap().pcm_loader;
+        assert!(matches!(loader.decoder().check_size(&path), Err(PcmLoadError::FileTooLarge(_))));
+        assert!(!loader.is_loaded(&path));
+
+        let reference = PathBuf::from(TEST_FILES_DIR).join("synth_keys_48000_16bit.wav");
+        assert!(loader.decoder().check_size(&reference).is_ok());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,609 @@
+// Metadata of audio files.
+//
+// Tags are read by symphonia. symphonia does not read the chunks of WAV files used by
+// samplers and broadcast software, so those are parsed here: `smpl` (loop points), `cue `
+// and `LIST/adtl` (markers), `acid` (tempo and root key), `bext` (Broadcast Wave
+// recording time), and `LIST/INFO` (tags).
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use rusty_daw_core::{SampleRate, Seconds};
+use symphonia::core::meta::MetadataRevision;
+
+/// Chunks larger than this are not read for metadata.
+static MAX_METADATA_CHUNK_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Tags that store the tempo of the file in BPM (compared ignoring case).
+static TEMPO_TAG_KEYS: [&str; 4] = ["BPM", "TBPM", "TEMPO", "IBPM"];
+
+/// Tags that store the musical key of the file (compared ignoring case).
+static KEY_TAG_KEYS: [&str; 4] = ["KEY", "TKEY", "INITIALKEY", "IKEY"];
+
+/// The metadata of an audio file.
+///
+/// All positions are in frames at the sample rate of the file (see `sample_rate`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PcmMetadata {
+    /// The sample rate of the file.
+    pub sample_rate: SampleRate,
+
+    /// The text tags of the file (title, artist, comments, etc).
+    pub tags: Vec<PcmTag>,
+
+    /// The tempo of the file in BPM, read from the ACID chunk or from a tag.
+    pub tempo_bpm: Option<f64>,
+
+    /// The musical key of the file as written in its tags (i.e. "Am" or "F#").
+    pub key: Option<String>,
+
+    /// The MIDI note number of the root note of the file, read from the ACID chunk or
+    /// the sampler chunk.
+    pub root_note: Option<u8>,
+
+    /// The length of the file in beats, read from the ACID chunk.
+    pub num_beats: Option<u32>,
+
+    /// The (numerator, denominator) of the time signature, read from the ACID chunk.
+    pub time_signature: Option<(u16, u16)>,
+
+    /// Whether the ACID chunk marks the file as a one-shot instead of a loop. One-shots
+    /// should not be stretched to the tempo of the project.
+    pub is_one_shot: bool,
+
+    /// The loop points of the file, read from the sampler chunk.
+    pub loops: Vec<PcmLoop>,
+
+    /// The markers of the file sorted by position, read from the cue chunk.
+    pub markers: Vec<PcmMarker>,
+
+    /// The Broadcast Wave information of the file (if any).
+    pub broadcast: Option<BroadcastInfo>,
+}
+
+impl PcmMetadata {
+    pub fn new(sample_rate: SampleRate) -> Self {
+        Self {
+            sample_rate,
+            tags: Vec::new(),
+            tempo_bpm: None,
+            key: None,
+            root_note: None,
+            num_beats: None,
+            time_signature: None,
+            is_one_shot: false,
+            loops: Vec::new(),
+            markers: Vec::new(),
+            broadcast: None,
+        }
+    }
+
+    /// Read the metadata of the given audio file.
+    ///
+    /// `tags` - The tags read by symphonia. Tags that were also read from the `LIST/INFO`
+    /// chunk are only added once.
+    pub fn read(path: &PathBuf, tags: Vec<PcmTag>, sample_rate: SampleRate) -> Self {
+        let mut metadata = Self::new(sample_rate);
+
+        match File::open(path) {
+            Ok(file) => {
+                if let Err(e) = read_riff_chunks(&mut BufReader::new(file), &mut metadata) {
+                    log::warn!("Failed to read metadata chunks of {:?}: {}", path, e);
+                }
+            }
+            Err(e) => log::warn!("Failed to read metadata of {:?}: {}", path, e),
+        }
+
+        metadata.add_tags(tags);
+
+        metadata
+    }
+
+    /// Add the given tags (skipping tags that were already added), and read the tempo
+    /// and key from the tags if they are not known yet.
+    fn add_tags(&mut self, tags: Vec<PcmTag>) {
+        for tag in tags {
+            if !self.tags.contains(&tag) {
+                self.tags.push(tag);
+            }
+        }
+
+        if self.tempo_bpm.is_none() {
+            self.tempo_bpm = TEMPO_TAG_KEYS
+                .iter()
+                .filter_map(|key| self.tag(key))
+                .filter_map(|value| value.trim().parse::<f64>().ok())
+                .find(|bpm| *bpm > 0.0);
+        }
+
+        if self.key.is_none() {
+            self.key = KEY_TAG_KEYS
+                .iter()
+                .filter_map(|key| self.tag(key))
+                .map(|value| value.trim())
+                .find(|value| !value.is_empty())
+                .map(String::from);
+        }
+    }
+
+    /// Returns the value of the first tag with the given key (ignoring case).
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.iter().find(|tag| tag.key.eq_ignore_ascii_case(key)).map(|tag| tag.value.as_str())
+    }
+
+    /// Convert a position in frames at the sample rate of the file to seconds.
+    pub fn frame_to_seconds(&self, frame: u64) -> Seconds {
+        Seconds(frame as f64 / self.sample_rate.0)
+    }
+
+    /// The time of day the file was recorded at (the time since midnight), read from the
+    /// Broadcast Wave information. Clips can be placed at this time on the timeline to
+    /// line up recordings from different devices.
+    pub fn recording_time(&self) -> Option<Seconds> {
+        self.broadcast.as_ref().map(|broadcast| self.frame_to_seconds(broadcast.time_reference))
+    }
+}
+
+/// A text tag of an audio file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcmTag {
+    /// The key of the tag as it is stored in the file (i.e. "TITLE", "TPE1" or "INAM").
+    pub key: String,
+    pub value: String,
+}
+
+/// A loop read from the sampler chunk of a WAV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcmLoop {
+    /// The ID of the marker of this loop (see `PcmMarker`).
+    pub cue_id: u32,
+
+    pub loop_type: PcmLoopType,
+
+    /// The first frame of the loop.
+    pub start: u64,
+
+    /// The frame after the last frame of the loop.
+    pub end: u64,
+
+    /// The number of times the loop is played, or `None` if it is played forever.
+    pub play_count: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmLoopType {
+    Forward,
+    /// Plays forward and then backward.
+    PingPong,
+    Backward,
+}
+
+/// A marker read from the cue chunk of a WAV file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcmMarker {
+    pub id: u32,
+
+    /// The position of the marker.
+    pub frame: u64,
+
+    /// The length of the region starting at this marker, or `None` if this marker is not
+    /// a region.
+    pub len: Option<u64>,
+
+    pub label: Option<String>,
+    pub note: Option<String>,
+}
+
+/// The Broadcast Wave information (`bext` chunk) of a WAV file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BroadcastInfo {
+    pub description: String,
+    pub originator: String,
+    pub originator_reference: String,
+
+    /// The date of the recording in the format "yyyy-mm-dd".
+    pub origination_date: String,
+
+    /// The time of the recording in the format "hh:mm:ss".
+    pub origination_time: String,
+
+    /// The position of the start of the file as the number of frames since midnight.
+    pub time_reference: u64,
+}
+
+/// Returns the tags of the given metadata revision read by symphonia.
+pub(super) fn symphonia_tags(revision: &MetadataRevision) -> impl Iterator<Item = PcmTag> + '_ {
+    revision.tags().iter().map(|tag| PcmTag { key: tag.key.clone(), value: tag.value.to_string() })
+}
+
+/// Read the metadata chunks of a RIFF WAVE (or RF64) file into `metadata`. Nothing is
+/// read if the file is not a WAV file.
+fn read_riff_chunks<R: Read + Seek>(reader: &mut R, metadata: &mut PcmMetadata) -> io::Result<()> {
+    let mut header = [0u8; 12];
+    if reader.read_exact(&mut header).is_err()
+        || (&header[0..4] != b"RIFF" && &header[0..4] != b"RF64")
+        || &header[8..12] != b"WAVE"
+    {
+        return Ok(());
+    }
+
+    let mut cue_ids: Vec<u32> = Vec::new();
+    let mut rf64_data_size = None;
+
+    loop {
+        let chunk_start = reader.stream_position()?;
+
+        let mut chunk_header = [0u8; 8];
+        match reader.read_exact(&mut chunk_header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+
+        let id = [chunk_header[0], chunk_header[1], chunk_header[2], chunk_header[3]];
+        let mut size = u64::from(read_u32(&chunk_header, 4).unwrap_or(0));
+
+        // The size of the data chunk of RF64 files is stored in the `ds64` chunk.
+        if &id == b"data" && size == 0xFFFF_FFFF {
+            size = rf64_data_size.unwrap_or(size);
+        }
+
+        let is_metadata_chunk =
+            matches!(&id, b"ds64" | b"smpl" | b"cue " | b"acid" | b"bext" | b"LIST");
+
+        if is_metadata_chunk && size <= MAX_METADATA_CHUNK_BYTES {
+            let mut data = vec![0; size as usize];
+            match reader.read_exact(&mut data) {
+                Ok(()) => {}
+                // Truncated files are common, so read what is there.
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+
+            match &id {
+                b"ds64" => rf64_data_size = read_u64(&data, 8),
+                b"smpl" => parse_smpl(&data, metadata),
+                b"cue " => parse_cue(&data, metadata, &mut cue_ids),
+                b"acid" => parse_acid(&data, metadata),
+                b"bext" => metadata.broadcast = parse_bext(&data),
+                b"LIST" => parse_list(&data, metadata),
+                _ => {}
+            }
+        } else {
+            // The size of RF64 data chunks is 64 bits, so it may not fit.
+            let size = match i64::try_from(size) {
+                Ok(size) => size,
+                Err(_) => break,
+            };
+            reader.seek(SeekFrom::Current(size))?;
+        }
+
+        // Chunks are padded to an even size.
+        if size % 2 == 1 {
+            reader.seek(SeekFrom::Current(1))?;
+        }
+
+        // Don't read the same chunks again if the sizes in the file are broken.
+        if reader.stream_position()? <= chunk_start {
+            break;
+        }
+    }
+
+    // Only keep the labels of markers that exist.
+    metadata.markers.retain(|marker| cue_ids.contains(&marker.id));
+    metadata.markers.sort_by_key(|marker| marker.frame);
+
+    Ok(())
+}
+
+fn parse_smpl(data: &[u8], metadata: &mut PcmMetadata) {
+    let (unity_note, num_loops) = match (read_u32(data, 12), read_u32(data, 28)) {
+        (Some(unity_note), Some(num_loops)) => (unity_note, num_loops),
+        _ => return,
+    };
+
+    if metadata.root_note.is_none() && unity_note <= 127 {
+        metadata.root_note = Some(unity_note as u8);
+    }
+
+    for i in 0..num_loops as usize {
+        let offset = 36 + (i * 24);
+        let fields: Option<Vec<u32>> = (0..6).map(|j| read_u32(data, offset + (j * 4))).collect();
+        let fields = match fields {
+            Some(fields) => fields,
+            None => break,
+        };
+
+        let loop_type = match fields[1] {
+            1 => PcmLoopType::PingPong,
+            2 => PcmLoopType::Backward,
+            _ => PcmLoopType::Forward,
+        };
+
+        metadata.loops.push(PcmLoop {
+            cue_id: fields[0],
+            loop_type,
+            start: u64::from(fields[2]),
+            // The end is stored as the last frame of the loop.
+            end: u64::from(fields[3]) + 1,
+            play_count: if fields[5] == 0 { None } else { Some(fields[5]) },
+        });
+    }
+}
+
+fn parse_cue(data: &[u8], metadata: &mut PcmMetadata, cue_ids: &mut Vec<u32>) {
+    let num_points = read_u32(data, 0).unwrap_or(0);
+
+    for i in 0..num_points as usize {
+        let offset = 4 + (i * 24);
+        let (id, sample_offset) = match (read_u32(data, offset), read_u32(data, offset + 20)) {
+            (Some(id), Some(sample_offset)) => (id, sample_offset),
+            _ => break,
+        };
+
+        marker_mut(&mut metadata.markers, id).frame = u64::from(sample_offset);
+        cue_ids.push(id);
+    }
+}
+
+fn parse_acid(data: &[u8], metadata: &mut PcmMetadata) {
+    let flags = match read_u32(data, 0) {
+        Some(flags) => flags,
+        None => return,
+    };
+
+    metadata.is_one_shot = flags & 0x01 != 0;
+
+    if flags & 0x02 != 0 {
+        if let Some(root_note) = read_u16(data, 4) {
+            if root_note <= 127 {
+                metadata.root_note = Some(root_note as u8);
+            }
+        }
+    }
+
+    metadata.num_beats = read_u32(data, 12).filter(|num_beats| *num_beats > 0);
+
+    if let (Some(denominator), Some(numerator)) = (read_u16(data, 16), read_u16(data, 18)) {
+        if denominator > 0 && numerator > 0 {
+            metadata.time_signature = Some((numerator, denominator));
+        }
+    }
+
+    if let Some(tempo) = read_u32(data, 20).map(f32::from_bits) {
+        if tempo.is_finite() && tempo > 0.0 {
+            metadata.tempo_bpm = Some(f64::from(tempo));
+        }
+    }
+}
+
+fn parse_bext(data: &[u8]) -> Option<BroadcastInfo> {
+    let time_reference_low = read_u32(data, 338)?;
+    let time_reference_high = read_u32(data, 342)?;
+
+    Some(BroadcastInfo {
+        description: read_text(&data[0..256]),
+        originator: read_text(&data[256..288]),
+        originator_reference: read_text(&data[288..320]),
+        origination_date: read_text(&data[320..330]),
+        // Some applications use '-' instead of ':' as the separator.
+        origination_time: read_text(&data[330..338]).replace('-', ":"),
+        time_reference: (u64::from(time_reference_high) << 32) | u64::from(time_reference_low),
+    })
+}
+
+fn parse_list(data: &[u8], metadata: &mut PcmMetadata) {
+    if data.len() < 4 {
+        return;
+    }
+    let list_type = &data[0..4];
+
+    let mut offset = 4;
+    while let Some(size) = read_u32(data, offset + 4) {
+        let id = &data[offset..offset + 4];
+        let start = offset + 8;
+        let end = (start + size as usize).min(data.len());
+        let sub_data = &data[start..end];
+
+        match (list_type, id) {
+            (b"INFO", _) => {
+                let value = read_text(sub_data);
+                if !value.is_empty() {
+                    metadata
+                        .tags
+                        .push(PcmTag { key: String::from_utf8_lossy(id).into_owned(), value });
+                }
+            }
+            (b"adtl", b"labl") | (b"adtl", b"note") if sub_data.len() >= 4 => {
+                let text = read_text(&sub_data[4..]);
+                if let Some(cue_id) = read_u32(sub_data, 0) {
+                    let marker = marker_mut(&mut metadata.markers, cue_id);
+                    if id == b"labl" {
+                        marker.label = Some(text);
+                    } else {
+                        marker.note = Some(text);
+                    }
+                }
+            }
+            (b"adtl", b"ltxt") => {
+                if let (Some(cue_id), Some(len)) = (read_u32(sub_data, 0), read_u32(sub_data, 4)) {
+                    marker_mut(&mut metadata.markers, cue_id).len = Some(u64::from(len));
+                }
+            }
+            _ => {}
+        }
+
+        // Sub-chunks are padded to an even size.
+        offset = start + size as usize + (size as usize % 2);
+    }
+}
+
+/// Returns the marker with the given ID, adding it if it doesn't exist yet.
+fn marker_mut(markers: &mut Vec<PcmMarker>, id: u32) -> &mut PcmMarker {
+    let index = match markers.iter().position(|marker| marker.id == id) {
+        Some(index) => index,
+        None => {
+            markers.push(PcmMarker { id, frame: 0, len: None, label: None, note: None });
+            markers.len() - 1
+        }
+    };
+
+    &mut markers[index]
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let low = read_u32(data, offset)?;
+    let high = read_u32(data, offset + 4)?;
+    Some((u64::from(high) << 32) | u64::from(low))
+}
+
+/// Read a string that is terminated by a NUL character or the end of the data.
+fn read_text(data: &[u8]) -> String {
+    let len = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[0..len]).trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_wav::{chunk, wav};
+    use super::*;
+    use std::io::Cursor;
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+    }
+
+    fn read(data: Vec<u8>) -> PcmMetadata {
+        let mut metadata = PcmMetadata::new(SampleRate(48_000.0));
+        read_riff_chunks(&mut Cursor::new(data), &mut metadata).unwrap();
+        metadata
+    }
+
+    #[test]
+    fn reads_loops_markers_and_acid() {
+        let mut smpl = u32s(&[0, 0, 20833, 62, 0, 0, 0, 1, 0]);
+        smpl.extend(u32s(&[7, 1, 1000, 1999, 0, 0]));
+
+        let mut cue = u32s(&[2]);
+        cue.extend(u32s(&[7, 0]));
+        cue.extend(b"data");
+        cue.extend(u32s(&[0, 0, 1000]));
+        cue.extend(u32s(&[3, 0]));
+        cue.extend(b"data");
+        cue.extend(u32s(&[0, 0, 500]));
+
+        let mut adtl = b"adtl".to_vec();
+        let mut labl = u32s(&[3]);
+        labl.extend(b"Verse\0");
+        adtl.extend(chunk(b"labl", &labl));
+        adtl.extend(chunk(b"ltxt", &u32s(&[7, 1000, 0, 0, 0])));
+
+        let mut acid = u32s(&[0x02, 60, 0, 8]);
+        acid.extend(&4u16.to_le_bytes());
+        acid.extend(&3u16.to_le_bytes());
+        acid.extend(&128.0f32.to_bits().to_le_bytes());
+
+        let metadata = read(wav(&[
+            // The label comes before the cue chunk.
+            chunk(b"LIST", &adtl),
+            chunk(b"fmt ", &[0; 16]),
+            chunk(b"data", &[0; 11]),
+            chunk(b"smpl", &smpl),
+            chunk(b"cue ", &cue),
+            chunk(b"acid", &acid),
+        ]));
+
+        assert_eq!(
+            metadata.loops,
+            vec![PcmLoop {
+                cue_id: 7,
+                loop_type: PcmLoopType::PingPong,
+                start: 1000,
+                end: 2000,
+                play_count: None,
+            }]
+        );
+        assert_eq!(
+            metadata.markers,
+            vec![
+                PcmMarker { id: 3, frame: 500, len: None, label: Some("Verse".into()), note: None },
+                PcmMarker { id: 7, frame: 1000, len: Some(1000), label: None, note: None },
+            ]
+        );
+
+        // The root note of the ACID chunk is used over the sampler chunk.
+        assert_eq!(metadata.root_note, Some(60));
+        assert_eq!(metadata.tempo_bpm, Some(128.0));
+        assert_eq!(metadata.num_beats, Some(8));
+        assert_eq!(metadata.time_signature, Some((3, 4)));
+        assert!(!metadata.is_one_shot);
+    }
+
+    #[test]
+    fn reads_bext_and_info() {
+        let mut bext = vec![0u8; 602];
+        bext[0..5].copy_from_slice(b"Scene");
+        bext[320..330].copy_from_slice(b"2021-06-01");
+        bext[330..338].copy_from_slice(b"13-30-00");
+        // 13:30:00 at 48kHz.
+        bext[338..342].copy_from_slice(&2_332_800_000u32.to_le_bytes());
+
+        let mut info = b"INFO".to_vec();
+        info.extend(chunk(b"INAM", b"Kick\0"));
+        info.extend(chunk(b"IBPM", b"96\0"));
+
+        let mut metadata = read(wav(&[chunk(b"bext", &bext), chunk(b"LIST", &info)]));
+
+        let broadcast = metadata.broadcast.clone().unwrap();
+        assert_eq!(broadcast.description, "Scene");
+        assert_eq!(broadcast.origination_date, "2021-06-01");
+        assert_eq!(broadcast.origination_time, "13:30:00");
+        assert_eq!(metadata.recording_time(), Some(Seconds(13.5 * 60.0 * 60.0)));
+
+        assert_eq!(metadata.tag("inam"), Some("Kick"));
+
+        // The tempo is read from the tags when there is no ACID chunk.
+        metadata.add_tags(vec![PcmTag { key: "INAM".into(), value: "Kick".into() }]);
+        assert_eq!(metadata.tempo_bpm, Some(96.0));
+        assert_eq!(metadata.tags.len(), 2);
+    }
+
+    #[test]
+    fn stops_at_broken_rf64_data_size() {
+        // A data size that is negative as an `i64` would seek back to the data chunk.
+        let mut ds64 = 0u64.to_le_bytes().to_vec();
+        ds64.extend(&0xFFFF_FFFF_FFFF_FFF8u64.to_le_bytes());
+        ds64.extend(&0u64.to_le_bytes());
+        ds64.extend(u32s(&[0]));
+
+        let mut data = b"data".to_vec();
+        data.extend(u32s(&[0xFFFF_FFFF, 0, 0]));
+
+        let mut rf64 =
+            wav(&[chunk(b"ds64", &ds64), chunk(b"acid", &u32s(&[0, 0, 0, 8, 0, 0])), data]);
+        rf64[0..4].copy_from_slice(b"RF64");
+
+        // The chunks before the data chunk are still read.
+        let metadata = read(rf64);
+        assert_eq!(metadata.num_beats, Some(8));
+    }
+
+    #[test]
+    fn ignores_other_files() {
+        let metadata = read(b"fLaC\0\0\0\0\0\0\0\0\0\0\0\0".to_vec());
+
+        assert_eq!(metadata, PcmMetadata::new(SampleRate(48_000.0)));
+    }
+}
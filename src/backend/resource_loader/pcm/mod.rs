@@ -1,61 +1,396 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
 static U24_TO_F32_RATIO: f32 = 2.0 / 0x00FFFFFF as f32;
 static I16_TO_F32_RATIO: f32 = 1.0 / std::i16::MAX as f32;
 static U8_TO_F32_RATIO: f32 = 2.0 / std::u8::MAX as f32;
 
+pub mod channel_layout;
 pub mod loader;
+pub mod metadata;
+pub mod stream;
+
+#[cfg(test)]
+mod test_wav;
 
-pub use loader::{PcmLoadError, PcmLoader};
+pub use channel_layout::{ChannelLayout, ChannelPosition};
+pub use loader::{PcmDecoder, PcmFileInfo, PcmFileKey, PcmLoadError, PcmLoader};
+pub use metadata::{BroadcastInfo, PcmLoop, PcmLoopType, PcmMarker, PcmMetadata, PcmTag};
 use rusty_daw_core::{SampleRate, SampleTime, Seconds};
+pub use stream::{PcmStream, PcmStreamInfo};
 
+/// A PCM resource.
+///
+/// Resources are kept in the original bit depth of the file where possible to save
+/// memory. The samples are converted to `f32` on the fly when they are read (see
+/// `PcmChannel`). Resources that have been processed (resampled, offline effects, etc.)
+/// are always stored as `f32`.
 #[non_exhaustive]
 #[derive(Debug)]
 pub enum AnyPcm {
     Mono(MonoPcm),
     Stereo(StereoPcm),
+    Multi(MultiPcm),
+
+    MonoI16(MonoPcm<i16>),
+    StereoI16(StereoPcm<i16>),
+    MultiI16(MultiPcm<i16>),
+
+    MonoU24(MonoPcm<U24>),
+    StereoU24(StereoPcm<U24>),
+    MultiU24(MultiPcm<U24>),
+
+    MonoU8(MonoPcm<u8>),
+    StereoU8(StereoPcm<u8>),
+    MultiU8(MultiPcm<u8>),
+}
+
+macro_rules! any_pcm_dispatch {
+    ($any_pcm:expr, $pcm:ident => $e:expr) => {
+        match $any_pcm {
+            AnyPcm::Mono($pcm) => $e,
+            AnyPcm::Stereo($pcm) => $e,
+            AnyPcm::Multi($pcm) => $e,
+            AnyPcm::MonoI16($pcm) => $e,
+            AnyPcm::StereoI16($pcm) => $e,
+            AnyPcm::MultiI16($pcm) => $e,
+            AnyPcm::MonoU24($pcm) => $e,
+            AnyPcm::StereoU24($pcm) => $e,
+            AnyPcm::MultiU24($pcm) => $e,
+            AnyPcm::MonoU8($pcm) => $e,
+            AnyPcm::StereoU8($pcm) => $e,
+            AnyPcm::MultiU8($pcm) => $e,
+        }
+    };
 }
 
 impl AnyPcm {
     pub fn sample_rate(&self) -> SampleRate {
+        any_pcm_dispatch!(self, pcm => pcm.sample_rate())
+    }
+
+    pub fn len(&self) -> usize {
+        any_pcm_dispatch!(self, pcm => pcm.len())
+    }
+
+    pub fn len_seconds(&self) -> Seconds {
+        any_pcm_dispatch!(self, pcm => pcm.len_seconds())
+    }
+
+    pub fn num_channels(&self) -> usize {
+        any_pcm_dispatch!(self, pcm => pcm.num_channels())
+    }
+
+    /// The memory used by the samples in bytes.
+    pub fn size_bytes(&self) -> u64 {
+        any_pcm_dispatch!(self, pcm => pcm.size_bytes())
+    }
+
+    /// Returns the channel at `index`.
+    ///
+    /// This will panic if `index` is out of bounds.
+    pub fn channel(&self, index: usize) -> PcmChannel<'_> {
+        any_pcm_dispatch!(self, pcm => pcm.channel(index))
+    }
+
+    /// The layout of the channels. This is only `Some` for resources with more than
+    /// two channels.
+    pub fn layout(&self) -> Option<&ChannelLayout> {
         match self {
-            AnyPcm::Mono(pcm) => pcm.sample_rate(),
-            AnyPcm::Stereo(pcm) => pcm.sample_rate(),
+            AnyPcm::Multi(pcm) => Some(pcm.layout()),
+            AnyPcm::MultiI16(pcm) => Some(pcm.layout()),
+            AnyPcm::MultiU24(pcm) => Some(pcm.layout()),
+            AnyPcm::MultiU8(pcm) => Some(pcm.layout()),
+            _ => None,
         }
     }
 
+    /// Create a PCM resource from the given channels. This creates a mono resource for
+    /// one channel, a stereo resource for two channels, and a multi-channel resource for
+    /// more channels.
+    ///
+    /// `layout` - The layout of the channels when there are more than two channels. If
+    /// this is `None`, then the positions of the channels are unknown.
+    pub fn from_channels<T: PcmSample>(
+        mut channels: Vec<Vec<T>>,
+        layout: Option<ChannelLayout>,
+        sample_rate: SampleRate,
+    ) -> Self {
+        match channels.len() {
+            0 => T::into_mono(MonoPcm::new(Vec::new(), sample_rate)),
+            1 => T::into_mono(MonoPcm::new(channels.pop().unwrap(), sample_rate)),
+            2 => {
+                let right = channels.pop().unwrap();
+                let left = channels.pop().unwrap();
+
+                T::into_stereo(StereoPcm::new(left, right, sample_rate))
+            }
+            n => {
+                let layout = layout
+                    .filter(|layout| layout.num_channels() == n)
+                    .unwrap_or_else(|| ChannelLayout::unknown(n));
+
+                T::into_multi(MultiPcm::new(channels, layout, sample_rate))
+            }
+        }
+    }
+}
+
+/// A sample format that PCM resources can be stored in.
+pub trait PcmSample: Copy + Send + Sync + 'static {
+    fn to_f32(self) -> f32;
+
+    fn from_f32(smp: f32) -> Self;
+
+    /// Convert a sample that uses the full range of an `i32`.
+    fn from_i32(smp: i32) -> Self;
+
+    #[doc(hidden)]
+    fn channel(data: &[Self]) -> PcmChannel<'_>;
+
+    #[doc(hidden)]
+    fn into_mono(pcm: MonoPcm<Self>) -> AnyPcm;
+    #[doc(hidden)]
+    fn into_stereo(pcm: StereoPcm<Self>) -> AnyPcm;
+    #[doc(hidden)]
+    fn into_multi(pcm: MultiPcm<Self>) -> AnyPcm;
+}
+
+impl PcmSample for f32 {
+    #[inline]
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    #[inline]
+    fn from_f32(smp: f32) -> Self {
+        smp
+    }
+
+    #[inline]
+    fn from_i32(smp: i32) -> Self {
+        smp as f32 / std::i32::MAX as f32
+    }
+
+    fn channel(data: &[Self]) -> PcmChannel<'_> {
+        PcmChannel::F32(data)
+    }
+
+    fn into_mono(pcm: MonoPcm<Self>) -> AnyPcm {
+        AnyPcm::Mono(pcm)
+    }
+    fn into_stereo(pcm: StereoPcm<Self>) -> AnyPcm {
+        AnyPcm::Stereo(pcm)
+    }
+    fn into_multi(pcm: MultiPcm<Self>) -> AnyPcm {
+        AnyPcm::Multi(pcm)
+    }
+}
+
+impl PcmSample for i16 {
+    #[inline]
+    fn to_f32(self) -> f32 {
+        self as f32 * I16_TO_F32_RATIO
+    }
+
+    #[inline]
+    fn from_f32(smp: f32) -> Self {
+        (smp.clamp(-1.0, 1.0) * std::i16::MAX as f32).round() as i16
+    }
+
+    #[inline]
+    fn from_i32(smp: i32) -> Self {
+        (smp >> 16) as i16
+    }
+
+    fn channel(data: &[Self]) -> PcmChannel<'_> {
+        PcmChannel::I16(data)
+    }
+
+    fn into_mono(pcm: MonoPcm<Self>) -> AnyPcm {
+        AnyPcm::MonoI16(pcm)
+    }
+    fn into_stereo(pcm: StereoPcm<Self>) -> AnyPcm {
+        AnyPcm::StereoI16(pcm)
+    }
+    fn into_multi(pcm: MultiPcm<Self>) -> AnyPcm {
+        AnyPcm::MultiI16(pcm)
+    }
+}
+
+/// A 24 bit sample stored as an unsigned (offset binary) little-endian integer, where
+/// `0x800000` is silence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct U24(pub [u8; 3]);
+
+impl U24 {
+    #[inline]
+    fn to_u32(self) -> u32 {
+        u32::from(self.0[0]) | (u32::from(self.0[1]) << 8) | (u32::from(self.0[2]) << 16)
+    }
+
+    #[inline]
+    fn from_u32(val: u32) -> Self {
+        Self([val as u8, (val >> 8) as u8, (val >> 16) as u8])
+    }
+}
+
+impl PcmSample for U24 {
+    #[inline]
+    fn to_f32(self) -> f32 {
+        (self.to_u32() as f32 * U24_TO_F32_RATIO) - 1.0
+    }
+
+    #[inline]
+    fn from_f32(smp: f32) -> Self {
+        Self::from_u32(((smp.clamp(-1.0, 1.0) + 1.0) / U24_TO_F32_RATIO).round() as u32)
+    }
+
+    #[inline]
+    fn from_i32(smp: i32) -> Self {
+        Self::from_u32(((smp >> 8) + 0x800000) as u32)
+    }
+
+    fn channel(data: &[Self]) -> PcmChannel<'_> {
+        PcmChannel::U24(data)
+    }
+
+    fn into_mono(pcm: MonoPcm<Self>) -> AnyPcm {
+        AnyPcm::MonoU24(pcm)
+    }
+    fn into_stereo(pcm: StereoPcm<Self>) -> AnyPcm {
+        AnyPcm::StereoU24(pcm)
+    }
+    fn into_multi(pcm: MultiPcm<Self>) -> AnyPcm {
+        AnyPcm::MultiU24(pcm)
+    }
+}
+
+impl PcmSample for u8 {
+    #[inline]
+    fn to_f32(self) -> f32 {
+        (self as f32 * U8_TO_F32_RATIO) - 1.0
+    }
+
+    #[inline]
+    fn from_f32(smp: f32) -> Self {
+        ((smp.clamp(-1.0, 1.0) + 1.0) / U8_TO_F32_RATIO).round() as u8
+    }
+
+    #[inline]
+    fn from_i32(smp: i32) -> Self {
+        ((smp >> 24) + 128) as u8
+    }
+
+    fn channel(data: &[Self]) -> PcmChannel<'_> {
+        PcmChannel::U8(data)
+    }
+
+    fn into_mono(pcm: MonoPcm<Self>) -> AnyPcm {
+        AnyPcm::MonoU8(pcm)
+    }
+    fn into_stereo(pcm: StereoPcm<Self>) -> AnyPcm {
+        AnyPcm::StereoU8(pcm)
+    }
+    fn into_multi(pcm: MultiPcm<Self>) -> AnyPcm {
+        AnyPcm::MultiU8(pcm)
+    }
+}
+
+/// A single channel of a PCM resource in the format it is stored in.
+#[derive(Debug, Clone, Copy)]
+pub enum PcmChannel<'a> {
+    F32(&'a [f32]),
+    I16(&'a [i16]),
+    U24(&'a [U24]),
+    U8(&'a [u8]),
+}
+
+impl<'a> PcmChannel<'a> {
     pub fn len(&self) -> usize {
         match self {
-            AnyPcm::Mono(pcm) => pcm.len(),
-            AnyPcm::Stereo(pcm) => pcm.len(),
+            PcmChannel::F32(data) => data.len(),
+            PcmChannel::I16(data) => data.len(),
+            PcmChannel::U24(data) => data.len(),
+            PcmChannel::U8(data) => data.len(),
         }
     }
 
-    pub fn len_seconds(&self) -> Seconds {
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the frames in the given range.
+    pub fn slice(&self, range: Range<usize>) -> PcmChannel<'a> {
+        match self {
+            PcmChannel::F32(data) => PcmChannel::F32(&data[range]),
+            PcmChannel::I16(data) => PcmChannel::I16(&data[range]),
+            PcmChannel::U24(data) => PcmChannel::U24(&data[range]),
+            PcmChannel::U8(data) => PcmChannel::U8(&data[range]),
+        }
+    }
+
+    /// Convert the frames starting at `start` to `f32`, and write them into `dst`.
+    ///
+    /// This is realtime safe.
+    pub fn read(&self, start: usize, dst: &mut [f32]) {
+        fn read<T: PcmSample>(src: &[T], dst: &mut [f32]) {
+            for (dst_smp, src_smp) in dst.iter_mut().zip(src.iter()) {
+                *dst_smp = src_smp.to_f32();
+            }
+        }
+
+        let end = start + dst.len();
+        match self {
+            PcmChannel::F32(data) => dst.copy_from_slice(&data[start..end]),
+            PcmChannel::I16(data) => read(&data[start..end], dst),
+            PcmChannel::U24(data) => read(&data[start..end], dst),
+            PcmChannel::U8(data) => read(&data[start..end], dst),
+        }
+    }
+
+    /// Returns the frames of this channel as `f32`. This only allocates if the channel
+    /// is not stored as `f32`.
+    pub fn to_f32(&self) -> Cow<'a, [f32]> {
         match self {
-            AnyPcm::Mono(pcm) => pcm.len_seconds(),
-            AnyPcm::Stereo(pcm) => pcm.len_seconds(),
+            PcmChannel::F32(data) => Cow::Borrowed(data),
+            PcmChannel::I16(data) => Cow::Owned(data.iter().map(|smp| smp.to_f32()).collect()),
+            PcmChannel::U24(data) => Cow::Owned(data.iter().map(|smp| smp.to_f32()).collect()),
+            PcmChannel::U8(data) => Cow::Owned(data.iter().map(|smp| smp.to_f32()).collect()),
         }
     }
 }
 
 #[derive(Debug)]
-pub struct MonoPcm {
-    data: Vec<f32>,
+pub struct MonoPcm<T: PcmSample = f32> {
+    data: Vec<T>,
     sample_rate: SampleRate,
     len_secs: Seconds,
 }
 
-impl MonoPcm {
-    pub fn new(data: Vec<f32>, sample_rate: SampleRate) -> Self {
+impl<T: PcmSample> MonoPcm<T> {
+    pub fn new(data: Vec<T>, sample_rate: SampleRate) -> Self {
         let len_secs = SampleTime(data.len() as i64).to_seconds(sample_rate);
 
         Self { data, sample_rate, len_secs }
     }
 
     #[inline]
-    pub fn data(&self) -> &[f32] {
+    pub fn data(&self) -> &[T] {
         &self.data
     }
 
+    #[inline]
+    pub fn channel(&self, index: usize) -> PcmChannel<'_> {
+        assert_eq!(index, 0);
+        T::channel(&self.data)
+    }
+
+    #[inline]
+    pub fn num_channels(&self) -> usize {
+        1
+    }
+
     #[inline]
     pub fn sample_rate(&self) -> SampleRate {
         self.sample_rate
@@ -70,19 +405,24 @@ impl MonoPcm {
     pub fn len_seconds(&self) -> Seconds {
         self.len_secs
     }
+
+    /// The memory used by the samples in bytes.
+    pub fn size_bytes(&self) -> u64 {
+        (self.len() * self.num_channels() * std::mem::size_of::<T>()) as u64
+    }
 }
 
 #[derive(Debug)]
-pub struct StereoPcm {
-    left: Vec<f32>,
-    right: Vec<f32>,
+pub struct StereoPcm<T: PcmSample = f32> {
+    left: Vec<T>,
+    right: Vec<T>,
 
     sample_rate: SampleRate,
     len_secs: Seconds,
 }
 
-impl StereoPcm {
-    pub fn new(left: Vec<f32>, right: Vec<f32>, sample_rate: SampleRate) -> Self {
+impl<T: PcmSample> StereoPcm<T> {
+    pub fn new(left: Vec<T>, right: Vec<T>, sample_rate: SampleRate) -> Self {
         assert_eq!(left.len(), right.len());
 
         let len_secs = SampleTime(left.len() as i64).to_seconds(sample_rate);
@@ -91,20 +431,34 @@ impl StereoPcm {
     }
 
     #[inline]
-    pub fn left(&self) -> &[f32] {
+    pub fn left(&self) -> &[T] {
         &self.left
     }
 
     #[inline]
-    pub fn right(&self) -> &[f32] {
+    pub fn right(&self) -> &[T] {
         &self.right
     }
 
     #[inline]
-    pub fn left_right(&self) -> (&[f32], &[f32]) {
+    pub fn left_right(&self) -> (&[T], &[T]) {
         (&self.left, &self.right)
     }
 
+    #[inline]
+    pub fn channel(&self, index: usize) -> PcmChannel<'_> {
+        match index {
+            0 => T::channel(&self.left),
+            1 => T::channel(&self.right),
+            _ => panic!("channel index out of bounds"),
+        }
+    }
+
+    #[inline]
+    pub fn num_channels(&self) -> usize {
+        2
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.left.len()
@@ -119,4 +473,144 @@ impl StereoPcm {
     pub fn len_seconds(&self) -> Seconds {
         self.len_secs
     }
+
+    /// The memory used by the samples in bytes.
+    pub fn size_bytes(&self) -> u64 {
+        (self.len() * self.num_channels() * std::mem::size_of::<T>()) as u64
+    }
+}
+
+/// A PCM resource with more than two channels (surround, ambisonic, multi-mic recordings,
+/// etc).
+#[derive(Debug)]
+pub struct MultiPcm<T: PcmSample = f32> {
+    channels: Vec<Vec<T>>,
+    layout: ChannelLayout,
+
+    sample_rate: SampleRate,
+    len_secs: Seconds,
+}
+
+impl<T: PcmSample> MultiPcm<T> {
+    pub fn new(channels: Vec<Vec<T>>, layout: ChannelLayout, sample_rate: SampleRate) -> Self {
+        assert!(!channels.is_empty());
+        assert_eq!(channels.len(), layout.num_channels());
+        for channel in channels.iter().skip(1) {
+            assert_eq!(channel.len(), channels[0].len());
+        }
+
+        let len_secs = SampleTime(channels[0].len() as i64).to_seconds(sample_rate);
+
+        Self { channels, layout, sample_rate, len_secs }
+    }
+
+    #[inline]
+    pub fn channel(&self, index: usize) -> PcmChannel<'_> {
+        T::channel(&self.channels[index])
+    }
+
+    #[inline]
+    pub fn channels(&self) -> &[Vec<T>] {
+        &self.channels
+    }
+
+    #[inline]
+    pub fn num_channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    #[inline]
+    pub fn layout(&self) -> &ChannelLayout {
+        &self.layout
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.channels[0].len()
+    }
+
+    #[inline]
+    pub fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    #[inline]
+    pub fn len_seconds(&self) -> Seconds {
+        self.len_secs
+    }
+
+    /// The memory used by the samples in bytes.
+    pub fn size_bytes(&self) -> u64 {
+        (self.len() * self.num_channels() * std::mem::size_of::<T>()) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_conversion() {
+        for &smp in [-1.0f32, -0.5, 0.0, 0.25, 1.0].iter() {
+            assert!((i16::from_f32(smp).to_f32() - smp).abs() < 1.0 / 32_000.0);
+            assert!((U24::from_f32(smp).to_f32() - smp).abs() < 1.0 / 8_000_000.0);
+            assert!((u8::from_f32(smp).to_f32() - smp).abs() < 1.0 / 100.0);
+        }
+
+        // Samples decoded as the full range of an `i32`.
+        assert_eq!(i16::from_i32(std::i32::MAX), std::i16::MAX);
+        assert_eq!(i16::from_i32(0), 0);
+        assert_eq!(U24::from_i32(0).to_u32(), 0x800000);
+        assert_eq!(U24::from_i32(std::i32::MIN).to_u32(), 0);
+        assert_eq!(u8::from_i32(0), 128);
+        assert_eq!(u8::from_i32(std::i32::MAX), 255);
+    }
+
+    #[test]
+    fn read_channel() {
+        let pcm = AnyPcm::from_channels(
+            vec![vec![0i16, 16384, -16384], vec![0i16, 0, 32767]],
+            None,
+            SampleRate(44100.0),
+        );
+        assert!(matches!(pcm, AnyPcm::StereoI16(_)));
+
+        let mut dst = [0.0f32; 2];
+        pcm.channel(0).read(1, &mut dst);
+        assert!((dst[0] - 0.5).abs() < 0.001);
+        assert!((dst[1] + 0.5).abs() < 0.001);
+
+        assert_eq!(pcm.channel(1).slice(2..3).to_f32()[0], 1.0);
+    }
+
+    #[test]
+    fn pcm_from_channels() {
+        let sample_rate = SampleRate(48_000.0);
+
+        let pcm = AnyPcm::from_channels(Vec::<Vec<f32>>::new(), None, sample_rate);
+        assert!(matches!(pcm, AnyPcm::Mono(_)));
+        assert_eq!(pcm.len(), 0);
+
+        let pcm = AnyPcm::from_channels(vec![vec![0u8; 4]], None, sample_rate);
+        assert!(matches!(pcm, AnyPcm::MonoU8(_)));
+        assert!(pcm.layout().is_none());
+
+        let layout = ChannelLayout::new(vec![
+            ChannelPosition::FrontLeft,
+            ChannelPosition::FrontRight,
+            ChannelPosition::FrontCentre,
+        ]);
+        let pcm =
+            AnyPcm::from_channels(vec![vec![0.0f32; 4]; 3], Some(layout.clone()), sample_rate);
+        assert!(matches!(pcm, AnyPcm::Multi(_)));
+        assert_eq!(pcm.num_channels(), 3);
+        assert_eq!(pcm.layout(), Some(&layout));
+
+        // A layout that doesn't match the number of channels is replaced with an unknown
+        // layout.
+        let pcm =
+            AnyPcm::from_channels(vec![vec![U24::from_f32(0.0); 4]; 4], Some(layout), sample_rate);
+        assert!(matches!(pcm, AnyPcm::MultiU24(_)));
+        assert_eq!(pcm.layout(), Some(&ChannelLayout::unknown(4)));
+    }
 }
@@ -0,0 +1,862 @@
+// Streaming of audio files from disk.
+//
+// The realtime side (`PcmStream`) owns a fixed number of preallocated blocks. Empty blocks
+// are sent to the stream server thread to be filled with decoded frames, and are sent back
+// once they are filled. This way the realtime thread never allocates, deallocates, or
+// waits on a lock.
+
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, Thread};
+
+use ringbuf::{Consumer, Producer, RingBuffer};
+use rusty_daw_core::SampleRate;
+use symphonia::core::codecs::{CodecParameters, CodecRegistry, Decoder, DecoderOptions};
+use symphonia::core::formats::{FormatReader, SeekMode, SeekTo};
+
+use super::loader::{append_decoded, channel_layout, OpenedFile, PcmLoadError};
+use super::ChannelLayout;
+use crate::backend::dsp::resample::{self, DeipResampler, InterpolationQuality, SincResampler};
+
+/// The number of frames in each block of a stream.
+pub static STREAM_BLOCK_FRAMES: usize = 16_384;
+
+/// The number of blocks after the playhead that are kept decoded.
+pub static STREAM_PREFETCH_BLOCKS: usize = 8;
+
+/// The number of blocks at the start of the transport loop that are kept decoded, so
+/// the stream does not underrun when the transport loops back.
+pub static STREAM_LOOP_BLOCKS: usize = 2;
+
+/// Extra blocks, so recently played blocks are not immediately replaced (for example
+/// when the transport crossfades out of a loop).
+static STREAM_SPARE_BLOCKS: usize = 2;
+
+/// Decoding up to this many frames to get to a new position is preferred over seeking.
+static STREAM_SEEK_THRESHOLD_FRAMES: u64 = 32_768;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PcmStreamInfo {
+    pub num_channels: usize,
+
+    /// The length of the stream in frames (at `sample_rate`).
+    pub len: usize,
+
+    /// The sample rate the stream is resampled to.
+    pub sample_rate: SampleRate,
+}
+
+/// An audio file streamed from disk.
+///
+/// Frames are decoded ahead of time on a separate thread. If the requested frames have
+/// not been decoded yet (for example right after seeking), then silence is returned
+/// instead and an underrun is reported.
+pub struct PcmStream {
+    info: PcmStreamInfo,
+
+    /// The layout of the channels of streams with more than two channels.
+    layout: Option<ChannelLayout>,
+
+    slots: Vec<StreamSlot>,
+
+    /// Empty blocks sent to the stream server to be filled.
+    requests: Producer<Box<StreamBlock>>,
+    /// Blocks filled by the stream server.
+    filled: Consumer<Box<StreamBlock>>,
+
+    /// The blocks that should be decoded, in order of priority. This is allocated
+    /// up front so prefetching never allocates.
+    wanted: Vec<usize>,
+
+    /// The loop of the audio clip (start, end) in frames. Prefetching continues at the
+    /// start of the loop once it reaches the end.
+    loop_range: Option<(usize, usize)>,
+
+    /// A frame that is kept decoded (the start of the transport loop).
+    pinned_frame: Option<usize>,
+
+    /// The stream server thread, which is woken up when blocks are requested.
+    server: Thread,
+
+    /// Incremented on every read, used to find the least recently used block.
+    clock: u64,
+
+    underruns: Arc<AtomicUsize>,
+    closed: Arc<AtomicBool>,
+}
+
+impl PcmStream {
+    pub fn info(&self) -> PcmStreamInfo {
+        self.info
+    }
+
+    /// The layout of the channels. This is only `Some` for streams with more than two
+    /// channels, which are downmixed or selected with `read_channel()`.
+    pub fn layout(&self) -> Option<&ChannelLayout> {
+        self.layout.as_ref()
+    }
+
+    /// Returns the number of times the requested frames were not decoded in time.
+    pub fn underruns(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.underruns)
+    }
+
+    /// Set the loop of the audio clip (start, end) in frames, so the start of the loop is
+    /// prefetched before the loop ends.
+    pub fn set_loop(&mut self, loop_range: Option<(usize, usize)>) {
+        self.loop_range = loop_range;
+    }
+
+    /// Keep the frames starting at the given frame decoded. This is used for the start
+    /// of the transport loop.
+    pub fn set_pinned_frame(&mut self, frame: Option<usize>) {
+        self.pinned_frame = frame;
+    }
+
+    /// Read frames starting at `frame` into `left` and `right`. Only `left` is used for
+    /// mono streams. Use `read_channel()` for streams with more than two channels.
+    ///
+    /// This is realtime safe.
+    ///
+    /// Returns `false` if some of the frames were not decoded in time (an underrun). These
+    /// frames are filled with silence.
+    pub fn read(&mut self, frame: usize, left: &mut [f32], right: &mut [f32]) -> bool {
+        let frames = left.len().min(right.len());
+
+        self.read_blocks(frame, frames, |block, offset, range| {
+            if let Some(block) = block {
+                block.copy_to(0, offset, &mut left[range.clone()]);
+                block.copy_to(1, offset, &mut right[range]);
+            } else {
+                silence(&mut left[range.clone()]);
+                silence(&mut right[range]);
+            }
+        })
+    }
+
+    /// Read the frames of the given channel starting at `frame` into `dst`. This is used
+    /// for streams with more than two channels (see `layout()`).
+    ///
+    /// This is realtime safe.
+    ///
+    /// Returns `false` if some of the frames were not decoded in time (an underrun). These
+    /// frames are filled with silence.
+    pub fn read_channel(&mut self, frame: usize, channel: usize, dst: &mut [f32]) -> bool {
+        self.read_blocks(frame, dst.len(), |block, offset, range| {
+            if let Some(block) = block {
+                block.copy_to(channel, offset, &mut dst[range]);
+            } else {
+                silence(&mut dst[range]);
+            }
+        })
+    }
+
+    /// Call `copy` with the block of every range of `frames` frames starting at `frame`,
+    /// the offset of the range in the block, and the range in the output. The block is
+    /// `None` if it was not decoded in time.
+    fn read_blocks<F: FnMut(Option<&StreamBlock>, usize, Range<usize>)>(
+        &mut self,
+        frame: usize,
+        frames: usize,
+        mut copy: F,
+    ) -> bool {
+        self.receive();
+
+        let mut complete = true;
+
+        let mut done = 0;
+        while done < frames {
+            let pos = frame + done;
+            let index = pos / STREAM_BLOCK_FRAMES;
+            let offset = pos % STREAM_BLOCK_FRAMES;
+            let block_frames = (STREAM_BLOCK_FRAMES - offset).min(frames - done);
+
+            let range = done..done + block_frames;
+
+            let clock = self.clock;
+            let slot = self
+                .slots
+                .iter_mut()
+                .find(|slot| slot.index == Some(index) && slot.block.is_some());
+
+            if let Some(slot) = slot {
+                slot.last_used = clock;
+                copy(slot.block.as_deref(), offset, range);
+            } else {
+                copy(None, offset, range);
+
+                // Frames past the end of the stream are always silent.
+                if pos < self.info.len {
+                    complete = false;
+                }
+            }
+
+            done += block_frames;
+        }
+
+        self.clock += 1;
+
+        if !complete {
+            self.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // Prefetch from the start of the read, so the blocks that were just read are
+        // requested too if they were missing (for example right after seeking to the end
+        // of the loop).
+        self.prefetch(frame);
+
+        complete
+    }
+
+    /// Request the blocks following `frame` (and the pinned blocks) from the stream
+    /// server, replacing the least recently used blocks.
+    ///
+    /// This is realtime safe (waking up the stream server never blocks).
+    pub fn prefetch(&mut self, frame: usize) {
+        self.receive();
+
+        let num_blocks = self.info.len.div_ceil(STREAM_BLOCK_FRAMES);
+
+        self.wanted.clear();
+
+        let mut index = frame / STREAM_BLOCK_FRAMES;
+        for _ in 0..STREAM_PREFETCH_BLOCKS {
+            if let Some((loop_start, loop_end)) = self.loop_range {
+                if index * STREAM_BLOCK_FRAMES >= loop_end {
+                    index = loop_start / STREAM_BLOCK_FRAMES;
+                }
+            }
+
+            if index >= num_blocks {
+                break;
+            }
+
+            if !self.wanted.contains(&index) {
+                self.wanted.push(index);
+            }
+
+            index += 1;
+        }
+
+        if let Some(pinned_frame) = self.pinned_frame {
+            let first = pinned_frame / STREAM_BLOCK_FRAMES;
+
+            for index in first..(first + STREAM_LOOP_BLOCKS).min(num_blocks) {
+                if !self.wanted.contains(&index) {
+                    self.wanted.push(index);
+                }
+            }
+        }
+
+        let mut requested = false;
+        for index in self.wanted.iter() {
+            if self.slots.iter().any(|slot| slot.index == Some(*index)) {
+                // The block is already decoded or requested.
+                continue;
+            }
+
+            // Replace the least recently used block that is not wanted. Blocks that are
+            // currently being filled by the server can't be replaced.
+            let wanted = &self.wanted;
+            let slot = self
+                .slots
+                .iter_mut()
+                .filter(|slot| {
+                    slot.block.is_some() && slot.index.map(|i| !wanted.contains(&i)).unwrap_or(true)
+                })
+                .min_by_key(|slot| (slot.index.is_some(), slot.last_used));
+
+            let slot = if let Some(slot) = slot {
+                slot
+            } else {
+                // All blocks are in use.
+                break;
+            };
+
+            let mut block = slot.block.take().unwrap();
+            block.index = *index;
+
+            // The ring buffer has room for every block, so this will never fail.
+            match self.requests.push(block) {
+                Ok(()) => {
+                    slot.index = Some(*index);
+                    slot.last_used = self.clock;
+                    requested = true;
+                }
+                Err(block) => {
+                    slot.block = Some(block);
+                    slot.index = None;
+                }
+            }
+        }
+
+        if requested {
+            self.server.unpark();
+        }
+    }
+
+    /// Move the blocks filled by the stream server into their slots.
+    fn receive(&mut self) {
+        while let Some(block) = self.filled.pop() {
+            // Every block sent to the server belongs to exactly one slot waiting for it.
+            if let Some(slot) = self
+                .slots
+                .iter_mut()
+                .find(|slot| slot.block.is_none() && slot.index == Some(block.index))
+            {
+                slot.block = Some(block);
+            }
+        }
+    }
+}
+
+impl Drop for PcmStream {
+    fn drop(&mut self) {
+        // Tell the stream server to stop decoding this stream.
+        self.closed.store(true, Ordering::Relaxed);
+        self.server.unpark();
+    }
+}
+
+struct StreamSlot {
+    /// This is `None` while the block is being filled by the stream server.
+    block: Option<Box<StreamBlock>>,
+
+    /// The index of the block that is (or will be) in this slot.
+    index: Option<usize>,
+
+    last_used: u64,
+}
+
+struct StreamBlock {
+    index: usize,
+
+    /// The number of valid frames in this block. This is less than `STREAM_BLOCK_FRAMES`
+    /// for the last block of the stream.
+    frames: usize,
+
+    channels: Vec<Vec<f32>>,
+}
+
+impl StreamBlock {
+    fn new(num_channels: usize) -> Self {
+        Self { index: 0, frames: 0, channels: vec![vec![0.0; STREAM_BLOCK_FRAMES]; num_channels] }
+    }
+
+    /// Copy the frames of the given channel starting at `offset` into `out`. Frames past
+    /// the end of the block are silent, and so are channels that don't exist.
+    fn copy_to(&self, channel: usize, offset: usize, out: &mut [f32]) {
+        let valid = match self.channels.get(channel) {
+            Some(channel) => {
+                let valid = self.frames.saturating_sub(offset).min(out.len());
+                out[0..valid].copy_from_slice(&channel[offset..offset + valid]);
+                valid
+            }
+            None => 0,
+        };
+
+        silence(&mut out[valid..]);
+    }
+}
+
+fn silence(out: &mut [f32]) {
+    for smp in out.iter_mut() {
+        *smp = 0.0;
+    }
+}
+
+/// The decoding side of a `PcmStream`, owned by the stream server thread.
+pub(super) struct StreamSource {
+    path: PathBuf,
+
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    codec_params: CodecParameters,
+    codec_registry: &'static CodecRegistry,
+    track_id: u32,
+
+    /// The sample rate of the file divided by the sample rate of the stream.
+    ratio: f64,
+
+    interpolation_quality: InterpolationQuality,
+    /// The filter table of the sinc interpolation, so it is not recomputed for every block.
+    sinc_resampler: Option<SincResampler>,
+    /// The oversampling filter of the polynomial interpolation, so it is not recomputed
+    /// for every block.
+    deip_resampler: Option<DeipResampler>,
+
+    /// The length of the stream in frames (at the sample rate of the stream).
+    len: usize,
+
+    /// Decoded frames of the file (at the sample rate of the file).
+    decoded: Vec<Vec<f32>>,
+    /// The frame in the file of the first decoded frame.
+    decoded_start: u64,
+
+    eof: bool,
+    failed: bool,
+
+    requests: Consumer<Box<StreamBlock>>,
+    filled: Producer<Box<StreamBlock>>,
+
+    closed: Arc<AtomicBool>,
+}
+
+impl StreamSource {
+    /// Fill the next requested block (if any).
+    ///
+    /// Returns `true` if a block was filled.
+    fn process(&mut self) -> bool {
+        if let Some(mut block) = self.requests.pop() {
+            self.fill(&mut block);
+
+            // The ring buffer has room for every block, so this will never fail.
+            let _ = self.filled.push(block);
+
+            true
+        } else {
+            false
+        }
+    }
+
+    fn fill(&mut self, block: &mut StreamBlock) {
+        let start = block.index * STREAM_BLOCK_FRAMES;
+        block.frames = STREAM_BLOCK_FRAMES.min(self.len.saturating_sub(start));
+
+        if block.frames == 0 {
+            return;
+        }
+
+        // The frames around the block are needed for interpolation.
+        let margin = self.interpolation_quality.margin_frames(1.0 / self.ratio) as u64;
+        let first = ((start as f64 * self.ratio).floor() as u64).saturating_sub(margin);
+        let last = ((start + block.frames) as f64 * self.ratio).ceil() as u64 + margin;
+
+        if !self.failed {
+            if let Err(e) = self.decode_range(first, last) {
+                log::error!("{}", e);
+
+                // Play silence from now on instead of flooding the log.
+                self.failed = true;
+            }
+        }
+
+        // The position of the start of the block in the decoded frames.
+        let src_start = (start as f64 * self.ratio) - self.decoded_start as f64;
+
+        for (channel, decoded) in block.channels.iter_mut().zip(self.decoded.iter()) {
+            if let Some(resampler) = &self.sinc_resampler {
+                let _ = resampler.process(
+                    decoded,
+                    src_start,
+                    1.0 / self.ratio,
+                    &mut channel[0..block.frames],
+                );
+            } else if let Some(resampler) = &mut self.deip_resampler {
+                resampler.process(decoded, src_start, &mut channel[0..block.frames]);
+            } else {
+                resample::resample_non_rt_into(
+                    decoded,
+                    src_start,
+                    1.0 / self.ratio,
+                    &mut channel[0..block.frames],
+                    self.interpolation_quality,
+                );
+            }
+        }
+    }
+
+    /// Make sure the frames `first..last` of the file are decoded.
+    fn decode_range(&mut self, first: u64, last: u64) -> Result<(), PcmLoadError> {
+        let decoded_end = self.decoded_start + self.decoded_len() as u64;
+
+        if first < self.decoded_start || first > decoded_end + STREAM_SEEK_THRESHOLD_FRAMES {
+            self.seek(first)?;
+        }
+
+        // Drop the frames that are no longer needed.
+        let consumed = (first.saturating_sub(self.decoded_start) as usize).min(self.decoded_len());
+        for channel in self.decoded.iter_mut() {
+            channel.drain(0..consumed);
+        }
+        self.decoded_start += consumed as u64;
+
+        while !self.eof && self.decoded_start + (self.decoded_len() as u64) < last {
+            let packet = if let Ok(packet) = self.format.next_packet() {
+                packet
+            } else {
+                self.eof = true;
+                break;
+            };
+
+            // If the packet does not belong to the selected track, skip over it.
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            match self.decoder.decode(&packet) {
+                Ok(decoded) => append_decoded(decoded, &mut self.decoded),
+                Err(symphonia::core::errors::Error::DecodeError(err)) => {
+                    // Decode errors are not fatal. Print the error message and try to decode
+                    // the next packet as usual.
+                    log::warn!("decode error: {}", err);
+                }
+                Err(e) => return Err(PcmLoadError::ErrorWhileDecoding((self.path.clone(), e))),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<(), PcmLoadError> {
+        let seeked = self
+            .format
+            .seek(SeekMode::Accurate, SeekTo::TimeStamp { ts: frame, track_id: self.track_id })
+            .map_err(|e| PcmLoadError::ErrorWhileDecoding((self.path.clone(), e)))?;
+
+        // Start with a fresh decoder, since the state of the old one is no longer valid.
+        self.decoder = self
+            .codec_registry
+            .make(&self.codec_params, &DecoderOptions::default())
+            .map_err(|e| PcmLoadError::CouldNotCreateDecoder((self.path.clone(), e)))?;
+
+        for channel in self.decoded.iter_mut() {
+            channel.clear();
+        }
+        self.decoded_start = seeked.actual_ts;
+        self.eof = false;
+
+        Ok(())
+    }
+
+    fn decoded_len(&self) -> usize {
+        self.decoded.first().map(|c| c.len()).unwrap_or(0)
+    }
+}
+
+/// Create a new stream of the given file.
+///
+/// `start_frame` - The frame (at `sample_rate`) to start prefetching from.
+///
+/// Returns the realtime side of the stream, and the decoding side to be sent to the
+/// stream server (see `StreamServer::add()`).
+pub(super) fn new_stream(
+    path: PathBuf,
+    file: OpenedFile,
+    codec_registry: &'static CodecRegistry,
+    sample_rate: SampleRate,
+    start_frame: usize,
+    interpolation_quality: InterpolationQuality,
+    server: &StreamServer,
+) -> Result<(PcmStream, StreamSource), PcmLoadError> {
+    let n_frames =
+        file.codec_params.n_frames.ok_or_else(|| PcmLoadError::UnknownLength(path.clone()))?;
+
+    let decoder = codec_registry
+        .make(&file.codec_params, &DecoderOptions::default())
+        .map_err(|e| PcmLoadError::CouldNotCreateDecoder((path.clone(), e)))?;
+
+    let ratio = f64::from(file.sample_rate) / sample_rate.0;
+    let len = (n_frames as f64 / ratio).ceil() as usize;
+
+    let num_slots = STREAM_PREFETCH_BLOCKS + STREAM_LOOP_BLOCKS + STREAM_SPARE_BLOCKS;
+
+    let (requests_tx, requests_rx) = RingBuffer::new(num_slots).split();
+    let (filled_tx, filled_rx) = RingBuffer::new(num_slots).split();
+
+    let slots = (0..num_slots)
+        .map(|_| StreamSlot {
+            block: Some(Box::new(StreamBlock::new(file.n_channels))),
+            index: None,
+            last_used: 0,
+        })
+        .collect();
+
+    let closed = Arc::new(AtomicBool::new(false));
+
+    let mut stream = PcmStream {
+        info: PcmStreamInfo { num_channels: file.n_channels, len, sample_rate },
+        layout: channel_layout(&path, &file.codec_params, file.n_channels),
+        slots,
+        requests: requests_tx,
+        filled: filled_rx,
+        wanted: Vec::with_capacity(STREAM_PREFETCH_BLOCKS + STREAM_LOOP_BLOCKS),
+        loop_range: None,
+        pinned_frame: None,
+        server: server.thread.clone(),
+        clock: 0,
+        underruns: Arc::new(AtomicUsize::new(0)),
+        closed: Arc::clone(&closed),
+    };
+
+    stream.prefetch(start_frame);
+
+    let sinc_resampler = if let InterpolationQuality::Sinc(quality) = interpolation_quality {
+        Some(SincResampler::new(quality))
+    } else {
+        None
+    };
+    let deip_resampler = if let InterpolationQuality::Deip(interpolator) = interpolation_quality {
+        Some(DeipResampler::new(interpolator, 1.0 / ratio))
+    } else {
+        None
+    };
+
+    let source = StreamSource {
+        path,
+        format: file.format,
+        decoder,
+        codec_params: file.codec_params,
+        codec_registry,
+        track_id: file.track_id,
+        ratio,
+        interpolation_quality,
+        sinc_resampler,
+        deip_resampler,
+        len,
+        decoded: vec![Vec::new(); file.n_channels],
+        decoded_start: 0,
+        eof: false,
+        failed: false,
+        requests: requests_rx,
+        filled: filled_tx,
+        closed,
+    };
+
+    Ok((stream, source))
+}
+
+/// The thread that decodes all streams.
+///
+/// The thread sleeps until blocks are requested, and stops once this is dropped and all
+/// of its streams are closed.
+pub(super) struct StreamServer {
+    sources: mpsc::Sender<StreamSource>,
+    thread: Thread,
+}
+
+impl StreamServer {
+    pub fn spawn() -> Self {
+        let (sources, rx) = mpsc::channel();
+
+        let thread = thread::spawn(move || run_stream_server(rx)).thread().clone();
+
+        Self { sources, thread }
+    }
+
+    /// Start decoding the given stream.
+    pub fn add(&self, source: StreamSource) {
+        // The server only stops once this is dropped.
+        let _ = self.sources.send(source);
+        self.thread.unpark();
+    }
+}
+
+fn run_stream_server(new_sources: mpsc::Receiver<StreamSource>) {
+    let mut sources: Vec<StreamSource> = Vec::new();
+
+    loop {
+        // Wait for a new stream when there are none left.
+        if sources.is_empty() {
+            match new_sources.recv() {
+                Ok(source) => sources.push(source),
+                Err(mpsc::RecvError) => return,
+            }
+        }
+
+        while let Ok(source) = new_sources.try_recv() {
+            sources.push(source);
+        }
+
+        sources.retain(|source| !source.closed.load(Ordering::Relaxed));
+
+        // Fill one block of each stream at a time, so one stream can't hold up the others.
+        let mut did_work = false;
+        for source in sources.iter_mut() {
+            did_work |= source.process();
+        }
+
+        // Streams wake up the server when they request blocks or are closed.
+        if !did_work && !sources.is_empty() {
+            thread::park();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    use basedrop::Collector;
+
+    use super::super::test_wav;
+    use super::*;
+    use crate::backend::resource_loader::PcmLoader;
+
+    /// Write a 16 bit stereo WAV file with `frames` frames of a sawtooth wave (inverted in
+    /// the right channel), long enough to need seeking.
+    fn write_test_wav(path: &PathBuf, frames: usize) {
+        let samples: Vec<i16> = (0..frames)
+            .flat_map(|i| {
+                let smp = ((i % 1000) as i16 - 500) * 60;
+                vec![smp, -smp]
+            })
+            .collect();
+
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, test_wav::pcm_wav(2, 48_000, &samples)).unwrap();
+    }
+
+    /// Read `len` frames starting at `frame`, waiting for the stream server when the
+    /// frames are not decoded yet.
+    ///
+    /// Returns the frames and the number of reads that underran.
+    fn read_until_complete(
+        stream: &mut PcmStream,
+        frame: usize,
+        len: usize,
+    ) -> (Vec<f32>, Vec<f32>, usize) {
+        let mut left = vec![0.0; len];
+        let mut right = vec![0.0; len];
+
+        let start = Instant::now();
+        let mut underruns = 0;
+        while !stream.read(frame, &mut left, &mut right) {
+            underruns += 1;
+
+            assert!(start.elapsed() < Duration::from_secs(10), "stream never filled");
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        (left, right, underruns)
+    }
+
+    /// Wait until the block at `index` is decoded.
+    fn wait_for_block(stream: &mut PcmStream, index: usize) {
+        let start = Instant::now();
+        loop {
+            stream.receive();
+            if stream.slots.iter().any(|slot| slot.index == Some(index) && slot.block.is_some()) {
+                return;
+            }
+
+            assert!(start.elapsed() < Duration::from_secs(10), "block never filled");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn streams_file() {
+        let path = std::env::temp_dir().join("meadowlark_test_stream").join("saw.wav");
+        let frames = (STREAM_BLOCK_FRAMES * 20) + 123;
+        write_test_wav(&path, frames);
+
+        let collector = Collector::new();
+        let sample_rate = SampleRate::new(48_000.0);
+        let mut loader = PcmLoader::new(collector.handle(), sample_rate);
+
+        let (decoded, _) = loader.decoder().decode(&path, &mut |_| true).unwrap();
+        let expected_l = decoded.channel(0).to_f32();
+        let expected_r = decoded.channel(1).to_f32();
+
+        let mut stream =
+            loader.open_stream(&path, sample_rate, 0, InterpolationQuality::Linear).unwrap();
+        assert_eq!(stream.info().num_channels, 2);
+        assert_eq!(stream.info().len, frames);
+
+        let mut total_underruns = 0;
+        let mut check = |stream: &mut PcmStream, frame: usize, len: usize| {
+            let (left, right, underruns) = read_until_complete(stream, frame, len);
+            total_underruns += underruns;
+
+            // Decoded integer samples are scaled slightly differently when streamed.
+            let end = (frame + len).min(frames);
+            for (out, expected) in [(&left, &expected_l), (&right, &expected_r)].iter() {
+                for (a, b) in out[0..(end - frame)].iter().zip(expected[frame..end].iter()) {
+                    assert!((a - b).abs() < 1e-4, "frame {}: {} != {}", frame, a, b);
+                }
+            }
+
+            // Frames past the end of the stream are silent.
+            assert!(left[(end - frame)..].iter().all(|smp| *smp == 0.0));
+
+            underruns
+        };
+
+        // Read across the boundary of two blocks.
+        check(&mut stream, STREAM_BLOCK_FRAMES - 100, 1000);
+
+        // Seek far past the prefetched blocks. These are never decoded in time.
+        let seek_frame = (STREAM_BLOCK_FRAMES * 15) + 50;
+        assert!(check(&mut stream, seek_frame, 512) > 0);
+
+        // Loop back to the start of the loop before the loop end.
+        let loop_start = (STREAM_BLOCK_FRAMES * 2) + 10;
+        let loop_end = STREAM_BLOCK_FRAMES * 12;
+        stream.set_loop(Some((loop_start, loop_end)));
+        check(&mut stream, loop_end - 300, 300);
+
+        // The start of the loop was prefetched when the end of the loop was read.
+        wait_for_block(&mut stream, loop_start / STREAM_BLOCK_FRAMES);
+        assert_eq!(check(&mut stream, loop_start, 512), 0);
+
+        // The end of the stream.
+        check(&mut stream, frames - 100, 512);
+
+        assert_eq!(stream.underruns().load(Ordering::Relaxed), total_underruns);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn streams_multichannel_file() {
+        let path = std::env::temp_dir().join("meadowlark_test_stream").join("surround.wav");
+        let frames = (STREAM_BLOCK_FRAMES * 3) + 45;
+
+        // A 5.1 file where every channel has a different level.
+        let samples: Vec<i16> = (0..frames)
+            .flat_map(|i| (0..6).map(move |channel| ((i % 1000) as i16 - 500) * (channel + 1)))
+            .collect();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, test_wav::pcm_wav(6, 48_000, &samples)).unwrap();
+
+        let collector = Collector::new();
+        let sample_rate = SampleRate::new(48_000.0);
+        let mut loader = PcmLoader::new(collector.handle(), sample_rate);
+
+        let (decoded, _) = loader.decoder().decode(&path, &mut |_| true).unwrap();
+
+        let mut stream =
+            loader.open_stream(&path, sample_rate, 0, InterpolationQuality::Linear).unwrap();
+        assert_eq!(stream.info().num_channels, 6);
+        assert_eq!(stream.layout(), decoded.layout());
+        assert_eq!(stream.layout().unwrap().downmix_gains(3), (0.0, 0.0));
+
+        // Read across the boundary of two blocks.
+        let frame = STREAM_BLOCK_FRAMES - 100;
+        for channel in 0..6 {
+            let expected = decoded.channel(channel).to_f32();
+
+            let mut out = vec![0.0; 1000];
+            let start = Instant::now();
+            while !stream.read_channel(frame, channel, &mut out) {
+                assert!(start.elapsed() < Duration::from_secs(10), "stream never filled");
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            for (a, b) in out.iter().zip(expected[frame..frame + 1000].iter()) {
+                assert!((a - b).abs() < 1e-4, "channel {}: {} != {}", channel, a, b);
+            }
+        }
+
+        // Channels that don't exist are silent.
+        let mut out = vec![1.0; 100];
+        stream.read_channel(frame, 6, &mut out);
+        assert!(out.iter().all(|smp| *smp == 0.0));
+
+        let _ = fs::remove_file(&path);
+    }
+}
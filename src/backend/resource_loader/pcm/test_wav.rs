@@ -0,0 +1,45 @@
+// Writes WAV files for the tests of the PCM loader.
+
+/// Returns a RIFF WAVE file containing the given chunks.
+pub fn wav(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let body: Vec<u8> = chunks.concat();
+
+    let mut wav = b"RIFF".to_vec();
+    wav.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    wav.extend_from_slice(&body);
+    wav
+}
+
+/// Returns a chunk with the given ID and data. Chunks are padded to an even size.
+pub fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+/// Returns the `fmt ` chunk of 16 bit integer PCM.
+pub fn fmt_chunk(num_channels: u16, sample_rate: u32) -> Vec<u8> {
+    let block_align = num_channels * 2;
+
+    let mut fmt = Vec::new();
+    fmt.extend_from_slice(&1u16.to_le_bytes());
+    fmt.extend_from_slice(&num_channels.to_le_bytes());
+    fmt.extend_from_slice(&sample_rate.to_le_bytes());
+    fmt.extend_from_slice(&(sample_rate * u32::from(block_align)).to_le_bytes());
+    fmt.extend_from_slice(&block_align.to_le_bytes());
+    fmt.extend_from_slice(&16u16.to_le_bytes());
+
+    chunk(b"fmt ", &fmt)
+}
+
+/// Returns a 16 bit WAV file with the given interleaved samples.
+pub fn pcm_wav(num_channels: u16, sample_rate: u32, samples: &[i16]) -> Vec<u8> {
+    let data: Vec<u8> = samples.iter().flat_map(|smp| smp.to_le_bytes().to_vec()).collect();
+
+    wav(&[fmt_chunk(num_channels, sample_rate), chunk(b"data", &data)])
+}
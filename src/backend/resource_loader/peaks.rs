@@ -0,0 +1,588 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rusty_daw_core::SampleRate;
+
+use super::AnyPcm;
+use crate::util::TwoXHashMap;
+
+/// The number of frames summarized by each peak in the finest level of a `PeakCache`.
+pub static PEAK_BASE_BLOCK_SIZE: usize = 256;
+
+/// The number of peaks of a level that are summarized by a single peak in the next
+/// (coarser) level of a `PeakCache`.
+pub static PEAK_LEVEL_FACTOR: usize = 4;
+
+/// The name of the directory next to the project file where peak files are saved (see
+/// `PeakLoader::set_cache_dir()`).
+pub static PROJECT_PEAKS_DIR: &str = "peaks";
+
+/// Identifies peak files. The last byte is the version of the format.
+static PEAK_FILE_MAGIC: [u8; 8] = *b"MDLKPKS\x01";
+
+/// A summary of a range of frames in a single channel, used for drawing waveforms.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Peak {
+    pub min: f32,
+    pub max: f32,
+    pub rms: f32,
+}
+
+impl Peak {
+    fn from_frames(frames: &[f32]) -> Self {
+        let mut min = std::f32::MAX;
+        let mut max = std::f32::MIN;
+        let mut sum_squares = 0.0f64;
+        for &smp in frames.iter() {
+            min = min.min(smp);
+            max = max.max(smp);
+            sum_squares += f64::from(smp) * f64::from(smp);
+        }
+
+        if frames.is_empty() {
+            Peak::default()
+        } else {
+            Peak { min, max, rms: (sum_squares / frames.len() as f64).sqrt() as f32 }
+        }
+    }
+}
+
+/// Combines peaks which may each summarize a different number of frames.
+struct PeakAccumulator {
+    min: f32,
+    max: f32,
+    sum_squares: f64,
+    frames: usize,
+}
+
+impl PeakAccumulator {
+    fn new() -> Self {
+        Self { min: std::f32::MAX, max: std::f32::MIN, sum_squares: 0.0, frames: 0 }
+    }
+
+    fn add(&mut self, peak: &Peak, frames: usize) {
+        self.min = self.min.min(peak.min);
+        self.max = self.max.max(peak.max);
+        self.sum_squares += f64::from(peak.rms) * f64::from(peak.rms) * frames as f64;
+        self.frames += frames;
+    }
+
+    fn finish(&self) -> Peak {
+        if self.frames == 0 {
+            Peak::default()
+        } else {
+            Peak {
+                min: self.min,
+                max: self.max,
+                rms: (self.sum_squares / self.frames as f64).sqrt() as f32,
+            }
+        }
+    }
+}
+
+struct PeakLevel {
+    /// The number of frames summarized by each peak.
+    block_size: usize,
+
+    /// The peaks of each channel.
+    channels: Vec<Vec<Peak>>,
+}
+
+impl PeakLevel {
+    fn num_blocks(&self) -> usize {
+        self.channels.first().map(|peaks| peaks.len()).unwrap_or(0)
+    }
+
+    /// The number of frames summarized by the peak at `index`. This is less than the block
+    /// size for the last peak if the resource does not fill the whole block.
+    fn block_frames(&self, index: usize, len: usize) -> usize {
+        self.block_size.min(len - (index * self.block_size))
+    }
+}
+
+/// A multi-resolution summary (min, max, and RMS) of a PCM resource, used for drawing
+/// waveforms at any zoom level.
+///
+/// The finest level summarizes every `PEAK_BASE_BLOCK_SIZE` frames, and each following
+/// level summarizes `PEAK_LEVEL_FACTOR` times as many frames as the one before it.
+///
+/// TODO: Compute peaks for files that are streamed from disk.
+pub struct PeakCache {
+    len: usize,
+    sample_rate: SampleRate,
+    levels: Vec<PeakLevel>,
+}
+
+impl PeakCache {
+    /// Compute the peaks of the given PCM resource.
+    pub fn compute(pcm: &AnyPcm) -> Self {
+        let len = pcm.len();
+
+        let mut buf = vec![0.0; PEAK_BASE_BLOCK_SIZE];
+        let channels: Vec<Vec<Peak>> = (0..pcm.num_channels())
+            .map(|i| {
+                let channel = pcm.channel(i);
+
+                (0..len)
+                    .step_by(PEAK_BASE_BLOCK_SIZE)
+                    .map(|start| {
+                        let frames = PEAK_BASE_BLOCK_SIZE.min(len - start);
+                        channel.read(start, &mut buf[0..frames]);
+
+                        Peak::from_frames(&buf[0..frames])
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut levels = vec![PeakLevel { block_size: PEAK_BASE_BLOCK_SIZE, channels }];
+
+        loop {
+            let prev = levels.last().unwrap();
+            if prev.num_blocks() <= 1 {
+                break;
+            }
+
+            let channels = prev
+                .channels
+                .iter()
+                .map(|peaks| {
+                    peaks
+                        .chunks(PEAK_LEVEL_FACTOR)
+                        .enumerate()
+                        .map(|(i, chunk)| {
+                            let mut acc = PeakAccumulator::new();
+                            for (j, peak) in chunk.iter().enumerate() {
+                                acc.add(peak, prev.block_frames(i * PEAK_LEVEL_FACTOR + j, len));
+                            }
+
+                            acc.finish()
+                        })
+                        .collect()
+                })
+                .collect();
+
+            let block_size = prev.block_size * PEAK_LEVEL_FACTOR;
+            levels.push(PeakLevel { block_size, channels });
+        }
+
+        Self { len, sample_rate: pcm.sample_rate(), levels }
+    }
+
+    /// Load the peaks of the audio file at `path` from the peak files in `cache_dir`. If
+    /// no valid peak file exists, then the peaks are computed from `pcm` (the decoded
+    /// file) and saved to a new peak file.
+    ///
+    /// Peak files are named after `hash`, the hash of the contents of the audio file (see
+    /// `PcmFileKey`), so they stay valid when the file is moved, and they are recomputed
+    /// when it is changed.
+    pub fn load_or_compute(path: &PathBuf, hash: u64, pcm: &AnyPcm, cache_dir: &Path) -> Self {
+        let peak_path = cache_dir.join(format!("{:016x}.peaks", hash));
+
+        if let Ok(file) = File::open(&peak_path) {
+            match Self::read_from(&mut BufReader::new(file), hash, pcm.len(), pcm.num_channels()) {
+                Ok(peaks) => {
+                    log::debug!("Loaded peak file {:?} for {:?}", peak_path, path);
+                    return peaks;
+                }
+                Err(e) => log::warn!("Could not read peak file {:?}: {}", peak_path, e),
+            }
+        }
+
+        let peaks = Self::compute(pcm);
+
+        if let Err(e) = peaks.save(&peak_path, hash) {
+            log::warn!("Could not write peak file {:?}: {}", peak_path, e);
+        }
+
+        peaks
+    }
+
+    /// Returns the peaks of `channel` for drawing the frames in `range` across
+    /// `num_pixels` pixels. The frames are in the sample rate of the original file (see
+    /// `sample_rate()`).
+    ///
+    /// When zoomed in further than `PEAK_BASE_BLOCK_SIZE` frames per pixel, the returned
+    /// peaks are still those of whole blocks. Read the frames of the PCM resource directly
+    /// to draw individual samples instead.
+    ///
+    /// This will panic if `channel` is out of bounds.
+    pub fn query(&self, channel: usize, range: Range<usize>, num_pixels: usize) -> Vec<Peak> {
+        assert!(channel < self.num_channels(), "channel index out of bounds");
+
+        let start = range.start.min(self.len);
+        let end = range.end.max(start).min(self.len);
+        let frames = end - start;
+
+        if frames == 0 {
+            return vec![Peak::default(); num_pixels];
+        }
+
+        // Use the coarsest level that still has at least one peak per pixel.
+        let frames_per_pixel = frames / num_pixels.max(1);
+        let level = self
+            .levels
+            .iter()
+            .rev()
+            .find(|level| level.block_size <= frames_per_pixel)
+            .unwrap_or(&self.levels[0]);
+
+        let peaks = &level.channels[channel];
+
+        (0..num_pixels)
+            .map(|pixel| {
+                let pixel_start = start + (frames * pixel / num_pixels);
+                let pixel_end = start + (frames * (pixel + 1) / num_pixels);
+
+                let first_block = pixel_start / level.block_size;
+                let last_block =
+                    pixel_end.div_ceil(level.block_size).max(first_block + 1).min(peaks.len());
+
+                let mut acc = PeakAccumulator::new();
+                for (i, peak) in peaks[first_block..last_block].iter().enumerate() {
+                    acc.add(peak, level.block_frames(first_block + i, self.len));
+                }
+
+                acc.finish()
+            })
+            .collect()
+    }
+
+    /// The length of the summarized resource in frames.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The sample rate of the summarized resource.
+    pub fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.levels[0].channels.len()
+    }
+
+    fn save(&self, peak_path: &Path, source_hash: u64) -> io::Result<()> {
+        if let Some(dir) = peak_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // Write to a temporary file first, so a partially written peak file is never read.
+        let tmp_path = peak_path.with_extension("peaks.tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            self.write_to(&mut writer, source_hash)?;
+            writer.flush()?;
+        }
+
+        fs::rename(&tmp_path, peak_path)
+    }
+
+    /// Write the peaks in the peak file format.
+    ///
+    /// `source_hash` - The hash of the audio file these peaks were computed from.
+    fn write_to(&self, writer: &mut impl Write, source_hash: u64) -> io::Result<()> {
+        writer.write_all(&PEAK_FILE_MAGIC)?;
+        writer.write_all(&source_hash.to_le_bytes())?;
+        writer.write_all(&(self.len as u64).to_le_bytes())?;
+        writer.write_all(&self.sample_rate.0.to_le_bytes())?;
+        writer.write_all(&(self.num_channels() as u32).to_le_bytes())?;
+        writer.write_all(&(self.levels.len() as u32).to_le_bytes())?;
+
+        for level in self.levels.iter() {
+            writer.write_all(&(level.block_size as u64).to_le_bytes())?;
+            writer.write_all(&(level.num_blocks() as u64).to_le_bytes())?;
+
+            for peaks in level.channels.iter() {
+                for peak in peaks.iter() {
+                    writer.write_all(&peak.min.to_le_bytes())?;
+                    writer.write_all(&peak.max.to_le_bytes())?;
+                    writer.write_all(&peak.rms.to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read peaks in the peak file format.
+    ///
+    /// `source_hash` - The hash of the audio file the peaks should have been computed from.
+    ///
+    /// `len`, `num_channels` - The length and number of channels of the decoded audio
+    /// file. Peak files are not trusted, so a file that does not match these (or that does
+    /// not have the levels `compute()` would create) is rejected before anything is
+    /// allocated for it.
+    fn read_from(
+        reader: &mut impl Read,
+        source_hash: u64,
+        len: usize,
+        num_channels: usize,
+    ) -> io::Result<Self> {
+        fn invalid(msg: &str) -> io::Error {
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        }
+
+        fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+            let mut bytes = [0; N];
+            reader.read_exact(&mut bytes)?;
+            Ok(bytes)
+        }
+
+        let magic: [u8; 8] = read_bytes(reader)?;
+        if magic != PEAK_FILE_MAGIC {
+            return Err(invalid("not a peak file or unsupported version"));
+        }
+
+        if u64::from_le_bytes(read_bytes(reader)?) != source_hash {
+            return Err(invalid("peak file is of a different audio file"));
+        }
+
+        if u64::from_le_bytes(read_bytes(reader)?) != len as u64 {
+            return Err(invalid("peak file has a different length than the audio file"));
+        }
+        let sample_rate = SampleRate(f64::from_le_bytes(read_bytes(reader)?));
+        if u32::from_le_bytes(read_bytes(reader)?) as usize != num_channels {
+            return Err(invalid(
+                "peak file has a different number of channels than the audio file",
+            ));
+        }
+        let num_levels = u32::from_le_bytes(read_bytes(reader)?) as usize;
+
+        if num_levels != num_levels_for_len(len) {
+            return Err(invalid("peak file has an invalid number of levels"));
+        }
+
+        let mut levels = Vec::with_capacity(num_levels);
+        let mut expected_block_size = Some(PEAK_BASE_BLOCK_SIZE);
+        for _ in 0..num_levels {
+            let block_size = u64::from_le_bytes(read_bytes(reader)?) as usize;
+            let num_blocks = u64::from_le_bytes(read_bytes(reader)?) as usize;
+
+            let expected_num_blocks = expected_block_size
+                .and_then(|block_size| len.checked_add(block_size - 1))
+                .map(|frames| frames / block_size);
+            if Some(block_size) != expected_block_size || Some(num_blocks) != expected_num_blocks {
+                return Err(invalid("peak file has an invalid level"));
+            }
+            expected_block_size = block_size.checked_mul(PEAK_LEVEL_FACTOR);
+
+            let mut channels = Vec::with_capacity(num_channels);
+            for _ in 0..num_channels {
+                let mut peaks = Vec::with_capacity(num_blocks);
+                for _ in 0..num_blocks {
+                    peaks.push(Peak {
+                        min: f32::from_le_bytes(read_bytes(reader)?),
+                        max: f32::from_le_bytes(read_bytes(reader)?),
+                        rms: f32::from_le_bytes(read_bytes(reader)?),
+                    });
+                }
+                channels.push(peaks);
+            }
+
+            levels.push(PeakLevel { block_size, channels });
+        }
+
+        Ok(Self { len, sample_rate, levels })
+    }
+}
+
+/// The number of levels `PeakCache::compute()` creates for a resource of `len` frames.
+fn num_levels_for_len(len: usize) -> usize {
+    let mut num_blocks = if len == 0 { 0 } else { ((len - 1) / PEAK_BASE_BLOCK_SIZE) + 1 };
+    let mut num_levels = 1;
+    while num_blocks > 1 {
+        num_blocks = num_blocks.div_ceil(PEAK_LEVEL_FACTOR);
+        num_levels += 1;
+    }
+
+    num_levels
+}
+
+/// Keeps the peaks of the loaded PCM resources (see `PeakCache`).
+pub struct PeakLoader {
+    /// The peaks of each audio file and the hash of the contents they were computed from.
+    loaded: TwoXHashMap<PathBuf, (u64, Arc<PeakCache>)>,
+
+    cache_dir: PathBuf,
+}
+
+impl PeakLoader {
+    pub fn new() -> Self {
+        Self {
+            loaded: Default::default(),
+            cache_dir: std::env::temp_dir().join("meadowlark").join("peaks"),
+        }
+    }
+
+    /// The directory peak files are saved in. By default this is a directory inside the
+    /// system's temporary directory.
+    pub fn cache_dir(&self) -> &PathBuf {
+        &self.cache_dir
+    }
+
+    /// Set the directory peak files are saved in. When a project is loaded, this is the
+    /// `PROJECT_PEAKS_DIR` directory next to the project file.
+    pub fn set_cache_dir(&mut self, cache_dir: PathBuf) {
+        self.cache_dir = cache_dir;
+    }
+
+    /// Returns the peaks of the audio file at the given path if they are loaded.
+    pub fn get(&self, path: &PathBuf) -> Option<Arc<PeakCache>> {
+        self.loaded.get(path).map(|(_, peaks)| Arc::clone(peaks))
+    }
+
+    /// Returns `true` if the peaks of the audio file at the given path are loaded, and
+    /// were computed from the contents with the given hash (see `PcmFileKey`).
+    pub fn contains(&self, path: &PathBuf, hash: u64) -> bool {
+        matches!(self.loaded.get(path), Some((loaded_hash, _)) if *loaded_hash == hash)
+    }
+
+    /// Add the peaks of the audio file at the given path, computed from the contents with
+    /// the given hash. This replaces the previous peaks of the file (if any).
+    pub fn insert(&mut self, path: &PathBuf, hash: u64, peaks: PeakCache) -> Arc<PeakCache> {
+        let peaks = Arc::new(peaks);
+
+        self.loaded.insert(path.to_owned(), (hash, Arc::clone(&peaks)));
+
+        peaks
+    }
+
+    /// Drop the peaks of all audio files for which `is_loaded` returns `false`.
+    pub fn retain(&mut self, mut is_loaded: impl FnMut(&PathBuf) -> bool) {
+        self.loaded.retain(|path, _| is_loaded(path));
+    }
+}
+
+impl Default for PeakLoader {
+    fn default() -> Self {
+        PeakLoader::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::resource_loader::{MonoPcm, StereoPcm};
+
+    fn test_pcm(len: usize) -> AnyPcm {
+        let left = (0..len).map(|i| ((i as f32) * 0.01).sin()).collect();
+        let right = (0..len).map(|i| if i < len / 2 { 0.5 } else { -0.25 }).collect();
+
+        AnyPcm::Stereo(StereoPcm::new(left, right, SampleRate(44_100.0)))
+    }
+
+    #[test]
+    fn levels_match_frames() {
+        let len = 100_000;
+        let pcm = test_pcm(len);
+        let peaks = PeakCache::compute(&pcm);
+        let right = pcm.channel(1).to_f32();
+
+        assert_eq!(peaks.levels[0].num_blocks(), len.div_ceil(PEAK_BASE_BLOCK_SIZE));
+        assert_eq!(peaks.levels.last().unwrap().num_blocks(), 1);
+
+        for level in peaks.levels.iter() {
+            for (i, peak) in level.channels[1].iter().enumerate() {
+                let start = i * level.block_size;
+                let expected =
+                    Peak::from_frames(&right[start..(start + level.block_frames(i, len))]);
+
+                assert_eq!(peak.min, expected.min);
+                assert_eq!(peak.max, expected.max);
+                assert!((peak.rms - expected.rms).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn query_range() {
+        // Each half of this is a whole number of blocks in every level.
+        let len = 1 << 17;
+        let pcm = test_pcm(len);
+        let peaks = PeakCache::compute(&pcm);
+
+        // The whole resource in one pixel.
+        let all = peaks.query(1, 0..len, 1);
+        assert_eq!(all, vec![Peak { min: -0.25, max: 0.5, rms: all[0].rms }]);
+        assert!((all[0].rms - (0.5f32 * 0.5 * 0.5 + 0.25 * 0.25 * 0.5).sqrt()).abs() < 1e-5);
+
+        // Each half of the resource in its own pixel.
+        let halves = peaks.query(1, 0..len, 2);
+        assert_eq!(halves[0], Peak { min: 0.5, max: 0.5, rms: 0.5 });
+        assert_eq!(halves[1], Peak { min: -0.25, max: -0.25, rms: 0.25 });
+
+        // A region past the end of the resource.
+        assert_eq!(peaks.query(0, len..(len + 1000), 3), vec![Peak::default(); 3]);
+
+        // Zoomed in further than a block per pixel.
+        let zoomed = peaks.query(0, 0..16, 16);
+        assert_eq!(zoomed.len(), 16);
+        assert_eq!(zoomed[0], peaks.levels[0].channels[0][0]);
+    }
+
+    #[test]
+    fn peak_file_round_trip() {
+        let pcm = AnyPcm::Mono(MonoPcm::new(vec![0.25; 3000], SampleRate(48_000.0)));
+        let peaks = PeakCache::compute(&pcm);
+
+        let mut file = Vec::new();
+        peaks.write_to(&mut file, 1234).unwrap();
+
+        let read = PeakCache::read_from(&mut &file[..], 1234, 3000, 1).unwrap();
+        assert_eq!(read.len(), peaks.len());
+        assert_eq!(read.sample_rate().0, peaks.sample_rate().0);
+        assert_eq!(read.levels.len(), peaks.levels.len());
+        for (a, b) in read.levels.iter().zip(peaks.levels.iter()) {
+            assert_eq!(a.block_size, b.block_size);
+            assert_eq!(a.channels, b.channels);
+        }
+
+        // The peaks of a different file.
+        assert!(PeakCache::read_from(&mut &file[..], 4321, 3000, 1).is_err());
+
+        // A file that does not match the decoded audio file.
+        assert!(PeakCache::read_from(&mut &file[..], 1234, 3001, 1).is_err());
+        assert!(PeakCache::read_from(&mut &file[..], 1234, 3000, 2).is_err());
+
+        // A truncated file.
+        assert!(PeakCache::read_from(&mut &file[..(file.len() - 1)], 1234, 3000, 1).is_err());
+    }
+
+    #[test]
+    fn rejects_corrupt_peak_files() {
+        let len = 3000;
+        let pcm = AnyPcm::Mono(MonoPcm::new(vec![0.25; len], SampleRate(48_000.0)));
+
+        let mut file = Vec::new();
+        PeakCache::compute(&pcm).write_to(&mut file, 1234).unwrap();
+
+        // The offsets of the fields in the header.
+        let len_offset = 16;
+        let num_levels_offset = 36;
+        let block_size_offset = 40;
+
+        let corrupt = |offset: usize, bytes: &[u8]| {
+            let mut file = file.clone();
+            file[offset..(offset + bytes.len())].copy_from_slice(bytes);
+            PeakCache::read_from(&mut &file[..], 1234, len, 1)
+        };
+
+        assert!(corrupt(len_offset, &u64::MAX.to_le_bytes()).is_err());
+        assert!(corrupt(num_levels_offset, &u32::MAX.to_le_bytes()).is_err());
+        assert!(corrupt(num_levels_offset, &0u32.to_le_bytes()).is_err());
+        assert!(corrupt(block_size_offset, &u64::MAX.to_le_bytes()).is_err());
+        assert!(corrupt(block_size_offset + 8, &u64::MAX.to_le_bytes()).is_err());
+
+        assert_eq!(num_levels_for_len(0), 1);
+        assert_eq!(num_levels_for_len(PEAK_BASE_BLOCK_SIZE), 1);
+        assert_eq!(num_levels_for_len(len), PeakCache::compute(&pcm).levels.len());
+    }
+}
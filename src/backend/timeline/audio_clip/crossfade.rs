@@ -0,0 +1,364 @@
+use rusty_daw_core::{SampleTime, Seconds};
+use std::collections::BinaryHeap;
+
+use crate::util::TwoXHashMap;
+
+use super::FadeCurve;
+
+/// The default length of automatic crossfades when a clip lies entirely inside of
+/// another clip.
+pub static DEFAULT_AUTO_CROSSFADE_TIME: Seconds = Seconds(10.0 / 1_000.0);
+
+/// What happens when audio clips on the same timeline track overlap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ClipOverlapPolicy {
+    /// Overlapping clips are summed together.
+    #[default]
+    Sum,
+
+    /// The clip that starts later on the timeline replaces the earlier clip in the
+    /// overlapping region. The transition between the two clips is smoothed with an
+    /// automatic equal-power crossfade.
+    LaterClipWins,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoCrossfadeKind {
+    FadeIn,
+    FadeOut,
+    Silence,
+}
+
+/// An automatic crossfade region on an audio clip. The times are in samples on
+/// the timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoCrossfade {
+    /// The start of the region (inclusive).
+    pub start: SampleTime,
+    /// The end of the region (exclusive).
+    pub end: SampleTime,
+
+    pub kind: AutoCrossfadeKind,
+}
+
+/// Compute the automatic crossfades for the given clips using the "later clip wins"
+/// overlap policy.
+///
+/// The clips are sorted by their start time to find the clip that is heard at every
+/// point on the timeline. Each clip is only crossfaded with its neighbouring clips, which
+/// are the clips heard right before and right after it. When a clip starts, it takes
+/// over from the clip heard before it, and when a clip ends, the clip it took over from
+/// (or the clip that took over from that one) is heard again.
+///
+/// `clips` - The (start, end) of each clip on the timeline in samples. If two clips
+/// start at the same time, then the one with the higher index wins.
+///
+/// `crossfade_len` - The length of the crossfades used when a clip lies entirely
+/// inside of another clip.
+///
+/// Returns the crossfades for each clip (in the same order as `clips`).
+pub fn compute_auto_crossfades(
+    clips: &[(SampleTime, SampleTime)],
+    crossfade_len: SampleTime,
+) -> Vec<Vec<AutoCrossfade>> {
+    let mut crossfades: Vec<Vec<AutoCrossfade>> = vec![Vec::new(); clips.len()];
+
+    let mut push = |index: usize, start: SampleTime, end: SampleTime, kind: AutoCrossfadeKind| {
+        if start < end {
+            crossfades[index].push(AutoCrossfade { start, end, kind });
+        }
+    };
+
+    // Clips that start later win, so sort the clips by their start time.
+    let mut order: Vec<usize> = (0..clips.len()).filter(|&i| clips[i].0 < clips[i].1).collect();
+    order.sort_by_key(|&i| (clips[i].0 .0, i));
+
+    // The times at which the clip that is heard can change.
+    let mut times: Vec<SampleTime> = Vec::with_capacity(order.len() * 2);
+    for &i in order.iter() {
+        times.push(clips[i].0);
+        times.push(clips[i].1);
+    }
+    times.sort_by_key(|time| time.0);
+    times.dedup();
+
+    // The (start, end, clip index) of the parts of the timeline where each clip is heard.
+    let mut segments: Vec<(SampleTime, SampleTime, usize)> = Vec::new();
+
+    // The positions in `order` of the clips that have started. Clips that have ended are
+    // removed once they are at the top.
+    let mut started: BinaryHeap<usize> = BinaryHeap::new();
+    let mut next = 0;
+    for window in times.windows(2) {
+        let (time, next_time) = (window[0], window[1]);
+
+        while next < order.len() && clips[order[next]].0 <= time {
+            started.push(next);
+            next += 1;
+        }
+        while let Some(&top) = started.peek() {
+            if clips[order[top]].1 > time {
+                break;
+            }
+            started.pop();
+        }
+
+        if let Some(&top) = started.peek() {
+            let index = order[top];
+            match segments.last_mut() {
+                Some(last) if last.1 == time && last.2 == index => last.1 = next_time,
+                _ => segments.push((time, next_time, index)),
+            }
+        }
+    }
+
+    // The lengths of the crossfades at the start and at the end of the parts of the
+    // timeline where a clip is not heard, keyed by (clip index, time).
+    let mut fade_out_lens: TwoXHashMap<(usize, i64), SampleTime> = Default::default();
+    let mut fade_in_lens: TwoXHashMap<(usize, i64), SampleTime> = Default::default();
+
+    for window in segments.windows(2) {
+        let ((_, prev_end, prev), (time, _, next)) = (window[0], window[1]);
+        if prev_end != time {
+            // Nothing is heard in between.
+            continue;
+        }
+
+        let (prev_start, prev_clip_end) = clips[prev];
+        let (next_start, next_end) = clips[next];
+
+        if next_start == time {
+            // `next` takes over from `prev`.
+            let len = if prev_clip_end <= next_end {
+                // Crossfade over the entire overlapping region.
+                prev_clip_end - time
+            } else {
+                // `next` lies inside of `prev`.
+                SampleTime::new(crossfade_len.0.min((next_end - next_start).0 / 2))
+            };
+
+            push(prev, time, time + len, AutoCrossfadeKind::FadeOut);
+            push(next, time, time + len, AutoCrossfadeKind::FadeIn);
+
+            fade_out_lens.insert((prev, time.0), len);
+        } else {
+            // `prev` ended, so `next` is heard again.
+            let len = SampleTime::new(crossfade_len.0.min((prev_clip_end - prev_start).0 / 2));
+
+            push(next, time - len, time, AutoCrossfadeKind::FadeIn);
+            push(prev, time - len, time, AutoCrossfadeKind::FadeOut);
+
+            fade_in_lens.insert((next, time.0), len);
+        }
+    }
+
+    // Clips are silent where they are not heard, except for their crossfades.
+    for &index in order.iter() {
+        let (start, end) = clips[index];
+
+        let mut silence_start = start;
+        let heard = segments.iter().filter(|segment| segment.2 == index);
+        for (heard_start, heard_end) in
+            heard.map(|segment| (segment.0, segment.1)).chain(std::iter::once((end, end)))
+        {
+            if silence_start < heard_start {
+                let fade_out_len = fade_out_lens
+                    .get(&(index, silence_start.0))
+                    .copied()
+                    .unwrap_or(SampleTime::new(0));
+                let fade_in_len = fade_in_lens
+                    .get(&(index, heard_start.0))
+                    .copied()
+                    .unwrap_or(SampleTime::new(0));
+
+                push(
+                    index,
+                    silence_start + fade_out_len,
+                    heard_start - fade_in_len,
+                    AutoCrossfadeKind::Silence,
+                );
+            }
+
+            silence_start = heard_end;
+        }
+    }
+
+    for crossfades in crossfades.iter_mut() {
+        // This is a stable sort.
+        crossfades.sort_by_key(|crossfade| crossfade.start.0);
+    }
+
+    crossfades
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct AutoCrossfadesProcInfo {
+    crossfades: Vec<AutoCrossfade>,
+}
+
+impl AutoCrossfadesProcInfo {
+    pub fn new(crossfades: Vec<AutoCrossfade>) -> Self {
+        Self { crossfades }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.crossfades.is_empty()
+    }
+
+    /// Returns true if any crossfade lies within the given range on the timeline.
+    pub fn is_active(&self, playhead: SampleTime, frames: usize) -> bool {
+        let end_frame = playhead + SampleTime::from_usize(frames);
+
+        self.crossfades.iter().any(|c| c.start < end_frame && playhead < c.end)
+    }
+
+    /// Multiply the gain of all crossfades into `gain`, starting from the sample
+    /// `playhead` on the timeline.
+    pub fn process(&self, playhead: SampleTime, gain: &mut [f32]) {
+        let end_frame = playhead + SampleTime::from_usize(gain.len());
+
+        for crossfade in self.crossfades.iter() {
+            if end_frame <= crossfade.start || crossfade.end <= playhead {
+                continue;
+            }
+
+            let first = (crossfade.start - playhead).0.max(0) as usize;
+            let last = ((crossfade.end - playhead).0 as usize).min(gain.len());
+
+            match crossfade.kind {
+                AutoCrossfadeKind::Silence => {
+                    for g in gain[first..last].iter_mut() {
+                        *g = 0.0;
+                    }
+                }
+                AutoCrossfadeKind::FadeIn | AutoCrossfadeKind::FadeOut => {
+                    let delta = 1.0 / (crossfade.end - crossfade.start).0 as f32;
+                    let mut x = (playhead + SampleTime::from_usize(first) - crossfade.start).0
+                        as f32
+                        * delta;
+
+                    for g in gain[first..last].iter_mut() {
+                        let fade_x =
+                            if crossfade.kind == AutoCrossfadeKind::FadeIn { x } else { 1.0 - x };
+
+                        *g *= FadeCurve::EqualPower.gain(fade_x.clamp(0.0, 1.0));
+
+                        x += delta;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_crossfades() {
+        let st = SampleTime::new;
+
+        // No overlap.
+        let c = compute_auto_crossfades(&[(st(0), st(10)), (st(10), st(20))], st(4));
+        assert!(c[0].is_empty() && c[1].is_empty());
+
+        // Second clip covers the end of the first clip.
+        let c = compute_auto_crossfades(&[(st(0), st(10)), (st(6), st(20))], st(4));
+        assert_eq!(
+            c[0],
+            vec![AutoCrossfade { start: st(6), end: st(10), kind: AutoCrossfadeKind::FadeOut }]
+        );
+        assert_eq!(
+            c[1],
+            vec![AutoCrossfade { start: st(6), end: st(10), kind: AutoCrossfadeKind::FadeIn }]
+        );
+
+        // The order of the clips should not matter.
+        let c = compute_auto_crossfades(&[(st(6), st(20)), (st(0), st(10))], st(4));
+        assert_eq!(c[1][0].kind, AutoCrossfadeKind::FadeOut);
+        assert_eq!(c[0][0].kind, AutoCrossfadeKind::FadeIn);
+
+        // Second clip lies inside of the first clip.
+        let c = compute_auto_crossfades(&[(st(0), st(100)), (st(20), st(40))], st(4));
+        assert_eq!(
+            c[0],
+            vec![
+                AutoCrossfade { start: st(20), end: st(24), kind: AutoCrossfadeKind::FadeOut },
+                AutoCrossfade { start: st(24), end: st(36), kind: AutoCrossfadeKind::Silence },
+                AutoCrossfade { start: st(36), end: st(40), kind: AutoCrossfadeKind::FadeIn },
+            ]
+        );
+        assert_eq!(
+            c[1],
+            vec![
+                AutoCrossfade { start: st(20), end: st(24), kind: AutoCrossfadeKind::FadeIn },
+                AutoCrossfade { start: st(36), end: st(40), kind: AutoCrossfadeKind::FadeOut },
+            ]
+        );
+
+        // Clips with the same start and end.
+        let c = compute_auto_crossfades(&[(st(0), st(10)), (st(0), st(10))], st(4));
+        assert_eq!(
+            c[0],
+            vec![AutoCrossfade { start: st(0), end: st(10), kind: AutoCrossfadeKind::Silence }]
+        );
+        assert!(c[1].is_empty());
+    }
+
+    #[test]
+    fn auto_crossfades_three_clips() {
+        let st = SampleTime::new;
+        let fade = |start, end, kind| AutoCrossfade { start: st(start), end: st(end), kind };
+        use AutoCrossfadeKind::*;
+
+        // Each clip takes over from the one before it.
+        let c =
+            compute_auto_crossfades(&[(st(0), st(50)), (st(30), st(80)), (st(60), st(120))], st(4));
+        assert_eq!(c[0], vec![fade(30, 50, FadeOut)]);
+        assert_eq!(c[1], vec![fade(30, 50, FadeIn), fade(60, 80, FadeOut)]);
+        assert_eq!(c[2], vec![fade(60, 80, FadeIn)]);
+
+        // The third clip takes over from the second clip, which lies inside of the first
+        // clip. The first clip is only heard again once the third clip ends.
+        let c =
+            compute_auto_crossfades(&[(st(40), st(80)), (st(0), st(100)), (st(20), st(60))], st(4));
+        assert_eq!(c[1], vec![fade(20, 24, FadeOut), fade(24, 76, Silence), fade(76, 80, FadeIn)]);
+        assert_eq!(c[2], vec![fade(20, 24, FadeIn), fade(40, 60, FadeOut)]);
+        assert_eq!(c[0], vec![fade(40, 60, FadeIn), fade(76, 80, FadeOut)]);
+
+        // The gain of the first clip stays at zero while the other clips are heard.
+        let mut gain = [1.0f32; 100];
+        AutoCrossfadesProcInfo::new(c[1].clone()).process(st(0), &mut gain);
+        assert!(gain[24..76].iter().all(|g| *g == 0.0));
+        assert_eq!(gain[80..], [1.0; 20][..]);
+    }
+
+    #[test]
+    fn auto_crossfades_process() {
+        let st = SampleTime::new;
+
+        let proc_info = AutoCrossfadesProcInfo::new(vec![
+            AutoCrossfade { start: st(2), end: st(6), kind: AutoCrossfadeKind::FadeOut },
+            AutoCrossfade { start: st(6), end: st(8), kind: AutoCrossfadeKind::Silence },
+        ]);
+
+        assert!(!proc_info.is_active(st(8), 4));
+        assert!(proc_info.is_active(st(0), 4));
+
+        let mut gain = [1.0f32; 10];
+        proc_info.process(st(0), &mut gain);
+
+        assert_eq!(gain[0], 1.0);
+        assert_eq!(gain[2], 1.0);
+        assert!(gain[3] < 1.0 && gain[3] > gain[4] && gain[4] > gain[5]);
+        assert_eq!(gain[6], 0.0);
+        assert_eq!(gain[7], 0.0);
+        assert_eq!(gain[8], 1.0);
+
+        // Starting in the middle of a crossfade must give the same values.
+        let mut gain_2 = [1.0f32; 4];
+        proc_info.process(st(4), &mut gain_2);
+        assert_eq!(&gain[4..8], &gain_2[..]);
+    }
+}
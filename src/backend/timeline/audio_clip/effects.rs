@@ -0,0 +1,365 @@
+use rusty_daw_core::{SampleRate, Seconds};
+use std::path::PathBuf;
+
+use crate::backend::dsp::convolution::PartitionedConvolver;
+use crate::backend::dsp::paulstretch::{
+    extreme_stretch, extreme_stretch_max_factor, extreme_stretch_window_len,
+};
+use crate::backend::dsp::{dc_offset, loudness};
+use crate::backend::resource_loader::pcm::loader::MAX_FILE_BYTES;
+use crate::backend::resource_loader::AnyPcm;
+
+/// The length of the fade out applied to the end of an impulse response when it is
+/// trimmed, to avoid clicks.
+pub static IR_TRIM_FADE_TIME: Seconds = Seconds(10.0 / 1_000.0);
+
+/// How to normalize an audio clip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NormalizeMode {
+    /// Normalize so the highest peak reaches the given level in dBFS.
+    Peak { target_dbfs: f32 },
+
+    /// Normalize so the integrated loudness (ITU-R BS.1770) reaches the given level
+    /// in LUFS.
+    ///
+    /// Note this may push the peaks of the clip above 0 dBFS.
+    Loudness { target_lufs: f32 },
+}
+
+/// How to remove the DC offset of an audio clip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DcOffsetRemoval {
+    /// Subtract the mean of all samples. This is only accurate when the offset stays
+    /// constant over the whole clip.
+    Mean,
+
+    /// Use a high-pass filter with the given cutoff. This also removes offsets that
+    /// drift over time.
+    HighPass { cutoff_hz: f32 },
+}
+
+/// A spectral "extreme" time stretch (PaulStretch) for sound design. This stretches the
+/// audio without changing its pitch, while smearing transients.
+///
+/// The stretched audio is rendered into memory, so the factor is limited to what fits in
+/// `MAX_FILE_BYTES` (the same limit as for a loaded audio file).
+///
+/// This can take a long time to render. Audio clip resources are rendered on background
+/// threads by the `AudioClipRenderQueue`, so this does not block the caller.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExtremeStretchEffect {
+    /// How many times longer the audio becomes. Values below `1.0` are not supported.
+    pub factor: f32,
+
+    /// The size of the analysis window. This is rounded up to a power of two number of
+    /// frames. Larger windows give smoother results, while smaller windows preserve more
+    /// of the time structure of the audio.
+    pub window_size: Seconds,
+}
+
+impl Default for ExtremeStretchEffect {
+    fn default() -> Self {
+        Self { factor: 8.0, window_size: Seconds(0.25) }
+    }
+}
+
+/// Convolution of an audio clip with an impulse response loaded from an audio file.
+///
+/// A stereo impulse response convolves each channel separately. When a mono clip is
+/// convolved with a stereo impulse response, the result is stereo.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvolutionEffect {
+    /// The path to the audio file of the impulse response.
+    pub ir_path: PathBuf,
+
+    /// The mix between the original (dry) and convolved (wet) signal, where `0.0` is
+    /// fully dry and `1.0` is fully wet.
+    pub mix: f32,
+
+    /// Where the impulse response starts in the audio file.
+    pub ir_start: Seconds,
+
+    /// The maximum length of the impulse response. If this is `None`, then the entire
+    /// impulse response after `ir_start` is used.
+    pub ir_length: Option<Seconds>,
+}
+
+impl ConvolutionEffect {
+    pub fn new(ir_path: PathBuf) -> Self {
+        Self { ir_path, mix: 1.0, ir_start: Seconds(0.0), ir_length: None }
+    }
+
+    /// Returns the trimmed channels of the impulse response.
+    fn trimmed_ir(&self, ir: &AnyPcm) -> Vec<Vec<f32>> {
+        let to_frames = |time: Seconds| (time.0.max(0.0) * ir.sample_rate().0).round() as usize;
+
+        let start = to_frames(self.ir_start).min(ir.len());
+        let end = if let Some(length) = self.ir_length {
+            (start + to_frames(length)).min(ir.len())
+        } else {
+            ir.len()
+        };
+
+        let mut channels: Vec<Vec<f32>> = (0..ir.num_channels())
+            .map(|i| ir.channel(i).slice(start..end).to_f32().into_owned())
+            .collect();
+
+        if end < ir.len() {
+            // Fade out the trimmed end of the impulse response.
+            let fade_len = to_frames(IR_TRIM_FADE_TIME).min(end - start);
+            if fade_len > 0 {
+                let fade_start = end - start - fade_len;
+
+                for channel in channels.iter_mut() {
+                    for (i, smp) in channel[fade_start..].iter_mut().enumerate() {
+                        *smp *= 1.0 - (i as f32 / fade_len as f32);
+                    }
+                }
+            }
+        }
+
+        channels
+    }
+
+    fn key(&self) -> ConvolutionKey {
+        ConvolutionKey {
+            ir_path: self.ir_path.clone(),
+            mix: self.mix.to_bits(),
+            ir_start: self.ir_start.0.to_bits(),
+            ir_length: self.ir_length.map(|l| l.0.to_bits()),
+        }
+    }
+}
+
+/// The offline effects applied to an audio clip. These are rendered when the audio
+/// clip resource is created.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AudioClipEffects {
+    /// DC offset removal. This is applied first.
+    pub dc_offset: Option<DcOffsetRemoval>,
+
+    /// Extreme time stretching. This is applied after DC offset removal.
+    pub extreme_stretch: Option<ExtremeStretchEffect>,
+
+    /// Convolution with an impulse response. This is applied after time stretching.
+    pub convolution: Option<ConvolutionEffect>,
+
+    /// Normalization. This is applied last.
+    pub normalize: Option<NormalizeMode>,
+}
+
+impl AudioClipEffects {
+    pub fn is_empty(&self) -> bool {
+        self.dc_offset.is_none()
+            && self.extreme_stretch.is_none()
+            && self.convolution.is_none()
+            && self.normalize.is_none()
+    }
+
+    /// The key used to identify the rendered resource in the resource cache.
+    pub(super) fn key(&self) -> Option<EffectsKey> {
+        if self.is_empty() {
+            return None;
+        }
+
+        // Floats can't be hashed, so use their bit representation instead.
+        Some(EffectsKey {
+            dc_offset: self.dc_offset.map(|dc_offset| match dc_offset {
+                DcOffsetRemoval::Mean => DcOffsetKey::Mean,
+                DcOffsetRemoval::HighPass { cutoff_hz } => {
+                    DcOffsetKey::HighPass { cutoff_hz: cutoff_hz.to_bits() }
+                }
+            }),
+            extreme_stretch: self.extreme_stretch.map(|stretch| ExtremeStretchKey {
+                factor: stretch.factor.to_bits(),
+                window_size: stretch.window_size.0.to_bits(),
+            }),
+            convolution: self.convolution.as_ref().map(|c| c.key()),
+            normalize: self.normalize.map(|normalize| match normalize {
+                NormalizeMode::Peak { target_dbfs } => {
+                    NormalizeKey::Peak { target_dbfs: target_dbfs.to_bits() }
+                }
+                NormalizeMode::Loudness { target_lufs } => {
+                    NormalizeKey::Loudness { target_lufs: target_lufs.to_bits() }
+                }
+            }),
+        })
+    }
+
+    /// Render these effects into a new PCM resource.
+    ///
+    /// `ir` - The impulse response used by the convolution effect. This must have the
+    /// same sample rate as `pcm`. If this is `None`, then the convolution effect is
+    /// skipped.
+    ///
+    /// `progress` - Called with the progress of the render in the range `[0.0, 1.0]`.
+    /// The render is cancelled if this returns `false`.
+    ///
+    /// This function allocates memory and is *not* realtime safe.
+    ///
+    /// Returns `None` if the render was cancelled.
+    pub(super) fn render(
+        &self,
+        pcm: &AnyPcm,
+        ir: Option<&AnyPcm>,
+        progress: &mut dyn FnMut(f32) -> bool,
+    ) -> Option<AnyPcm> {
+        let sample_rate = pcm.sample_rate();
+
+        let mut channels: Vec<Vec<f32>> =
+            (0..pcm.num_channels()).map(|i| pcm.channel(i).to_f32().into_owned()).collect();
+
+        if let Some(removal) = self.dc_offset {
+            for channel in channels.iter_mut() {
+                match removal {
+                    DcOffsetRemoval::Mean => dc_offset::remove_dc_offset_mean(channel),
+                    DcOffsetRemoval::HighPass { cutoff_hz } => {
+                        dc_offset::remove_dc_offset_high_pass(channel, cutoff_hz, sample_rate)
+                    }
+                }
+            }
+        }
+
+        if let Some(stretch) = self.extreme_stretch {
+            let window_len = extreme_stretch_window_len(stretch.window_size, sample_rate);
+            let src: Vec<&[f32]> = channels.iter().map(|c| c.as_slice()).collect();
+
+            let max_factor = extreme_stretch_max_factor(pcm.len(), src.len(), MAX_FILE_BYTES);
+            let factor = if stretch.factor > max_factor {
+                log::warn!(
+                    "Extreme stretch factor {} is too large to fit in memory, using {} instead",
+                    stretch.factor,
+                    max_factor
+                );
+                max_factor
+            } else {
+                stretch.factor
+            };
+
+            channels = extreme_stretch(&src, factor, window_len, progress)?;
+        }
+
+        if let (Some(convolution), Some(ir)) = (&self.convolution, ir) {
+            channels = convolve(channels, &convolution.trimmed_ir(ir), convolution.mix);
+        }
+
+        if let Some(normalize) = self.normalize {
+            normalize_channels(&mut channels, normalize, sample_rate);
+        }
+
+        if !progress(1.0) {
+            return None;
+        }
+
+        // The layout is kept as long as the number of channels did not change.
+        Some(AnyPcm::from_channels(channels, pcm.layout().cloned(), sample_rate))
+    }
+}
+
+fn convolve(channels: Vec<Vec<f32>>, ir: &[Vec<f32>], mix: f32) -> Vec<Vec<f32>> {
+    if ir.is_empty() || ir[0].is_empty() {
+        return channels;
+    }
+
+    let mix = mix.clamp(0.0, 1.0);
+
+    // A mono clip convolved with a stereo impulse response becomes stereo.
+    let channels = if channels.len() == 1 && ir.len() == 2 {
+        vec![channels[0].clone(), channels[0].clone()]
+    } else {
+        channels
+    };
+
+    channels
+        .into_iter()
+        .enumerate()
+        .map(|(i, dry)| {
+            let convolver = PartitionedConvolver::new(&ir[i.min(ir.len() - 1)]);
+            let mut wet = convolver.process(&dry);
+
+            for smp in wet.iter_mut() {
+                *smp *= mix;
+            }
+            for (wet_smp, dry_smp) in wet.iter_mut().zip(dry.iter()) {
+                *wet_smp += *dry_smp * (1.0 - mix);
+            }
+
+            wet
+        })
+        .collect()
+}
+
+fn normalize_channels(
+    channels: &mut [Vec<f32>],
+    normalize: NormalizeMode,
+    sample_rate: SampleRate,
+) {
+    let gain = {
+        let channels: Vec<&[f32]> = channels.iter().map(|c| c.as_slice()).collect();
+
+        match normalize {
+            NormalizeMode::Peak { target_dbfs } => {
+                let peak = loudness::peak(&channels);
+                if peak > 0.0 {
+                    Some(10.0f32.powf(target_dbfs / 20.0) / peak)
+                } else {
+                    None
+                }
+            }
+            NormalizeMode::Loudness { target_lufs } => {
+                loudness::integrated_loudness(&channels, sample_rate)
+                    .map(|loudness| 10.0f64.powf((f64::from(target_lufs) - loudness) / 20.0) as f32)
+            }
+        }
+    };
+
+    // Silent clips are left untouched.
+    if let Some(gain) = gain {
+        for channel in channels.iter_mut() {
+            for smp in channel.iter_mut() {
+                *smp *= gain;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct EffectsKey {
+    dc_offset: Option<DcOffsetKey>,
+    extreme_stretch: Option<ExtremeStretchKey>,
+    convolution: Option<ConvolutionKey>,
+    normalize: Option<NormalizeKey>,
+}
+
+impl EffectsKey {
+    /// The path of the impulse response used by the convolution effect (if any).
+    pub(super) fn ir_path(&self) -> Option<&PathBuf> {
+        self.convolution.as_ref().map(|convolution| &convolution.ir_path)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum DcOffsetKey {
+    Mean,
+    HighPass { cutoff_hz: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ExtremeStretchKey {
+    factor: u32,
+    window_size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ConvolutionKey {
+    ir_path: PathBuf,
+    mix: u32,
+    ir_start: u64,
+    ir_length: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum NormalizeKey {
+    Peak { target_dbfs: u32 },
+    Loudness { target_lufs: u32 },
+}
@@ -0,0 +1,372 @@
+use rusty_daw_core::{SampleRate, SampleTime, Seconds};
+
+use super::{AUDIO_CLIP_GAIN_MAX_DB, AUDIO_CLIP_GAIN_MIN_DB};
+
+/// The shape of the segment between an envelope point and the point after it.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EnvelopeShape {
+    /// Linearly interpolate to the next point.
+    #[default]
+    Linear,
+
+    /// Hold the value of this point until the next point.
+    Step,
+
+    /// Interpolate to the next point along a power curve.
+    ///
+    /// `tension` is in the range `[-1.0, 1.0]`. Positive values make the curve change
+    /// slowly at first, and negative values make the curve change quickly at first.
+    /// A tension of `0.0` is the same as `Linear`.
+    Curve { tension: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GainEnvelopePoint {
+    /// The time of this point relative to the start of the clip on the timeline.
+    pub time: Seconds,
+
+    /// The gain at this point in decibels.
+    pub gain_db: f32,
+
+    /// The shape of the segment from this point to the next point.
+    pub shape: EnvelopeShape,
+}
+
+/// A gain envelope (automation) inside of an audio clip.
+///
+/// If the envelope has no points, then it has no effect on the clip.
+#[derive(Debug, Clone, Default)]
+pub struct AudioClipGainEnvelope {
+    /// The points in this envelope, sorted by time.
+    points: Vec<GainEnvelopePoint>,
+}
+
+impl AudioClipGainEnvelope {
+    pub fn new() -> Self {
+        Self { points: Vec::new() }
+    }
+
+    /// The points in this envelope, sorted by time.
+    pub fn points(&self) -> &[GainEnvelopePoint] {
+        &self.points
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Insert a new point into the envelope.
+    ///
+    /// The gain will be clamped to fit within range of the clip gain parameter, and
+    /// the time will be clamped to be non-negative. If a point already exists at the
+    /// same time, then the new point is placed after it.
+    ///
+    /// Returns the index of the new point.
+    pub fn insert_point(&mut self, mut point: GainEnvelopePoint) -> usize {
+        point.time = Seconds(point.time.0.max(0.0));
+        point.gain_db = point.gain_db.clamp(AUDIO_CLIP_GAIN_MIN_DB, AUDIO_CLIP_GAIN_MAX_DB);
+        if let EnvelopeShape::Curve { tension } = &mut point.shape {
+            *tension = tension.clamp(-1.0, 1.0);
+        }
+
+        let index = self.points.partition_point(|p| p.time.0 <= point.time.0);
+        self.points.insert(index, point);
+
+        index
+    }
+
+    /// Remove the point with the given index.
+    pub fn remove_point(&mut self, index: usize) -> Option<GainEnvelopePoint> {
+        if index < self.points.len() {
+            Some(self.points.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Remove all points from the envelope.
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    /// Returns the gain in decibels at the given time (relative to the start of the clip).
+    ///
+    /// Returns `None` if the envelope has no points.
+    pub fn gain_db_at(&self, time: Seconds) -> Option<f32> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        let next = self.points.partition_point(|p| p.time.0 <= time.0);
+
+        if next == 0 {
+            return Some(self.points[0].gain_db);
+        } else if next == self.points.len() {
+            return Some(self.points[next - 1].gain_db);
+        }
+
+        let a = &self.points[next - 1];
+        let b = &self.points[next];
+
+        let x = ((time.0 - a.time.0) / (b.time.0 - a.time.0)) as f32;
+        let (a_amp, b_amp) = (db_to_amp(a.gain_db), db_to_amp(b.gain_db));
+
+        let amp = match a.shape {
+            EnvelopeShape::Linear => a_amp + ((b_amp - a_amp) * x),
+            EnvelopeShape::Step => return Some(a.gain_db),
+            EnvelopeShape::Curve { tension } => {
+                a_amp + ((b_amp - a_amp) * x.powf(curve_exponent(tension)))
+            }
+        };
+
+        Some(amp_to_db(amp))
+    }
+
+    /// Split this envelope into two envelopes at the given time (relative to the start
+    /// of the clip). The times in the second envelope are relative to the split point.
+    ///
+    /// A point is inserted at the split point in both envelopes so the gain stays the
+    /// same at the split. Note that a curved segment that crosses the split point is
+    /// only approximated in the second envelope.
+    pub fn split_at(&self, time: Seconds) -> (Self, Self) {
+        let gain_db = if let Some(gain_db) = self.gain_db_at(time) {
+            gain_db
+        } else {
+            return (Self::new(), Self::new());
+        };
+
+        let split = self.points.partition_point(|p| p.time.0 < time.0);
+
+        let mut first: Vec<GainEnvelopePoint> = self.points[0..split].to_vec();
+        first.push(GainEnvelopePoint { time, gain_db, shape: EnvelopeShape::Linear });
+
+        // The shape of the segment the split point lies in.
+        let shape = if split < self.points.len() && self.points[split].time.0 == time.0 {
+            self.points[split].shape
+        } else if split > 0 {
+            self.points[split - 1].shape
+        } else {
+            EnvelopeShape::Linear
+        };
+
+        let mut second = vec![GainEnvelopePoint { time: Seconds(0.0), gain_db, shape }];
+        second.extend(
+            self.points[split..]
+                .iter()
+                .filter(|p| p.time.0 > time.0)
+                .map(|p| GainEnvelopePoint { time: Seconds(p.time.0 - time.0), ..*p }),
+        );
+
+        (Self { points: first }, Self { points: second })
+    }
+
+    pub(super) fn to_proc_info(&self, sample_rate: SampleRate) -> GainEnvelopeProcInfo {
+        let points = self
+            .points
+            .iter()
+            .map(|p| GainEnvelopeProcPoint {
+                time: p.time.to_nearest_sample_round(sample_rate).0,
+                amp: db_to_amp(p.gain_db),
+                shape: match p.shape {
+                    EnvelopeShape::Linear => ProcShape::Linear,
+                    EnvelopeShape::Step => ProcShape::Step,
+                    EnvelopeShape::Curve { tension } => {
+                        if tension == 0.0 {
+                            ProcShape::Linear
+                        } else {
+                            ProcShape::Curve { exponent: curve_exponent(tension) }
+                        }
+                    }
+                },
+            })
+            .collect();
+
+        GainEnvelopeProcInfo { points }
+    }
+}
+
+#[inline]
+fn db_to_amp(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+#[inline]
+fn amp_to_db(amp: f32) -> f32 {
+    if amp <= 0.0 {
+        AUDIO_CLIP_GAIN_MIN_DB
+    } else {
+        (20.0 * amp.log10()).clamp(AUDIO_CLIP_GAIN_MIN_DB, AUDIO_CLIP_GAIN_MAX_DB)
+    }
+}
+
+#[inline]
+fn curve_exponent(tension: f32) -> f32 {
+    2.0f32.powf(tension * 3.0)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ProcShape {
+    Linear,
+    Step,
+    Curve { exponent: f32 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GainEnvelopeProcPoint {
+    /// The time of this point in samples relative to the start of the clip.
+    time: i64,
+    amp: f32,
+    shape: ProcShape,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct GainEnvelopeProcInfo {
+    points: Vec<GainEnvelopeProcPoint>,
+}
+
+impl GainEnvelopeProcInfo {
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        !self.points.is_empty()
+    }
+
+    /// Fill `out` with the amplitude of the envelope, starting from the sample `clip_pos`
+    /// (relative to the start of the clip).
+    ///
+    /// Before the first point the value of the first point is used, and after the last
+    /// point the value of the last point is used.
+    pub fn process(&self, clip_pos: SampleTime, out: &mut [f32]) {
+        let points = &self.points[..];
+
+        if points.is_empty() {
+            for smp in out.iter_mut() {
+                *smp = 1.0;
+            }
+            return;
+        }
+
+        // The index of the first point that lies after the current sample.
+        let mut next = points.partition_point(|p| p.time <= clip_pos.0);
+
+        for (i, smp) in out.iter_mut().enumerate() {
+            let t = clip_pos.0 + i as i64;
+
+            while next < points.len() && points[next].time <= t {
+                next += 1;
+            }
+
+            *smp = if next == 0 {
+                points[0].amp
+            } else if next == points.len() {
+                points[points.len() - 1].amp
+            } else {
+                let a = &points[next - 1];
+                let b = &points[next];
+
+                // This will never divide by zero because `a.time <= t < b.time`.
+                let x = (t - a.time) as f32 / (b.time - a.time) as f32;
+
+                match a.shape {
+                    ProcShape::Linear => a.amp + ((b.amp - a.amp) * x),
+                    ProcShape::Step => a.amp,
+                    ProcShape::Curve { exponent } => a.amp + ((b.amp - a.amp) * x.powf(exponent)),
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gain_envelope_process() {
+        let mut envelope = AudioClipGainEnvelope::new();
+
+        let mut out = [0.0f32; 8];
+        envelope.to_proc_info(SampleRate(1.0)).process(SampleTime(0), &mut out);
+        assert!(out.iter().all(|s| *s == 1.0));
+
+        envelope.insert_point(GainEnvelopePoint {
+            time: Seconds(6.0),
+            gain_db: 0.0,
+            shape: EnvelopeShape::Linear,
+        });
+        envelope.insert_point(GainEnvelopePoint {
+            time: Seconds(2.0),
+            gain_db: AUDIO_CLIP_GAIN_MIN_DB,
+            shape: EnvelopeShape::Linear,
+        });
+        assert_eq!(envelope.points()[0].time, Seconds(2.0));
+
+        let proc_info = envelope.to_proc_info(SampleRate(1.0));
+        let min_amp = db_to_amp(AUDIO_CLIP_GAIN_MIN_DB);
+
+        proc_info.process(SampleTime(0), &mut out);
+        assert_eq!(out[0], min_amp);
+        assert_eq!(out[2], min_amp);
+        assert!((out[4] - (min_amp + ((1.0 - min_amp) * 0.5))).abs() < 0.0001);
+        assert_eq!(out[6], 1.0);
+        assert_eq!(out[7], 1.0);
+
+        // Starting in the middle of a segment must give the same values.
+        let mut out_2 = [0.0f32; 4];
+        proc_info.process(SampleTime(3), &mut out_2);
+        assert_eq!(&out[3..7], &out_2[..]);
+
+        envelope.insert_point(GainEnvelopePoint {
+            time: Seconds(0.0),
+            gain_db: 0.0,
+            shape: EnvelopeShape::Step,
+        });
+
+        envelope.to_proc_info(SampleRate(1.0)).process(SampleTime(0), &mut out);
+        assert_eq!(out[0], 1.0);
+        assert_eq!(out[1], 1.0);
+        assert_eq!(out[2], min_amp);
+    }
+
+    #[test]
+    fn gain_envelope_split() {
+        let mut envelope = AudioClipGainEnvelope::new();
+        envelope.insert_point(GainEnvelopePoint {
+            time: Seconds(0.0),
+            gain_db: -20.0,
+            shape: EnvelopeShape::Step,
+        });
+        envelope.insert_point(GainEnvelopePoint {
+            time: Seconds(4.0),
+            gain_db: 0.0,
+            shape: EnvelopeShape::Linear,
+        });
+        envelope.insert_point(GainEnvelopePoint {
+            time: Seconds(8.0),
+            gain_db: 6.0,
+            shape: EnvelopeShape::Linear,
+        });
+
+        let (first, second) = envelope.split_at(Seconds(2.0));
+
+        assert_eq!(first.points().len(), 2);
+        assert_eq!(first.points()[1].time, Seconds(2.0));
+        assert_eq!(first.points()[1].gain_db, -20.0);
+
+        assert_eq!(second.points().len(), 3);
+        assert_eq!(second.points()[0].time, Seconds(0.0));
+        assert_eq!(second.points()[0].gain_db, -20.0);
+        assert_eq!(second.points()[0].shape, EnvelopeShape::Step);
+        assert_eq!(second.points()[1].time, Seconds(2.0));
+        assert_eq!(second.points()[2].time, Seconds(6.0));
+
+        let (first, second) = envelope.split_at(Seconds(4.0));
+        assert_eq!(first.points().len(), 2);
+        assert_eq!(first.points()[1].gain_db, 0.0);
+        assert_eq!(second.points().len(), 2);
+        assert_eq!(second.points()[0].gain_db, 0.0);
+        assert_eq!(second.points()[0].shape, EnvelopeShape::Linear);
+
+        let (first, second) = AudioClipGainEnvelope::new().split_at(Seconds(1.0));
+        assert!(first.is_empty() && second.is_empty());
+    }
+}
@@ -0,0 +1,250 @@
+use rusty_daw_core::{SampleRate, SampleTime, Seconds};
+
+use crate::backend::resource_loader::{PcmChannel, PcmSample};
+
+/// The loop region of a looping audio clip.
+///
+/// When an audio clip is looping, the region between `loop_start` and `loop_end` in
+/// the PCM resource repeats until the end of the clip on the timeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioClipLoop {
+    /// The start of the loop in the PCM resource.
+    pub loop_start: Seconds,
+
+    /// The end of the loop in the PCM resource. If this lies past the end of the
+    /// resource, then the end of the resource is used instead.
+    pub loop_end: Seconds,
+
+    /// The duration of the crossfade at the loop point. This crossfades the end
+    /// of the loop with the audio leading up to the start of the loop.
+    ///
+    /// A linear crossfade is used since both sides of the crossfade usually come
+    /// from the same (correlated) material.
+    ///
+    /// This will be shortened if there is not enough audio before the start of the
+    /// loop, or if it is longer than half of the loop.
+    pub crossfade: Seconds,
+}
+
+impl AudioClipLoop {
+    pub(super) fn to_region(self, sample_rate: SampleRate) -> LoopRegion {
+        LoopRegion {
+            start: self.loop_start.to_nearest_sample_round(sample_rate),
+            end: self.loop_end.to_nearest_sample_round(sample_rate),
+            crossfade: (self.crossfade.0.max(0.0) * sample_rate.0).round() as usize,
+        }
+    }
+}
+
+/// The loop region of an audio clip in samples. This does not depend on the resource of
+/// the clip, which may still be rendering (see `LoopRegion::to_proc_info()`).
+#[derive(Debug, Clone, Copy)]
+pub(super) struct LoopRegion {
+    start: SampleTime,
+    end: SampleTime,
+    crossfade: usize,
+}
+
+impl LoopRegion {
+    /// Convert the loop region to frames in a resource that starts at `original_offset`
+    /// and is `pcm_len` frames long.
+    ///
+    /// Returns `None` if the loop is not in the resource.
+    pub(super) fn to_proc_info(
+        self,
+        original_offset: SampleTime,
+        pcm_len: usize,
+    ) -> Option<LoopProcInfo> {
+        let to_pcm_frame = |time: SampleTime| -> usize {
+            let frame = (time - original_offset).0;
+            (frame.max(0) as usize).min(pcm_len)
+        };
+
+        let start = to_pcm_frame(self.start);
+        let end = to_pcm_frame(self.end);
+
+        if end <= start {
+            // Invalid loop.
+            return None;
+        }
+
+        let crossfade = self.crossfade.min(start).min((end - start) / 2);
+
+        Some(LoopProcInfo { start, end, crossfade })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct LoopProcInfo {
+    /// The start of the loop in frames (inclusive).
+    start: usize,
+    /// The end of the loop in frames (exclusive).
+    end: usize,
+    /// The length of the crossfade in frames.
+    crossfade: usize,
+}
+
+impl LoopProcInfo {
+    /// The (start, end) of the loop in frames.
+    pub fn range(&self) -> (usize, usize) {
+        (self.start, self.end)
+    }
+
+    /// Returns the position in the source of the position `pos` in the "unrolled" loop.
+    pub fn wrap(&self, pos: usize) -> usize {
+        if pos < self.end {
+            pos
+        } else {
+            self.start + ((pos - self.end) % (self.end - self.start))
+        }
+    }
+
+    /// Split `frames` frames of the "unrolled" loop starting at `pos` into contiguous
+    /// ranges of the source, and call `f(src_pos, dst_offset, frames)` for each range.
+    ///
+    /// This is used for sources that can't be read with `fill()` (such as streams). Note
+    /// the crossfade is not applied.
+    pub fn for_each_range<F: FnMut(usize, usize, usize)>(
+        &self,
+        pos: usize,
+        frames: usize,
+        mut f: F,
+    ) {
+        let mut pos = self.wrap(pos);
+        let mut offset = 0;
+
+        while offset < frames {
+            let range_frames = (self.end - pos).min(frames - offset);
+
+            f(pos, offset, range_frames);
+
+            offset += range_frames;
+            pos = self.start;
+        }
+    }
+
+    /// Fill `dst` with frames from `src`, where `pos` is the position in `src` as if the
+    /// loop was "unrolled" (frames past the end of the loop continue from the start of
+    /// the loop).
+    ///
+    /// `src` must be longer than the end of the loop.
+    pub fn fill<T: PcmSample>(&self, src: &[T], pos: usize, dst: &mut [f32]) {
+        let mut pos = self.wrap(pos);
+
+        let crossfade_start = self.end - self.crossfade;
+        let crossfade_delta = if self.crossfade > 0 { 1.0 / self.crossfade as f32 } else { 0.0 };
+
+        for smp in dst.iter_mut() {
+            *smp = if pos >= crossfade_start && self.crossfade > 0 {
+                // Crossfade with the frames leading up to the start of the loop.
+                let crossfade_pos = pos - crossfade_start;
+                let x = crossfade_pos as f32 * crossfade_delta;
+
+                let pre_loop_smp = src[self.start - self.crossfade + crossfade_pos].to_f32();
+                let smp = src[pos].to_f32();
+
+                smp + ((pre_loop_smp - smp) * x)
+            } else {
+                src[pos].to_f32()
+            };
+
+            pos += 1;
+            if pos >= self.end {
+                pos = self.start;
+            }
+        }
+    }
+
+    /// The same as `fill()`, but for a channel in any sample format.
+    pub fn fill_channel(&self, src: PcmChannel, pos: usize, dst: &mut [f32]) {
+        match src {
+            PcmChannel::F32(src) => self.fill(src, pos, dst),
+            PcmChannel::I16(src) => self.fill(src, pos, dst),
+            PcmChannel::U24(src) => self.fill(src, pos, dst),
+            PcmChannel::U8(src) => self.fill(src, pos, dst),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loop_fill() {
+        let src: Vec<f32> = (0..10).map(|i| i as f32).collect();
+
+        let looping = AudioClipLoop {
+            loop_start: Seconds(4.0),
+            loop_end: Seconds(8.0),
+            crossfade: Seconds(0.0),
+        }
+        .to_region(SampleRate(1.0))
+        .to_proc_info(SampleTime(0), src.len())
+        .unwrap();
+
+        let mut dst = [0.0f32; 12];
+        looping.fill(&src, 2, &mut dst);
+        assert_eq!(dst, [2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 4.0, 5.0, 6.0, 7.0, 4.0, 5.0]);
+
+        // Starting in the middle of the unrolled loop must give the same values.
+        let mut dst_2 = [0.0f32; 4];
+        looping.fill(&src, 10, &mut dst_2);
+        assert_eq!(&dst[8..12], &dst_2[..]);
+
+        // The loop end is clamped to the end of the resource.
+        let looping = AudioClipLoop {
+            loop_start: Seconds(6.0),
+            loop_end: Seconds(20.0),
+            crossfade: Seconds(0.0),
+        }
+        .to_region(SampleRate(1.0))
+        .to_proc_info(SampleTime(0), src.len())
+        .unwrap();
+
+        looping.fill(&src, 8, &mut dst_2);
+        assert_eq!(dst_2, [8.0, 9.0, 6.0, 7.0]);
+
+        // The ranges of a stream must match the unrolled loop.
+        let mut ranges = Vec::new();
+        looping.for_each_range(8, 6, |pos, offset, frames| ranges.push((pos, offset, frames)));
+        assert_eq!(ranges, [(8, 0, 2), (6, 2, 4)]);
+
+        // Invalid loops.
+        assert!(AudioClipLoop {
+            loop_start: Seconds(6.0),
+            loop_end: Seconds(6.0),
+            crossfade: Seconds(0.0)
+        }
+        .to_region(SampleRate(1.0))
+        .to_proc_info(SampleTime(0), src.len())
+        .is_none());
+    }
+
+    #[test]
+    fn loop_crossfade() {
+        let src = [1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0];
+
+        let looping = AudioClipLoop {
+            loop_start: Seconds(4.0),
+            loop_end: Seconds(8.0),
+            crossfade: Seconds(10.0),
+        }
+        .to_region(SampleRate(1.0))
+        .to_proc_info(SampleTime(0), src.len())
+        .unwrap();
+
+        // The crossfade is shortened to half of the loop.
+        assert_eq!(looping.crossfade, 2);
+
+        let mut dst = [0.0f32; 6];
+        looping.fill(&src, 4, &mut dst);
+
+        assert_eq!(dst[0], 1.0);
+        assert_eq!(dst[1], 1.0);
+        // Crossfading with the frames before the loop start (which are `0.0`).
+        assert_eq!(dst[2], 1.0);
+        assert_eq!(dst[3], 0.5);
+        assert_eq!(dst[4], 1.0);
+    }
+}
@@ -3,45 +3,128 @@ use basedrop::{Handle, Shared, SharedCell};
 use rusty_daw_audio_graph::node::{DB_GRADIENT, SMOOTH_SECS};
 use rusty_daw_core::block_buffer::StereoBlockBuffer;
 use rusty_daw_core::{
-    MusicalTime, ParamF32, ParamF32Handle, SampleRate, SampleTime, Seconds, Unit,
+    Gradient, MusicalTime, ParamF32, ParamF32Handle, SampleRate, SampleTime, Seconds, Unit,
 };
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use tuix::Lens;
 
-use crate::backend::resource_loader::{AnyPcm, PcmLoadError, ResourceLoader};
+use crate::backend::dsp::resample::InterpolationQuality;
+use crate::backend::resource_loader::{AnyPcm, ChannelLayout, PcmMetadata, PeakCache};
 use crate::backend::{ResourceCache, MAX_BLOCKSIZE};
 
 use super::{AudioClipSaveState, TempoMap};
 
+use crossfade::AutoCrossfadesProcInfo;
+use gain_envelope::GainEnvelopeProcInfo;
+use looping::LoopRegion;
+use render_queue::RenderTarget;
+
+mod crossfade;
 mod declick;
+mod effects;
+mod gain_envelope;
+mod looping;
+mod pan;
+mod render_queue;
 mod resource;
 
+pub use crossfade::{
+    compute_auto_crossfades, AutoCrossfade, AutoCrossfadeKind, ClipOverlapPolicy,
+    DEFAULT_AUTO_CROSSFADE_TIME,
+};
 pub use declick::{AudioClipDeclick, DEFAULT_AUDIO_CLIP_DECLICK_TIME};
+pub use effects::{
+    AudioClipEffects, ConvolutionEffect, DcOffsetRemoval, ExtremeStretchEffect, NormalizeMode,
+    IR_TRIM_FADE_TIME,
+};
+pub use gain_envelope::{AudioClipGainEnvelope, EnvelopeShape, GainEnvelopePoint};
+pub use looping::AudioClipLoop;
+pub use pan::{
+    MultiChannelMode, PanLaw, StereoChannelMode, AUDIO_CLIP_PAN_MAX, AUDIO_CLIP_PAN_MIN,
+};
+pub use render_queue::{AudioClipRenderJob, AudioClipRenderQueue, AUDIO_CLIP_RENDER_THREADS};
 pub use resource::{AudioClipResource, AudioClipResourceCache};
 
 pub static AUDIO_CLIP_GAIN_MIN_DB: f32 = -40.0;
 pub static AUDIO_CLIP_GAIN_MAX_DB: f32 = 40.0;
 
+/// The shape of a fade in/out on an audio clip.
+///
+/// All curves are described as a fade in. Fade outs use the mirrored curve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FadeCurve {
+    /// The gain changes linearly.
+    #[default]
+    Linear,
+
+    /// The power of the signal stays constant when crossfading with an equal-power fade
+    /// in the opposite direction (sine curve).
+    EqualPower,
+
+    /// The gain changes slowly at first and quickly at the end (cubic curve).
+    Exponential,
+
+    /// The gain changes quickly at first and slowly at the end (inverted cubic curve).
+    Logarithmic,
+
+    /// The gain changes slowly at the start and end, and quickly in the middle.
+    SCurve,
+}
+
+impl FadeCurve {
+    /// Returns the gain of this curve at `x`, where `x` is the (linear) position
+    /// in the fade in the range `[0.0, 1.0]`.
+    #[inline]
+    pub fn gain(&self, x: f32) -> f32 {
+        match self {
+            FadeCurve::Linear => x,
+            FadeCurve::EqualPower => (x * std::f32::consts::FRAC_PI_2).sin(),
+            FadeCurve::Exponential => x * x * x,
+            FadeCurve::Logarithmic => {
+                let inv_x = 1.0 - x;
+                1.0 - (inv_x * inv_x * inv_x)
+            }
+            FadeCurve::SCurve => x * x * (3.0 - (2.0 * x)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Lens)]
 pub struct AudioClipFades {
     pub start_fade_duration: Seconds,
     pub end_fade_duration: Seconds,
+
+    pub start_fade_curve: FadeCurve,
+    pub end_fade_curve: FadeCurve,
 }
 
 impl AudioClipFades {
     pub const DEFAULT_FADE_DURATION: Seconds = Seconds(10.0 / 1_000.0);
 
     pub fn no_fade() -> Self {
-        Self { start_fade_duration: Seconds(0.0), end_fade_duration: Seconds(0.0) }
+        Self {
+            start_fade_duration: Seconds(0.0),
+            end_fade_duration: Seconds(0.0),
+            start_fade_curve: FadeCurve::Linear,
+            end_fade_curve: FadeCurve::Linear,
+        }
     }
 
     pub fn set_start_fade_duration(&mut self, duration: Seconds) {
-        self.start_fade_duration = Seconds(duration.0.min(0.0));
+        self.start_fade_duration = Seconds(duration.0.max(0.0));
     }
 
     pub fn set_end_fade_duration(&mut self, duration: Seconds) {
-        self.end_fade_duration = Seconds(duration.0.min(0.0));
+        self.end_fade_duration = Seconds(duration.0.max(0.0));
+    }
+
+    pub fn set_start_fade_curve(&mut self, curve: FadeCurve) {
+        self.start_fade_curve = curve;
+    }
+
+    pub fn set_end_fade_curve(&mut self, curve: FadeCurve) {
+        self.end_fade_curve = curve;
     }
 
     pub fn set_default_start_fade(&mut self) {
@@ -53,13 +136,13 @@ impl AudioClipFades {
     }
 
     fn to_proc_info(
-        &self,
+        self,
         sample_rate: SampleRate,
         timeline_start: SampleTime,
         timeline_end: SampleTime,
     ) -> AudioClipFadesProcInfo {
-        let start_fade_duration = Seconds(self.start_fade_duration.0.min(0.0));
-        let end_fade_duration = Seconds(self.end_fade_duration.0.min(0.0));
+        let start_fade_duration = Seconds(self.start_fade_duration.0.max(0.0));
+        let end_fade_duration = Seconds(self.end_fade_duration.0.max(0.0));
 
         let start_fade_duration =
             start_fade_duration.to_nearest_sample_round(sample_rate).0 as usize;
@@ -78,6 +161,9 @@ impl AudioClipFades {
             start_fade_delta,
             end_fade_delta,
 
+            start_fade_curve: self.start_fade_curve,
+            end_fade_curve: self.end_fade_curve,
+
             start_fade_timeline_end: timeline_start + SampleTime::from_usize(start_fade_duration),
             end_fade_timeline_start: timeline_end - SampleTime::from_usize(end_fade_duration),
         }
@@ -89,6 +175,8 @@ impl Default for AudioClipFades {
         Self {
             start_fade_duration: Self::DEFAULT_FADE_DURATION,
             end_fade_duration: Self::DEFAULT_FADE_DURATION,
+            start_fade_curve: FadeCurve::Linear,
+            end_fade_curve: FadeCurve::Linear,
         }
     }
 }
@@ -101,15 +189,22 @@ struct AudioClipFadesProcInfo {
     start_fade_delta: f32,
     end_fade_delta: f32,
 
+    start_fade_curve: FadeCurve,
+    end_fade_curve: FadeCurve,
+
     start_fade_timeline_end: SampleTime,
     end_fade_timeline_start: SampleTime,
 }
 
 pub struct AudioClipHandle {
     clip_gain_db: ParamF32Handle,
+    pan: ParamF32Handle,
 
     info: Shared<SharedCell<AudioClipProcInfo>>,
     coll_handle: Handle,
+
+    render_target: Arc<RenderTarget>,
+    render_job: Option<Arc<AudioClipRenderJob>>,
 }
 
 impl AudioClipHandle {
@@ -131,6 +226,60 @@ impl AudioClipHandle {
         gain_db
     }
 
+    /// Set the pan (or balance for stereo clips) of this audio clip, where `-1.0` is
+    /// hard left and `1.0` is hard right.
+    ///
+    /// Returns the pan (this may be clamped to fit within range of the pan parameter).
+    pub fn set_pan(&mut self, pan: f32, save_state: &mut AudioClipSaveState) -> f32 {
+        self.pan.set_value(pan);
+
+        // Make sure value is clamped within range.
+        let pan = self.pan.value();
+        save_state.pan = pan;
+
+        pan
+    }
+
+    /// Set the pan law used when panning this audio clip.
+    pub fn set_pan_law(&mut self, pan_law: PanLaw, save_state: &mut AudioClipSaveState) {
+        save_state.pan_law = pan_law;
+
+        let mut new_info = AudioClipProcInfo::clone(&self.info.get());
+        new_info.pan_law = pan_law;
+
+        self.info.set(Shared::new(&self.coll_handle, new_info));
+    }
+
+    /// Set how the channels of this audio clip are routed to the output. This has no
+    /// effect on mono audio clips.
+    pub fn set_channel_mode(
+        &mut self,
+        channel_mode: StereoChannelMode,
+        save_state: &mut AudioClipSaveState,
+    ) {
+        save_state.channel_mode = channel_mode;
+
+        let mut new_info = AudioClipProcInfo::clone(&self.info.get());
+        new_info.channel_mode = channel_mode;
+
+        self.info.set(Shared::new(&self.coll_handle, new_info));
+    }
+
+    /// Set how the channels of this audio clip are routed to the output when it has more
+    /// than two channels. This has no effect on mono and stereo audio clips.
+    pub fn set_multichannel_mode(
+        &mut self,
+        multichannel_mode: MultiChannelMode,
+        save_state: &mut AudioClipSaveState,
+    ) {
+        save_state.multichannel_mode = multichannel_mode;
+
+        let mut new_info = AudioClipProcInfo::clone(&self.info.get());
+        new_info.multichannel_mode = multichannel_mode;
+
+        self.info.set(Shared::new(&self.coll_handle, new_info));
+    }
+
     /// Set where the clip starts on the timeline.
     pub fn set_timeline_start(
         &mut self,
@@ -194,23 +343,102 @@ impl AudioClipHandle {
     }
 
     /// Set the PCM resource to use from the given path to an audio file.
+    ///
+    /// The file is loaded and rendered on a background thread if it is not already
+    /// cached (see `render_job()`). Any errors that occur while loading are reported by
+    /// the render job.
     pub fn set_pcm(
         &mut self,
         pcm_path: PathBuf,
-        resource_loader: &Arc<Mutex<ResourceLoader>>,
-        cache: &Arc<Mutex<AudioClipResourceCache>>,
+        resource_cache: &ResourceCache,
         save_state: &mut AudioClipSaveState,
-    ) -> Result<(), PcmLoadError> {
-        let (resource, pcm_load_res) = { cache.lock().unwrap().cache(save_state, resource_loader) };
-
+    ) {
         save_state.pcm_path = pcm_path;
 
+        self.update_resource(resource_cache, save_state)
+    }
+
+    /// Set the offline effects (normalize, DC offset removal, stretching, convolution)
+    /// of this audio clip.
+    ///
+    /// The new resource is rendered on a background thread if one with the same effects
+    /// is not already cached (see `render_job()`). Until then, the clip keeps playing its
+    /// current resource.
+    pub fn set_effects(
+        &mut self,
+        effects: AudioClipEffects,
+        resource_cache: &ResourceCache,
+        save_state: &mut AudioClipSaveState,
+    ) {
+        save_state.effects = effects;
+
+        self.update_resource(resource_cache, save_state)
+    }
+
+    /// Set the interpolation used when the audio file of this clip is resampled to the
+    /// project's sample rate.
+    ///
+    /// The new resource is rendered on a background thread if one with the same
+    /// interpolation is not already cached (see `render_job()`). This has no effect if the
+    /// audio file is already at the project's sample rate (unless the clip has a
+    /// convolution effect whose impulse response is resampled).
+    pub fn set_interpolation_quality(
+        &mut self,
+        interpolation_quality: InterpolationQuality,
+        resource_cache: &ResourceCache,
+        save_state: &mut AudioClipSaveState,
+    ) {
+        save_state.interpolation_quality = interpolation_quality;
+
+        self.update_resource(resource_cache, save_state)
+    }
+
+    /// Returns the most recent background load and render of this clip's PCM resource.
+    /// This is `None` if the resource was already cached.
+    pub fn render_job(&self) -> Option<&AudioClipRenderJob> {
+        self.render_job.as_deref()
+    }
+
+    /// Cancel the background load and render of this clip's PCM resource (if it is still
+    /// in progress). The clip keeps playing its current resource.
+    pub fn cancel_render(&mut self) {
+        self.render_target.cancel();
+    }
+
+    fn update_resource(&mut self, resource_cache: &ResourceCache, save_state: &AudioClipSaveState) {
+        // This also cancels the previous render (if it is still in progress).
+        self.render_job =
+            resource_cache.audio_clip_render_queue.request(save_state, &self.render_target);
+    }
+
+    /// Set the gain envelope (automation) of this audio clip.
+    pub fn set_gain_envelope(
+        &mut self,
+        gain_envelope: AudioClipGainEnvelope,
+        tempo_map: &TempoMap,
+        save_state: &mut AudioClipSaveState,
+    ) {
         let mut new_info = AudioClipProcInfo::clone(&self.info.get());
-        new_info.resource = resource;
+        new_info.gain_envelope = gain_envelope.to_proc_info(tempo_map.sample_rate);
+
+        save_state.gain_envelope = gain_envelope;
 
         self.info.set(Shared::new(&self.coll_handle, new_info));
+    }
+
+    /// Set the loop region of this audio clip. Set this to `None` to disable looping.
+    pub fn set_looping(
+        &mut self,
+        looping: Option<AudioClipLoop>,
+        tempo_map: &TempoMap,
+        save_state: &mut AudioClipSaveState,
+    ) {
+        save_state.looping = looping;
 
-        pcm_load_res
+        let mut new_info = AudioClipProcInfo::clone(&self.info.get());
+        new_info.looping = looping.map(|looping| looping.to_region(tempo_map.sample_rate));
+
+        self.info.set(Shared::new(&self.coll_handle, new_info));
     }
 
     pub fn set_fades(
@@ -231,6 +459,59 @@ impl AudioClipHandle {
         self.info.set(Shared::new(&self.coll_handle, new_info));
     }
 
+    /// Returns the PCM resource currently used by this audio clip.
+    ///
+    /// This is an empty placeholder resource while the first render of this clip's
+    /// resource is in progress.
+    pub fn resource(&self) -> Shared<AudioClipResource> {
+        self.render_target.resource()
+    }
+
+    /// Returns the waveform peaks of this clip's audio file, or `None` if they are not
+    /// loaded yet. The peaks are loaded on a background thread after the file is loaded.
+    ///
+    /// Note the peaks are of the original file, so regions of the clip must be converted
+    /// to frames at the sample rate of the file (see `PeakCache::sample_rate()`).
+    pub fn peaks(
+        &self,
+        resource_cache: &ResourceCache,
+        save_state: &AudioClipSaveState,
+    ) -> Option<Arc<PeakCache>> {
+        resource_cache.resource_loader.lock().unwrap().peak_loader.get(&save_state.pcm_path)
+    }
+
+    /// Returns the metadata of this clip's audio file (tempo, loop points, markers,
+    /// recording time, etc), or `None` if the file is not loaded yet.
+    ///
+    /// Note positions in the metadata are in frames at the sample rate of the file (see
+    /// `PcmMetadata::frame_to_seconds()`).
+    pub fn metadata(
+        &self,
+        resource_cache: &ResourceCache,
+        save_state: &AudioClipSaveState,
+    ) -> Option<Arc<PcmMetadata>> {
+        resource_cache.resource_loader.lock().unwrap().pcm_loader.metadata(&save_state.pcm_path)
+    }
+
+    /// Returns the (start, end) of this clip on the timeline in samples.
+    pub(super) fn timeline_range(&self) -> (SampleTime, SampleTime) {
+        let info = self.info.get();
+        (info.timeline_start, info.timeline_end)
+    }
+
+    /// Set the automatic crossfades computed by the timeline track.
+    pub(super) fn set_auto_crossfades(&mut self, auto_crossfades: Vec<AutoCrossfade>) {
+        if auto_crossfades.is_empty() && self.info.get().auto_crossfades.is_empty() {
+            // Nothing has changed.
+            return;
+        }
+
+        let mut new_info = AudioClipProcInfo::clone(&self.info.get());
+        new_info.auto_crossfades = AutoCrossfadesProcInfo::new(auto_crossfades);
+
+        self.info.set(Shared::new(&self.coll_handle, new_info));
+    }
+
     pub(super) fn update_tempo_map(
         &mut self,
         tempo_map: &TempoMap,
@@ -247,6 +528,8 @@ impl AudioClipHandle {
             new_info.timeline_start,
             new_info.timeline_end,
         );
+        new_info.looping =
+            save_state.looping.map(|looping| looping.to_region(tempo_map.sample_rate));
 
         self.info.set(Shared::new(&self.coll_handle, new_info));
     }
@@ -254,6 +537,22 @@ impl AudioClipHandle {
 
 struct AudioClipParams {
     pub clip_gain_amp: ParamF32<MAX_BLOCKSIZE>,
+
+    pub pan: ParamF32<MAX_BLOCKSIZE>,
+
+    /// Scratch buffer used to combine the clip gain with the gain envelope.
+    pub gain_buffer: [f32; MAX_BLOCKSIZE],
+
+    /// Scratch buffers used to combine the gain with the pan.
+    pub pan_gain_left: [f32; MAX_BLOCKSIZE],
+    pub pan_gain_right: [f32; MAX_BLOCKSIZE],
+
+    /// Scratch buffers used to read the frames of a looping or streamed clip.
+    pub loop_left: [f32; MAX_BLOCKSIZE],
+    pub loop_right: [f32; MAX_BLOCKSIZE],
+
+    /// Scratch buffer used to downmix a clip with more than two channels.
+    pub channel_scratch: [f32; MAX_BLOCKSIZE],
 }
 
 #[derive(Clone)]
@@ -261,13 +560,19 @@ pub(super) struct AudioClipProcInfo {
     pub(super) timeline_start: SampleTime,
     pub(super) timeline_end: SampleTime,
 
-    // Audio clip resources are always immutable. This reflects the non-destructive nature
-    // of this sampler engine.
-    resource: Shared<AudioClipResource>,
-
     clip_start_offset: SampleTime,
 
     fades: AudioClipFadesProcInfo,
+
+    gain_envelope: GainEnvelopeProcInfo,
+
+    auto_crossfades: AutoCrossfadesProcInfo,
+
+    looping: Option<LoopRegion>,
+
+    pan_law: PanLaw,
+    channel_mode: StereoChannelMode,
+    multichannel_mode: MultiChannelMode,
 }
 
 #[derive(Clone)]
@@ -278,6 +583,13 @@ pub struct AudioClipProcess {
     params: Shared<AtomicRefCell<AudioClipParams>>,
 
     pub(super) info: Shared<SharedCell<AudioClipProcInfo>>,
+
+    // Audio clip resources are always immutable. This reflects the non-destructive nature
+    // of this sampler engine.
+    //
+    // This is separate from the rest of the info, since it is swapped in by the render
+    // threads once a new resource is rendered.
+    resource: Shared<SharedCell<AudioClipResource>>,
 }
 
 impl AudioClipProcess {
@@ -286,7 +598,7 @@ impl AudioClipProcess {
         resource_cache: &ResourceCache,
         tempo_map: &TempoMap,
         coll_handle: &Handle,
-    ) -> (Self, AudioClipHandle, Result<(), PcmLoadError>) {
+    ) -> (Self, AudioClipHandle) {
         let clip_gain_db =
             save_state.clip_gain_db.clamp(AUDIO_CLIP_GAIN_MIN_DB, AUDIO_CLIP_GAIN_MAX_DB);
 
@@ -300,13 +612,23 @@ impl AudioClipProcess {
             tempo_map.sample_rate,
         );
 
-        let (resource, pcm_load_res) = {
-            resource_cache
-                .audio_clip_resource_cache
-                .lock()
-                .unwrap()
-                .cache(save_state, &resource_cache.resource_loader)
-        };
+        let (pan, pan_handle) = ParamF32::from_value(
+            save_state.pan.clamp(AUDIO_CLIP_PAN_MIN, AUDIO_CLIP_PAN_MAX),
+            AUDIO_CLIP_PAN_MIN,
+            AUDIO_CLIP_PAN_MAX,
+            Gradient::Linear,
+            Unit::Generic,
+            SMOOTH_SECS,
+            tempo_map.sample_rate,
+        );
+
+        // Play silence until the resource is rendered.
+        let placeholder =
+            { resource_cache.audio_clip_resource_cache.lock().unwrap().placeholder() };
+        let resource = Shared::new(coll_handle, SharedCell::new(placeholder));
+
+        let render_target = Arc::new(RenderTarget::new(Shared::clone(&resource)));
+        let render_job = resource_cache.audio_clip_render_queue.request(save_state, &render_target);
 
         let timeline_start = tempo_map.musical_to_nearest_sample_round(save_state.timeline_start);
         let timeline_end = tempo_map.seconds_to_nearest_sample_round(
@@ -318,7 +640,6 @@ impl AudioClipProcess {
             SharedCell::new(Shared::new(
                 coll_handle,
                 AudioClipProcInfo {
-                    resource,
                     timeline_start,
                     timeline_end,
                     clip_start_offset: save_state
@@ -329,6 +650,14 @@ impl AudioClipProcess {
                         timeline_start,
                         timeline_end,
                     ),
+                    gain_envelope: save_state.gain_envelope.to_proc_info(tempo_map.sample_rate),
+                    auto_crossfades: Default::default(),
+                    looping: save_state
+                        .looping
+                        .map(|looping| looping.to_region(tempo_map.sample_rate)),
+                    pan_law: save_state.pan_law,
+                    channel_mode: save_state.channel_mode,
+                    multichannel_mode: save_state.multichannel_mode,
                 },
             )),
         );
@@ -337,15 +666,79 @@ impl AudioClipProcess {
             Self {
                 params: Shared::new(
                     &coll_handle,
-                    AtomicRefCell::new(AudioClipParams { clip_gain_amp: gain_amp }),
+                    AtomicRefCell::new(AudioClipParams {
+                        clip_gain_amp: gain_amp,
+                        pan,
+                        gain_buffer: [0.0; MAX_BLOCKSIZE],
+                        pan_gain_left: [0.0; MAX_BLOCKSIZE],
+                        pan_gain_right: [0.0; MAX_BLOCKSIZE],
+                        loop_left: [0.0; MAX_BLOCKSIZE],
+                        loop_right: [0.0; MAX_BLOCKSIZE],
+                        channel_scratch: [0.0; MAX_BLOCKSIZE],
+                    }),
                 ),
                 info: Shared::clone(&info),
+                resource,
+            },
+            AudioClipHandle {
+                clip_gain_db: gain_handle,
+                pan: pan_handle,
+                info,
+                coll_handle: coll_handle.clone(),
+                render_target,
+                render_job,
             },
-            AudioClipHandle { clip_gain_db: gain_handle, info, coll_handle: coll_handle.clone() },
-            pcm_load_res,
         )
     }
 
+    /// Tell the stream of a clip streamed from disk which frames are going to be read
+    /// next, so they can be decoded ahead of time. This does nothing if the clip is not
+    /// streamed.
+    ///
+    /// `transport_loop_start` - The start of the transport loop (if it is active), so the
+    /// frames played after the transport loops back are kept in memory.
+    ///
+    /// This is realtime safe.
+    pub fn prefetch(&self, playhead: SampleTime, transport_loop_start: Option<SampleTime>) {
+        let resource = self.resource.get();
+
+        let stream = if let Some(stream) = &resource.stream {
+            stream
+        } else {
+            return;
+        };
+
+        let info = self.info.get();
+
+        let looping = info
+            .looping
+            .and_then(|looping| looping.to_proc_info(resource.original_offset, resource.len()));
+
+        // Map a position on the timeline to a frame in the stream.
+        let to_frame = |timeline_pos: SampleTime| -> usize {
+            // Positions before the clip map to where the clip starts playing.
+            let timeline_pos =
+                if timeline_pos < info.timeline_start { info.timeline_start } else { timeline_pos };
+
+            let frame = (timeline_pos - info.timeline_start + info.clip_start_offset
+                - resource.original_offset)
+                .0
+                .max(0) as usize;
+
+            if let Some(looping) = &looping {
+                looping.wrap(frame)
+            } else {
+                frame
+            }
+        };
+
+        let mut stream = stream.stream.borrow_mut();
+
+        stream.set_loop(looping.as_ref().map(|looping| looping.range()));
+        stream.set_pinned_frame(transport_loop_start.map(to_frame));
+        stream.prefetch(to_frame(playhead));
+    }
+
     pub fn process(
         &self,
         playhead: SampleTime,
@@ -354,9 +747,16 @@ impl AudioClipProcess {
         out_offset: usize,
     ) {
         let info = self.info.get();
+        let resource = self.resource.get();
+
+        let looping = info
+            .looping
+            .and_then(|looping| looping.to_proc_info(resource.original_offset, resource.len()));
 
-        let mut params = self.params.borrow_mut();
+        let mut params_ref = self.params.borrow_mut();
+        let params = &mut *params_ref;
         let amp = params.clip_gain_amp.smoothed(frames);
+        let pan = params.pan.smoothed(frames);
 
         let mut copy_frames = frames;
         let mut copy_out_offset = out_offset;
@@ -364,9 +764,10 @@ impl AudioClipProcess {
 
         // Find the sample to start reading from in the PCM resource.
         let pcm_start =
-            playhead - info.timeline_start + info.clip_start_offset - info.resource.original_offset;
+            playhead - info.timeline_start + info.clip_start_offset - resource.original_offset;
 
-        if pcm_start >= SampleTime::from_usize(info.resource.pcm.len()) {
+        // Looping clips keep playing past the end of the loop until the end of the clip.
+        if looping.is_none() && pcm_start >= SampleTime::from_usize(resource.len()) {
             // Out of range. Do nothing (add silence).
             return;
         }
@@ -387,176 +788,630 @@ impl AudioClipProcess {
             pcm_start.0 as usize
         };
 
-        if pcm_start + copy_frames > info.resource.pcm.len() {
+        if looping.is_none() && pcm_start + copy_frames > resource.len() {
             // Skip frames (add silence) after the end of the resource.
-            copy_frames = info.resource.pcm.len() - pcm_start;
+            copy_frames = resource.len() - pcm_start;
         }
 
-        let amp = if amp.is_smoothing() {
-            Some(amp)
-        } else if amp[0] != 1.0 {
-            Some(amp)
+        let apply_clip_gain = amp.is_smoothing() || amp[0] != 1.0;
+
+        // The position on the timeline of the first copied frame.
+        let copy_playhead = playhead + SampleTime::from_usize(skip);
+
+        let do_auto_crossfades = info.auto_crossfades.is_active(copy_playhead, copy_frames);
+
+        let amp = if info.gain_envelope.is_active() || do_auto_crossfades {
+            let gain_buffer = &mut params.gain_buffer[0..copy_frames];
+
+            if info.gain_envelope.is_active() {
+                // Evaluate the gain envelope for the frames that will be copied.
+                info.gain_envelope.process(copy_playhead - info.timeline_start, gain_buffer);
+            } else {
+                for gain in gain_buffer.iter_mut() {
+                    *gain = 1.0;
+                }
+            }
+
+            if do_auto_crossfades {
+                info.auto_crossfades.process(copy_playhead, gain_buffer);
+            }
+
+            if apply_clip_gain {
+                for (gain, amp) in
+                    gain_buffer.iter_mut().zip(amp.values[skip..skip + copy_frames].iter())
+                {
+                    *gain *= *amp;
+                }
+            }
+
+            Some(&params.gain_buffer[0..copy_frames])
+        } else if apply_clip_gain {
+            Some(&amp.values[skip..skip + copy_frames])
         } else {
             // Don't need to apply gain if amp is 1.0.
             None
         };
 
+        let amp = if pan.is_smoothing() || pan[0] != 0.0 {
+            // Mono signals are panned, while stereo signals use balance instead.
+            let is_mono = resource.is_mono()
+                || info.channel_mode == StereoChannelMode::SumToMono
+                || (resource.num_channels() > 2
+                    && matches!(info.multichannel_mode, MultiChannelMode::SingleChannel(_)));
+
+            let pan_gain_left = &mut params.pan_gain_left[0..copy_frames];
+            let pan_gain_right = &mut params.pan_gain_right[0..copy_frames];
+
+            if pan.is_smoothing() {
+                let pan = &pan.values[skip..skip + copy_frames];
+
+                for i in 0..copy_frames {
+                    let (left, right) = if is_mono {
+                        info.pan_law.mono_gains(pan[i])
+                    } else {
+                        info.pan_law.balance_gains(pan[i])
+                    };
+
+                    pan_gain_left[i] = left;
+                    pan_gain_right[i] = right;
+                }
+            } else {
+                let (left, right) = if is_mono {
+                    info.pan_law.mono_gains(pan[0])
+                } else {
+                    info.pan_law.balance_gains(pan[0])
+                };
+
+                for i in 0..copy_frames {
+                    pan_gain_left[i] = left;
+                    pan_gain_right[i] = right;
+                }
+            }
+
+            if let Some(amp) = amp {
+                for i in 0..copy_frames {
+                    pan_gain_left[i] *= amp[i];
+                    pan_gain_right[i] *= amp[i];
+                }
+            }
+
+            Some((&params.pan_gain_left[0..copy_frames], &params.pan_gain_right[0..copy_frames]))
+        } else {
+            amp.map(|amp| (amp, amp))
+        };
+
+        let src = if let Some(streamed) = &resource.stream {
+            // Read the frames from the stream into the scratch buffers. Frames that were
+            // not decoded in time are played as silence.
+            let mut stream = streamed.stream.borrow_mut();
+            let loop_left = &mut params.loop_left[0..copy_frames];
+            let loop_right = &mut params.loop_right[0..copy_frames];
+
+            if streamed.info.num_channels > 2 {
+                let read = |channel: usize, dst: &mut [f32]| {
+                    if let Some(looping) = &looping {
+                        looping.for_each_range(
+                            pcm_start,
+                            copy_frames,
+                            |src_pos, offset, frames| {
+                                stream.read_channel(
+                                    src_pos,
+                                    channel,
+                                    &mut dst[offset..offset + frames],
+                                );
+                            },
+                        );
+                    } else {
+                        stream.read_channel(pcm_start, channel, dst);
+                    }
+                };
+
+                read_multichannel(
+                    streamed.info.num_channels,
+                    streamed.layout.as_ref(),
+                    info.multichannel_mode,
+                    read,
+                    loop_left,
+                    loop_right,
+                    &mut params.channel_scratch[0..copy_frames],
+                )
+            } else {
+                if let Some(looping) = &looping {
+                    looping.for_each_range(pcm_start, copy_frames, |src_pos, offset, frames| {
+                        stream.read(
+                            src_pos,
+                            &mut loop_left[offset..offset + frames],
+                            &mut loop_right[offset..offset + frames],
+                        );
+                    });
+                } else {
+                    stream.read(pcm_start, loop_left, loop_right);
+                }
+
+                if resource.is_mono() {
+                    simd::Source::Mono(&params.loop_left[0..copy_frames])
+                } else {
+                    simd::Source::Stereo(
+                        &params.loop_left[0..copy_frames],
+                        &params.loop_right[0..copy_frames],
+                    )
+                }
+            }
+        } else {
+            match &*resource.pcm {
+                // Resources stored as `f32` can be read directly when not looping.
+                AnyPcm::Mono(pcm) if looping.is_none() => {
+                    simd::Source::Mono(&pcm.data()[pcm_start..pcm_start + copy_frames])
+                }
+                AnyPcm::Stereo(pcm) if looping.is_none() => simd::Source::Stereo(
+                    &pcm.left()[pcm_start..pcm_start + copy_frames],
+                    &pcm.right()[pcm_start..pcm_start + copy_frames],
+                ),
+                pcm => {
+                    // Read the frames into the scratch buffers, converting them to `f32` and
+                    // unrolling the loop (if needed).
+                    let read = |channel: usize, dst: &mut [f32]| {
+                        if let Some(looping) = &looping {
+                            looping.fill_channel(pcm.channel(channel), pcm_start, dst);
+                        } else {
+                            pcm.channel(channel).read(pcm_start, dst);
+                        }
+                    };
+
+                    let loop_left = &mut params.loop_left[0..copy_frames];
+                    let loop_right = &mut params.loop_right[0..copy_frames];
+
+                    match pcm.num_channels() {
+                        1 => {
+                            read(0, loop_left);
+
+                            simd::Source::Mono(loop_left)
+                        }
+                        2 => {
+                            read(0, loop_left);
+                            read(1, loop_right);
+
+                            simd::Source::Stereo(loop_left, loop_right)
+                        }
+                        num_channels => read_multichannel(
+                            num_channels,
+                            pcm.layout(),
+                            info.multichannel_mode,
+                            read,
+                            loop_left,
+                            loop_right,
+                            &mut params.channel_scratch[0..copy_frames],
+                        ),
+                    }
+                }
+            }
+        };
+
         // Apply gain to the samples and add them to the output.
         //
         // TODO: SIMD optimizations.
-        simd::process_fallback(
-            playhead,
-            &*info,
-            out,
-            amp,
-            copy_out_offset,
-            pcm_start,
-            skip,
-            copy_frames,
-        )
+        simd::process_fallback(copy_playhead, &*info, src, out, amp, copy_out_offset, copy_frames)
+    }
+}
+
+/// Select or downmix the channels of a PCM resource or stream with more than two channels
+/// into the `left` and `right` scratch buffers.
+///
+/// `read` - Reads the frames of the given channel into the given buffer.
+///
+/// `scratch` - Scratch buffer used when downmixing.
+fn read_multichannel<'a, R: FnMut(usize, &mut [f32])>(
+    num_channels: usize,
+    layout: Option<&ChannelLayout>,
+    mode: MultiChannelMode,
+    mut read: R,
+    left: &'a mut [f32],
+    right: &'a mut [f32],
+    scratch: &mut [f32],
+) -> simd::Source<'a> {
+    let last_channel = num_channels - 1;
+
+    match mode {
+        MultiChannelMode::DownmixToStereo => {
+            for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+                *l = 0.0;
+                *r = 0.0;
+            }
+
+            if let Some(layout) = layout {
+                for channel in 0..layout.num_channels() {
+                    let (gain_l, gain_r) = layout.downmix_gains(channel);
+                    if gain_l == 0.0 && gain_r == 0.0 {
+                        continue;
+                    }
+
+                    read(channel, scratch);
+
+                    for ((l, r), smp) in left.iter_mut().zip(right.iter_mut()).zip(scratch.iter()) {
+                        *l += *smp * gain_l;
+                        *r += *smp * gain_r;
+                    }
+                }
+            }
+
+            simd::Source::Stereo(left, right)
+        }
+        MultiChannelMode::ChannelPair { left: left_index, right: right_index } => {
+            read(left_index.min(last_channel), left);
+            read(right_index.min(last_channel), right);
+
+            simd::Source::Stereo(left, right)
+        }
+        MultiChannelMode::SingleChannel(index) => {
+            read(index.min(last_channel), left);
+
+            simd::Source::Mono(left)
+        }
     }
 }
 
 mod simd {
-    use super::{AnyPcm, AudioClipProcInfo, StereoBlockBuffer};
+    use super::{AudioClipProcInfo, FadeCurve, StereoBlockBuffer, StereoChannelMode};
     use crate::backend::MAX_BLOCKSIZE;
-    use rusty_daw_core::{SampleTime, SmoothOutputF32};
+    use rusty_daw_core::SampleTime;
+
+    /// The frames to copy from the PCM resource.
+    pub(super) enum Source<'a> {
+        Mono(&'a [f32]),
+        Stereo(&'a [f32], &'a [f32]),
+    }
 
+    /// `playhead` - The position on the timeline of the first copied frame.
+    ///
+    /// `src` - The frames to copy.
+    ///
+    /// `amp` - The (left, right) gain to apply to each copied frame (if any gain needs to
+    /// be applied).
     pub(super) fn process_fallback(
         playhead: SampleTime,
         info: &AudioClipProcInfo,
+        src: Source,
         out: &mut StereoBlockBuffer<f32, MAX_BLOCKSIZE>,
-        amp: Option<SmoothOutputF32<MAX_BLOCKSIZE>>,
+        amp: Option<(&[f32], &[f32])>,
         copy_out_offset: usize,
-        pcm_start: usize,
-        skip: usize,
         frames: usize,
     ) {
         // Hint to compiler to optimize loops.
         let frames = frames.min(MAX_BLOCKSIZE);
 
-        // Calculate fades.
-        let mut do_fades = false;
-        let (mut start_fade_amp, start_fade_delta) =
-            if playhead >= info.timeline_start && playhead < info.fades.start_fade_timeline_end {
-                do_fades = true;
+        let mut fades = Fades::new(playhead, frames, info);
+
+        let out_left = &mut out.left[copy_out_offset..copy_out_offset + frames];
+        let out_right = &mut out.right[copy_out_offset..copy_out_offset + frames];
+        match src {
+            Source::Mono(src) => {
+                let src = &src[0..frames];
+
+                mix_mono(|i| src[i], out_left, out_right, amp, &mut fades, frames);
+            }
+            Source::Stereo(src_left, src_right) => {
+                let src_left = &src_left[0..frames];
+                let src_right = &src_right[0..frames];
+
+                match info.channel_mode {
+                    StereoChannelMode::Normal => mix_stereo(
+                        src_left, src_right, out_left, out_right, amp, &mut fades, frames,
+                    ),
+                    StereoChannelMode::Swap => mix_stereo(
+                        src_right, src_left, out_left, out_right, amp, &mut fades, frames,
+                    ),
+                    StereoChannelMode::SumToMono => mix_mono(
+                        |i| (src_left[i] + src_right[i]) * 0.5,
+                        out_left,
+                        out_right,
+                        amp,
+                        &mut fades,
+                        frames,
+                    ),
+                }
+            }
+        }
+    }
+
+    /// The state of the fades in the current process cycle.
+    struct Fades {
+        do_fades: bool,
+
+        /// The linear position in the start fade, where `0.0` is the start of the fade
+        /// and `1.0` is the end of the fade.
+        start_fade_x: f32,
+        start_fade_delta: f32,
+        start_fade_curve: FadeCurve,
+
+        /// The linear position in the end fade, where `1.0` is the start of the fade
+        /// and `0.0` is the end of the fade.
+        end_fade_x: f32,
+        end_fade_delta: f32,
+        end_fade_curve: FadeCurve,
+    }
+
+    impl Fades {
+        /// `playhead` - The position on the timeline of the first copied frame.
+        fn new(playhead: SampleTime, frames: usize, info: &AudioClipProcInfo) -> Self {
+            let fades = &info.fades;
+            let end_frame = playhead + SampleTime::from_usize(frames);
+
+            let do_start_fade = fades.start_fade_delta != 0.0
+                && playhead < fades.start_fade_timeline_end
+                && info.timeline_start < end_frame;
+            let do_end_fade = fades.end_fade_delta != 0.0
+                && playhead < info.timeline_end
+                && fades.end_fade_timeline_start < end_frame;
 
+            let (start_fade_x, start_fade_delta) = if do_start_fade {
                 (
-                    (playhead - info.timeline_start).0 as f32 * info.fades.start_fade_delta,
-                    info.fades.start_fade_delta,
+                    (playhead - info.timeline_start).0 as f32 * fades.start_fade_delta,
+                    fades.start_fade_delta,
                 )
             } else {
                 (1.0, 0.0)
             };
-        let (mut end_fade_amp, end_fade_delta) =
-            if playhead >= info.fades.end_fade_timeline_start && playhead < info.timeline_end {
-                do_fades = true;
-
+            let (end_fade_x, end_fade_delta) = if do_end_fade {
                 (
-                    1.0 - ((playhead - info.fades.end_fade_timeline_start).0 as f32
-                        * info.fades.end_fade_delta),
-                    info.fades.end_fade_delta,
+                    (info.timeline_end - playhead).0 as f32 * fades.end_fade_delta,
+                    fades.end_fade_delta,
                 )
             } else {
                 (1.0, 0.0)
             };
 
-        let out_left = &mut out.left[copy_out_offset..copy_out_offset + frames];
-        let out_right = &mut out.right[copy_out_offset..copy_out_offset + frames];
-        match &*info.resource.pcm {
-            AnyPcm::Mono(pcm) => {
-                let src = &pcm.data()[pcm_start..pcm_start + frames];
+            Self {
+                do_fades: do_start_fade || do_end_fade,
 
-                if let Some(amp) = amp {
-                    // Hint to compiler to optimize loops.
-                    let skip = skip.min(MAX_BLOCKSIZE - frames);
+                start_fade_x,
+                start_fade_delta,
+                start_fade_curve: fades.start_fade_curve,
 
-                    if do_fades {
-                        for i in 0..frames {
-                            let amp = &amp.values[skip..skip + frames];
+                end_fade_x,
+                end_fade_delta,
+                end_fade_curve: fades.end_fade_curve,
+            }
+        }
 
-                            let total_amp = amp[i] * start_fade_amp * end_fade_amp;
+        /// Returns the total fade gain of the current frame and advances to the next frame.
+        #[inline]
+        fn next(&mut self) -> f32 {
+            let total_amp = self.start_fade_curve.gain(self.start_fade_x.clamp(0.0, 1.0))
+                * self.end_fade_curve.gain(self.end_fade_x.clamp(0.0, 1.0));
 
-                            out_left[i] += src[i] * total_amp;
-                            out_right[i] += src[i] * total_amp;
+            self.start_fade_x += self.start_fade_delta;
+            self.end_fade_x -= self.end_fade_delta;
 
-                            start_fade_amp = (start_fade_amp + start_fade_delta).min(1.0);
-                            end_fade_amp = (end_fade_amp - end_fade_delta).max(0.0);
-                        }
-                    } else {
-                        for i in 0..frames {
-                            let amp = &amp.values[skip..skip + frames];
+            total_amp
+        }
+    }
 
-                            out_left[i] += src[i] * amp[i];
-                            out_right[i] += src[i] * amp[i];
-                        }
-                    }
-                } else {
-                    if do_fades {
-                        for i in 0..frames {
-                            let total_amp = start_fade_amp * end_fade_amp;
+    #[inline]
+    fn mix_mono<F: Fn(usize) -> f32>(
+        src: F,
+        out_left: &mut [f32],
+        out_right: &mut [f32],
+        amp: Option<(&[f32], &[f32])>,
+        fades: &mut Fades,
+        frames: usize,
+    ) {
+        // Hint to compiler to optimize loops.
+        let frames = frames.min(MAX_BLOCKSIZE);
+        let out_left = &mut out_left[0..frames];
+        let out_right = &mut out_right[0..frames];
 
-                            out_left[i] += src[i] * total_amp;
-                            out_right[i] += src[i] * total_amp;
+        if let Some((amp_left, amp_right)) = amp {
+            let amp_left = &amp_left[0..frames];
+            let amp_right = &amp_right[0..frames];
 
-                            start_fade_amp = (start_fade_amp + start_fade_delta).min(1.0);
-                            end_fade_amp = (end_fade_amp - end_fade_delta).max(0.0);
-                        }
-                    } else {
-                        for i in 0..frames {
-                            out_left[i] += src[i];
-                            out_right[i] += src[i];
-                        }
-                    }
+            if fades.do_fades {
+                for i in 0..frames {
+                    let smp = src(i) * fades.next();
+
+                    out_left[i] += smp * amp_left[i];
+                    out_right[i] += smp * amp_right[i];
                 }
-            }
-            AnyPcm::Stereo(pcm) => {
-                let src_left = &pcm.left()[pcm_start..pcm_start + frames];
-                let src_right = &pcm.right()[pcm_start..pcm_start + frames];
+            } else {
+                for i in 0..frames {
+                    let smp = src(i);
 
-                if let Some(amp) = amp {
-                    // Hint to compiler to optimize loops.
-                    let skip = skip.min(MAX_BLOCKSIZE - frames);
+                    out_left[i] += smp * amp_left[i];
+                    out_right[i] += smp * amp_right[i];
+                }
+            }
+        } else {
+            if fades.do_fades {
+                for i in 0..frames {
+                    let smp = src(i) * fades.next();
 
-                    if do_fades {
-                        for i in 0..frames {
-                            let amp = &amp.values[skip..skip + frames];
+                    out_left[i] += smp;
+                    out_right[i] += smp;
+                }
+            } else {
+                for i in 0..frames {
+                    let smp = src(i);
 
-                            let total_amp = amp[i] * start_fade_amp * end_fade_amp;
+                    out_left[i] += smp;
+                    out_right[i] += smp;
+                }
+            }
+        }
+    }
 
-                            out_left[i] += src_left[i] * total_amp;
-                            out_right[i] += src_right[i] * total_amp;
+    #[inline]
+    fn mix_stereo(
+        src_left: &[f32],
+        src_right: &[f32],
+        out_left: &mut [f32],
+        out_right: &mut [f32],
+        amp: Option<(&[f32], &[f32])>,
+        fades: &mut Fades,
+        frames: usize,
+    ) {
+        // Hint to compiler to optimize loops.
+        let frames = frames.min(MAX_BLOCKSIZE);
+        let src_left = &src_left[0..frames];
+        let src_right = &src_right[0..frames];
+        let out_left = &mut out_left[0..frames];
+        let out_right = &mut out_right[0..frames];
 
-                            start_fade_amp = (start_fade_amp + start_fade_delta).min(1.0);
-                            end_fade_amp = (end_fade_amp - end_fade_delta).max(0.0);
-                        }
-                    } else {
-                        for i in 0..frames {
-                            let amp = &amp.values[skip..skip + frames];
+        if let Some((amp_left, amp_right)) = amp {
+            let amp_left = &amp_left[0..frames];
+            let amp_right = &amp_right[0..frames];
 
-                            out_left[i] += src_left[i] * amp[i];
-                            out_right[i] += src_right[i] * amp[i];
-                        }
-                    }
-                } else {
-                    if do_fades {
-                        for i in 0..frames {
-                            let total_amp = start_fade_amp * end_fade_amp;
+            if fades.do_fades {
+                for i in 0..frames {
+                    let fade_amp = fades.next();
 
-                            out_left[i] += src_left[i] * total_amp;
-                            out_right[i] += src_right[i] * total_amp;
+                    out_left[i] += src_left[i] * amp_left[i] * fade_amp;
+                    out_right[i] += src_right[i] * amp_right[i] * fade_amp;
+                }
+            } else {
+                for i in 0..frames {
+                    out_left[i] += src_left[i] * amp_left[i];
+                    out_right[i] += src_right[i] * amp_right[i];
+                }
+            }
+        } else {
+            if fades.do_fades {
+                for i in 0..frames {
+                    let fade_amp = fades.next();
 
-                            start_fade_amp = (start_fade_amp + start_fade_delta).min(1.0);
-                            end_fade_amp = (end_fade_amp - end_fade_delta).max(0.0);
-                        }
-                    } else {
-                        for i in 0..frames {
-                            out_left[i] += src_left[i];
-                            out_right[i] += src_right[i];
-                        }
-                    }
+                    out_left[i] += src_left[i] * fade_amp;
+                    out_right[i] += src_right[i] * fade_amp;
                 }
+            } else {
+                for i in 0..frames {
+                    out_left[i] += src_left[i];
+                    out_right[i] += src_right[i];
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fade_curves() {
+        let curves = [
+            FadeCurve::Linear,
+            FadeCurve::EqualPower,
+            FadeCurve::Exponential,
+            FadeCurve::Logarithmic,
+            FadeCurve::SCurve,
+        ];
+
+        for curve in curves.iter() {
+            assert!(curve.gain(0.0).abs() < 1e-6, "{:?}", curve);
+            assert!((curve.gain(1.0) - 1.0).abs() < 1e-6, "{:?}", curve);
+
+            // Every curve rises from silence to unity gain.
+            let mut last = curve.gain(0.0);
+            for i in 1..=100 {
+                let gain = curve.gain(i as f32 / 100.0);
+                assert!(gain > last, "{:?} at {}", curve, i);
+                last = gain;
             }
         }
+
+        let x = 0.25;
+        assert_eq!(FadeCurve::Linear.gain(x), x);
+        assert_eq!(FadeCurve::Exponential.gain(x), x * x * x);
+        assert!(FadeCurve::Logarithmic.gain(x) > x);
+        assert!(FadeCurve::SCurve.gain(x) < x);
+        assert!(FadeCurve::SCurve.gain(1.0 - x) > 1.0 - x);
+
+        for i in 0..=10 {
+            let x = i as f32 / 10.0;
+
+            // An equal-power fade in and fade out keep the power constant.
+            let (fade_in, fade_out) =
+                (FadeCurve::EqualPower.gain(x), FadeCurve::EqualPower.gain(1.0 - x));
+            assert!(((fade_in * fade_in) + (fade_out * fade_out) - 1.0).abs() < 1e-6);
+
+            // The exponential and logarithmic curves mirror each other, and the S-curve
+            // mirrors itself.
+            let mirrored = 1.0 - FadeCurve::Logarithmic.gain(1.0 - x);
+            assert!((FadeCurve::Exponential.gain(x) - mirrored).abs() < 1e-6);
+            assert!(
+                (FadeCurve::SCurve.gain(x) + FadeCurve::SCurve.gain(1.0 - x) - 1.0).abs() < 1e-6
+            );
+        }
+    }
+
+    #[test]
+    fn fade_durations_are_not_negative() {
+        let mut fades = AudioClipFades::no_fade();
+
+        fades.set_start_fade_duration(Seconds(-1.0));
+        fades.set_end_fade_duration(Seconds(-1.0));
+        assert_eq!(fades.start_fade_duration, Seconds(0.0));
+        assert_eq!(fades.end_fade_duration, Seconds(0.0));
+
+        fades.set_start_fade_duration(Seconds(0.5));
+        fades.set_end_fade_duration(Seconds(0.25));
+        assert_eq!(fades.start_fade_duration, Seconds(0.5));
+        assert_eq!(fades.end_fade_duration, Seconds(0.25));
+    }
+
+    #[test]
+    fn reads_multichannel() {
+        use crate::backend::resource_loader::{ChannelLayout, ChannelPosition};
+
+        // A 5.1 resource where every channel holds a different constant.
+        let layout = ChannelLayout::new(vec![
+            ChannelPosition::FrontLeft,
+            ChannelPosition::FrontRight,
+            ChannelPosition::FrontCentre,
+            ChannelPosition::LowFrequency,
+            ChannelPosition::RearLeft,
+            ChannelPosition::RearRight,
+        ]);
+        let values = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6];
+        let pcm = AnyPcm::from_channels(
+            values.iter().map(|value| vec![*value; 4]).collect(),
+            Some(layout),
+            SampleRate(48_000.0),
+        );
+
+        let read = |channel: usize, buf: &mut [f32]| pcm.channel(channel).read(0, buf);
+        let (mut left, mut right, mut scratch) = ([1.0f32; 4], [1.0f32; 4], [0.0f32; 4]);
+        let mut read_mode = |mode| {
+            let (num_channels, layout) = (pcm.num_channels(), pcm.layout());
+            match read_multichannel(
+                num_channels,
+                layout,
+                mode,
+                read,
+                &mut left,
+                &mut right,
+                &mut scratch,
+            ) {
+                simd::Source::Mono(src) => (src[3], None),
+                simd::Source::Stereo(l, r) => (l[3], Some(r[3])),
+            }
+        };
+
+        // The centre and rear channels are mixed in at -3dB, and the LFE is dropped.
+        let g = std::f32::consts::FRAC_1_SQRT_2;
+        let (l, r) = read_mode(MultiChannelMode::DownmixToStereo);
+        assert!((l - (0.1 + 0.3 * g + 0.5 * g)).abs() < 1e-6);
+        assert!((r.unwrap() - (0.2 + 0.3 * g + 0.6 * g)).abs() < 1e-6);
+
+        assert_eq!(
+            read_mode(MultiChannelMode::ChannelPair { left: 4, right: 5 }),
+            (0.5, Some(0.6))
+        );
+        assert_eq!(read_mode(MultiChannelMode::SingleChannel(2)), (0.3, None));
+
+        // Channels past the last channel select the last channel.
+        assert_eq!(
+            read_mode(MultiChannelMode::ChannelPair { left: 1, right: 10 }),
+            (0.2, Some(0.6))
+        );
+        assert_eq!(read_mode(MultiChannelMode::SingleChannel(6)), (0.6, None));
     }
 }
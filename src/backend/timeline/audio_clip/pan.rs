@@ -0,0 +1,148 @@
+use std::f32::consts::FRAC_PI_2;
+
+pub static AUDIO_CLIP_PAN_MIN: f32 = -1.0;
+pub static AUDIO_CLIP_PAN_MAX: f32 = 1.0;
+
+/// The pan law used when panning an audio clip.
+///
+/// All pan laws are normalized so that a centered clip is played back at unity
+/// gain. The pan law decides how much louder the clip gets on one side as it is
+/// panned towards that side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PanLaw {
+    /// Linear gain law (+6dB when panned hard to one side).
+    Linear,
+
+    /// Circular (sine/cosine) constant-power law (+3dB when panned hard to one side).
+    #[default]
+    ConstantPower,
+
+    /// A compromise between the linear and constant-power laws (+4.5dB when panned
+    /// hard to one side).
+    Compromise,
+}
+
+impl PanLaw {
+    /// Returns the (left, right) gain of a mono signal with the given pan.
+    ///
+    /// `pan` - The pan in the range `[-1.0, 1.0]`, where `-1.0` is hard left and
+    /// `1.0` is hard right.
+    #[inline]
+    pub fn mono_gains(&self, pan: f32) -> (f32, f32) {
+        // Normalized position in the range `[0.0, 1.0]`.
+        let x = (pan.clamp(AUDIO_CLIP_PAN_MIN, AUDIO_CLIP_PAN_MAX) + 1.0) * 0.5;
+
+        match self {
+            PanLaw::Linear => (2.0 * (1.0 - x), 2.0 * x),
+            PanLaw::ConstantPower => {
+                let (r, l) = (x * FRAC_PI_2).sin_cos();
+                (l * std::f32::consts::SQRT_2, r * std::f32::consts::SQRT_2)
+            }
+            PanLaw::Compromise => {
+                let (r, l) = (x * FRAC_PI_2).sin_cos();
+                (
+                    (2.0 * (1.0 - x) * l * std::f32::consts::SQRT_2).sqrt(),
+                    (2.0 * x * r * std::f32::consts::SQRT_2).sqrt(),
+                )
+            }
+        }
+    }
+
+    /// Returns the (left, right) gain of a stereo signal with the given balance.
+    ///
+    /// Unlike `mono_gains`, the balance only ever attenuates the side opposite to the
+    /// direction of the pan, so it never boosts the signal above unity gain.
+    #[inline]
+    pub fn balance_gains(&self, pan: f32) -> (f32, f32) {
+        let (l, r) = self.mono_gains(pan);
+        (l.min(1.0), r.min(1.0))
+    }
+}
+
+/// How the channels of a stereo audio clip are routed to the output.
+///
+/// This has no effect on mono audio clips.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum StereoChannelMode {
+    /// Play the left and right channels as-is.
+    #[default]
+    Normal,
+
+    /// Swap the left and right channels.
+    Swap,
+
+    /// Sum both channels into a mono signal. The clip is then panned like a mono
+    /// audio clip.
+    SumToMono,
+}
+
+/// How the channels of an audio clip with more than two channels (surround, ambisonic,
+/// multi-mic recordings, etc.) are routed to the output.
+///
+/// This has no effect on mono and stereo audio clips. The `StereoChannelMode` is applied
+/// after the channels have been selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MultiChannelMode {
+    /// Downmix all channels to stereo using the channel layout of the file.
+    #[default]
+    DownmixToStereo,
+
+    /// Play the channels at the given indexes as the left and right channels.
+    ChannelPair { left: usize, right: usize },
+
+    /// Play only the channel at the given index. The clip is then panned like a mono
+    /// audio clip.
+    SingleChannel(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_gains(gains: (f32, f32), expected: (f32, f32)) {
+        assert!(
+            (gains.0 - expected.0).abs() < 1e-5 && (gains.1 - expected.1).abs() < 1e-5,
+            "{:?} != {:?}",
+            gains,
+            expected
+        );
+    }
+
+    fn to_db(gain: f32) -> f32 {
+        20.0 * gain.log10()
+    }
+
+    #[test]
+    fn pan_law_gains() {
+        let laws =
+            [(PanLaw::Linear, 6.02), (PanLaw::ConstantPower, 3.01), (PanLaw::Compromise, 4.52)];
+
+        for (law, hard_pan_db) in laws.iter() {
+            // Centered clips are played at unity gain.
+            assert_gains(law.mono_gains(0.0), (1.0, 1.0));
+            assert_gains(law.balance_gains(0.0), (1.0, 1.0));
+
+            // Clips panned hard to one side are silent on the other side.
+            let (l, r) = law.mono_gains(-1.0);
+            assert!((to_db(l) - hard_pan_db).abs() < 0.01, "{:?}: {}dB", law, to_db(l));
+            assert_eq!(r, 0.0);
+            assert_gains(law.mono_gains(1.0), (r, l));
+
+            // The pan is clamped.
+            assert_gains(law.mono_gains(-2.0), (l, r));
+            assert_gains(law.mono_gains(2.0), (r, l));
+
+            // The balance only attenuates.
+            assert_gains(law.balance_gains(-1.0), (1.0, 0.0));
+            assert_gains(law.balance_gains(1.0), (0.0, 1.0));
+        }
+
+        // The constant-power law keeps the power constant at every pan.
+        for i in -10..=10 {
+            let (l, r) = PanLaw::ConstantPower.mono_gains(i as f32 / 10.0);
+            assert!((l * l + r * r - 2.0).abs() < 1e-5);
+        }
+
+        assert_gains(PanLaw::Linear.mono_gains(-0.5), (1.5, 0.5));
+    }
+}
@@ -0,0 +1,681 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, Weak};
+
+use basedrop::{Shared, SharedCell};
+
+use super::resource::{AudioClipResource, AudioClipResourceCache};
+use super::AudioClipSaveState;
+use crate::backend::resource_loader::{AnyPcm, PcmLoadError, PeakCache, ResourceLoader};
+
+/// The number of background threads used to render audio clip resources.
+pub static AUDIO_CLIP_RENDER_THREADS: usize = 2;
+
+/// Called with the path of a file being loaded and the progress of loading it in the
+/// range `[0.0, 1.0]`. This is only called when the progress changes by at least a whole
+/// percent. Note this is called from the render threads.
+pub type PcmLoadProgressFn = Arc<dyn Fn(&PathBuf, f32) + Send + Sync>;
+
+/// A pool of background threads that load and render audio clip resources (decoding,
+/// resampling and offline effects), so loading never blocks the caller.
+///
+/// While a resource is being loaded or rendered, the audio clip keeps playing its current
+/// resource (or silence for new clips). Once the render is finished, the new resource
+/// is swapped in directly. This means clips whose resources are already loaded can be
+/// played while the rest are still loading.
+///
+/// The render queue also keeps track of the render target of every audio clip, so the
+/// resources of clips can be rendered again when their audio files are modified on disk
+/// (see `reload_files()`).
+pub struct AudioClipRenderQueue {
+    // `mpsc::Sender` is not `Sync`, so it can't be shared between threads otherwise.
+    job_tx: Mutex<mpsc::Sender<QueuedRender>>,
+
+    context: Arc<WorkerContext>,
+
+    /// The render target of every audio clip that requested a resource. Targets of audio
+    /// clips that were dropped are removed when a new target is added, and when files are
+    /// reloaded.
+    targets: Mutex<Vec<Weak<RenderTarget>>>,
+}
+
+impl AudioClipRenderQueue {
+    pub fn new(
+        resource_loader: Arc<Mutex<ResourceLoader>>,
+        cache: Arc<Mutex<AudioClipResourceCache>>,
+        num_threads: usize,
+    ) -> Self {
+        let (job_tx, job_rx) = mpsc::channel();
+
+        let context = Arc::new(WorkerContext {
+            job_rx: Mutex::new(job_rx),
+            resource_loader,
+            cache,
+            epoch: AtomicU64::new(0),
+            load_progress_fn: Mutex::new(None),
+        });
+
+        for _ in 0..num_threads.max(1) {
+            let context = Arc::clone(&context);
+
+            std::thread::spawn(move || run_worker(context));
+        }
+
+        Self { job_tx: Mutex::new(job_tx), context, targets: Mutex::new(Vec::new()) }
+    }
+
+    /// Set the resource of the given render target to the resource described by `state`.
+    ///
+    /// If the PCM resource is already loaded and the resource is already cached, then it
+    /// is swapped in immediately. Otherwise it is queued to be loaded and rendered on a
+    /// background thread. Any render still in progress for the same target is cancelled.
+    ///
+    /// Files that are too large to be loaded into memory are opened for streaming from
+    /// disk instead.
+    ///
+    /// Returns the queued render (if any). Errors that occur while loading are reported
+    /// by the render job.
+    pub(super) fn request(
+        &self,
+        state: &AudioClipSaveState,
+        target: &Arc<RenderTarget>,
+    ) -> Option<Arc<AudioClipRenderJob>> {
+        // Keep track of the target, so its resource can be rendered again when its files
+        // are modified.
+        let is_new_target = target.state.lock().unwrap().replace(state.clone()).is_none();
+        if is_new_target {
+            let mut targets = self.targets.lock().unwrap();
+            targets.retain(|target| target.strong_count() > 0);
+            targets.push(Arc::downgrade(target));
+        }
+
+        self.request_render(state, target, false)
+    }
+
+    /// `is_reload` - Whether the files of the audio clip were modified. If loading the
+    /// files fails, then the audio clip keeps its current resource.
+    fn request_render(
+        &self,
+        state: &AudioClipSaveState,
+        target: &Arc<RenderTarget>,
+        is_reload: bool,
+    ) -> Option<Arc<AudioClipRenderJob>> {
+        let generation = target.next_generation();
+
+        let loaded = {
+            let resource_loader = self.context.resource_loader.lock().unwrap();
+
+            resource_loader.pcm_loader.get(&state.pcm_path).and_then(|pcm| {
+                let key = resource_loader.pcm_loader.file_key(&state.pcm_path)?;
+                Some((pcm, key.hash))
+            })
+        };
+
+        if let Some((pcm, hash)) = loaded {
+            let cached = { self.context.cache.lock().unwrap().get(state, &pcm, Some(hash)) };
+
+            if let Some(resource) = cached {
+                target.swap(generation, resource);
+
+                return None;
+            }
+        }
+
+        let job = Arc::new(AudioClipRenderJob {
+            load_progress: AtomicU32::new(0.0f32.to_bits()),
+            progress: AtomicU32::new(0.0f32.to_bits()),
+            finished: AtomicBool::new(false),
+            error: Mutex::new(None),
+        });
+
+        // The workers only exit once the queue is dropped, so this can't fail.
+        let _ = self.job_tx.lock().unwrap().send(QueuedRender {
+            state: state.clone(),
+            target: Arc::clone(target),
+            generation,
+            epoch: self.context.epoch.load(Ordering::Acquire),
+            is_reload,
+            job: Arc::clone(&job),
+        });
+
+        Some(job)
+    }
+
+    /// Render the resource described by `state` into the cache on a background thread,
+    /// without swapping it into an audio clip. Once the render is finished, audio clips
+    /// that request the same resource use it immediately.
+    ///
+    /// Returns the queued render, or `None` if the resource is already cached.
+    ///
+    /// The resource is not rendered again when its files are modified, since no audio
+    /// clip is using it yet.
+    pub fn cache_in_background(
+        &self,
+        state: &AudioClipSaveState,
+    ) -> Option<Arc<AudioClipRenderJob>> {
+        let target = {
+            let cache = self.context.cache.lock().unwrap();
+            let resource = Shared::new(cache.coll_handle(), SharedCell::new(cache.placeholder()));
+
+            Arc::new(RenderTarget::new(resource))
+        };
+
+        self.request_render(state, &target, false)
+    }
+
+    /// Load the given audio files again and render the resources of all audio clips that
+    /// use them (as their PCM resource or as the impulse response of their convolution
+    /// effect) again. The new resources are swapped into the clips once they are
+    /// rendered, and the clips keep playing their current resources until then.
+    ///
+    /// This is used to pick up files that were modified on disk by another application
+    /// (see `PcmLoader::modified_files()`). Note files that are streamed from disk are
+    /// not reloaded. If a file can no longer be loaded, then the error is reported by the
+    /// render job, and the audio clips keep their current resources.
+    ///
+    /// Returns the queued renders.
+    ///
+    /// `paths` - The canonical paths of the files.
+    pub fn reload_files(&self, paths: &[PathBuf]) -> Vec<Arc<AudioClipRenderJob>> {
+        if paths.is_empty() {
+            return Vec::new();
+        }
+
+        {
+            let mut resource_loader = self.context.resource_loader.lock().unwrap();
+            for path in paths.iter() {
+                log::info!("Reloading modified file: {:?}", path);
+
+                resource_loader.pcm_loader.forget(path);
+            }
+        }
+
+        // Resources rendered with a modified file as their PCM resource get a new key
+        // (see `PcmFileKey`), but the convolution effect is keyed by the path of its
+        // impulse response.
+        self.context.cache.lock().unwrap().remove_using_irs(paths);
+
+        let targets: Vec<Arc<RenderTarget>> = {
+            let mut targets = self.targets.lock().unwrap();
+            targets.retain(|target| target.strong_count() > 0);
+            targets.iter().filter_map(Weak::upgrade).collect()
+        };
+
+        let mut jobs = Vec::new();
+        for target in targets.iter() {
+            let state = { target.state.lock().unwrap().clone() };
+
+            if let Some(state) = state {
+                if uses_any_file(&state, paths) {
+                    jobs.extend(self.request_render(&state, target, true));
+                }
+            }
+        }
+
+        jobs
+    }
+
+    /// Cancel all renders that are currently queued or in progress. This is used when a
+    /// different project is loaded, and when the render queue is dropped.
+    pub fn cancel_all(&self) {
+        self.context.epoch.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Set the function that is called with the progress of every file being loaded. Set
+    /// this to `None` to remove it.
+    pub fn set_load_progress_fn(&self, load_progress_fn: Option<PcmLoadProgressFn>) {
+        *self.context.load_progress_fn.lock().unwrap() = load_progress_fn;
+    }
+}
+
+impl Drop for AudioClipRenderQueue {
+    fn drop(&mut self) {
+        // Don't keep working on renders nobody is waiting for.
+        self.cancel_all();
+    }
+}
+
+/// The progress of an audio clip resource being rendered by the `AudioClipRenderQueue`.
+pub struct AudioClipRenderJob {
+    load_progress: AtomicU32,
+    progress: AtomicU32,
+    finished: AtomicBool,
+
+    error: Mutex<Option<PcmLoadError>>,
+}
+
+impl AudioClipRenderJob {
+    /// The progress of loading the PCM resource in the range `[0.0, 1.0]`. This is `1.0`
+    /// if the resource was already loaded.
+    pub fn load_progress(&self) -> f32 {
+        f32::from_bits(self.load_progress.load(Ordering::Relaxed))
+    }
+
+    /// The progress of the render in the range `[0.0, 1.0]`.
+    pub fn progress(&self) -> f32 {
+        f32::from_bits(self.progress.load(Ordering::Relaxed))
+    }
+
+    /// Whether the render has finished (or was cancelled).
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+
+    /// Returns the error that occurred when loading the PCM resource, when loading the
+    /// impulse response of the convolution effect, or when opening the file for streaming
+    /// from disk (if any). This will only return the error once.
+    pub fn take_error(&self) -> Option<PcmLoadError> {
+        self.error.lock().unwrap().take()
+    }
+}
+
+/// The place a rendered resource is swapped into. This is shared between an audio clip
+/// and the render threads.
+pub(super) struct RenderTarget {
+    resource: Shared<SharedCell<AudioClipResource>>,
+
+    /// Incremented every time a new resource is requested, so renders of outdated
+    /// parameters are cancelled.
+    generation: Mutex<u64>,
+
+    /// The state of the audio clip in the last request, used to render the resource
+    /// again when its files are modified.
+    state: Mutex<Option<AudioClipSaveState>>,
+}
+
+impl RenderTarget {
+    pub(super) fn new(resource: Shared<SharedCell<AudioClipResource>>) -> Self {
+        Self { resource, generation: Mutex::new(0), state: Mutex::new(None) }
+    }
+
+    /// Returns the resource currently used by the audio clip.
+    pub(super) fn resource(&self) -> Shared<AudioClipResource> {
+        self.resource.get()
+    }
+
+    fn next_generation(&self) -> u64 {
+        let mut generation = self.generation.lock().unwrap();
+        *generation += 1;
+        *generation
+    }
+
+    /// Cancel the render in progress (if any).
+    pub(super) fn cancel(&self) {
+        self.next_generation();
+    }
+
+    fn is_cancelled(&self, generation: u64) -> bool {
+        *self.generation.lock().unwrap() != generation
+    }
+
+    fn swap(&self, generation: u64, resource: Shared<AudioClipResource>) {
+        // Hold the lock while swapping, so a newer request can't be overwritten by an
+        // older render.
+        let current = self.generation.lock().unwrap();
+
+        if *current == generation {
+            self.resource.set(resource);
+        }
+    }
+}
+
+struct QueuedRender {
+    state: AudioClipSaveState,
+    target: Arc<RenderTarget>,
+    generation: u64,
+    epoch: u64,
+    is_reload: bool,
+    job: Arc<AudioClipRenderJob>,
+}
+
+/// The state shared between the render queue and its threads.
+struct WorkerContext {
+    job_rx: Mutex<mpsc::Receiver<QueuedRender>>,
+
+    resource_loader: Arc<Mutex<ResourceLoader>>,
+    cache: Arc<Mutex<AudioClipResourceCache>>,
+
+    /// Incremented by `AudioClipRenderQueue::cancel_all()`.
+    epoch: AtomicU64,
+
+    load_progress_fn: Mutex<Option<PcmLoadProgressFn>>,
+}
+
+fn run_worker(context: Arc<WorkerContext>) {
+    loop {
+        let render = context.job_rx.lock().unwrap().recv();
+
+        let QueuedRender { state, target, generation, epoch, is_reload, job } = match render {
+            Ok(render) => render,
+            // The queue was dropped.
+            Err(_) => return,
+        };
+
+        let is_cancelled =
+            || target.is_cancelled(generation) || context.epoch.load(Ordering::Acquire) != epoch;
+
+        if !is_cancelled() {
+            let load_progress_fn = { context.load_progress_fn.lock().unwrap().clone() };
+            let mut last_percent = None;
+
+            let loaded = ResourceLoader::load_pcm(
+                &context.resource_loader,
+                &state.pcm_path,
+                &mut |progress| {
+                    job.load_progress.store(progress.to_bits(), Ordering::Relaxed);
+                    if let Some(load_progress_fn) = &load_progress_fn {
+                        // The decoder reports the progress of every packet.
+                        let percent = (progress * 100.0) as u32;
+                        if last_percent != Some(percent) {
+                            last_percent = Some(percent);
+                            (load_progress_fn)(&state.pcm_path, progress);
+                        }
+                    }
+
+                    !is_cancelled()
+                },
+            );
+
+            let mut loaded_pcm = None;
+            let pcm = match loaded {
+                Ok((pcm, hash)) => {
+                    loaded_pcm = Some((Shared::clone(&pcm), hash));
+                    Some((pcm, Some(hash)))
+                }
+                // Files that are too large to be loaded into memory are streamed from disk.
+                Err(PcmLoadError::FileTooLarge(_)) => {
+                    match AudioClipResourceCache::open_stream(
+                        &context.cache,
+                        &state,
+                        &context.resource_loader,
+                    ) {
+                        Ok(resource) => target.swap(generation, resource),
+                        Err(e) => {
+                            log::error!("{}", e);
+                            *job.error.lock().unwrap() = Some(e);
+                        }
+                    }
+
+                    None
+                }
+                Err(PcmLoadError::Cancelled(_)) => None,
+                Err(e) => {
+                    log::error!("{}", e);
+                    *job.error.lock().unwrap() = Some(e);
+
+                    if is_reload {
+                        // Keep playing the resource that was loaded before the file was
+                        // modified.
+                        None
+                    } else {
+                        // Use an "empty" PCM resource instead.
+                        Some((context.resource_loader.lock().unwrap().pcm_loader.empty_pcm(), None))
+                    }
+                }
+            };
+
+            if let Some((pcm, pcm_hash)) = pcm {
+                let rendered = AudioClipResourceCache::render(
+                    &context.cache,
+                    &state,
+                    pcm,
+                    pcm_hash,
+                    &context.resource_loader,
+                    &mut |progress| {
+                        job.progress.store(progress.to_bits(), Ordering::Relaxed);
+                        !is_cancelled()
+                    },
+                );
+
+                if let Some((resource, ir_load_res)) = rendered {
+                    if let Err(e) = ir_load_res {
+                        *job.error.lock().unwrap() = Some(e);
+                    }
+
+                    target.swap(generation, resource);
+                }
+            }
+
+            // The waveform peaks are loaded after the resource is swapped in, so the clip
+            // can be played sooner.
+            if let Some((pcm, hash)) = loaded_pcm {
+                if !is_cancelled() {
+                    load_peaks(&context.resource_loader, &state.pcm_path, hash, &pcm);
+                }
+            }
+        }
+
+        job.load_progress.store(1.0f32.to_bits(), Ordering::Relaxed);
+        job.progress.store(1.0f32.to_bits(), Ordering::Relaxed);
+        job.finished.store(true, Ordering::Release);
+    }
+}
+
+/// Returns `true` if the audio clip uses any of the files at the given canonical paths.
+fn uses_any_file(state: &AudioClipSaveState, paths: &[PathBuf]) -> bool {
+    let ir_path = state.effects.convolution.as_ref().map(|convolution| &convolution.ir_path);
+
+    std::iter::once(&state.pcm_path).chain(ir_path).any(|path| match fs::canonicalize(path) {
+        Ok(path) => paths.contains(&path),
+        Err(_) => paths.contains(path),
+    })
+}
+
+/// Load the waveform peaks of the PCM resource at `path` from its peak file (or compute
+/// them) if they are not already loaded. See `PeakCache`.
+///
+/// `hash` - The hash of the contents of the file (see `PcmFileKey`).
+fn load_peaks(resource_loader: &Mutex<ResourceLoader>, path: &PathBuf, hash: u64, pcm: &AnyPcm) {
+    let cache_dir = {
+        let resource_loader = resource_loader.lock().unwrap();
+
+        if resource_loader.peak_loader.contains(path, hash) {
+            return;
+        }
+
+        resource_loader.peak_loader.cache_dir().clone()
+    };
+
+    let peaks = PeakCache::load_or_compute(path, hash, pcm, &cache_dir);
+
+    resource_loader.lock().unwrap().peak_loader.insert(path, hash, peaks);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use basedrop::Collector;
+    use rusty_daw_core::SampleRate;
+
+    use super::*;
+
+    static TEST_FILE: &str = "./assets/test_files/synth_keys/synth_keys_48000_16bit.wav";
+
+    /// Returns a render queue with a single render thread, and a new render target.
+    fn test_queue(collector: &Collector) -> (AudioClipRenderQueue, Arc<RenderTarget>) {
+        let sample_rate = SampleRate::new(48_000.0);
+
+        let resource_loader =
+            Arc::new(Mutex::new(ResourceLoader::new(collector.handle(), sample_rate)));
+        let cache =
+            Arc::new(Mutex::new(AudioClipResourceCache::new(collector.handle(), sample_rate)));
+
+        let placeholder = { cache.lock().unwrap().placeholder() };
+        let target = Arc::new(RenderTarget::new(Shared::new(
+            &collector.handle(),
+            SharedCell::new(placeholder),
+        )));
+
+        (AudioClipRenderQueue::new(resource_loader, cache, 1), target)
+    }
+
+    /// Pause the render thread once it starts loading a file. Returns a receiver that is
+    /// notified when the thread is paused, and a sender that resumes it when dropped.
+    fn pause_loading(queue: &AudioClipRenderQueue) -> (mpsc::Receiver<()>, mpsc::Sender<()>) {
+        let (paused_tx, paused_rx) = mpsc::channel();
+        let (resume_tx, resume_rx) = mpsc::channel::<()>();
+
+        let paused_tx = Mutex::new(paused_tx);
+        let resume_rx = Mutex::new(resume_rx);
+        queue.set_load_progress_fn(Some(Arc::new(move |_: &PathBuf, _: f32| {
+            let _ = paused_tx.lock().unwrap().send(());
+            let _ = resume_rx.lock().unwrap().recv();
+        })));
+
+        (paused_rx, resume_tx)
+    }
+
+    fn wait_until_finished(job: &AudioClipRenderJob) {
+        let start = Instant::now();
+        while !job.is_finished() {
+            assert!(start.elapsed() < Duration::from_secs(10), "render never finished");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn is_same(a: &Shared<AudioClipResource>, b: &Shared<AudioClipResource>) -> bool {
+        std::ptr::eq::<AudioClipResource>(&**a, &**b)
+    }
+
+    #[test]
+    fn cancels_outdated_renders() {
+        let collector = Collector::new();
+        let (queue, target) = test_queue(&collector);
+        let placeholder = target.resource();
+        let (paused, resume) = pause_loading(&queue);
+
+        let state = AudioClipSaveState::test(TEST_FILE, 0.0, 1.0);
+        let job = queue.request(&state, &target).unwrap();
+
+        paused.recv().unwrap();
+        target.cancel();
+        drop(resume);
+
+        wait_until_finished(&job);
+        assert!(is_same(&target.resource(), &placeholder));
+        assert!(job.take_error().is_none());
+
+        // The next request is rendered and swapped in.
+        let job = queue.request(&state, &target).unwrap();
+        wait_until_finished(&job);
+        assert!(!is_same(&target.resource(), &placeholder));
+        assert!(target.resource().len() > 0);
+    }
+
+    #[test]
+    fn cancels_all_renders() {
+        let collector = Collector::new();
+        let (queue, target_a) = test_queue(&collector);
+        let placeholder = target_a.resource();
+        let target_b = Arc::new(RenderTarget::new(Shared::new(
+            &collector.handle(),
+            SharedCell::new(Shared::clone(&placeholder)),
+        )));
+        let (paused, resume) = pause_loading(&queue);
+
+        let state = AudioClipSaveState::test(TEST_FILE, 0.0, 1.0);
+        let job_a = queue.request(&state, &target_a).unwrap();
+        let job_b = queue.request(&state, &target_b).unwrap();
+
+        // Both the render in progress and the queued render are cancelled.
+        paused.recv().unwrap();
+        queue.cancel_all();
+        drop(resume);
+
+        wait_until_finished(&job_a);
+        wait_until_finished(&job_b);
+        assert!(is_same(&target_a.resource(), &placeholder));
+        assert!(is_same(&target_b.resource(), &placeholder));
+
+        // Renders requested after cancelling are not cancelled.
+        let job_a = queue.request(&state, &target_a).unwrap();
+        wait_until_finished(&job_a);
+        assert!(!is_same(&target_a.resource(), &placeholder));
+    }
+
+    #[test]
+    fn caches_in_background() {
+        let collector = Collector::new();
+        let (queue, target) = test_queue(&collector);
+        let placeholder = target.resource();
+
+        let state = AudioClipSaveState::test(TEST_FILE, 0.0, 1.0);
+        let job = queue.cache_in_background(&state).unwrap();
+        wait_until_finished(&job);
+        assert!(job.take_error().is_none());
+        assert!(queue.cache_in_background(&state).is_none());
+        assert!(queue.targets.lock().unwrap().is_empty());
+
+        // The cached resource is swapped in immediately.
+        assert!(queue.request(&state, &target).is_none());
+        let rendered = target.resource();
+        assert!(!is_same(&rendered, &placeholder));
+
+        // Renders of outdated requests are not swapped in.
+        let other_target = Arc::new(RenderTarget::new(Shared::new(
+            &collector.handle(),
+            SharedCell::new(Shared::clone(&placeholder)),
+        )));
+        let outdated = other_target.next_generation();
+        let latest = other_target.next_generation();
+        other_target.swap(outdated, Shared::clone(&rendered));
+        assert!(is_same(&other_target.resource(), &placeholder));
+        other_target.swap(latest, Shared::clone(&rendered));
+        assert!(is_same(&other_target.resource(), &rendered));
+    }
+
+    #[test]
+    fn reloads_modified_files() {
+        let dir = std::env::temp_dir().join("meadowlark_test_render_queue");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("clip.wav");
+        fs::copy(TEST_FILE, &path).unwrap();
+
+        let collector = Collector::new();
+        let (queue, target) = test_queue(&collector);
+        let other_target = Arc::new(RenderTarget::new(Shared::new(
+            &collector.handle(),
+            SharedCell::new(target.resource()),
+        )));
+
+        let state = AudioClipSaveState::test(path.to_str().unwrap(), 0.0, 1.0);
+        wait_until_finished(&queue.request(&state, &target).unwrap());
+        let other_state = AudioClipSaveState::test(TEST_FILE, 0.0, 1.0);
+        wait_until_finished(&queue.request(&other_state, &other_target).unwrap());
+        let loaded = target.resource();
+        let other_loaded = other_target.resource();
+
+        // The targets of dropped audio clips are forgotten when new targets are added.
+        for _ in 0..2 {
+            let dropped_target = Arc::new(RenderTarget::new(Shared::new(
+                &collector.handle(),
+                SharedCell::new(target.resource()),
+            )));
+            let _ = queue.request(&state, &dropped_target);
+        }
+        assert_eq!(queue.targets.lock().unwrap().len(), 3);
+
+        // Only the clips that use the modified file are rendered again.
+        fs::copy("./assets/test_files/synth_keys/synth_keys_48000_24bit.wav", &path).unwrap();
+        let jobs = queue.reload_files(&[fs::canonicalize(&path).unwrap()]);
+        assert_eq!(jobs.len(), 1);
+        wait_until_finished(&jobs[0]);
+        assert!(jobs[0].take_error().is_none());
+        assert!(!is_same(&target.resource(), &loaded));
+        assert!(is_same(&other_target.resource(), &other_loaded));
+
+        // Files that can no longer be loaded keep their current resource.
+        let reloaded = target.resource();
+        fs::write(&path, "not audio").unwrap();
+        let jobs = queue.reload_files(&[fs::canonicalize(&path).unwrap()]);
+        assert_eq!(jobs.len(), 1);
+        wait_until_finished(&jobs[0]);
+        assert!(jobs[0].take_error().is_some());
+        assert!(is_same(&target.resource(), &reloaded));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,13 +1,21 @@
+use std::fs;
 use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
+use atomic_refcell::AtomicRefCell;
 use basedrop::{Handle, Shared};
 use rusty_daw_core::{SampleRate, SampleTime};
 
+use super::effects::EffectsKey;
 use super::AudioClipSaveState;
-use crate::backend::dsp::resample;
-use crate::backend::resource_loader::{AnyPcm, MonoPcm, PcmLoadError, ResourceLoader, StereoPcm};
+use crate::backend::dsp::resample::{self, InterpolationQuality};
+use crate::backend::resource_loader::memory::UnusedResources;
+use crate::backend::resource_loader::{
+    AnyPcm, ChannelLayout, MemoryUsage, MonoPcm, PcmLoadError, PcmStream, PcmStreamInfo,
+    ResourceLoader, StereoPcm,
+};
 use crate::util::TwoXHashMap;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -33,13 +41,16 @@ pub enum ResampledType {
     /// is likely to want to edit these parameters again. This is so we can avoid
     /// re-resampling (which has poor sound quality);
     HasEffects,
-    // TODO: Streamed from disk type.
-    //
-    // Note, due to the nature of streaming from disk, all resampling must be done
-    // at playback. Because of this, time stretching effects may prove to be
-    // unfeasible for streamed audio clips. We will probably end up using
-    // destructive editing in that case by asking the user to render the audio
-    // clip into a new file in order to apply the effect.
+
+    /// Used when the file is too large to be loaded into memory, so it is streamed
+    /// from disk instead (see `PcmStream`).
+    ///
+    /// Note, due to the nature of streaming from disk, all resampling must be done
+    /// while streaming. Because of this, offline effects are not supported on streamed
+    /// audio clips. We will probably end up using destructive editing in that case by
+    /// asking the user to render the audio clip into a new file in order to apply the
+    /// effect.
+    Streamed,
 }
 
 // The following is only relevant when the type is `HasEffects`. I'm not
@@ -54,26 +65,45 @@ struct EffectKeyParams {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct ResourceKey {
-    pcm_path: PathBuf,
+    /// The hash of the contents of the audio file (see `PcmFileKey`). Files with the same
+    /// contents share their resources, and a file that was modified since it was rendered
+    /// gets a new key. This is `None` for the "empty" resource used when a file could
+    /// not be loaded.
+    pcm_hash: Option<u64>,
     resampled_type: ResampledType,
 
     effect_params: Option<EffectKeyParams>,
+
+    /// The interpolation used to resample the audio file and the impulse response of the
+    /// convolution effect. This is `None` if nothing is resampled, so clips that only
+    /// differ in their interpolation quality share their resources.
+    interpolation_quality: Option<InterpolationQuality>,
+
+    /// The offline effects rendered into this resource (if any).
+    clip_effects: Option<EffectsKey>,
 }
 
 impl Hash for ResourceKey {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.pcm_path.hash(state);
+        self.pcm_hash.hash(state);
         self.resampled_type.hash(state);
 
         if let Some(params) = self.effect_params {
             params.hash(state);
         }
+
+        self.interpolation_quality.hash(state);
+        self.clip_effects.hash(state);
     }
 }
 
 pub struct AudioClipResource {
+    /// This is empty when the resource is streamed from disk.
     pub pcm: Shared<AnyPcm>,
 
+    /// The stream used when the resource is streamed from disk.
+    pub(super) stream: Option<StreamedPcm>,
+
     /// This is the start offset from the start of the original resource.
     pub original_offset: SampleTime,
 
@@ -82,31 +112,279 @@ pub struct AudioClipResource {
     /// When the rendered type is `HasEffects`, we want to keep the original samples
     /// around in memory since the user is likely to want to edit the pitch shifting
     /// and/or time stretching effects again.
-    _original: Option<Shared<AnyPcm>>,
+    ///
+    /// This is dropped when the memory budget is exceeded (see
+    /// `AudioClipResourceCache::evict_originals()`). This is never accessed by the rt
+    /// thread.
+    original: Mutex<Option<Shared<AnyPcm>>>,
+
+    /// Whether `pcm` is the resource loaded by the `PcmLoader` as is, in which case its
+    /// memory is counted by the loader instead.
+    pcm_is_loaded: bool,
+}
+
+impl AudioClipResource {
+    /// The memory used by this resource in bytes. This does not include the memory used
+    /// by PCM resources shared with the `PcmLoader` (including the original samples).
+    pub fn size_bytes(&self) -> u64 {
+        if self.pcm_is_loaded {
+            0
+        } else {
+            self.pcm.size_bytes()
+        }
+    }
+
+    /// The memory used by the original samples kept by this resource in bytes.
+    fn original_bytes(&self) -> u64 {
+        self.original.lock().unwrap().as_ref().map(|original| original.size_bytes()).unwrap_or(0)
+    }
+
+    /// Returns the number of times the stream of this resource could not keep up with
+    /// playback, or `None` if this resource is not streamed from disk.
+    pub fn stream_underruns(&self) -> Option<usize> {
+        self.stream.as_ref().map(|stream| stream.underruns.load(Ordering::Relaxed))
+    }
+
+    /// The length of this resource in frames.
+    pub(super) fn len(&self) -> usize {
+        if let Some(stream) = &self.stream {
+            stream.info.len
+        } else {
+            self.pcm.len()
+        }
+    }
+
+    pub(super) fn sample_rate(&self) -> SampleRate {
+        if let Some(stream) = &self.stream {
+            stream.info.sample_rate
+        } else {
+            self.pcm.sample_rate()
+        }
+    }
+
+    pub(super) fn num_channels(&self) -> usize {
+        if let Some(stream) = &self.stream {
+            stream.info.num_channels
+        } else {
+            self.pcm.num_channels()
+        }
+    }
+
+    pub(super) fn is_mono(&self) -> bool {
+        self.num_channels() == 1
+    }
+}
+
+pub(super) struct StreamedPcm {
+    pub(super) info: PcmStreamInfo,
+
+    /// The layout of the channels of streams with more than two channels. This is kept
+    /// here so it can be used while the stream is borrowed.
+    pub(super) layout: Option<ChannelLayout>,
+
+    underruns: Arc<AtomicUsize>,
+
+    // This is only ever borrowed by the audio clip process in the rt thread. Streamed
+    // resources are never shared between audio clips.
+    pub(super) stream: AtomicRefCell<PcmStream>,
 }
 
 pub struct AudioClipResourceCache {
     resources: TwoXHashMap<ResourceKey, Shared<AudioClipResource>>,
 
-    sample_rate: SampleRate,
+    /// Resources that are no longer used are kept until the memory budget is exceeded.
+    unused: UnusedResources<ResourceKey>,
 
-    coll_handle: Handle,
+    /// An empty resource used by audio clips while their resource is being rendered.
+    placeholder: Shared<AudioClipResource>,
+
+    renderer: ResourceRenderer,
 }
 
 impl AudioClipResourceCache {
     pub fn new(coll_handle: Handle, sample_rate: SampleRate) -> Self {
-        Self { resources: Default::default(), sample_rate, coll_handle }
+        let placeholder = Shared::new(
+            &coll_handle,
+            AudioClipResource {
+                pcm: Shared::new(&coll_handle, AnyPcm::Mono(MonoPcm::new(Vec::new(), sample_rate))),
+                stream: None,
+                original_offset: SampleTime::new(0),
+                resampled_type: ResampledType::Original,
+                original: Mutex::new(None),
+                pcm_is_loaded: false,
+            },
+        );
+
+        Self {
+            resources: Default::default(),
+            unused: UnusedResources::new(),
+            placeholder,
+            renderer: ResourceRenderer { sample_rate, coll_handle },
+        }
     }
 
-    pub fn cache(
-        &mut self,
+    /// Returns an empty (silent) resource, used by audio clips while their resource is
+    /// being rendered.
+    pub(super) fn placeholder(&self) -> Shared<AudioClipResource> {
+        Shared::clone(&self.placeholder)
+    }
+
+    pub(super) fn coll_handle(&self) -> &Handle {
+        &self.renderer.coll_handle
+    }
+
+    /// Returns the resource for the given state if it is already cached.
+    ///
+    /// `pcm_hash` - The hash of the contents of the audio file (see `PcmFileKey`).
+    pub(super) fn get(
+        &self,
+        state: &AudioClipSaveState,
+        pcm: &AnyPcm,
+        pcm_hash: Option<u64>,
+    ) -> Option<Shared<AudioClipResource>> {
+        self.resources.get(&self.renderer.key(state, pcm, pcm_hash)).map(Shared::clone)
+    }
+
+    /// Render the resource for the given state and add it to the cache. If the resource
+    /// was cached in the meantime, then the cached resource is returned instead.
+    ///
+    /// The cache is not locked while the resource is being rendered.
+    ///
+    /// `pcm_hash` - The hash of the contents of the audio file (see `PcmFileKey`).
+    ///
+    /// `progress` - Called with the progress of the render in the range `[0.0, 1.0]`.
+    /// The render is cancelled if this returns `false`.
+    ///
+    /// Returns the resource and the result of loading the impulse response of the
+    /// convolution effect, or `None` if the render was cancelled.
+    pub(super) fn render(
+        cache: &Mutex<Self>,
+        state: &AudioClipSaveState,
+        pcm: Shared<AnyPcm>,
+        pcm_hash: Option<u64>,
+        resource_loader: &Arc<Mutex<ResourceLoader>>,
+        progress: &mut dyn FnMut(f32) -> bool,
+    ) -> Option<(Shared<AudioClipResource>, Result<(), PcmLoadError>)> {
+        let (key, cached, renderer) = {
+            let cache = cache.lock().unwrap();
+
+            let key = cache.renderer.key(state, &pcm, pcm_hash);
+            let cached = cache.resources.get(&key).map(Shared::clone);
+
+            (key, cached, cache.renderer.clone())
+        };
+
+        if let Some(resource) = cached {
+            return Some((resource, Ok(())));
+        }
+
+        let (new_resource, ir_load_res) =
+            renderer.render(state, pcm, key.resampled_type, resource_loader, progress)?;
+
+        cache.lock().unwrap().insert(key, &new_resource, &ir_load_res);
+
+        Some((new_resource, ir_load_res))
+    }
+
+    /// Open a new resource that is streamed from disk. This is used for files that are too
+    /// large to be loaded into memory.
+    ///
+    /// Streamed resources are never shared between audio clips, so they are not cached.
+    pub(super) fn open_stream(
+        cache: &Mutex<Self>,
         state: &AudioClipSaveState,
         resource_loader: &Arc<Mutex<ResourceLoader>>,
-    ) -> (Shared<AudioClipResource>, Result<(), PcmLoadError>) {
-        // Load the resource from disk / retrieve from cache.
-        let (pcm, pcm_load_res) =
-            { resource_loader.lock().unwrap().pcm_loader.load(&state.pcm_path) };
+    ) -> Result<Shared<AudioClipResource>, PcmLoadError> {
+        let renderer = { cache.lock().unwrap().renderer.clone() };
 
+        renderer.open_stream(state, resource_loader)
+    }
+
+    fn insert(
+        &mut self,
+        key: ResourceKey,
+        resource: &Shared<AudioClipResource>,
+        ir_load_res: &Result<(), PcmLoadError>,
+    ) {
+        // Don't cache resources rendered without their impulse response, so they are
+        // rendered again once the impulse response can be loaded.
+        if ir_load_res.is_ok() {
+            let _ = self.resources.insert(key, Shared::clone(resource));
+        }
+    }
+
+    /// Remove all cached resources whose convolution effect uses an impulse response at
+    /// one of the given canonical paths, so they are rendered again. Audio clips keep
+    /// using the removed resources until they request a new one.
+    pub(super) fn remove_using_irs(&mut self, paths: &[PathBuf]) {
+        self.resources.retain(|key, _| {
+            match key.clip_effects.as_ref().and_then(|effects| effects.ir_path()) {
+                Some(ir_path) => match fs::canonicalize(ir_path) {
+                    Ok(ir_path) => !paths.contains(&ir_path),
+                    Err(_) => !paths.contains(ir_path),
+                },
+                None => true,
+            }
+        });
+    }
+
+    /// Returns the memory used by the cached resources.
+    pub fn memory_usage(&mut self) -> MemoryUsage {
+        self.evict(std::u64::MAX)
+    }
+
+    /// Drop audio clip resources not being currently used (the ones unused the longest
+    /// first) until the cached resources use at most `max_bytes`.
+    ///
+    /// Returns the memory used by the cached resources afterwards.
+    pub fn evict(&mut self, max_bytes: u64) -> MemoryUsage {
+        // If no other extant Shared pointers to the resource exists, then it is unused.
+        self.unused.evict(
+            &mut self.resources,
+            |r| Shared::get_mut(r).is_some(),
+            |r| r.size_bytes(),
+            max_bytes,
+        )
+    }
+
+    /// Drop the original samples kept by all cached resources with pitch shifting or
+    /// time stretching effects.
+    ///
+    /// Returns the memory used by the dropped originals in bytes. Note this memory is
+    /// only freed once the `PcmLoader` drops the PCM resources as well, after which they
+    /// are loaded from disk again the next time the effects are edited.
+    pub fn evict_originals(&mut self) -> u64 {
+        let mut bytes = 0;
+        for resource in self.resources.values() {
+            if let Some(original) = resource.original.lock().unwrap().take() {
+                bytes += original.size_bytes();
+            }
+        }
+        bytes
+    }
+
+    /// The memory used by the original samples kept by the cached resources in bytes.
+    pub fn original_bytes(&self) -> u64 {
+        self.resources.values().map(|r| r.original_bytes()).sum()
+    }
+
+    /// Drop all audio clip resources not being currently used.
+    pub fn collect(&mut self) {
+        let _ = self.evict(0);
+    }
+}
+
+/// Renders new audio clip resources. This is separate from the cache so resources can be
+/// rendered without locking the cache.
+#[derive(Clone)]
+struct ResourceRenderer {
+    sample_rate: SampleRate,
+
+    coll_handle: Handle,
+}
+
+impl ResourceRenderer {
+    fn key(&self, state: &AudioClipSaveState, pcm: &AnyPcm, pcm_hash: Option<u64>) -> ResourceKey {
         // TODO: Check for pitch shifting and time stretching effects.
         let (resampled_type, effect_params) = if pcm.sample_rate() == self.sample_rate {
             (ResampledType::Original, None)
@@ -114,86 +392,264 @@ impl AudioClipResourceCache {
             (ResampledType::OnlySampleRateChange, None)
         };
 
-        if let Some(resource) = self.resources.get(&ResourceKey {
-            // TODO: Find a way to do this without cloning the path every time.
-            pcm_path: state.pcm_path.clone(),
+        // The impulse response may need to be resampled even if the audio file does not.
+        let interpolation_quality =
+            if resampled_type != ResampledType::Original || state.effects.convolution.is_some() {
+                Some(state.interpolation_quality)
+            } else {
+                None
+            };
+
+        ResourceKey {
+            pcm_hash,
             resampled_type,
             effect_params,
-        }) {
-            (Shared::clone(resource), pcm_load_res)
-        } else {
-            // Render a new resource.
+            interpolation_quality,
+            clip_effects: state.effects.key(),
+        }
+    }
 
-            let new_resource = Shared::new(
-                &self.coll_handle,
-                match resampled_type {
-                    ResampledType::Original => AudioClipResource {
-                        pcm,
-                        original_offset: SampleTime::new(0),
-                        resampled_type,
-                        _original: None,
-                    },
-                    ResampledType::OnlySampleRateChange => {
-                        let resample_ratio = self.sample_rate.0 / pcm.sample_rate().0;
-
-                        // TODO: Use something better than linear resampling.
-
-                        let resampled_pcm = Shared::new(
-                            &self.coll_handle,
-                            match &*pcm {
-                                AnyPcm::Mono(pcm) => {
-                                    let res = resample::linear_resample_non_rt_mono(
-                                        pcm.data(),
-                                        resample_ratio,
-                                    );
-
-                                    AnyPcm::Mono(MonoPcm::new(res, self.sample_rate))
-                                }
-                                AnyPcm::Stereo(pcm) => {
-                                    let (res_l, res_r) = resample::linear_resample_non_rt_stereo(
-                                        pcm.left(),
-                                        pcm.right(),
-                                        resample_ratio,
-                                    );
-
-                                    AnyPcm::Stereo(StereoPcm::new(res_l, res_r, self.sample_rate))
-                                }
-                            },
-                        );
-
-                        AudioClipResource {
-                            pcm: resampled_pcm,
-                            original_offset: SampleTime::new(0),
-                            resampled_type,
-                            _original: None,
-                        }
-                    }
-                    ResampledType::HasEffects => {
-                        // TODO: Pitch shifting and time stretching effects.
-
-                        AudioClipResource {
-                            pcm,
-                            original_offset: SampleTime::new(0),
-                            resampled_type,
-                            _original: None,
-                        }
+    /// Render a new resource.
+    ///
+    /// Returns the result of loading the impulse response of the convolution effect, or
+    /// `None` if the render was cancelled.
+    fn render(
+        &self,
+        state: &AudioClipSaveState,
+        pcm: Shared<AnyPcm>,
+        resampled_type: ResampledType,
+        resource_loader: &Arc<Mutex<ResourceLoader>>,
+        progress: &mut dyn FnMut(f32) -> bool,
+    ) -> Option<(Shared<AudioClipResource>, Result<(), PcmLoadError>)> {
+        let mut new_resource = match resampled_type {
+            ResampledType::Original => AudioClipResource {
+                pcm: Shared::clone(&pcm),
+                stream: None,
+                original_offset: SampleTime::new(0),
+                resampled_type,
+                original: Mutex::new(None),
+                pcm_is_loaded: true,
+            },
+            ResampledType::OnlySampleRateChange => {
+                let resampled_pcm = Shared::new(
+                    &self.coll_handle,
+                    self.resample(&pcm, state.interpolation_quality),
+                );
+
+                AudioClipResource {
+                    pcm: resampled_pcm,
+                    stream: None,
+                    original_offset: SampleTime::new(0),
+                    resampled_type,
+                    original: Mutex::new(None),
+                    pcm_is_loaded: false,
+                }
+            }
+            // Streamed resources are created with `open_stream()` instead.
+            ResampledType::Streamed => unreachable!(),
+            ResampledType::HasEffects => {
+                // TODO: Pitch shifting and time stretching effects.
+
+                AudioClipResource {
+                    pcm: Shared::clone(&pcm),
+                    stream: None,
+                    original_offset: SampleTime::new(0),
+                    resampled_type,
+                    original: Mutex::new(None),
+                    pcm_is_loaded: true,
+                }
+            }
+        };
+
+        // Resampling can take a while, so check if the render was cancelled in the
+        // meantime.
+        if !progress(0.0) {
+            return None;
+        }
+
+        let mut ir_load_res = Ok(());
+
+        // Keep the original samples of time stretched clips, so editing the stretch does
+        // not have to load the audio file again.
+        if state.effects.extreme_stretch.is_some() {
+            *new_resource.original.get_mut().unwrap() = Some(Shared::clone(&pcm));
+        }
+
+        if !state.effects.is_empty() {
+            // Load the impulse response used by the convolution effect.
+            let ir = if let Some(convolution) = &state.effects.convolution {
+                // The impulse response is loaded before any effect is rendered.
+                let loaded =
+                    ResourceLoader::load_pcm(resource_loader, &convolution.ir_path, &mut |_| {
+                        progress(0.0)
+                    });
+                let ir = match loaded {
+                    Ok((ir, _)) => ir,
+                    Err(PcmLoadError::Cancelled(_)) => return None,
+                    Err(e) => {
+                        log::error!("{}", e);
+                        ir_load_res = Err(e);
+
+                        // Use an "empty" PCM resource instead.
+                        resource_loader.lock().unwrap().pcm_loader.empty_pcm()
                     }
-                },
+                };
+
+                if ir.sample_rate() == self.sample_rate {
+                    Some(ir)
+                } else {
+                    Some(Shared::new(
+                        &self.coll_handle,
+                        self.resample(&ir, state.interpolation_quality),
+                    ))
+                }
+            } else {
+                None
+            };
+
+            // The effects are rendered after resampling so they are measured at the
+            // project's sample rate.
+            new_resource.pcm = Shared::new(
+                &self.coll_handle,
+                state.effects.render(&new_resource.pcm, ir.as_ref().map(|ir| &**ir), progress)?,
+            );
+            new_resource.pcm_is_loaded = false;
+        }
+
+        Some((Shared::new(&self.coll_handle, new_resource), ir_load_res))
+    }
+
+    fn open_stream(
+        &self,
+        state: &AudioClipSaveState,
+        resource_loader: &Arc<Mutex<ResourceLoader>>,
+    ) -> Result<Shared<AudioClipResource>, PcmLoadError> {
+        if !state.effects.is_empty() {
+            log::warn!(
+                "Offline effects are not supported on audio clips streamed from disk: {:?}",
+                state.pcm_path
             );
+        }
 
-            let new_key =
-                ResourceKey { pcm_path: state.pcm_path.clone(), resampled_type, effect_params };
+        // Start prefetching from where the clip starts playing.
+        let start_frame =
+            state.clip_start_offset.to_nearest_sample_round(self.sample_rate).0.max(0) as usize;
+
+        let stream = {
+            resource_loader.lock().unwrap().pcm_loader.open_stream(
+                &state.pcm_path,
+                self.sample_rate,
+                start_frame,
+                state.interpolation_quality,
+            )?
+        };
 
-            let _ = self.resources.insert(new_key, Shared::clone(&new_resource));
+        Ok(Shared::new(
+            &self.coll_handle,
+            AudioClipResource {
+                pcm: Shared::new(
+                    &self.coll_handle,
+                    AnyPcm::Mono(MonoPcm::new(Vec::new(), self.sample_rate)),
+                ),
+                stream: Some(StreamedPcm {
+                    info: stream.info(),
+                    layout: stream.layout().cloned(),
+                    underruns: stream.underruns(),
+                    stream: AtomicRefCell::new(stream),
+                }),
+                original_offset: SampleTime::new(0),
+                resampled_type: ResampledType::Streamed,
+                original: Mutex::new(None),
+                pcm_is_loaded: false,
+            },
+        ))
+    }
 
-            (new_resource, pcm_load_res)
+    /// Resample the PCM resource to the project's sample rate.
+    fn resample(&self, pcm: &AnyPcm, quality: InterpolationQuality) -> AnyPcm {
+        let resample_ratio = self.sample_rate.0 / pcm.sample_rate().0;
+
+        match pcm {
+            AnyPcm::Mono(pcm) => {
+                let res = resample::resample_non_rt_mono(pcm.data(), resample_ratio, quality);
+
+                AnyPcm::Mono(MonoPcm::new(res, self.sample_rate))
+            }
+            AnyPcm::Stereo(pcm) => {
+                let (res_l, res_r) = resample::resample_non_rt_stereo(
+                    pcm.left(),
+                    pcm.right(),
+                    resample_ratio,
+                    quality,
+                );
+
+                AnyPcm::Stereo(StereoPcm::new(res_l, res_r, self.sample_rate))
+            }
+            // The resampled resource is always stored as `f32`.
+            pcm => {
+                let channels = (0..pcm.num_channels())
+                    .map(|i| {
+                        resample::resample_non_rt_mono(
+                            &pcm.channel(i).to_f32(),
+                            resample_ratio,
+                            quality,
+                        )
+                    })
+                    .collect();
+
+                AnyPcm::from_channels(channels, pcm.layout().cloned(), self.sample_rate)
+            }
         }
     }
+}
 
-    /// Drop all audio clip resources not being currently used.
-    pub fn collect(&mut self) {
-        // If no other extant Shared pointers to the resource exists, then
-        // remove that entry.
-        self.resources.retain(|_, r| Shared::get_mut(r).is_none());
+#[cfg(test)]
+mod tests {
+    use basedrop::Collector;
+    use rusty_daw_core::Seconds;
+
+    use super::*;
+    use crate::backend::timeline::ExtremeStretchEffect;
+
+    #[test]
+    fn evicts_originals_of_stretched_clips() {
+        let collector = Collector::new();
+        let sample_rate = SampleRate::new(48_000.0);
+
+        let cache = Mutex::new(AudioClipResourceCache::new(collector.handle(), sample_rate));
+        let resource_loader =
+            Arc::new(Mutex::new(ResourceLoader::new(collector.handle(), sample_rate)));
+
+        let pcm = Shared::new(
+            &collector.handle(),
+            AnyPcm::Mono(MonoPcm::new(vec![0.5; 4_800], sample_rate)),
+        );
+
+        let mut state = AudioClipSaveState::test("stretched.wav", 0.0, 0.1);
+        let render = |state: &AudioClipSaveState| {
+            AudioClipResourceCache::render(
+                &cache,
+                state,
+                Shared::clone(&pcm),
+                Some(0),
+                &resource_loader,
+                &mut |_| true,
+            )
+            .unwrap()
+            .0
+        };
+
+        // Resources without time stretching don't keep the original samples.
+        let _plain = render(&state);
+        assert_eq!(cache.lock().unwrap().original_bytes(), 0);
+
+        state.effects.extreme_stretch =
+            Some(ExtremeStretchEffect { factor: 2.0, window_size: Seconds(0.01) });
+        let _stretched = render(&state);
+        assert_eq!(cache.lock().unwrap().original_bytes(), pcm.size_bytes());
+
+        assert_eq!(cache.lock().unwrap().evict_originals(), pcm.size_bytes());
+        assert_eq!(cache.lock().unwrap().original_bytes(), 0);
+        assert_eq!(cache.lock().unwrap().evict_originals(), 0);
     }
 }
@@ -8,9 +8,15 @@ pub mod timeline_track_node;
 pub mod transport;
 
 pub use audio_clip::{
-    AudioClipFades, AudioClipHandle, AudioClipProcess, AudioClipResource, AudioClipResourceCache,
+    AudioClipEffects, AudioClipFades, AudioClipGainEnvelope, AudioClipHandle, AudioClipLoop,
+    AudioClipProcess, AudioClipRenderJob, AudioClipRenderQueue, AudioClipResource,
+    AudioClipResourceCache, ClipOverlapPolicy, ConvolutionEffect, DcOffsetRemoval, EnvelopeShape,
+    ExtremeStretchEffect, FadeCurve, GainEnvelopePoint, MultiChannelMode, NormalizeMode, PanLaw,
+    StereoChannelMode, AUDIO_CLIP_RENDER_THREADS,
 };
 pub use save_state::{AudioClipSaveState, TimelineTrackSaveState, TimelineTransportSaveState};
 pub use tempo_map::TempoMap;
-pub use timeline_track_node::{TimelineTrackHandle, TimelineTrackNode};
+pub use timeline_track_node::{
+    DetachedAudioClip, TimelineTrackEdit, TimelineTrackHandle, TimelineTrackNode,
+};
 pub use transport::{LoopState, TimelineTransport, TimelineTransportHandle};
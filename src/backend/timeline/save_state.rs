@@ -2,7 +2,13 @@ use rusty_daw_core::{MusicalTime, Seconds};
 use std::path::PathBuf;
 use tuix::Lens;
 
-use super::{AudioClipFades, LoopState};
+use crate::backend::dsp::resample::InterpolationQuality;
+use crate::backend::resource_loader::PcmFileInfo;
+
+use super::{
+    AudioClipEffects, AudioClipFades, AudioClipGainEnvelope, AudioClipLoop, ClipOverlapPolicy,
+    LoopState, MultiChannelMode, PanLaw, StereoChannelMode,
+};
 
 #[derive(Debug, Clone, Copy, Lens)]
 pub struct TimelineTransportSaveState {
@@ -24,6 +30,9 @@ pub struct TimelineTrackSaveState {
     /// The audio clips on this timeline track. These may not be
     /// in any particular order.
     pub audio_clips: Vec<AudioClipSaveState>,
+
+    /// What happens when audio clips on this timeline track overlap.
+    pub overlap_policy: ClipOverlapPolicy,
 }
 
 #[derive(Debug, Clone, Lens)]
@@ -34,6 +43,10 @@ pub struct AudioClipSaveState {
     /// The path to the audio file containing the PCM data.
     pub pcm_path: PathBuf,
 
+    /// The size and length of the audio file when it was last found. This is used to find
+    /// the file again if it is moved.
+    pub pcm_file_info: Option<PcmFileInfo>,
+
     /// Where the clip starts on the timeline.
     pub timeline_start: MusicalTime,
 
@@ -46,6 +59,69 @@ pub struct AudioClipSaveState {
     /// The gain of the audio clip in decibels.
     pub clip_gain_db: f32,
 
+    /// The gain envelope (automation) inside of the audio clip.
+    pub gain_envelope: AudioClipGainEnvelope,
+
+    /// The pan (or balance for stereo clips) of the audio clip, where `-1.0` is hard
+    /// left and `1.0` is hard right.
+    pub pan: f32,
+
+    /// The pan law used when panning the audio clip.
+    pub pan_law: PanLaw,
+
+    /// How the channels of a stereo audio clip are routed to the output.
+    pub channel_mode: StereoChannelMode,
+
+    /// How the channels of an audio clip with more than two channels are routed to the
+    /// output.
+    pub multichannel_mode: MultiChannelMode,
+
     /// The fades on this audio clip.
     pub fades: AudioClipFades,
+
+    /// The offline effects (normalize, DC offset removal, convolution) on this audio clip.
+    pub effects: AudioClipEffects,
+
+    /// The interpolation used when the audio file is resampled to the project's sample
+    /// rate.
+    pub interpolation_quality: InterpolationQuality,
+
+    /// The loop region of the audio clip. If this is `None`, then the clip stops at
+    /// the end of the PCM resource.
+    pub looping: Option<AudioClipLoop>,
+}
+
+impl AudioClipSaveState {
+    /// Call `f` with the path of every file used by this audio clip (the audio file and
+    /// the impulse response of the convolution effect).
+    pub fn for_each_media_path_mut(&mut self, mut f: impl FnMut(&mut PathBuf)) {
+        f(&mut self.pcm_path);
+
+        if let Some(convolution) = &mut self.effects.convolution {
+            f(&mut convolution.ir_path);
+        }
+    }
+
+    /// An audio clip with default settings, used in tests.
+    #[cfg(test)]
+    pub(crate) fn test(pcm_path: &str, timeline_start: f64, duration: f64) -> Self {
+        Self {
+            name: String::from("Audio Clip"),
+            pcm_path: pcm_path.into(),
+            pcm_file_info: None,
+            timeline_start: MusicalTime::new(timeline_start),
+            duration: Seconds::new(duration),
+            clip_start_offset: Seconds::new(0.0),
+            clip_gain_db: 0.0,
+            gain_envelope: Default::default(),
+            pan: 0.0,
+            pan_law: Default::default(),
+            channel_mode: Default::default(),
+            multichannel_mode: Default::default(),
+            fades: Default::default(),
+            effects: Default::default(),
+            interpolation_quality: Default::default(),
+            looping: None,
+        }
+    }
 }
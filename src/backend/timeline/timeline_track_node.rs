@@ -2,15 +2,22 @@ use atomic_refcell::AtomicRefCell;
 use basedrop::{Handle, Shared, SharedCell};
 use rusty_daw_audio_graph::{AudioGraphNode, ProcBuffers, ProcInfo};
 use rusty_daw_core::block_buffer::StereoBlockBuffer;
-use rusty_daw_core::{SampleRate, SampleTime, SmoothOutputF32};
+use rusty_daw_core::{MusicalTime, SampleRate, SampleTime, Seconds, SmoothOutputF32};
 
-use crate::backend::resource_loader::{PcmLoadError, ResourceLoadError};
+use crate::backend::dsp::transients::{detect_transients, TransientDetectorParams};
 use crate::backend::{GlobalNodeData, ResourceCache, MAX_BLOCKSIZE};
 
+use super::audio_clip::{compute_auto_crossfades, DEFAULT_AUTO_CROSSFADE_TIME};
+use super::transport::LoopStateProcInfo;
 use super::{
-    AudioClipHandle, AudioClipProcess, AudioClipSaveState, TempoMap, TimelineTrackSaveState,
+    AudioClipHandle, AudioClipProcess, AudioClipSaveState, ClipOverlapPolicy, FadeCurve, TempoMap,
+    TimelineTrackSaveState,
 };
 
+/// The length of the fades added between slices when slicing an audio clip at its
+/// transients.
+pub static TRANSIENT_SLICE_FADE_TIME: Seconds = Seconds(2.0 / 1_000.0);
+
 pub struct TimelineTrackHandle {
     audio_clip_handles: Vec<AudioClipHandle>,
 
@@ -26,6 +33,17 @@ impl TimelineTrackHandle {
         save_state.name = name;
     }
 
+    /// Set what happens when audio clips on this track overlap.
+    pub fn set_overlap_policy(
+        &mut self,
+        overlap_policy: ClipOverlapPolicy,
+        save_state: &mut TimelineTrackSaveState,
+    ) {
+        save_state.overlap_policy = overlap_policy;
+
+        self.update_auto_crossfades(save_state);
+    }
+
     /// Return an immutable handle to the audio clip with the given index.
     pub fn audio_clip<'a>(
         &'a self,
@@ -52,75 +70,462 @@ impl TimelineTrackHandle {
         }
     }
 
+    /// Perform a batch of edits on this track.
+    ///
+    /// A closure is used for batch edits instead of only individual methods, so the track
+    /// process only gets compiled once after the user is done, instead of being recompiled
+    /// after every method.
+    pub fn edit<R, F: FnOnce(&mut TimelineTrackEdit<'_>) -> R>(
+        &mut self,
+        save_state: &mut TimelineTrackSaveState,
+        f: F,
+    ) -> R {
+        let mut edit = TimelineTrackEdit {
+            audio_clip_handles: &mut self.audio_clip_handles,
+            audio_clip_procs: Vec::clone(&self.process.get().audio_clips),
+            save_state: &mut *save_state,
+            coll_handle: &self.coll_handle,
+            procs_changed: false,
+        };
+
+        let res = f(&mut edit);
+
+        let TimelineTrackEdit { audio_clip_procs, procs_changed, .. } = edit;
+
+        if procs_changed {
+            // Compile the new process.
+            let mut new_process = TimelineTrackProcess::clone(&self.process.get());
+            new_process.audio_clips = Shared::new(&self.coll_handle, audio_clip_procs);
+            self.process.set(Shared::new(&self.coll_handle, new_process));
+        }
+
+        self.update_auto_crossfades(save_state);
+
+        res
+    }
+
     /// Add a new audio clip to this track.
+    ///
+    /// Returns the index of the new clip. The PCM resource of the clip is loaded in the
+    /// background (see `AudioClipHandle::render_job()`).
     pub fn add_audio_clip(
         &mut self,
         clip: AudioClipSaveState,
         resource_cache: &ResourceCache,
         tempo_map: &TempoMap,
         save_state: &mut TimelineTrackSaveState,
-    ) -> Result<(), PcmLoadError> {
-        let (audio_clip_proc, params_handle, pcm_load_res) =
-            AudioClipProcess::new(&clip, resource_cache, tempo_map, &self.coll_handle);
+    ) -> usize {
+        self.edit(save_state, |edit| edit.add_audio_clip(clip, resource_cache, tempo_map))
+    }
+
+    /// Move the audio clip with the given index to a new position on the timeline.
+    pub fn move_audio_clip(
+        &mut self,
+        index: usize,
+        timeline_start: MusicalTime,
+        tempo_map: &TempoMap,
+        save_state: &mut TimelineTrackSaveState,
+    ) -> Result<(), ()> {
+        self.edit(save_state, |edit| edit.move_audio_clip(index, timeline_start, tempo_map))
+    }
+
+    /// Remove an audio clip from this track.
+    pub fn remove_audio_clip(
+        &mut self,
+        index: usize,
+        save_state: &mut TimelineTrackSaveState,
+    ) -> Result<(), ()> {
+        self.edit(save_state, |edit| edit.remove_audio_clip(index))
+    }
+
+    /// Split the audio clip with the given index into two clips at the given time.
+    ///
+    /// Returns the index of the new clip (the second half).
+    pub fn split_audio_clip(
+        &mut self,
+        index: usize,
+        split_at: MusicalTime,
+        resource_cache: &ResourceCache,
+        tempo_map: &TempoMap,
+        save_state: &mut TimelineTrackSaveState,
+    ) -> Result<usize, ()> {
+        self.edit(save_state, |edit| {
+            edit.split_audio_clip(index, split_at, resource_cache, tempo_map)
+        })
+    }
+
+    /// Split the audio clip with the given index into one clip per slice between the
+    /// transients detected in the clip.
+    ///
+    /// Returns the indices of all slices in the order they appear on the timeline. The
+    /// original clip becomes the first slice, so the first index is always `index`.
+    pub fn slice_audio_clip_at_transients(
+        &mut self,
+        index: usize,
+        params: &TransientDetectorParams,
+        resource_cache: &ResourceCache,
+        tempo_map: &TempoMap,
+        save_state: &mut TimelineTrackSaveState,
+    ) -> Result<Vec<usize>, ()> {
+        self.edit(save_state, |edit| {
+            edit.slice_audio_clip_at_transients(index, params, resource_cache, tempo_map)
+        })
+    }
+
+    /// Duplicate the audio clip with the given index and place the copy at the given time.
+    ///
+    /// Returns the index of the new clip.
+    pub fn duplicate_audio_clip(
+        &mut self,
+        index: usize,
+        timeline_start: MusicalTime,
+        resource_cache: &ResourceCache,
+        tempo_map: &TempoMap,
+        save_state: &mut TimelineTrackSaveState,
+    ) -> Result<usize, ()> {
+        self.edit(save_state, |edit| {
+            edit.duplicate_audio_clip(index, timeline_start, resource_cache, tempo_map)
+        })
+    }
+
+    /// Move the audio clip with the given index from this track to another track, and
+    /// place it at the given time.
+    ///
+    /// Returns the index of the clip in the other track.
+    pub fn move_audio_clip_to_track(
+        &mut self,
+        index: usize,
+        save_state: &mut TimelineTrackSaveState,
+        dst_track: &mut TimelineTrackHandle,
+        dst_save_state: &mut TimelineTrackSaveState,
+        timeline_start: MusicalTime,
+        tempo_map: &TempoMap,
+    ) -> Result<usize, ()> {
+        let clip = self.edit(save_state, |edit| edit.detach_audio_clip(index))?;
+
+        Ok(dst_track.edit(dst_save_state, |edit| {
+            let new_index = edit.attach_audio_clip(clip);
+
+            // This will not fail because the index is valid.
+            let _ = edit.move_audio_clip(new_index, timeline_start, tempo_map);
+
+            new_index
+        }))
+    }
+
+    /// Recompute the automatic crossfades between overlapping audio clips on this track.
+    ///
+    /// This is done automatically when editing clips through this handle. This only needs
+    /// to be called manually after changing the position or duration of a clip through
+    /// `audio_clip_mut()`.
+    pub fn update_auto_crossfades(&mut self, save_state: &TimelineTrackSaveState) {
+        let crossfades = match save_state.overlap_policy {
+            ClipOverlapPolicy::Sum => vec![Vec::new(); self.audio_clip_handles.len()],
+            ClipOverlapPolicy::LaterClipWins => {
+                let clips: Vec<(SampleTime, SampleTime)> =
+                    self.audio_clip_handles.iter().map(|c| c.timeline_range()).collect();
+
+                compute_auto_crossfades(
+                    &clips,
+                    DEFAULT_AUTO_CROSSFADE_TIME.to_nearest_sample_round(self.sample_rate),
+                )
+            }
+        };
+
+        for (clip, crossfades) in self.audio_clip_handles.iter_mut().zip(crossfades.into_iter()) {
+            clip.set_auto_crossfades(crossfades);
+        }
+    }
+
+    pub(super) fn update_tempo_map(
+        &mut self,
+        tempo_map: &TempoMap,
+        save_state: &TimelineTrackSaveState,
+    ) {
+        for (clip, save) in self.audio_clip_handles.iter_mut().zip(save_state.audio_clips.iter()) {
+            clip.update_tempo_map(tempo_map, save);
+        }
+
+        self.update_auto_crossfades(save_state);
+    }
+}
 
-        // Compile the new process.
+/// An audio clip that has been removed from a timeline track, so it can be added to
+/// another timeline track without needing to create a new process.
+pub struct DetachedAudioClip {
+    process: AudioClipProcess,
+    handle: AudioClipHandle,
+    save_state: AudioClipSaveState,
+}
 
-        let mut new_process = TimelineTrackProcess::clone(&self.process.get());
+/// A batch of edits on a timeline track (see `TimelineTrackHandle::edit()`).
+pub struct TimelineTrackEdit<'a> {
+    audio_clip_handles: &'a mut Vec<AudioClipHandle>,
+    audio_clip_procs: Vec<AudioClipProcess>,
+    save_state: &'a mut TimelineTrackSaveState,
 
-        // Clone the old processes.
-        let mut new_audio_clip_procs = Vec::clone(&new_process.audio_clips);
+    coll_handle: &'a Handle,
 
-        // Add the new clip.
-        new_audio_clip_procs.push(audio_clip_proc);
+    procs_changed: bool,
+}
 
-        // Use the new process info.
-        new_process.audio_clips = Shared::new(&self.coll_handle, new_audio_clip_procs);
-        self.process.set(Shared::new(&self.coll_handle, new_process));
+impl<'a> TimelineTrackEdit<'a> {
+    /// The number of audio clips on this track.
+    pub fn num_audio_clips(&self) -> usize {
+        self.audio_clip_handles.len()
+    }
 
+    /// Return a mutable handle to the audio clip with the given index.
+    pub fn audio_clip_mut(
+        &mut self,
+        index: usize,
+    ) -> Option<(&mut AudioClipHandle, &mut AudioClipSaveState)> {
+        if let Some(audio_clip) = self.audio_clip_handles.get_mut(index) {
+            Some((audio_clip, &mut self.save_state.audio_clips[index]))
+        } else {
+            None
+        }
+    }
+
+    /// Add a new audio clip to this track.
+    ///
+    /// Returns the index of the new clip. The PCM resource of the clip is loaded in the
+    /// background (see `AudioClipHandle::render_job()`). Note the clip plays silence until
+    /// then, or if the PCM resource failed to load.
+    pub fn add_audio_clip(
+        &mut self,
+        clip: AudioClipSaveState,
+        resource_cache: &ResourceCache,
+        tempo_map: &TempoMap,
+    ) -> usize {
+        let (audio_clip_proc, params_handle) =
+            AudioClipProcess::new(&clip, resource_cache, tempo_map, self.coll_handle);
+
+        self.audio_clip_procs.push(audio_clip_proc);
         self.audio_clip_handles.push(params_handle);
-        save_state.audio_clips.push(clip);
+        self.save_state.audio_clips.push(clip);
+
+        self.procs_changed = true;
 
-        pcm_load_res
+        self.audio_clip_handles.len() - 1
     }
 
     /// Remove an audio clip from this track.
-    pub fn remove_audio_clip(
+    pub fn remove_audio_clip(&mut self, index: usize) -> Result<(), ()> {
+        self.detach_audio_clip(index).map(|_| ())
+    }
+
+    /// Move the audio clip with the given index to a new position on the timeline.
+    pub fn move_audio_clip(
         &mut self,
         index: usize,
-        save_state: &mut TimelineTrackSaveState,
+        timeline_start: MusicalTime,
+        tempo_map: &TempoMap,
     ) -> Result<(), ()> {
-        if index >= self.audio_clip_handles.len() {
+        let (clip, save_state) = self.audio_clip_mut(index).ok_or(())?;
+
+        clip.set_timeline_start(timeline_start, tempo_map, save_state);
+
+        Ok(())
+    }
+
+    /// Split the audio clip with the given index into two clips at the given time.
+    ///
+    /// The first half keeps its start fade and the second half keeps its end fade. The
+    /// new fades at the split point use the default fade duration.
+    ///
+    /// Returns the index of the new clip (the second half). This will return an error
+    /// if the split point does not lie inside the clip.
+    pub fn split_audio_clip(
+        &mut self,
+        index: usize,
+        split_at: MusicalTime,
+        resource_cache: &ResourceCache,
+        tempo_map: &TempoMap,
+    ) -> Result<usize, ()> {
+        let old_state = self.save_state.audio_clips.get(index).ok_or(())?.clone();
+
+        // Where the split point lies relative to the start of the clip.
+        let split_offset = Seconds(
+            tempo_map.musical_to_seconds(split_at).0
+                - tempo_map.musical_to_seconds(old_state.timeline_start).0,
+        );
+        if split_offset.0 <= 0.0 || split_offset.0 >= old_state.duration.0 {
+            return Err(());
+        }
+
+        let (first_envelope, second_envelope) = old_state.gain_envelope.split_at(split_offset);
+
+        let mut second_state = old_state.clone();
+        second_state.timeline_start = split_at;
+        second_state.duration = Seconds(old_state.duration.0 - split_offset.0);
+        second_state.clip_start_offset = Seconds(old_state.clip_start_offset.0 + split_offset.0);
+        second_state.gain_envelope = second_envelope;
+        second_state.fades.set_default_start_fade();
+        second_state.fades.start_fade_curve = FadeCurve::Linear;
+        second_state.fades.set_end_fade_duration(Seconds(
+            old_state.fades.end_fade_duration.0.min(second_state.duration.0),
+        ));
+
+        let mut first_fades = old_state.fades;
+        first_fades.set_default_end_fade();
+        first_fades.end_fade_curve = FadeCurve::Linear;
+        first_fades.set_start_fade_duration(Seconds(
+            old_state.fades.start_fade_duration.0.min(split_offset.0),
+        ));
+
+        // Shorten the first half.
+        {
+            let (clip, save_state) = self.audio_clip_mut(index).ok_or(())?;
+
+            clip.set_duration(split_offset, tempo_map, save_state);
+            clip.set_fades(first_fades, tempo_map, save_state);
+            clip.set_gain_envelope(first_envelope, tempo_map, save_state);
+        }
+
+        // Add the second half. This uses the same PCM resource as the first half, so any
+        // load errors would have already been reported when the first half was added.
+        let new_index = self.add_audio_clip(second_state, resource_cache, tempo_map);
+
+        Ok(new_index)
+    }
+
+    /// Split the audio clip with the given index into one clip per slice between the
+    /// transients detected in the clip. Short fades are added between the slices.
+    ///
+    /// Looping audio clips cannot be sliced. This also returns an error if the resource
+    /// of the clip is not rendered yet or is streamed from disk, since there are no
+    /// samples to detect transients in. The clip is not changed if an error is returned.
+    ///
+    /// Returns the indices of all slices in the order they appear on the timeline. The
+    /// original clip becomes the first slice, so the first index is always `index`.
+    pub fn slice_audio_clip_at_transients(
+        &mut self,
+        index: usize,
+        params: &TransientDetectorParams,
+        resource_cache: &ResourceCache,
+        tempo_map: &TempoMap,
+    ) -> Result<Vec<usize>, ()> {
+        let (clip, state) = self.audio_clip_mut(index).ok_or(())?;
+        if state.looping.is_some() {
             return Err(());
         }
 
-        self.audio_clip_handles.remove(index);
-        save_state.audio_clips.remove(index);
+        let resource = clip.resource();
+        if resource.pcm.len() == 0 {
+            return Err(());
+        }
 
-        // Compile the new process.
+        let sample_rate = resource.pcm.sample_rate();
+
+        // The range of the PCM resource played by the clip.
+        let pcm_start = (state.clip_start_offset.to_nearest_sample_round(sample_rate)
+            - resource.original_offset)
+            .0
+            .max(0) as usize;
+        let pcm_end =
+            pcm_start + state.duration.to_nearest_sample_round(sample_rate).0.max(0) as usize;
+
+        let clip_timeline_start = tempo_map.musical_to_seconds(state.timeline_start);
+
+        // A transient at the start of the clip does not split it.
+        let split_points: Vec<MusicalTime> =
+            detect_transients(&resource.pcm, pcm_start..pcm_end, params)
+                .iter()
+                .filter(|transient| **transient > pcm_start)
+                .map(|transient| {
+                    let offset =
+                        SampleTime::from_usize(transient - pcm_start).to_seconds(sample_rate);
+                    tempo_map.seconds_to_musical(Seconds(clip_timeline_start.0 + offset.0))
+                })
+                .collect();
+
+        // Check every split point before splitting, so the clip is not left half sliced.
+        // Each split point must lie inside the part of the clip before the next one (see
+        // `split_audio_clip()`).
+        let mut slice_end = state.duration.0;
+        for split_at in split_points.iter().rev() {
+            let split_offset = tempo_map.musical_to_seconds(*split_at).0 - clip_timeline_start.0;
+            if split_offset <= 0.0 || split_offset >= slice_end {
+                return Err(());
+            }
+            slice_end = split_offset;
+        }
 
-        let mut new_process = TimelineTrackProcess::clone(&self.process.get());
+        // Split from the end so the original clip always contains the next split point.
+        let mut slices = Vec::with_capacity(split_points.len() + 1);
+        for split_at in split_points.iter().rev() {
+            slices.push(self.split_audio_clip(index, *split_at, resource_cache, tempo_map)?);
+        }
+        slices.push(index);
+        slices.reverse();
 
-        // Clone the old processes.
-        let mut new_audio_clip_procs = Vec::clone(&new_process.audio_clips);
+        let num_slices = slices.len();
+        for (i, slice) in slices.iter().enumerate() {
+            // This will not fail because the index is valid.
+            let (clip, save_state) = self.audio_clip_mut(*slice).ok_or(())?;
 
-        // Remove the old clip.
-        new_audio_clip_procs.remove(index);
+            let mut fades = save_state.fades;
+            if i > 0 {
+                fades.set_start_fade_duration(TRANSIENT_SLICE_FADE_TIME);
+            }
+            if i + 1 < num_slices {
+                fades.set_end_fade_duration(TRANSIENT_SLICE_FADE_TIME);
+            }
 
-        // Use the new processes.
-        new_process.audio_clips = Shared::new(&self.coll_handle, new_audio_clip_procs);
-        self.process.set(Shared::new(&self.coll_handle, new_process));
+            clip.set_fades(fades, tempo_map, save_state);
+        }
 
-        Ok(())
+        Ok(slices)
     }
 
-    pub(super) fn update_tempo_map(
+    /// Duplicate the audio clip with the given index and place the copy at the given time.
+    ///
+    /// Returns the index of the new clip.
+    pub fn duplicate_audio_clip(
         &mut self,
+        index: usize,
+        timeline_start: MusicalTime,
+        resource_cache: &ResourceCache,
         tempo_map: &TempoMap,
-        save_state: &TimelineTrackSaveState,
-    ) {
-        for (clip, save) in self.audio_clip_handles.iter_mut().zip(save_state.audio_clips.iter()) {
-            clip.update_tempo_map(tempo_map, save);
+    ) -> Result<usize, ()> {
+        let mut new_state = self.save_state.audio_clips.get(index).ok_or(())?.clone();
+        new_state.timeline_start = timeline_start;
+
+        // This uses the same PCM resource as the original clip, so any load errors would
+        // have already been reported when the original clip was added.
+        let new_index = self.add_audio_clip(new_state, resource_cache, tempo_map);
+
+        Ok(new_index)
+    }
+
+    /// Remove the audio clip with the given index from this track so it can be added to
+    /// another track with `attach_audio_clip()`.
+    pub fn detach_audio_clip(&mut self, index: usize) -> Result<DetachedAudioClip, ()> {
+        if index >= self.audio_clip_handles.len() {
+            return Err(());
         }
+
+        let handle = self.audio_clip_handles.remove(index);
+        let save_state = self.save_state.audio_clips.remove(index);
+        let process = self.audio_clip_procs.remove(index);
+
+        self.procs_changed = true;
+
+        Ok(DetachedAudioClip { process, handle, save_state })
+    }
+
+    /// Add an audio clip that was removed from another track with `detach_audio_clip()`.
+    ///
+    /// Returns the index of the clip on this track.
+    pub fn attach_audio_clip(&mut self, clip: DetachedAudioClip) -> usize {
+        self.audio_clip_procs.push(clip.process);
+        self.audio_clip_handles.push(clip.handle);
+        self.save_state.audio_clips.push(clip.save_state);
+
+        self.procs_changed = true;
+
+        self.audio_clip_handles.len() - 1
     }
 }
 
@@ -136,19 +541,15 @@ impl TimelineTrackNode {
         tempo_map: &TempoMap,
         sample_rate: SampleRate,
         coll_handle: &Handle,
-    ) -> (Self, TimelineTrackHandle, Vec<ResourceLoadError>) {
+    ) -> (Self, TimelineTrackHandle) {
         let mut audio_clip_procs = Vec::<AudioClipProcess>::new();
-        let mut audio_clip_errors = Vec::<ResourceLoadError>::new();
         let mut audio_clip_handles = Vec::<AudioClipHandle>::new();
 
+        // The PCM resources of the audio clips are loaded in the background.
         for audio_clip_save in save_state.audio_clips.iter() {
-            let (process, handle, res) =
+            let (process, handle) =
                 AudioClipProcess::new(audio_clip_save, resource_cache, tempo_map, coll_handle);
 
-            if let Err(e) = res {
-                audio_clip_errors.push(ResourceLoadError::PCM(e));
-            }
-
             audio_clip_procs.push(process);
             audio_clip_handles.push(handle);
         }
@@ -161,18 +562,23 @@ impl TimelineTrackNode {
             )),
         );
 
+        let mut handle = TimelineTrackHandle {
+            audio_clip_handles,
+            process: Shared::clone(&process),
+            sample_rate,
+            coll_handle: coll_handle.clone(),
+        };
+
+        if save_state.overlap_policy != ClipOverlapPolicy::Sum {
+            handle.update_auto_crossfades(save_state);
+        }
+
         (
             Self {
-                process: Shared::clone(&process),
-                temp_buffer: Shared::new(coll_handle, AtomicRefCell::new(StereoBlockBuffer::new())),
-            },
-            TimelineTrackHandle {
-                audio_clip_handles,
                 process,
-                sample_rate,
-                coll_handle: coll_handle.clone(),
+                temp_buffer: Shared::new(coll_handle, AtomicRefCell::new(StereoBlockBuffer::new())),
             },
-            audio_clip_errors,
+            handle,
         )
     }
 
@@ -292,6 +698,20 @@ impl AudioGraphNode<GlobalNodeData, MAX_BLOCKSIZE> for TimelineTrackNode {
         // of overwriting them.
         stereo_out.clear_frames(frames);
 
+        // Let clips streamed from disk decode the frames that will be played next, even
+        // while the transport is stopped.
+        {
+            let loop_start = match global_data.transport.loop_state() {
+                LoopStateProcInfo::Active { loop_start, .. } => Some(loop_start),
+                LoopStateProcInfo::Inactive => None,
+            };
+
+            let process = self.process.get();
+            for audio_clip in process.audio_clips.iter() {
+                audio_clip.prefetch(global_data.transport.playhead(), loop_start);
+            }
+        }
+
         if !global_data.transport.audio_clip_declick().is_active() {
             // Nothing to do.
             return;
@@ -463,3 +883,182 @@ impl AudioGraphNode<GlobalNodeData, MAX_BLOCKSIZE> for TimelineTrackNode {
 pub struct TimelineTrackProcess {
     audio_clips: Shared<Vec<AudioClipProcess>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use basedrop::Collector;
+
+    use super::*;
+    use crate::backend::timeline::AudioClipFades;
+
+    static TEST_FILE: &str = "./assets/test_files/synth_keys/synth_keys_48000_16bit.wav";
+
+    struct TestTracks {
+        // The collector must outlive the tracks.
+        _collector: Collector,
+        resource_cache: ResourceCache,
+        // One beat per second, so musical times are the same as seconds.
+        tempo_map: TempoMap,
+        tracks: Vec<(TimelineTrackHandle, TimelineTrackSaveState)>,
+    }
+
+    /// Returns timeline tracks with an audio clip for each (timeline start, duration) of
+    /// every track in `clips`.
+    fn test_tracks(clips: &[&[(f64, f64)]]) -> TestTracks {
+        let collector = Collector::new();
+        let sample_rate = SampleRate::new(48_000.0);
+        let resource_cache = ResourceCache::test(&collector.handle(), sample_rate);
+        let tempo_map = TempoMap::new(60.0, sample_rate);
+
+        let tracks = clips
+            .iter()
+            .map(|clips| {
+                let save_state = TimelineTrackSaveState {
+                    name: String::from("Track"),
+                    audio_clips: clips
+                        .iter()
+                        .map(|(start, duration)| {
+                            AudioClipSaveState::test(TEST_FILE, *start, *duration)
+                        })
+                        .collect(),
+                    overlap_policy: Default::default(),
+                };
+
+                let (_, handle) = TimelineTrackNode::new(
+                    &save_state,
+                    &resource_cache,
+                    &tempo_map,
+                    sample_rate,
+                    &collector.handle(),
+                );
+
+                (handle, save_state)
+            })
+            .collect();
+
+        TestTracks { _collector: collector, resource_cache, tempo_map, tracks }
+    }
+
+    /// Returns the (start, end) of the audio clip with the given index in seconds.
+    fn clip_range(
+        track: &(TimelineTrackHandle, TimelineTrackSaveState),
+        index: usize,
+    ) -> (f64, f64) {
+        let (start, end) = track.0.audio_clip_handles[index].timeline_range();
+        (start.0 as f64 / 48_000.0, end.0 as f64 / 48_000.0)
+    }
+
+    #[test]
+    fn splits_audio_clips() {
+        let mut t = test_tracks(&[&[(1.0, 2.0)]]);
+        let (track, save_state) = &mut t.tracks[0];
+
+        let second = track.split_audio_clip(
+            0,
+            MusicalTime::new(2.5),
+            &t.resource_cache,
+            &t.tempo_map,
+            save_state,
+        );
+        assert_eq!(second, Ok(1));
+
+        let (first, second) = (&save_state.audio_clips[0], &save_state.audio_clips[1]);
+        assert_eq!(first.duration, Seconds(1.5));
+        assert_eq!(second.timeline_start, MusicalTime::new(2.5));
+        assert_eq!(second.duration, Seconds(0.5));
+        assert_eq!(second.clip_start_offset, Seconds(1.5));
+        assert_eq!(first.fades.end_fade_duration, AudioClipFades::DEFAULT_FADE_DURATION);
+        assert_eq!(second.fades.start_fade_duration, AudioClipFades::DEFAULT_FADE_DURATION);
+        assert_eq!(track.process.get().audio_clips.len(), 2);
+
+        let track = &t.tracks[0];
+        assert_eq!(clip_range(track, 0), (1.0, 2.5));
+        assert_eq!(clip_range(track, 1), (2.5, 3.0));
+
+        // Split points outside of the clip (including its start and end) are rejected.
+        let (track, save_state) = &mut t.tracks[0];
+        for split_at in [0.5, 1.0, 2.5, 3.0, 4.0].iter() {
+            let split_at = MusicalTime::new(*split_at);
+            assert_eq!(
+                track.split_audio_clip(0, split_at, &t.resource_cache, &t.tempo_map, save_state),
+                Err(())
+            );
+        }
+        assert_eq!(
+            track.split_audio_clip(
+                2,
+                MusicalTime::new(2.7),
+                &t.resource_cache,
+                &t.tempo_map,
+                save_state
+            ),
+            Err(())
+        );
+        assert_eq!(save_state.audio_clips.len(), 2);
+        assert_eq!(save_state.audio_clips[0].duration, Seconds(1.5));
+    }
+
+    #[test]
+    fn duplicates_and_moves_audio_clips() {
+        let mut t = test_tracks(&[&[(1.0, 2.0)], &[]]);
+
+        {
+            let (track, save_state) = &mut t.tracks[0];
+
+            let copy = track.duplicate_audio_clip(
+                0,
+                MusicalTime::new(4.0),
+                &t.resource_cache,
+                &t.tempo_map,
+                save_state,
+            );
+            assert_eq!(copy, Ok(1));
+            assert_eq!(save_state.audio_clips[1].pcm_path, save_state.audio_clips[0].pcm_path);
+            assert_eq!(save_state.audio_clips[1].duration, Seconds(2.0));
+
+            assert_eq!(
+                track.move_audio_clip(1, MusicalTime::new(6.0), &t.tempo_map, save_state),
+                Ok(())
+            );
+            assert_eq!(save_state.audio_clips[1].timeline_start, MusicalTime::new(6.0));
+            assert_eq!(
+                track.move_audio_clip(2, MusicalTime::new(6.0), &t.tempo_map, save_state),
+                Err(())
+            );
+        }
+        assert_eq!(clip_range(&t.tracks[0], 1), (6.0, 8.0));
+
+        // Move the first clip to the second track.
+        let (src, dst) = t.tracks.split_at_mut(1);
+        let ((src_track, src_state), (dst_track, dst_state)) = (&mut src[0], &mut dst[0]);
+        let moved = src_track.move_audio_clip_to_track(
+            0,
+            src_state,
+            dst_track,
+            dst_state,
+            MusicalTime::new(10.0),
+            &t.tempo_map,
+        );
+        assert_eq!(moved, Ok(0));
+        assert_eq!(src_state.audio_clips.len(), 1);
+        assert_eq!(src_state.audio_clips[0].timeline_start, MusicalTime::new(6.0));
+        assert_eq!(dst_state.audio_clips.len(), 1);
+        assert_eq!(dst_state.audio_clips[0].timeline_start, MusicalTime::new(10.0));
+        assert_eq!(src_track.process.get().audio_clips.len(), 1);
+        assert_eq!(dst_track.process.get().audio_clips.len(), 1);
+
+        let moved = src_track.move_audio_clip_to_track(
+            1,
+            src_state,
+            dst_track,
+            dst_state,
+            MusicalTime::new(10.0),
+            &t.tempo_map,
+        );
+        assert!(moved.is_err());
+        assert_eq!(src_state.audio_clips.len(), 1);
+        assert_eq!(dst_state.audio_clips.len(), 1);
+
+        assert_eq!(clip_range(&t.tracks[1], 0), (10.0, 12.0));
+    }
+}
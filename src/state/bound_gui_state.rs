@@ -1,6 +1,6 @@
-use tuix::{Entity, Event, Lens, Model, State};
+use tuix::{BindEvent, Entity, Event, Lens, Model, PropSet, State};
 
-use super::{ProjectSaveState, StateSystem};
+use super::{MissingMediaReport, ProjectSaveState, StateSystem};
 
 #[derive(Lens)]
 pub struct BoundGuiState {
@@ -9,6 +9,9 @@ pub struct BoundGuiState {
 
     pub save_state: ProjectSaveState,
 
+    /// The audio files used by the project that could not be found.
+    pub missing_media: MissingMediaReport,
+
     pub backend_loaded: bool,
     pub is_playing: bool,
     pub bpm: f64,
@@ -19,6 +22,7 @@ impl BoundGuiState {
         Self {
             state_system: Some(StateSystem::new()),
             save_state: ProjectSaveState::new_empty(),
+            missing_media: MissingMediaReport::default(),
             backend_loaded: false,
             is_playing: false,
             bpm: 110.0,
@@ -28,13 +32,18 @@ impl BoundGuiState {
 
 impl Model for BoundGuiState {
     fn on_event(&mut self, state: &mut State, entity: Entity, event: &mut Event) {
-        if let Some(state_system_event) = event.message.downcast() {
-            // This is to get around the borrow checker.
-            let mut state_system = self.state_system.take().unwrap();
+        // This is to get around the borrow checker.
+        let mut state_system = self.state_system.take().unwrap();
 
-            state_system.on_event(self, state, entity, state_system_event);
+        // Scans of the audio files of the project run in the background.
+        if state_system.poll_media_scans(self) {
+            entity.emit(state, BindEvent::Update);
+        }
 
-            self.state_system = Some(state_system);
+        if let Some(state_system_event) = event.message.downcast() {
+            state_system.on_event(self, state, entity, state_system_event);
         }
+
+        self.state_system = Some(state_system);
     }
 }
@@ -0,0 +1,207 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::ProjectSaveState;
+use crate::util::{hash_file, TwoXHashMap};
+
+/// The directory inside the project directory that media files are collected into.
+pub static COLLECTED_MEDIA_DIR: &str = "media";
+
+/// Copy every media file used by the project that is outside of the project directory
+/// into the `COLLECTED_MEDIA_DIR` directory inside of it, so the project folder can be
+/// moved to another machine.
+///
+/// Files with the same name are renamed, unless they have the same contents (such as
+/// when media is collected again). Files that don't exist are skipped (see
+/// `MissingMediaReport`).
+///
+/// The media paths of the project are not changed. Returns the (old path, new path) of
+/// every copied file, which is used to update the paths of the audio clips (see
+/// `StateSystem::collect_media()`).
+pub fn collect_media(
+    project: &ProjectSaveState,
+) -> Result<TwoXHashMap<PathBuf, PathBuf>, CollectMediaError> {
+    let project_dir = project.project_dir().ok_or(CollectMediaError::NoProjectDir)?;
+    let media_dir = project_dir.join(COLLECTED_MEDIA_DIR);
+
+    let mut media_paths: Vec<&PathBuf> = Vec::new();
+    for clip in project.timeline_tracks.iter().flat_map(|track| track.audio_clips.iter()) {
+        let ir_path = clip.effects.convolution.as_ref().map(|convolution| &convolution.ir_path);
+
+        for path in std::iter::once(&clip.pcm_path).chain(ir_path) {
+            if !path.starts_with(project_dir) && !media_paths.contains(&path) {
+                media_paths.push(path);
+            }
+        }
+    }
+
+    let mut collected: TwoXHashMap<PathBuf, PathBuf> = Default::default();
+
+    for path in media_paths {
+        if !path.is_file() {
+            log::warn!("Not collecting missing media file: {:?}", path);
+            continue;
+        }
+
+        fs::create_dir_all(&media_dir)
+            .map_err(|e| CollectMediaError::CouldNotCreateDir((media_dir.clone(), e)))?;
+
+        let new_path = collected_path(path, &media_dir)
+            .map_err(|e| CollectMediaError::CouldNotCopy((path.clone(), e)))?;
+
+        if !new_path.exists() {
+            log::info!("Collecting media file {:?} to {:?}", path, &new_path);
+
+            fs::copy(path, &new_path)
+                .map_err(|e| CollectMediaError::CouldNotCopy((path.clone(), e)))?;
+        }
+
+        collected.insert(path.clone(), new_path);
+    }
+
+    Ok(collected)
+}
+
+/// Returns the path in `media_dir` to copy the file at `path` to. If a different file
+/// with the same name already exists, then a number is added to the name.
+fn collected_path(path: &Path, media_dir: &Path) -> io::Result<PathBuf> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|extension| extension.to_string_lossy());
+
+    let mut hash = None;
+    let mut i = 1;
+    loop {
+        let file_name = match (i, &extension) {
+            (1, Some(extension)) => format!("{}.{}", stem, extension),
+            (1, None) => stem.to_string(),
+            (_, Some(extension)) => format!("{}_{}.{}", stem, i, extension),
+            (_, None) => format!("{}_{}", stem, i),
+        };
+
+        let new_path = media_dir.join(file_name);
+        if !new_path.exists() {
+            return Ok(new_path);
+        }
+
+        // The file was already collected before.
+        if hash.is_none() {
+            hash = Some(hash_file(path)?);
+        }
+        if Some(hash_file(&new_path)?) == hash {
+            return Ok(new_path);
+        }
+
+        i += 1;
+    }
+}
+
+#[derive(Debug)]
+pub enum CollectMediaError {
+    /// The project has not been saved yet, so there is no project directory to collect
+    /// the media into.
+    NoProjectDir,
+    CouldNotCreateDir((PathBuf, io::Error)),
+    CouldNotCopy((PathBuf, io::Error)),
+}
+
+impl Error for CollectMediaError {}
+
+impl fmt::Display for CollectMediaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use CollectMediaError::*;
+
+        match self {
+            NoProjectDir => {
+                write!(f, "Failed to collect media: the project has not been saved yet")
+            }
+            CouldNotCreateDir((path, e)) => write!(
+                f,
+                "Failed to collect media: could not create directory | {} | path: {:?}",
+                e, path
+            ),
+            CouldNotCopy((path, e)) => {
+                write!(f, "Failed to collect media: could not copy file | {} | path: {:?}", e, path)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::timeline::{AudioClipSaveState, TimelineTrackSaveState};
+
+    fn test_project(dir: &Path, clips: &[&Path]) -> ProjectSaveState {
+        let mut project = ProjectSaveState::new_empty();
+        project.set_project_path(dir.join("project").join("song.mdl"));
+        project.timeline_tracks.push(TimelineTrackSaveState {
+            name: String::from("Track 1"),
+            audio_clips: clips
+                .iter()
+                .map(|path| AudioClipSaveState::test(path.to_str().unwrap(), 0.0, 1.0))
+                .collect(),
+            overlap_policy: Default::default(),
+        });
+        project
+    }
+
+    #[test]
+    fn collects_media_into_project_dir() {
+        let dir = std::env::temp_dir().join("meadowlark_test_collect_media");
+        let _ = fs::remove_dir_all(&dir);
+
+        let kick_a = dir.join("samples_a").join("kick.wav");
+        let kick_b = dir.join("samples_b").join("kick.wav");
+        let snare = dir.join("project").join("snare.wav");
+        let missing = dir.join("samples_a").join("missing.wav");
+        for (path, contents) in [(&kick_a, "a"), (&kick_b, "b"), (&snare, "snare")].iter() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let project = test_project(&dir, &[&kick_a, &kick_b, &snare, &missing, &kick_a]);
+        let collected = collect_media(&project).unwrap();
+
+        // Files with the same name get different names, and files that are already in
+        // the project directory or missing are skipped.
+        let media_dir = dir.join("project").join(COLLECTED_MEDIA_DIR);
+        assert_eq!(collected.len(), 2);
+        assert_eq!(collected[&kick_a], media_dir.join("kick.wav"));
+        assert_eq!(collected[&kick_b], media_dir.join("kick_2.wav"));
+        assert_eq!(fs::read_to_string(&collected[&kick_b]).unwrap(), "b");
+
+        // Collecting again reuses the copies.
+        let project = test_project(&dir, &[&kick_b, &kick_a]);
+        assert_eq!(collect_media(&project).unwrap(), collected);
+        assert!(!media_dir.join("kick_3.wav").exists());
+
+        // The collected files are stored relative to the project file.
+        let project = test_project(&dir, &[&collected[&kick_a], &kick_b]);
+        let relative = project.with_relative_media_paths();
+        assert_eq!(
+            relative.timeline_tracks[0].audio_clips[0].pcm_path,
+            PathBuf::from("media/kick.wav")
+        );
+        assert_eq!(
+            relative.timeline_tracks[0].audio_clips[1].pcm_path,
+            PathBuf::from("../samples_b/kick.wav")
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn needs_project_dir() {
+        let mut project = ProjectSaveState::new_empty();
+        project.timeline_tracks.push(TimelineTrackSaveState {
+            name: String::from("Track 1"),
+            audio_clips: vec![AudioClipSaveState::test("kick.wav", 0.0, 1.0)],
+            overlap_policy: Default::default(),
+        });
+
+        assert!(matches!(collect_media(&project), Err(CollectMediaError::NoProjectDir)));
+    }
+}
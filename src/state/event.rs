@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use super::ProjectSaveState;
 
 #[derive(Debug, Clone)]
@@ -18,6 +20,21 @@ impl PartialEq for StateSystemEvent {
 #[derive(Debug, Clone)]
 pub enum ProjectEvent {
     LoadProject(Box<ProjectSaveState>),
+
+    /// Search the given directory tree for the missing audio files of the project in the
+    /// background. Files found with a matching size and length are relinked automatically.
+    SearchMissingMedia(PathBuf),
+
+    /// Use the file at `new_path` for every audio clip that uses the missing file at
+    /// `missing`.
+    RelinkMissingFile {
+        missing: PathBuf,
+        new_path: PathBuf,
+    },
+
+    /// Copy every media file used by the project that is outside of the project directory
+    /// into it, and use the copies (see `collect_media()`).
+    CollectMedia,
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,449 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use rusty_daw_core::Seconds;
+
+use crate::backend::resource_loader::{PcmDecoder, PcmFileInfo};
+use crate::backend::timeline::TimelineTrackSaveState;
+use crate::util::TwoXHashMap;
+
+/// The maximum difference in length between a missing file and a file found while
+/// searching for it, for the two to be considered the same file.
+pub static RELINK_LEN_TOLERANCE: Seconds = Seconds(0.001);
+
+/// An audio file used by the project that could not be found.
+#[derive(Debug, Clone)]
+pub struct MissingFile {
+    pub path: PathBuf,
+
+    /// The size and length of the file when it was last found (if known).
+    pub info: Option<PcmFileInfo>,
+
+    /// The files found by the last search for this file, with the best matches first.
+    /// See `find_relink_candidates()`.
+    pub candidates: Vec<RelinkCandidate>,
+}
+
+/// All of the audio files used by the project that could not be found.
+#[derive(Debug, Clone, Default)]
+pub struct MissingMediaReport {
+    pub files: Vec<MissingFile>,
+}
+
+impl MissingMediaReport {
+    /// Find all of the audio files (and impulse responses) used by the given timeline
+    /// tracks that don't exist.
+    ///
+    /// The audio clips that use a missing file are found by its path when the file is
+    /// relinked, since the clips may be edited in the meantime.
+    pub fn new(timeline_tracks: &[TimelineTrackSaveState]) -> Self {
+        let mut files: Vec<MissingFile> = Vec::new();
+
+        for clip in timeline_tracks.iter().flat_map(|track| track.audio_clips.iter()) {
+            let ir_path =
+                clip.effects.convolution.as_ref().map(|convolution| (&convolution.ir_path, None));
+
+            // The size and length are only recorded for the audio file of the clip.
+            for (path, info) in std::iter::once((&clip.pcm_path, clip.pcm_file_info)).chain(ir_path)
+            {
+                if let Some(file) = files.iter_mut().find(|file| &file.path == path) {
+                    if file.info.is_none() {
+                        file.info = info;
+                    }
+                } else if !path.is_file() {
+                    log::warn!("Missing audio file: {:?}", path);
+
+                    files.push(MissingFile { path: path.clone(), info, candidates: Vec::new() });
+                }
+            }
+        }
+
+        Self { files }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Returns the missing file at the given path (if it is missing).
+    pub fn get(&self, path: &PathBuf) -> Option<&MissingFile> {
+        self.files.iter().find(|file| &file.path == path)
+    }
+
+    /// Remove the given file from the report once it has been relinked.
+    pub fn remove(&mut self, path: &PathBuf) -> Option<MissingFile> {
+        let index = self.files.iter().position(|file| &file.path == path)?;
+        Some(self.files.remove(index))
+    }
+
+    /// Store the candidates found for each missing file (see `find_relink_candidates()`).
+    /// Files that were relinked since the search was started are skipped.
+    pub fn set_candidates(&mut self, mut candidates: TwoXHashMap<PathBuf, Vec<RelinkCandidate>>) {
+        for file in self.files.iter_mut() {
+            if let Some(candidates) = candidates.remove(&file.path) {
+                file.candidates = candidates;
+            }
+        }
+    }
+}
+
+/// A file found while searching for a missing file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelinkCandidate {
+    pub path: PathBuf,
+
+    /// Whether the size of this file matches the size of the missing file. This is `None`
+    /// if the size of either file is unknown.
+    pub size_matches: Option<bool>,
+
+    /// Whether the length of this file matches the length of the missing file. This is
+    /// `None` if the length of either file is unknown.
+    pub len_matches: Option<bool>,
+
+    /// The size and length of this file, if it could be read.
+    pub info: Option<PcmFileInfo>,
+}
+
+impl RelinkCandidate {
+    /// Whether both the size and the length of this file are known to match the missing
+    /// file. Such files can be relinked without asking the user.
+    pub fn is_exact_match(&self) -> bool {
+        self.size_matches == Some(true) && self.len_matches == Some(true)
+    }
+
+    /// Used to sort candidates. Files with a mismatch are always ranked below files
+    /// that could not be checked.
+    fn score(&self) -> i32 {
+        [self.size_matches, self.len_matches]
+            .iter()
+            .map(|matches| match matches {
+                Some(true) => 1,
+                Some(false) => -2,
+                None => 0,
+            })
+            .sum()
+    }
+}
+
+/// Search the directory tree at `search_dir` for files with the same file name as a
+/// missing file (ignoring case). Returns the files found for each missing file, sorted
+/// with the best matches first based on the size and length of the files.
+///
+/// This reads every file found, so it should not be called on the GUI thread (see
+/// `MediaScanner`).
+pub fn find_relink_candidates(
+    report: &MissingMediaReport,
+    search_dir: &Path,
+    decoder: &PcmDecoder,
+) -> TwoXHashMap<PathBuf, Vec<RelinkCandidate>> {
+    let mut found: TwoXHashMap<String, Vec<PathBuf>> = report
+        .files
+        .iter()
+        .filter_map(|file| file.path.file_name())
+        .map(|file_name| (file_name.to_string_lossy().to_lowercase(), Vec::new()))
+        .collect();
+
+    find_files_named(search_dir, &mut found);
+
+    let mut candidates: TwoXHashMap<PathBuf, Vec<RelinkCandidate>> = Default::default();
+
+    for file in report.files.iter() {
+        let paths = match file.path.file_name() {
+            Some(file_name) => &found[&file_name.to_string_lossy().to_lowercase()],
+            None => continue,
+        };
+
+        let mut file_candidates: Vec<RelinkCandidate> = paths
+            .iter()
+            .map(|path| {
+                let info = decoder.file_info(path).ok();
+
+                let (size_matches, len_matches) = match (&file.info, &info) {
+                    (Some(missing_info), Some(info)) => (
+                        Some(missing_info.size_bytes == info.size_bytes),
+                        match (missing_info.len_seconds, info.len_seconds) {
+                            (Some(a), Some(b)) => Some((a.0 - b.0).abs() <= RELINK_LEN_TOLERANCE.0),
+                            _ => None,
+                        },
+                    ),
+                    _ => (None, None),
+                };
+
+                RelinkCandidate { path: path.clone(), size_matches, len_matches, info }
+            })
+            .collect();
+
+        // This is a stable sort, so candidates with the same score stay in search order.
+        file_candidates.sort_by_key(|candidate| -candidate.score());
+
+        candidates.insert(file.path.clone(), file_candidates);
+    }
+
+    candidates
+}
+
+/// Add every file in the directory tree at `dir` whose (lowercase) file name is a key
+/// of `found` to that entry. Directories that can't be read are skipped, and symbolic
+/// links to directories are not followed (so the search always finishes).
+fn find_files_named(dir: &Path, found: &mut TwoXHashMap<String, Vec<PathBuf>>) {
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::debug!("Skipping {:?} while searching for missing files: {}", &dir, e);
+                continue;
+            }
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(_) => continue,
+            };
+
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if let Some(paths) =
+                found.get_mut(&entry.file_name().to_string_lossy().to_lowercase())
+            {
+                paths.push(entry.path());
+            }
+        }
+    }
+}
+
+/// Read the size and length of every audio file used by the given timeline tracks that
+/// doesn't have them recorded yet. Files that can't be read are `None`.
+///
+/// This reads every file, so it should not be called on the GUI thread (see
+/// `MediaScanner`).
+pub fn read_pcm_file_info(
+    timeline_tracks: &[TimelineTrackSaveState],
+    decoder: &PcmDecoder,
+) -> TwoXHashMap<PathBuf, Option<PcmFileInfo>> {
+    let mut read_info: TwoXHashMap<PathBuf, Option<PcmFileInfo>> = Default::default();
+
+    for track in timeline_tracks.iter() {
+        for clip in track.audio_clips.iter().filter(|clip| clip.pcm_file_info.is_none()) {
+            read_info
+                .entry(clip.pcm_path.clone())
+                .or_insert_with(|| decoder.file_info(&clip.pcm_path).ok());
+        }
+    }
+
+    read_info
+}
+
+/// Record the size and length of the audio files used by the given timeline tracks that
+/// don't have them recorded yet, so the files can be found again if they are moved.
+///
+/// `read_info` - The info read by `read_pcm_file_info()`.
+pub fn update_pcm_file_info(
+    timeline_tracks: &mut [TimelineTrackSaveState],
+    read_info: &TwoXHashMap<PathBuf, Option<PcmFileInfo>>,
+) {
+    for track in timeline_tracks.iter_mut() {
+        for clip in track.audio_clips.iter_mut().filter(|clip| clip.pcm_file_info.is_none()) {
+            if let Some(info) = read_info.get(&clip.pcm_path) {
+                clip.pcm_file_info = *info;
+            }
+        }
+    }
+}
+
+/// The result of a scan started by the `MediaScanner`.
+pub enum MediaScanResult {
+    /// See `read_pcm_file_info()`.
+    PcmFileInfo(TwoXHashMap<PathBuf, Option<PcmFileInfo>>),
+    /// See `find_relink_candidates()`.
+    RelinkCandidates(TwoXHashMap<PathBuf, Vec<RelinkCandidate>>),
+}
+
+/// Reads the info of audio files and searches for missing files on background threads,
+/// so the GUI thread is not blocked while a large project or directory tree is scanned.
+///
+/// The results are collected with `try_recv()`. Results of scans that finish after the
+/// scanner is dropped are discarded.
+pub struct MediaScanner {
+    result_tx: mpsc::Sender<MediaScanResult>,
+    result_rx: mpsc::Receiver<MediaScanResult>,
+}
+
+impl MediaScanner {
+    pub fn new() -> Self {
+        let (result_tx, result_rx) = mpsc::channel();
+
+        Self { result_tx, result_rx }
+    }
+
+    /// Start reading the info of the audio files used by the given timeline tracks. See
+    /// `read_pcm_file_info()`.
+    pub fn read_pcm_file_info(
+        &self,
+        timeline_tracks: &[TimelineTrackSaveState],
+        decoder: PcmDecoder,
+    ) {
+        let timeline_tracks = timeline_tracks.to_vec();
+        let result_tx = self.result_tx.clone();
+
+        std::thread::spawn(move || {
+            let read_info = read_pcm_file_info(&timeline_tracks, &decoder);
+            let _ = result_tx.send(MediaScanResult::PcmFileInfo(read_info));
+        });
+    }
+
+    /// Start searching the directory tree at `search_dir` for the missing files in
+    /// `report`. See `find_relink_candidates()`.
+    pub fn find_relink_candidates(
+        &self,
+        report: &MissingMediaReport,
+        search_dir: PathBuf,
+        decoder: PcmDecoder,
+    ) {
+        let report = report.clone();
+        let result_tx = self.result_tx.clone();
+
+        std::thread::spawn(move || {
+            let candidates = find_relink_candidates(&report, &search_dir, &decoder);
+            let _ = result_tx.send(MediaScanResult::RelinkCandidates(candidates));
+        });
+    }
+
+    /// Returns the result of a finished scan (if any).
+    pub fn try_recv(&self) -> Option<MediaScanResult> {
+        self.result_rx.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use basedrop::Collector;
+    use rusty_daw_core::SampleRate;
+
+    use super::*;
+    use crate::backend::resource_loader::PcmLoader;
+    use crate::backend::timeline::{AudioClipSaveState, ConvolutionEffect};
+
+    static TEST_FILES_DIR: &str = "./assets/test_files/synth_keys";
+
+    fn test_decoder() -> PcmDecoder {
+        let collector = Collector::new();
+        PcmLoader::new(collector.handle(), SampleRate::new(48_000.0)).decoder()
+    }
+
+    fn test_tracks(clips: &[&Path]) -> Vec<TimelineTrackSaveState> {
+        vec![TimelineTrackSaveState {
+            name: String::from("Track 1"),
+            audio_clips: clips
+                .iter()
+                .map(|path| AudioClipSaveState::test(path.to_str().unwrap(), 0.0, 1.0))
+                .collect(),
+            overlap_policy: Default::default(),
+        }]
+    }
+
+    #[test]
+    fn ranks_relink_candidates() {
+        let dir = std::env::temp_dir().join("meadowlark_test_missing_media");
+        let _ = fs::remove_dir_all(&dir);
+
+        // The same recording with a different bit depth has the same length but not the
+        // same size, and a file that can't be decoded can't be checked.
+        let original = PathBuf::from(TEST_FILES_DIR).join("synth_keys_48000_16bit.wav");
+        let other_depth = PathBuf::from(TEST_FILES_DIR).join("synth_keys_48000_24bit.wav");
+        let exact = dir.join("c").join("Kick.wav");
+        let mismatch = dir.join("a").join("kick.wav");
+        let unknown = dir.join("b").join("KICK.WAV");
+        for (path, source) in [(&exact, Some(&original)), (&mismatch, Some(&other_depth))].iter() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::copy(source.unwrap(), path).unwrap();
+        }
+        fs::create_dir_all(unknown.parent().unwrap()).unwrap();
+        fs::write(&unknown, "not audio").unwrap();
+
+        let decoder = test_decoder();
+        let missing = dir.join("moved").join("kick.wav");
+        let mut tracks = test_tracks(&[&missing]);
+        tracks[0].audio_clips[0].pcm_file_info = Some(decoder.file_info(&original).unwrap());
+
+        let mut report = MissingMediaReport::new(&tracks);
+        let candidates = find_relink_candidates(&report, &dir, &decoder);
+        report.set_candidates(candidates);
+
+        let candidates = &report.get(&missing).unwrap().candidates;
+        assert_eq!(candidates.len(), 3);
+        assert_eq!(candidates[0].path, exact);
+        assert!(candidates[0].is_exact_match());
+        assert_eq!(candidates[0].info, tracks[0].audio_clips[0].pcm_file_info);
+        assert_eq!((candidates[1].path.clone(), candidates[1].size_matches), (unknown, None));
+        assert_eq!(candidates[2].path, mismatch);
+        assert_eq!(
+            (candidates[2].size_matches, candidates[2].len_matches),
+            (Some(false), Some(true))
+        );
+        assert!(!candidates[2].is_exact_match());
+
+        // Without the info of the missing file, nothing can be checked.
+        let mut report = MissingMediaReport::new(&test_tracks(&[&missing]));
+        let candidates = find_relink_candidates(&report, &dir, &decoder);
+        report.set_candidates(candidates);
+        assert!(report.files[0].candidates.iter().all(|candidate| candidate.score() == 0));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reports_missing_impulse_responses() {
+        let path = PathBuf::from(TEST_FILES_DIR).join("synth_keys_48000_16bit.wav");
+        let missing = PathBuf::from(TEST_FILES_DIR).join("missing.wav");
+        let missing_ir = PathBuf::from(TEST_FILES_DIR).join("missing_ir.wav");
+
+        let mut tracks = test_tracks(&[&path, &missing, &path]);
+        for (clip, ir_path) in
+            tracks[0].audio_clips.iter_mut().zip([&missing_ir, &path, &missing].iter())
+        {
+            clip.effects.convolution = Some(ConvolutionEffect {
+                ir_path: PathBuf::clone(ir_path),
+                mix: 1.0,
+                ir_start: Seconds(0.0),
+                ir_length: None,
+            });
+        }
+
+        let report = MissingMediaReport::new(&tracks);
+        let paths: Vec<&PathBuf> = report.files.iter().map(|file| &file.path).collect();
+        assert_eq!(paths, vec![&missing_ir, &missing]);
+    }
+
+    #[test]
+    fn records_pcm_file_info_in_background() {
+        let path = PathBuf::from(TEST_FILES_DIR).join("synth_keys_48000_16bit.wav");
+        let missing = PathBuf::from(TEST_FILES_DIR).join("missing.wav");
+        let mut tracks = test_tracks(&[&path, &missing, &path]);
+
+        let scanner = MediaScanner::new();
+        scanner.read_pcm_file_info(&tracks, test_decoder());
+
+        let start = std::time::Instant::now();
+        let read_info = loop {
+            match scanner.try_recv() {
+                Some(MediaScanResult::PcmFileInfo(read_info)) => break read_info,
+                Some(_) => unreachable!(),
+                None => {
+                    assert!(start.elapsed().as_secs() < 10, "scan never finished");
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+            }
+        };
+        assert_eq!(read_info.len(), 2);
+
+        update_pcm_file_info(&mut tracks, &read_info);
+        let info = tracks[0].audio_clips[0].pcm_file_info.unwrap();
+        assert_eq!(info.size_bytes, fs::metadata(&path).unwrap().len());
+        assert!(info.len_seconds.is_some());
+        assert_eq!(tracks[0].audio_clips[2].pcm_file_info, Some(info));
+        assert_eq!(tracks[0].audio_clips[1].pcm_file_info, None);
+    }
+}
@@ -1,9 +1,13 @@
 mod bound_gui_state;
+mod collect_media;
+mod missing_media;
 mod project_save_state;
 mod state_system;
 
 pub mod event;
 
 pub use bound_gui_state::BoundGuiState;
+pub use collect_media::{collect_media, CollectMediaError, COLLECTED_MEDIA_DIR};
+pub use missing_media::{MissingFile, MissingMediaReport, RelinkCandidate};
 pub use project_save_state::ProjectSaveState;
 pub use state_system::StateSystem;
@@ -1,4 +1,5 @@
 use rusty_daw_core::{MusicalTime, Seconds};
+use std::path::{Component, Path, PathBuf};
 
 use crate::backend::timeline::{
     AudioClipSaveState, LoopState, TempoMap, TimelineTrackSaveState, TimelineTransportSaveState,
@@ -11,13 +12,92 @@ use crate::backend::BackendSaveState;
 /// TODO: Project file format. This will need to be future-proof.
 #[derive(Debug, Clone)]
 pub struct ProjectSaveState {
+    /// The path of the project file. This is `None` if the project has not been saved yet.
+    ///
+    /// In the project file, media paths are stored relative to the directory of the
+    /// project file where possible, so the project folder can be moved (see
+    /// `with_relative_media_paths()` and `resolve_media_paths()`).
+    pub project_path: Option<PathBuf>,
+
     pub backend: BackendSaveState,
     pub timeline_tracks: Vec<TimelineTrackSaveState>,
 }
 
 impl ProjectSaveState {
     pub fn new_empty() -> Self {
-        Self { backend: BackendSaveState::default(), timeline_tracks: Vec::new() }
+        Self {
+            project_path: None,
+            backend: BackendSaveState::default(),
+            timeline_tracks: Vec::new(),
+        }
+    }
+
+    /// The directory containing the project file (if the project has been saved).
+    pub fn project_dir(&self) -> Option<&Path> {
+        self.project_path.as_ref().and_then(|project_path| project_path.parent())
+    }
+
+    /// Set the path of the project file (such as when the project is saved to a new
+    /// location). Relative media paths are made absolute first, so they keep pointing to
+    /// the same files.
+    pub fn set_project_path(&mut self, project_path: PathBuf) {
+        let current_dir = std::env::current_dir().unwrap_or_default();
+
+        let base_dir = match self.project_dir() {
+            Some(project_dir) => project_dir.to_path_buf(),
+            None => current_dir.clone(),
+        };
+        self.for_each_media_path_mut(|path| {
+            if path.is_relative() {
+                *path = base_dir.join(path.as_path());
+            }
+        });
+
+        self.project_path = Some(current_dir.join(project_path));
+    }
+
+    /// Make every relative media path absolute by joining it to the project directory.
+    /// This is used after a project file is read.
+    ///
+    /// Relative paths in a project that has not been saved are relative to the working
+    /// directory of the process, and are left as they are.
+    pub fn resolve_media_paths(&mut self) {
+        let project_dir = match self.project_dir() {
+            Some(project_dir) => project_dir.to_path_buf(),
+            None => return,
+        };
+
+        self.for_each_media_path_mut(|path| {
+            if path.is_relative() {
+                *path = project_dir.join(path.as_path());
+            }
+        });
+    }
+
+    /// Returns a copy of this project where every media path that shares a directory
+    /// with the project directory is made relative to it. This is what should be written
+    /// to the project file (see the TODO above about the project file format).
+    pub fn with_relative_media_paths(&self) -> Self {
+        let mut project = self.clone();
+
+        if let Some(project_dir) = self.project_dir() {
+            project.for_each_media_path_mut(|path| {
+                if let Some(relative_path) = relative_path(path, project_dir) {
+                    *path = relative_path;
+                }
+            });
+        }
+
+        project
+    }
+
+    /// Call `f` with the path of every media file used by this project.
+    pub fn for_each_media_path_mut(&mut self, mut f: impl FnMut(&mut PathBuf)) {
+        for track in self.timeline_tracks.iter_mut() {
+            for clip in track.audio_clips.iter_mut() {
+                clip.for_each_media_path_mut(&mut f);
+            }
+        }
     }
 
     pub fn test() -> Self {
@@ -38,12 +118,22 @@ impl ProjectSaveState {
             audio_clips: vec![AudioClipSaveState {
                 name: String::from("Audio Clip 1"),
                 pcm_path: "./assets/test_files/synth_keys/synth_keys_48000_16bit.wav".into(),
+                pcm_file_info: None,
                 timeline_start: MusicalTime::new(0.0),
                 duration: Seconds::new(3.0),
                 clip_start_offset: Seconds::new(0.0),
                 clip_gain_db: -3.0,
+                gain_envelope: Default::default(),
+                pan: 0.0,
+                pan_law: Default::default(),
+                channel_mode: Default::default(),
+                multichannel_mode: Default::default(),
+                looping: None,
+                effects: Default::default(),
+                interpolation_quality: Default::default(),
                 fades: Default::default(),
             }],
+            overlap_policy: Default::default(),
         });
 
         timeline_tracks.push(TimelineTrackSaveState {
@@ -51,14 +141,84 @@ impl ProjectSaveState {
             audio_clips: vec![AudioClipSaveState {
                 name: String::from("Audio Clip 1"),
                 pcm_path: "./assets/test_files/synth_keys/synth_keys_48000_16bit.wav".into(),
+                pcm_file_info: None,
                 timeline_start: MusicalTime::new(1.0),
                 duration: Seconds::new(3.0),
                 clip_start_offset: Seconds::new(0.0),
                 clip_gain_db: -3.0,
+                gain_envelope: Default::default(),
+                pan: 0.0,
+                pan_law: Default::default(),
+                channel_mode: Default::default(),
+                multichannel_mode: Default::default(),
+                looping: None,
+                effects: Default::default(),
+                interpolation_quality: Default::default(),
                 fades: Default::default(),
             }],
+            overlap_policy: Default::default(),
         });
 
-        Self { backend, timeline_tracks }
+        Self { project_path: None, backend, timeline_tracks }
+    }
+}
+
+/// Returns `path` relative to the directory `base`, or `None` if the two don't share any
+/// directory besides the root (or if either is not absolute).
+fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    if !path.is_absolute() || !base.is_absolute() {
+        return None;
+    }
+
+    let mut path_components = path.components().peekable();
+    let mut base_components = base.components().peekable();
+
+    let mut shared_dirs = 0;
+    while let (Some(a), Some(b)) = (path_components.peek(), base_components.peek()) {
+        if a != b {
+            break;
+        }
+
+        if let Component::Normal(_) = a {
+            shared_dirs += 1;
+        }
+
+        path_components.next();
+        base_components.next();
+    }
+
+    if shared_dirs == 0 {
+        return None;
+    }
+
+    let mut relative_path = PathBuf::new();
+    for _ in base_components {
+        relative_path.push("..");
+    }
+    for component in path_components {
+        relative_path.push(component);
+    }
+
+    Some(relative_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_media_paths() {
+        let base = Path::new("/home/user/projects/song");
+
+        assert_eq!(
+            relative_path(Path::new("/home/user/projects/song/media/kick.wav"), base),
+            Some(PathBuf::from("media/kick.wav"))
+        );
+        assert_eq!(
+            relative_path(Path::new("/home/user/samples/kick.wav"), base),
+            Some(PathBuf::from("../../samples/kick.wav"))
+        );
+        assert_eq!(relative_path(Path::new("/mnt/samples/kick.wav"), base), None);
+        assert_eq!(relative_path(Path::new("samples/kick.wav"), base), None);
     }
 }
@@ -1,14 +1,19 @@
 use cpal::Stream;
 use rusty_daw_audio_graph::{NodeRef, PortType};
 use rusty_daw_core::SampleRate;
+use std::path::{Path, PathBuf};
 use tuix::PropSet;
 use tuix::{BindEvent, Entity, State};
 
+use crate::backend::resource_loader::peaks::PROJECT_PEAKS_DIR;
 use crate::backend::timeline::{TimelineTrackHandle, TimelineTrackNode};
-use crate::backend::{BackendHandle, ResourceLoadError};
+use crate::backend::BackendHandle;
+use crate::util::TwoXHashMap;
 
+use super::collect_media::{collect_media, CollectMediaError};
 use super::event::*;
-use super::{BoundGuiState, ProjectSaveState};
+use super::missing_media::{update_pcm_file_info, MediaScanResult, MediaScanner};
+use super::{BoundGuiState, MissingMediaReport, ProjectSaveState};
 
 pub struct StateSystem {
     stream: Option<Stream>,
@@ -16,6 +21,9 @@ pub struct StateSystem {
     //event_queue: VecDeque<StateSystemEvent>,
     timeline_tracks: Vec<(NodeRef, TimelineTrackHandle)>,
 
+    /// Reads the info of the audio files of the project and searches for missing files.
+    media_scanner: MediaScanner,
+
     sample_rate: SampleRate,
 }
 
@@ -27,6 +35,8 @@ impl StateSystem {
             //event_queue: VecDeque::with_capacity(EVENT_QUEUE_INITIAL_SIZE),
             timeline_tracks: Vec::new(),
 
+            media_scanner: MediaScanner::new(),
+
             sample_rate: SampleRate::default(),
         }
     }
@@ -129,6 +139,20 @@ impl StateSystem {
             ProjectEvent::LoadProject(project_save_state) => {
                 self.load_project(bound_gui_state, project_save_state, state, entity)
             }
+            ProjectEvent::SearchMissingMedia(search_dir) => {
+                self.search_missing_media(bound_gui_state, search_dir);
+                entity.emit(state, BindEvent::Update);
+            }
+            ProjectEvent::RelinkMissingFile { missing, new_path } => {
+                self.relink_missing_file(bound_gui_state, missing, new_path.clone());
+                entity.emit(state, BindEvent::Update);
+            }
+            ProjectEvent::CollectMedia => {
+                if let Err(e) = self.collect_media(bound_gui_state) {
+                    log::error!("{}", e);
+                }
+                entity.emit(state, BindEvent::Update);
+            }
         }
     }
 
@@ -150,14 +174,25 @@ impl StateSystem {
         bound_gui_state.is_playing = false;
         update_gui();
 
+        // Don't keep loading the audio files of the previous project.
+        if let Some(backend_handle) = &self.backend_handle {
+            backend_handle.resource_cache().audio_clip_render_queue.cancel_all();
+        }
+
         // This will drop and automatically close any active backend/stream.
         self.backend_handle = None;
         self.stream = None;
+        self.timeline_tracks.clear();
 
         // This function is temporary. Eventually we should use rusty-daw-io instead.
         let sample_rate =
             crate::backend::hardware_io::default_sample_rate().unwrap_or(SampleRate::default());
 
+        // Media paths are stored relative to the project file.
+        let mut project_save_state = ProjectSaveState::clone(project_save_state);
+        project_save_state.resolve_media_paths();
+
+        bound_gui_state.save_state.project_path = project_save_state.project_path.clone();
         bound_gui_state.save_state.backend =
             project_save_state.backend.clone_with_sample_rate(sample_rate);
         bound_gui_state.save_state.timeline_tracks = project_save_state.timeline_tracks.clone();
@@ -165,50 +200,42 @@ impl StateSystem {
         let (mut backend_handle, rt_state) =
             BackendHandle::from_save_state(sample_rate, &mut bound_gui_state.save_state.backend);
 
-        let mut resource_load_errors: Vec<ResourceLoadError> = Vec::new();
+        // Save the waveform peaks next to the project file, so they are kept with it.
+        if let Some(project_dir) = project_save_state.project_dir() {
+            backend_handle
+                .resource_cache()
+                .resource_loader
+                .lock()
+                .unwrap()
+                .peak_loader
+                .set_cache_dir(project_dir.join(PROJECT_PEAKS_DIR));
+        }
+
+        // Find the audio files that were moved or deleted, so the user can relink them.
+        bound_gui_state.missing_media =
+            MissingMediaReport::new(&bound_gui_state.save_state.timeline_tracks);
+
+        // The results of scans of the previous project are discarded.
+        self.media_scanner = MediaScanner::new();
+        self.media_scanner.read_pcm_file_info(
+            &bound_gui_state.save_state.timeline_tracks,
+            backend_handle.resource_cache().resource_loader.lock().unwrap().pcm_loader.decoder(),
+        );
+
+        // Audio files are loaded in the background, so tracks can start playing while the
+        // rest of the project is still loading.
+        backend_handle.resource_cache().audio_clip_render_queue.set_load_progress_fn(Some(
+            std::sync::Arc::new(|path: &std::path::PathBuf, progress: f32| {
+                log::debug!("Loading {:?}: {:.0}%", path, progress * 100.0);
+            }),
+        ));
 
         // This function is temporary. Eventually we should use rusty-daw-io instead.
         if let Ok(stream) = crate::backend::rt_thread::run_with_default_output(rt_state) {
             bound_gui_state.bpm = project_save_state.backend.tempo_map.bpm();
             update_gui();
 
-            // TODO: errors and reverting to previous working state
-            let _ = backend_handle.modify_graph(|mut graph, resource_cache| {
-                let root_node_ref = graph.root_node();
-
-                for timeline_track_save_state in project_save_state.timeline_tracks.iter() {
-                    let (timeline_track_node, timeline_track_handle, mut res) =
-                        TimelineTrackNode::new(
-                            timeline_track_save_state,
-                            resource_cache,
-                            &project_save_state.backend.tempo_map,
-                            sample_rate,
-                            graph.coll_handle(),
-                        );
-
-                    // Append any errors that happened while loading resources.
-                    resource_load_errors.append(&mut res);
-
-                    // Add the track node to the graph.
-                    let timeline_track_node_ref = graph.add_new_node(Box::new(timeline_track_node));
-
-                    // Keep a reference and a handle to the track node.
-                    self.timeline_tracks.push((timeline_track_node_ref, timeline_track_handle));
-
-                    // Connect the track node to the root node.
-                    graph
-                        .connect_ports(
-                            PortType::StereoAudio,
-                            timeline_track_node_ref,
-                            0,
-                            root_node_ref,
-                            0,
-                        )
-                        .unwrap();
-
-                    // TODO: GUI stuff
-                }
-            });
+            self.add_timeline_tracks(&mut backend_handle, &project_save_state, sample_rate);
 
             self.backend_handle = Some(backend_handle);
             self.stream = Some(stream);
@@ -222,4 +249,302 @@ impl StateSystem {
             panic!("Failed to start audio stream");
         }
     }
+
+    /// Add a node to the graph of the backend for every timeline track of the project,
+    /// and keep a handle to it.
+    fn add_timeline_tracks(
+        &mut self,
+        backend_handle: &mut BackendHandle,
+        project_save_state: &ProjectSaveState,
+        sample_rate: SampleRate,
+    ) {
+        // TODO: errors and reverting to previous working state
+        let _ = backend_handle.modify_graph(|mut graph, resource_cache| {
+            let root_node_ref = graph.root_node();
+
+            for timeline_track_save_state in project_save_state.timeline_tracks.iter() {
+                let (timeline_track_node, timeline_track_handle) = TimelineTrackNode::new(
+                    timeline_track_save_state,
+                    resource_cache,
+                    &project_save_state.backend.tempo_map,
+                    sample_rate,
+                    graph.coll_handle(),
+                );
+
+                // Add the track node to the graph.
+                let timeline_track_node_ref = graph.add_new_node(Box::new(timeline_track_node));
+
+                // Keep a reference and a handle to the track node.
+                self.timeline_tracks.push((timeline_track_node_ref, timeline_track_handle));
+
+                // Connect the track node to the root node.
+                graph
+                    .connect_ports(
+                        PortType::StereoAudio,
+                        timeline_track_node_ref,
+                        0,
+                        root_node_ref,
+                        0,
+                    )
+                    .unwrap();
+
+                // TODO: GUI stuff
+            }
+        });
+    }
+
+    /// Apply the results of the scans of the `MediaScanner` that finished since this was
+    /// last called. Returns `true` if the GUI state was changed.
+    pub fn poll_media_scans(&mut self, bound_gui_state: &mut BoundGuiState) -> bool {
+        let mut changed = false;
+
+        while let Some(result) = self.media_scanner.try_recv() {
+            match result {
+                MediaScanResult::PcmFileInfo(read_info) => {
+                    update_pcm_file_info(
+                        &mut bound_gui_state.save_state.timeline_tracks,
+                        &read_info,
+                    );
+                }
+                MediaScanResult::RelinkCandidates(candidates) => {
+                    bound_gui_state.missing_media.set_candidates(candidates);
+                    self.relink_exact_matches(bound_gui_state);
+                }
+            }
+
+            changed = true;
+        }
+
+        changed
+    }
+
+    /// Search the given directory tree for the missing audio files of the project (see
+    /// `MissingMediaReport`) on a background thread. Once the search is finished, files
+    /// found with a matching size and length are relinked automatically. The other files
+    /// found are kept as candidates for the user to choose from.
+    fn search_missing_media(&mut self, bound_gui_state: &mut BoundGuiState, search_dir: &Path) {
+        let decoder = match &self.backend_handle {
+            Some(backend_handle) => {
+                backend_handle.resource_cache().resource_loader.lock().unwrap().pcm_loader.decoder()
+            }
+            None => return,
+        };
+
+        self.media_scanner.find_relink_candidates(
+            &bound_gui_state.missing_media,
+            search_dir.to_path_buf(),
+            decoder,
+        );
+    }
+
+    /// Relink every missing file whose best candidate matches its size and length.
+    fn relink_exact_matches(&mut self, bound_gui_state: &mut BoundGuiState) {
+        let relinks: Vec<(PathBuf, PathBuf)> = bound_gui_state
+            .missing_media
+            .files
+            .iter()
+            .filter_map(|file| match file.candidates.first() {
+                Some(candidate) if candidate.is_exact_match() => {
+                    Some((file.path.clone(), candidate.path.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+
+        for (missing, new_path) in relinks {
+            self.relink_missing_file(bound_gui_state, &missing, new_path);
+        }
+    }
+
+    /// Use the file at `new_path` for every audio clip that uses the missing file at
+    /// `missing`.
+    fn relink_missing_file(
+        &mut self,
+        bound_gui_state: &mut BoundGuiState,
+        missing: &PathBuf,
+        new_path: PathBuf,
+    ) {
+        let missing_file = match bound_gui_state.missing_media.remove(missing) {
+            Some(missing_file) => missing_file,
+            None => {
+                log::warn!("Cannot relink {:?}: the file is not missing", missing);
+                return;
+            }
+        };
+
+        log::info!("Relinking missing file {:?} to {:?}", missing, &new_path);
+
+        let mut new_paths: TwoXHashMap<PathBuf, PathBuf> = Default::default();
+        new_paths.insert(missing.clone(), new_path.clone());
+
+        self.replace_media_paths(bound_gui_state, &new_paths);
+
+        // The size and length of the new file were read when it was found by a search.
+        // Otherwise they are read in the background.
+        let info = missing_file
+            .candidates
+            .iter()
+            .find(|candidate| candidate.path == new_path)
+            .and_then(|candidate| candidate.info);
+
+        let mut relinked_clips = false;
+        for clip in bound_gui_state
+            .save_state
+            .timeline_tracks
+            .iter_mut()
+            .flat_map(|track| track.audio_clips.iter_mut())
+            .filter(|clip| clip.pcm_path == new_path)
+        {
+            clip.pcm_file_info = info;
+            relinked_clips = true;
+        }
+
+        if relinked_clips && info.is_none() {
+            if let Some(backend_handle) = &self.backend_handle {
+                let decoder = backend_handle
+                    .resource_cache()
+                    .resource_loader
+                    .lock()
+                    .unwrap()
+                    .pcm_loader
+                    .decoder();
+
+                self.media_scanner
+                    .read_pcm_file_info(&bound_gui_state.save_state.timeline_tracks, decoder);
+            }
+        }
+    }
+
+    /// Copy every media file used by the project that is outside of the project directory
+    /// into the project directory (see `collect_media()`), and use the copies for every
+    /// audio clip of the project.
+    ///
+    /// Returns the project with its media paths relative to the project file, which is
+    /// what should be written to the project file.
+    pub fn collect_media(
+        &mut self,
+        bound_gui_state: &mut BoundGuiState,
+    ) -> Result<ProjectSaveState, CollectMediaError> {
+        let new_paths = collect_media(&bound_gui_state.save_state)?;
+
+        self.replace_media_paths(bound_gui_state, &new_paths);
+
+        Ok(bound_gui_state.save_state.with_relative_media_paths())
+    }
+
+    /// Use the value of `new_paths` in place of its key for every media file used by the
+    /// audio clips of the project.
+    ///
+    /// The recorded size and length of the files are kept. Files that are not copies of
+    /// the old files need their info updated afterwards (see `relink_missing_file()`).
+    fn replace_media_paths(
+        &mut self,
+        bound_gui_state: &mut BoundGuiState,
+        new_paths: &TwoXHashMap<PathBuf, PathBuf>,
+    ) {
+        let resource_cache = match &self.backend_handle {
+            Some(backend_handle) => backend_handle.resource_cache().clone(),
+            None => return,
+        };
+        for ((_, track_handle), track_save_state) in self
+            .timeline_tracks
+            .iter_mut()
+            .zip(bound_gui_state.save_state.timeline_tracks.iter_mut())
+        {
+            for index in 0..track_save_state.audio_clips.len() {
+                let (audio_clip, save_state) =
+                    match track_handle.audio_clip_mut(index, track_save_state) {
+                        Some(audio_clip) => audio_clip,
+                        None => continue,
+                    };
+
+                if let Some(new_path) = new_paths.get(&save_state.pcm_path) {
+                    audio_clip.set_pcm(new_path.clone(), &resource_cache, save_state);
+                }
+
+                let new_ir_path = save_state
+                    .effects
+                    .convolution
+                    .as_ref()
+                    .and_then(|convolution| new_paths.get(&convolution.ir_path))
+                    .cloned();
+                if let Some(new_ir_path) = new_ir_path {
+                    let mut effects = save_state.effects.clone();
+                    if let Some(convolution) = &mut effects.convolution {
+                        convolution.ir_path = new_ir_path;
+                    }
+
+                    audio_clip.set_effects(effects, &resource_cache, save_state);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusty_daw_core::Seconds;
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::backend::timeline::{AudioClipSaveState, ConvolutionEffect, TimelineTrackSaveState};
+    use crate::state::COLLECTED_MEDIA_DIR;
+
+    #[test]
+    fn collects_media_and_uses_the_copies() {
+        let dir = std::env::temp_dir().join("meadowlark_test_state_collect_media");
+        let _ = fs::remove_dir_all(&dir);
+
+        let samples_dir = dir.join("samples");
+        fs::create_dir_all(&samples_dir).unwrap();
+        let pcm_path = samples_dir.join("keys.wav");
+        let ir_path = samples_dir.join("room.wav");
+        for path in [&pcm_path, &ir_path].iter() {
+            fs::copy("./assets/test_files/synth_keys/synth_keys_44100_16bit.wav", path).unwrap();
+        }
+
+        let mut audio_clip = AudioClipSaveState::test(pcm_path.to_str().unwrap(), 0.0, 1.0);
+        audio_clip.effects.convolution = Some(ConvolutionEffect {
+            ir_path: ir_path.clone(),
+            mix: 0.5,
+            ir_start: Seconds(0.0),
+            ir_length: None,
+        });
+
+        let mut bound_gui_state = BoundGuiState::new();
+        let mut state_system = bound_gui_state.state_system.take().unwrap();
+
+        let save_state = &mut bound_gui_state.save_state;
+        save_state.set_project_path(dir.join("project").join("song.mdl"));
+        save_state.timeline_tracks.push(TimelineTrackSaveState {
+            name: String::from("Track 1"),
+            audio_clips: vec![audio_clip],
+            overlap_policy: Default::default(),
+        });
+
+        let sample_rate = SampleRate::default();
+        let (mut backend_handle, _rt_state) =
+            BackendHandle::from_save_state(sample_rate, &mut save_state.backend);
+        state_system.add_timeline_tracks(&mut backend_handle, save_state, sample_rate);
+        state_system.backend_handle = Some(backend_handle);
+
+        let project = state_system.collect_media(&mut bound_gui_state).unwrap();
+
+        // The audio clip uses the copies in the project directory.
+        let media_dir = dir.join("project").join(COLLECTED_MEDIA_DIR);
+        let audio_clip = &bound_gui_state.save_state.timeline_tracks[0].audio_clips[0];
+        assert_eq!(audio_clip.pcm_path, media_dir.join("keys.wav"));
+        assert_eq!(
+            audio_clip.effects.convolution.as_ref().unwrap().ir_path,
+            media_dir.join("room.wav")
+        );
+        assert!(media_dir.join("keys.wav").is_file());
+
+        // The project file stores the paths relative to it.
+        let audio_clip = &project.timeline_tracks[0].audio_clips[0];
+        assert_eq!(audio_clip.pcm_path, Path::new(COLLECTED_MEDIA_DIR).join("keys.wav"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
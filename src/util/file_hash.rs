@@ -0,0 +1,25 @@
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{self, Read};
+use std::path::Path;
+use twox_hash::XxHash64;
+
+/// Hash the contents of the file at `path` using the XXHash algorithm.
+pub fn hash_file(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut hasher = XxHash64::default();
+
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        hasher.write(&buf[0..n]);
+    }
+
+    Ok(hasher.finish())
+}
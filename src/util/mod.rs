@@ -1,3 +1,5 @@
+mod file_hash;
 mod twox_hash_map;
 
+pub use file_hash::hash_file;
 pub use twox_hash_map::TwoXHashMap;